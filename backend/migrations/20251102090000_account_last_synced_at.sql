@@ -0,0 +1,2 @@
+-- When a sync of the account last completed, as an ISO 8601 UTC timestamp
+ALTER TABLE accounts ADD COLUMN last_synced_at TEXT;
@@ -0,0 +1,8 @@
+-- Raw header fields of emails, fetched on demand for "show original"
+CREATE TABLE email_headers (
+    account_id INTEGER NOT NULL,
+    email_id TEXT NOT NULL,
+    headers TEXT NOT NULL, -- JSON array of {name, value}, in message order
+    PRIMARY KEY (account_id, email_id),
+    FOREIGN KEY (account_id, email_id) REFERENCES emails(account_id, id) ON DELETE CASCADE
+) WITHOUT ROWID;
@@ -0,0 +1,7 @@
+-- Read-only tokens for sharing a view of some accounts/mailboxes
+CREATE TABLE api_tokens (
+    token_hash TEXT NOT NULL PRIMARY KEY, -- SHA-256 of the token, hex encoded
+    account_ids TEXT, -- JSON array, NULL for all accounts
+    mailbox_ids TEXT, -- JSON array, NULL for all mailboxes
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+) WITHOUT ROWID;
@@ -0,0 +1,9 @@
+-- Outcomes of mutating requests, replayed when a client retries with the same Idempotency-Key
+CREATE TABLE idempotency_keys (
+    account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    endpoint TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    response TEXT NOT NULL, -- JSON body of the original response
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    PRIMARY KEY (account_id, endpoint, idempotency_key)
+) WITHOUT ROWID;
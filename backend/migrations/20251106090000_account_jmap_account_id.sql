@@ -0,0 +1,2 @@
+-- The JMAP account to sync, NULL for the session's primary mail account
+ALTER TABLE accounts ADD COLUMN jmap_account_id TEXT;
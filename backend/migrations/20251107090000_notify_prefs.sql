@@ -0,0 +1,5 @@
+-- Per-account notification preferences
+CREATE TABLE notify_prefs (
+    account_id INTEGER NOT NULL PRIMARY KEY REFERENCES accounts(id) ON DELETE CASCADE,
+    prefs TEXT NOT NULL -- JSON object, see NotifyPrefs
+);
@@ -0,0 +1,5 @@
+-- Per-account settings
+CREATE TABLE account_settings (
+    account_id INTEGER NOT NULL PRIMARY KEY REFERENCES accounts(id) ON DELETE CASCADE,
+    settings TEXT NOT NULL -- JSON object, see AccountSettings
+);
@@ -0,0 +1,38 @@
+-- What list views show of an email, so they don't have to read the whole of jmap_data.
+-- Kept in step with jmap_data by the triggers below.
+ALTER TABLE emails ADD COLUMN summary_json TEXT;
+
+CREATE TRIGGER trg_update_email_summary_after_email_insert
+AFTER INSERT ON emails
+BEGIN
+    UPDATE emails SET summary_json = json_object(
+        'id', NEW.id,
+        'subject', NEW.jmap_data->>'$.subject',
+        'from', NEW.jmap_data->'$.from',
+        'receivedAt', NEW.jmap_data->>'$.receivedAt',
+        'unread', json(iif(NEW.jmap_data->>'$.keywords."$seen"', 'false', 'true')),
+        'flagged', json(iif(NEW.jmap_data->>'$.keywords."$flagged"', 'true', 'false')),
+        'hasAttachment', json(iif(NEW.jmap_data->>'$.hasAttachment', 'true', 'false')),
+        'preview', NEW.jmap_data->>'$.preview'
+    )
+    WHERE account_id = NEW.account_id AND id = NEW.id;
+END;
+
+CREATE TRIGGER trg_update_email_summary_after_email_changed
+AFTER UPDATE OF jmap_data ON emails
+BEGIN
+    UPDATE emails SET summary_json = json_object(
+        'id', NEW.id,
+        'subject', NEW.jmap_data->>'$.subject',
+        'from', NEW.jmap_data->'$.from',
+        'receivedAt', NEW.jmap_data->>'$.receivedAt',
+        'unread', json(iif(NEW.jmap_data->>'$.keywords."$seen"', 'false', 'true')),
+        'flagged', json(iif(NEW.jmap_data->>'$.keywords."$flagged"', 'true', 'false')),
+        'hasAttachment', json(iif(NEW.jmap_data->>'$.hasAttachment', 'true', 'false')),
+        'preview', NEW.jmap_data->>'$.preview'
+    )
+    WHERE account_id = NEW.account_id AND id = NEW.id;
+END;
+
+-- Fill in the emails already stored, by touching jmap_data
+UPDATE emails SET jmap_data = jmap_data;
@@ -0,0 +1,48 @@
+-- Add who actually sent an email, and where replies should go, to the summary. "onBehalfOf" is
+-- set when Sender differs from From, e.g. for mailing lists.
+DROP TRIGGER trg_update_email_summary_after_email_insert;
+DROP TRIGGER trg_update_email_summary_after_email_changed;
+
+CREATE TRIGGER trg_update_email_summary_after_email_insert
+AFTER INSERT ON emails
+BEGIN
+    UPDATE emails SET summary_json = json_object(
+        'id', NEW.id,
+        'subject', NEW.jmap_data->>'$.subject',
+        'from', NEW.jmap_data->'$.from',
+        'sender', NEW.jmap_data->'$.sender',
+        'replyTo', NEW.jmap_data->'$.replyTo',
+        'onBehalfOf', json(iif(
+            lower(NEW.jmap_data->>'$.sender[0].email') != lower(NEW.jmap_data->>'$.from[0].email'),
+            'true', 'false')),
+        'receivedAt', NEW.jmap_data->>'$.receivedAt',
+        'unread', json(iif(NEW.jmap_data->>'$.keywords."$seen"', 'false', 'true')),
+        'flagged', json(iif(NEW.jmap_data->>'$.keywords."$flagged"', 'true', 'false')),
+        'hasAttachment', json(iif(NEW.jmap_data->>'$.hasAttachment', 'true', 'false')),
+        'preview', NEW.jmap_data->>'$.preview'
+    )
+    WHERE account_id = NEW.account_id AND id = NEW.id;
+END;
+
+CREATE TRIGGER trg_update_email_summary_after_email_changed
+AFTER UPDATE OF jmap_data ON emails
+BEGIN
+    UPDATE emails SET summary_json = json_object(
+        'id', NEW.id,
+        'subject', NEW.jmap_data->>'$.subject',
+        'from', NEW.jmap_data->'$.from',
+        'sender', NEW.jmap_data->'$.sender',
+        'replyTo', NEW.jmap_data->'$.replyTo',
+        'onBehalfOf', json(iif(
+            lower(NEW.jmap_data->>'$.sender[0].email') != lower(NEW.jmap_data->>'$.from[0].email'),
+            'true', 'false')),
+        'receivedAt', NEW.jmap_data->>'$.receivedAt',
+        'unread', json(iif(NEW.jmap_data->>'$.keywords."$seen"', 'false', 'true')),
+        'flagged', json(iif(NEW.jmap_data->>'$.keywords."$flagged"', 'true', 'false')),
+        'hasAttachment', json(iif(NEW.jmap_data->>'$.hasAttachment', 'true', 'false')),
+        'preview', NEW.jmap_data->>'$.preview'
+    )
+    WHERE account_id = NEW.account_id AND id = NEW.id;
+END;
+
+UPDATE emails SET jmap_data = jmap_data;
@@ -0,0 +1,37 @@
+-- An email without a threadId (or receivedAt) made the mailbox_emails insert fail its NOT NULL
+-- constraints, which took the whole batch of emails down with it. Such an email is now a thread
+-- of its own. The update trigger's condition also had its AND/OR precedence wrong and missed
+-- changes from or to NULL.
+DROP TRIGGER trg_update_mailbox_emails_after_email_insert;
+DROP TRIGGER trg_update_mailbox_emails_after_email_changed;
+
+CREATE TRIGGER trg_update_mailbox_emails_after_email_insert
+AFTER INSERT ON emails
+BEGIN
+    INSERT INTO mailbox_emails (account_id, mailbox_id, email_id, thread_id, received_at)
+        SELECT NEW.account_id, mb.key, NEW.id,
+               COALESCE(NEW.thread_id, NEW.id),
+               COALESCE(NEW.received_at, NEW.sent_at, '')
+        FROM json_each(NEW.jmap_data->'$.mailboxIds') AS mb
+        WHERE mb.value == true;
+END;
+
+CREATE TRIGGER trg_update_mailbox_emails_after_email_changed
+AFTER UPDATE ON emails WHEN
+    (OLD.jmap_data->'$.mailboxIds' IS NOT NEW.jmap_data->'$.mailboxIds') OR
+    (OLD.thread_id IS NOT NEW.thread_id) OR
+    (OLD.received_at IS NOT NEW.received_at)
+BEGIN
+    INSERT OR REPLACE INTO mailbox_emails (account_id, mailbox_id, email_id, thread_id, received_at)
+        SELECT NEW.account_id, mb.key, NEW.id,
+               COALESCE(NEW.thread_id, NEW.id),
+               COALESCE(NEW.received_at, NEW.sent_at, '')
+        FROM json_each(NEW.jmap_data->'$.mailboxIds') AS mb
+        WHERE mb.value == true;
+
+    DELETE FROM mailbox_emails
+    WHERE account_id = OLD.account_id AND email_id = OLD.id AND mailbox_id NOT IN
+    (SELECT mb.key
+     FROM json_each(NEW.jmap_data->'$.mailboxIds') AS mb
+     WHERE mb.value == true);
+END;
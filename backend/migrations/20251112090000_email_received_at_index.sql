@@ -0,0 +1,4 @@
+-- thread_id, received_at, subject and the other email columns are generated from jmap_data
+-- already. What was missing is an index for ordering and ranging over an account's emails by
+-- time, e.g. for the export.
+CREATE INDEX idx_emails_account_received_at ON emails(account_id, received_at);
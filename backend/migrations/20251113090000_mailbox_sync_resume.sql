@@ -0,0 +1,4 @@
+-- Progress of a full mailbox sync, so one interrupted by a restart carries on where it was
+-- rather than fetching every email again. Cleared once the sync completes.
+ALTER TABLE mailboxes ADD COLUMN email_sync_resume_state TEXT; -- The state the sync started from
+ALTER TABLE mailboxes ADD COLUMN email_sync_resume_anchor TEXT; -- The last email id it stored
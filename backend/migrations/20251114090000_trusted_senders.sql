@@ -0,0 +1,6 @@
+-- Senders whose emails always load remote images, whatever the account's image policy
+CREATE TABLE trusted_senders (
+    account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    pattern TEXT NOT NULL, -- A lowercase address, or *@domain for a whole domain
+    PRIMARY KEY (account_id, pattern)
+);
@@ -0,0 +1,8 @@
+-- Per-mailbox preferences, e.g. how its email list is sorted and filtered by default
+CREATE TABLE mailbox_prefs (
+    account_id INTEGER NOT NULL,
+    mailbox_id TEXT NOT NULL,
+    default_query TEXT, -- JSON object, see MailboxDefaultQuery
+    PRIMARY KEY (account_id, mailbox_id),
+    FOREIGN KEY (account_id, mailbox_id) REFERENCES mailboxes(account_id, id) ON DELETE CASCADE
+) WITHOUT ROWID;
@@ -0,0 +1,4 @@
+-- What sorting by sender compares: the first sender's name, or their address when unnamed
+ALTER TABLE emails ADD COLUMN from_addr TEXT GENERATED ALWAYS AS (
+    lower(COALESCE(NULLIF(jmap_data->>'$.from[0].name', ''), jmap_data->>'$.from[0].email'))
+) VIRTUAL;
@@ -0,0 +1,77 @@
+-- Full-text index of the emails, for searching locally the way the server's text filter does.
+-- emails has no rowid for the index to refer to, so email_search_ids gives each email one.
+CREATE TABLE email_search_ids (
+    rowid INTEGER PRIMARY KEY,
+    account_id INTEGER NOT NULL,
+    email_id TEXT NOT NULL,
+    UNIQUE (account_id, email_id)
+);
+
+CREATE VIRTUAL TABLE email_search USING fts5(
+    subject,
+    sender,
+    recipients,
+    body,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIEW email_search_source AS
+SELECT e.account_id,
+       e.id AS email_id,
+       COALESCE(e.subject, '') AS subject,
+       (SELECT group_concat(COALESCE(a.value->>'$.name', '') || ' ' || COALESCE(a.value->>'$.email', ''), ' ')
+        FROM json_each(e.jmap_data, '$.from') AS a) AS sender,
+       (SELECT group_concat(COALESCE(a.value->>'$.name', '') || ' ' || COALESCE(a.value->>'$.email', ''), ' ')
+        FROM (SELECT value FROM json_each(e.jmap_data, '$.to')
+              UNION ALL
+              SELECT value FROM json_each(e.jmap_data, '$.cc')) AS a) AS recipients,
+       -- The preview, and the full text once the body values are stored
+       COALESCE(e.jmap_data->>'$.preview', '') || ' ' ||
+       COALESCE((SELECT group_concat(v.value->>'$.value', ' ')
+                 FROM json_each(e.jmap_data, '$.bodyValues') AS v), '') AS body
+FROM emails e;
+
+CREATE TRIGGER trg_email_search_after_email_insert
+AFTER INSERT ON emails
+BEGIN
+    INSERT OR IGNORE INTO email_search_ids (account_id, email_id) VALUES (NEW.account_id, NEW.id);
+
+    INSERT OR REPLACE INTO email_search (rowid, subject, sender, recipients, body)
+    SELECT s.rowid, c.subject, c.sender, c.recipients, c.body
+    FROM email_search_ids s, email_search_source c
+    WHERE s.account_id = NEW.account_id AND s.email_id = NEW.id
+      AND c.account_id = NEW.account_id AND c.email_id = NEW.id;
+END;
+
+CREATE TRIGGER trg_email_search_after_email_changed
+AFTER UPDATE OF jmap_data ON emails
+BEGIN
+    DELETE FROM email_search
+    WHERE rowid = (SELECT rowid FROM email_search_ids
+                   WHERE account_id = NEW.account_id AND email_id = NEW.id);
+
+    INSERT INTO email_search (rowid, subject, sender, recipients, body)
+    SELECT s.rowid, c.subject, c.sender, c.recipients, c.body
+    FROM email_search_ids s, email_search_source c
+    WHERE s.account_id = NEW.account_id AND s.email_id = NEW.id
+      AND c.account_id = NEW.account_id AND c.email_id = NEW.id;
+END;
+
+CREATE TRIGGER trg_email_search_after_email_delete
+AFTER DELETE ON emails
+BEGIN
+    DELETE FROM email_search
+    WHERE rowid = (SELECT rowid FROM email_search_ids
+                   WHERE account_id = OLD.account_id AND email_id = OLD.id);
+
+    DELETE FROM email_search_ids WHERE account_id = OLD.account_id AND email_id = OLD.id;
+END;
+
+-- Index the emails already stored
+INSERT INTO email_search_ids (account_id, email_id)
+SELECT account_id, id FROM emails;
+
+INSERT INTO email_search (rowid, subject, sender, recipients, body)
+SELECT s.rowid, c.subject, c.sender, c.recipients, c.body
+FROM email_search_ids s
+JOIN email_search_source c ON c.account_id = s.account_id AND c.email_id = s.email_id;
@@ -0,0 +1,11 @@
+-- Resources fetched through the image proxy. Remote resources look the same to every account,
+-- so they're keyed by URL alone. They expire as their Cache-Control or Expires header says.
+CREATE TABLE external_cache(
+    url TEXT NOT NULL PRIMARY KEY,
+    content_type TEXT NOT NULL,
+    data BLOB NOT NULL,
+    last_accessed DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL
+) WITHOUT ROWID;
+
+CREATE INDEX idx_external_cache_last_accessed ON external_cache(last_accessed);
@@ -0,0 +1,4 @@
+-- Mailbox email sync states used to be Email/query states, which Email/changes doesn't take.
+-- Forgetting them makes each mailbox resync once, from a state of the emails.
+UPDATE mailboxes
+SET email_sync_state = NULL, email_sync_resume_state = NULL, email_sync_resume_anchor = NULL;
@@ -0,0 +1,10 @@
+-- Accounts being deleted stay around until their data is purged, but are no longer synced or
+-- listed.
+ALTER TABLE accounts ADD COLUMN deleting BOOLEAN NOT NULL DEFAULT false;
+
+-- Names are unique among the accounts in use, as the accounts file matches accounts by name.
+-- Earlier duplicates get their id appended.
+UPDATE accounts SET name = name || ' (' || id || ')'
+WHERE id NOT IN (SELECT MIN(id) FROM accounts GROUP BY name);
+
+CREATE UNIQUE INDEX accounts_name ON accounts (name) WHERE NOT deleting;
@@ -0,0 +1,263 @@
+use super::ApiState;
+use super::identities::default_identity;
+use crate::jmap_account::{
+    Account, AccountId, AccountRepositoryExt, AccountSummary, is_duplicate_account_name,
+};
+use crate::jmap_api::ConnectionStatus;
+use crate::repo::{AccountSettings, RoleMailbox};
+use crate::sync::AccountSyncStatus;
+use crate::util::custom_headers::check_custom_headers;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use jmap_client::identity::Identity;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{Instrument, instrument};
+use url::Url;
+
+/// How long a forced reconnect waits for the outcome before answering with what it has.
+const RECONNECT_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountResponse {
+    #[serde(flatten)]
+    pub summary: AccountSummary,
+    /// Absent while the account's sync hasn't been started.
+    pub sync_status: Option<AccountSyncStatus>,
+    /// Absent while the account's sync hasn't been started.
+    pub connection: Option<ConnectionStatus>,
+}
+
+#[instrument(skip(state))]
+pub async fn list_accounts(
+    state: extract::State<ApiState>,
+) -> HttpResult<Json<Vec<AccountResponse>>> {
+    let summaries = state
+        .repo
+        .list_account_summaries()
+        .await
+        .context("Error listing accounts")
+        .into_internal_error_result()?;
+
+    Ok(Json(
+        summaries
+            .into_iter()
+            .map(|summary| with_sync_status(&state, summary))
+            .collect(),
+    ))
+}
+
+/// Adds an account, which starts syncing shortly after. Names are unique, as the accounts file
+/// matches accounts by name.
+#[instrument(skip(state))]
+pub async fn add_account(
+    state: extract::State<ApiState>,
+    Json(account): Json<Account>,
+) -> HttpResult<(StatusCode, Json<AccountResponse>)> {
+    if account.name.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The name can't be empty".to_string(),
+        )
+            .into());
+    }
+
+    Url::parse(&account.server_url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .context("The server URL must be an http or https URL")
+        .into_error_result(StatusCode::BAD_REQUEST)?;
+
+    let account_id = match state.repo.add_account(&account).await {
+        Ok(account_id) => account_id,
+        Err(e) if is_duplicate_account_name(&e) => {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("An account named {} already exists", account.name),
+            )
+                .into());
+        }
+        Err(e) => return Err(e.context("Error adding account")).into_internal_error_result(),
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(find_account(&state, account_id).await?),
+    ))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSnapshot {
+    #[serde(flatten)]
+    pub account: AccountResponse,
+    /// Unread emails in the mailboxes the notification preferences count toward the badge,
+    /// the Inbox by default.
+    pub unread_emails: i64,
+    pub role_mailboxes: Vec<RoleMailbox>,
+    /// Absent until the identities have been fetched at least once.
+    pub default_identity: Option<Identity>,
+}
+
+#[instrument(skip(state))]
+pub async fn get_account(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<AccountResponse>> {
+    find_account(&state, account_id).await.map(Json)
+}
+
+/// Everything a client shows of an account on startup, in one go. Only local and cached data
+/// is read, so this never waits on the JMAP server.
+#[instrument(skip(state))]
+pub async fn get_account_summary(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<AccountSnapshot>> {
+    let account = find_account(&state, account_id).await?;
+
+    let role_mailboxes = state
+        .repo
+        .get_role_mailboxes(account_id)
+        .await
+        .context("Error getting role mailboxes")
+        .into_internal_error_result()?;
+
+    let notify_prefs = state
+        .repo
+        .get_notify_prefs(account_id)
+        .await
+        .context("Error getting notification preferences")
+        .into_internal_error_result()?;
+    let badge_roles = notify_prefs.badge_roles();
+
+    let default_identity = state.jmap_api(account_id).ok().and_then(|api| {
+        default_identity(
+            api.cached_identities()?,
+            &api.connected_session_username().unwrap_or_default(),
+        )
+    });
+
+    Ok(Json(AccountSnapshot {
+        account,
+        unread_emails: role_mailboxes
+            .iter()
+            .filter(|m| badge_roles.contains(&m.role.as_str()))
+            .map(|m| m.unread_emails)
+            .sum(),
+        role_mailboxes,
+        default_identity,
+    }))
+}
+
+async fn find_account(state: &ApiState, account_id: AccountId) -> HttpResult<AccountResponse> {
+    let summary = state
+        .repo
+        .list_account_summaries()
+        .await
+        .context("Error listing accounts")
+        .into_internal_error_result()?
+        .into_iter()
+        .find(|account| account.id == account_id)
+        .context("Account not found")
+        .into_not_found_error_result()?;
+
+    Ok(with_sync_status(state, summary))
+}
+
+#[instrument(skip(state))]
+pub async fn get_account_settings(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<AccountSettings>> {
+    state.ensure_account(account_id).await?;
+
+    state
+        .repo
+        .get_account_settings(account_id)
+        .await
+        .context("Error getting account settings")
+        .into_internal_error_result()
+        .map(Json)
+}
+
+#[instrument(skip(state))]
+pub async fn put_account_settings(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    Json(settings): Json<AccountSettings>,
+) -> HttpResult<Json<AccountSettings>> {
+    state.ensure_account(account_id).await?;
+    check_custom_headers(&settings.custom_headers).into_error_result(StatusCode::BAD_REQUEST)?;
+
+    state
+        .repo
+        .set_account_settings(account_id, &settings)
+        .await
+        .context("Error saving account settings")
+        .into_internal_error_result()?;
+
+    Ok(Json(settings))
+}
+
+fn with_sync_status(state: &ApiState, summary: AccountSummary) -> AccountResponse {
+    let account_states = state.account_states.read();
+    let account_state = account_states.get(&summary.id);
+
+    AccountResponse {
+        sync_status: account_state.map(|s| s.sync_status.lock().clone()),
+        connection: account_state.map(|s| s.jmap_api.connection_status()),
+        summary,
+    }
+}
+
+/// Reconnects to the JMAP server right away instead of waiting out the backoff, e.g. once the
+/// user knows the network is back. Answers with the connection status the attempt ends in.
+#[instrument(skip(state))]
+pub async fn reconnect_account(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<ConnectionStatus>> {
+    let jmap_api = state.jmap_api(account_id)?;
+    Ok(Json(jmap_api.reconnect_now(RECONNECT_WAIT).await))
+}
+
+/// Deletes the account. Purging its stored mail can take a while for large
+/// accounts, so it happens in the background and the request returns `202 Accepted`. The
+/// account is gone from the API right away, and a purge cut short resumes on the next start.
+#[instrument(skip(state))]
+pub async fn delete_account(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<StatusCode> {
+    let found = state
+        .repo
+        .mark_account_deleting(account_id)
+        .await
+        .context("Error deleting account")
+        .into_internal_error_result()?;
+    if !found {
+        return Err((StatusCode::NOT_FOUND, "Account not found".to_string()).into());
+    }
+
+    // Stop syncing right away rather than once the purge is done, which drops the account's
+    // connection and sync tasks. Being marked as deleting, the account won't be picked up again.
+    state.account_states.write().remove(&account_id);
+
+    let repo = state.repo.clone();
+    tokio::spawn(
+        async move {
+            match repo.delete_account(account_id).await {
+                Ok(deleted) => tracing::info!(deleted, "Account purged"),
+                Err(e) => tracing::error!(?e, "Error purging account"),
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,82 @@
+use super::ApiState;
+use super::drafts::resolve_sender;
+use super::idempotency::idempotent;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::DraftEmail;
+use crate::util::custom_headers::check_custom_headers;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    pub mailbox_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppendRequest {
+    /// The identity to write as, defaulting to the account's default identity.
+    pub identity_id: Option<String>,
+    /// Keywords to create the email with, e.g. `$seen` or `$flagged`.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(flatten)]
+    pub email: DraftEmail,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppendedEmail {
+    pub id: String,
+}
+
+/// Adds an email straight to a mailbox without sending it, e.g. to save a note to a folder.
+#[instrument(skip(state, headers))]
+pub async fn append_email(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    extract::Query(Params { mailbox_id }): extract::Query<Params>,
+    headers: HeaderMap,
+    Json(AppendRequest {
+        identity_id,
+        keywords,
+        email,
+    }): Json<AppendRequest>,
+) -> HttpResult<(StatusCode, Json<AppendedEmail>)> {
+    let mailbox_ids = state
+        .repo
+        .get_mailbox_ids(account_id)
+        .await
+        .context("Error getting mailboxes")
+        .into_internal_error_result()?;
+
+    if !mailbox_ids.contains(&mailbox_id) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Mailbox {mailbox_id} not found"),
+        )
+            .into());
+    }
+
+    check_custom_headers(&email.headers).into_error_result(StatusCode::BAD_REQUEST)?;
+
+    let appended = idempotent(&state, account_id, "append_email", &headers, || async {
+        let api = state.jmap_api(account_id)?;
+        let from = resolve_sender(&api, identity_id.as_deref()).await?;
+
+        let id = api
+            .append_email(email, from, mailbox_id, keywords)
+            .await
+            .context("Error adding email")
+            .into_internal_error_result()?;
+
+        Ok(AppendedEmail { id })
+    })
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(appended)))
+}
@@ -0,0 +1,250 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::repo::TokenScope;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{MatchedPath, RawPathParams, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use tracing::instrument;
+
+enum Access {
+    Admin,
+    ReadOnly(TokenScope),
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    pub token: String,
+}
+
+/// Lets through anyone who may read, checking read-only tokens against their scope.
+pub async fn require_read(
+    State(state): State<ApiState>,
+    path: Option<MatchedPath>,
+    params: RawPathParams,
+    req: Request,
+    next: Next,
+) -> HttpResult<Response> {
+    let token = request_token(&req).map(str::to_string);
+    if let Access::ReadOnly(scope) = authenticate(&state, token.as_deref()).await? {
+        // The request can't be held across the database lookups, as its body isn't Sync
+        let mailbox_query = query_param(&req, "mailboxId").map(str::to_string);
+        check_scope(
+            &state,
+            &scope,
+            path.as_ref().map(MatchedPath::as_str),
+            &params,
+            mailbox_query.as_deref(),
+        )
+        .await?;
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Rejects read-only tokens, for routes that send, change or delete anything.
+pub async fn require_admin(
+    State(state): State<ApiState>,
+    req: Request,
+    next: Next,
+) -> HttpResult<Response> {
+    let token = request_token(&req).map(str::to_string);
+    match authenticate(&state, token.as_deref()).await? {
+        Access::Admin => Ok(next.run(req).await),
+        Access::ReadOnly(_) => Err((StatusCode::FORBIDDEN, "Token is read-only").into()),
+    }
+}
+
+/// Mints a read-only token. The token itself is only ever returned here; just its hash is kept.
+#[instrument(skip(state))]
+pub async fn create_token(
+    State(state): State<ApiState>,
+    Json(scope): Json<TokenScope>,
+) -> HttpResult<(StatusCode, Json<CreatedToken>)> {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+
+    state
+        .repo
+        .add_api_token(&hash_token(&token), &scope)
+        .await
+        .context("Error saving token")
+        .into_internal_error_result()?;
+
+    Ok((StatusCode::CREATED, Json(CreatedToken { token })))
+}
+
+/// Without an admin token configured the API is open, as it always has been.
+async fn authenticate(state: &ApiState, token: Option<&str>) -> HttpResult<Access> {
+    let Some(admin_token) = &state.admin_token else {
+        return Ok(Access::Admin);
+    };
+
+    let token = token
+        .context("Missing token")
+        .into_error_result(StatusCode::UNAUTHORIZED)?;
+
+    if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) {
+        return Ok(Access::Admin);
+    }
+
+    state
+        .repo
+        .get_api_token_scope(&hash_token(token))
+        .await
+        .context("Error checking token")
+        .into_internal_error_result()?
+        .map(Access::ReadOnly)
+        .context("Invalid token")
+        .into_error_result(StatusCode::UNAUTHORIZED)
+}
+
+/// Reads the bearer token, or the `token` query parameter since browsers can't set headers
+/// on websocket connections.
+fn request_token(req: &Request) -> Option<&str> {
+    if let Some(token) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(token.trim());
+    }
+
+    query_param(req, "token")
+}
+
+fn query_param<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+async fn check_scope(
+    state: &ApiState,
+    scope: &TokenScope,
+    path: Option<&str>,
+    params: &RawPathParams,
+    mailbox_query: Option<&str>,
+) -> HttpResult<()> {
+    let forbidden = || Err((StatusCode::FORBIDDEN, "Outside of the token's scope").into());
+    let param = |name: &str| {
+        params
+            .iter()
+            .find_map(|(key, value)| (key == name).then_some(value))
+    };
+    let account_id = param("account_id").and_then(|id| id.parse::<AccountId>().ok());
+
+    if let Some(account_ids) = &scope.account_ids {
+        // Routes that aren't about a particular account, like the account list, are off limits
+        match account_id {
+            Some(account_id) if account_ids.contains(&account_id) => {}
+            _ => return forbidden(),
+        }
+    }
+
+    let Some(mailbox_ids) = &scope.mailbox_ids else {
+        return Ok(());
+    };
+
+    // Only the routes known to stay within the mailboxes, so a new route is off limits until
+    // it's been considered here
+    let in_scope = |ids: &HashSet<String>| mailbox_ids.iter().any(|id| ids.contains(id));
+    let allowed = match path {
+        // Nothing about any email
+        Some(
+            "/status"
+            | "/proxy"
+            | "/accounts/{account_id}"
+            | "/capabilities/{account_id}"
+            | "/mailboxes/{account_id}"
+            | "/mails/{account_id}/sortable-columns",
+        ) => true,
+
+        Some(
+            "/mails/{account_id}"
+            | "/mailboxes/sync/{account_id}/{mailbox_id}"
+            | "/mailboxes/{account_id}/{mailbox_id}/default-query"
+            | "/mailboxes/{account_id}/{mailbox_id}/export.mbox",
+        ) => param("mailbox_id")
+            .or(mailbox_query)
+            .is_some_and(|id| mailbox_ids.iter().any(|m| m == id)),
+
+        Some(
+            "/mails/{account_id}/{email_id}/body"
+            | "/mails/{account_id}/{email_id}/details"
+            | "/mails/{account_id}/{email_id}/authentication"
+            | "/mails/{account_id}/{email_id}/headers"
+            | "/mails/{account_id}/{email_id}/preview"
+            | "/mails/{account_id}/{email_id}/availability"
+            | "/mails/{account_id}/{email_id}/reply",
+        ) => match (account_id, param("email_id")) {
+            (Some(account_id), Some(email_id)) => in_scope(
+                &state
+                    .repo
+                    .get_mailbox_ids_of_emails(account_id, &[email_id.to_string()])
+                    .await
+                    .context("Error checking the email's mailboxes")
+                    .into_internal_error_result()?,
+            ),
+            _ => false,
+        },
+
+        Some("/blobs/{account_id}/{blob_id}" | "/blobs/{account_id}/{blob_id}/info") => {
+            match (account_id, param("blob_id")) {
+                (Some(account_id), Some(blob_id)) => in_scope(
+                    &state
+                        .repo
+                        .get_mailbox_ids_of_blob(account_id, blob_id)
+                        .await
+                        .context("Error checking the blob's mailboxes")
+                        .into_internal_error_result()?,
+                ),
+                _ => false,
+            }
+        }
+
+        // Everything else can reach beyond the mailboxes, e.g. threads, search, or syncing
+        // queries sent over a websocket
+        _ => false,
+    };
+
+    if allowed { Ok(()) } else { forbidden() }
+}
+
+/// Takes as long whether the tokens differ early or late, so the admin token can't be guessed
+/// byte by byte. Comparing hashes hides the length too.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
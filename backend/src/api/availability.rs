@@ -0,0 +1,26 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::repo::EmailAvailability;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use tracing::instrument;
+
+/// Tells which of an email's body and attachments can be served without the server, so clients
+/// can mark emails available offline and prefetch the rest.
+#[instrument(skip(state))]
+pub async fn get_email_availability(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Json<EmailAvailability>> {
+    state
+        .repo
+        .get_email_availability(account_id, &email_id)
+        .await
+        .context("Error querying email availability")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()
+        .map(Json)
+}
@@ -0,0 +1,36 @@
+use super::ApiState;
+use crate::repo::AccountUnread;
+use crate::util::http_error::HttpResult;
+use axum::extract;
+use axum::response::IntoResponse;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Badge {
+    /// Unread emails counted toward the badge by all accounts together.
+    pub unread_emails: i64,
+    pub accounts: Vec<AccountUnread>,
+}
+
+/// Streams the unread emails across all accounts, for an app badge. A new count is sent
+/// whenever emails or mailboxes change, when accounts are added or removed, and when an
+/// account's notification preferences change which mailboxes count.
+pub async fn watch_badge(
+    state: extract::State<ApiState>,
+    upgrade: extract::ws::WebSocketUpgrade,
+) -> HttpResult<impl IntoResponse> {
+    Ok(super::stream::websocket_db_stream(
+        upgrade,
+        state.stream_limits.open_unscoped()?,
+        state.repo.clone(),
+        &["emails", "mailboxes", "accounts", "notify_prefs"],
+        |repo| async move {
+            let accounts = repo.get_badge_unread_counts().await?;
+            anyhow::Ok(Badge {
+                unread_emails: accounts.iter().map(|a| a.unread_emails).sum(),
+                accounts,
+            })
+        },
+    ))
+}
@@ -0,0 +1,26 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::JmapAccountInfo;
+use crate::util::http_error::HttpResult;
+use axum::Json;
+use axum::extract;
+use serde::Serialize;
+use tracing::instrument;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// The mail accounts available in the JMAP session. Set an account's `jmap_account_id` to
+    /// one of these to sync it instead of the primary one.
+    pub mail_accounts: Vec<JmapAccountInfo>,
+}
+
+#[instrument(skip(state))]
+pub async fn get_capabilities(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<Capabilities>> {
+    Ok(Json(Capabilities {
+        mail_accounts: state.jmap_api(account_id)?.mail_accounts().await,
+    }))
+}
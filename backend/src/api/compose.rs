@@ -0,0 +1,64 @@
+use super::ApiState;
+use super::identities::default_identity;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use jmap_client::identity::Identity;
+use serde::Serialize;
+use tracing::instrument;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComposeDefaults {
+    /// The identity a new email is written as, unless the user picks another one.
+    pub identity: Option<Identity>,
+    pub text_signature: Option<String>,
+    pub html_signature: Option<String>,
+    pub identities: Vec<Identity>,
+    pub drafts_mailbox_id: Option<String>,
+    pub sent_mailbox_id: Option<String>,
+}
+
+/// Everything a client needs to open a blank compose window, in one go.
+#[instrument(skip(state))]
+pub async fn get_compose_defaults(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<ComposeDefaults>> {
+    let api = state.jmap_api(account_id)?;
+
+    let identities = api
+        .get_identities()
+        .await
+        .context("Error getting identities")
+        .into_internal_error_result()?;
+
+    let identity = default_identity(identities.clone(), &api.session_username().await);
+
+    let repo = &state.repo;
+    let find_mailbox = |role| async move {
+        repo.find_mailbox_id_by_role(account_id, role)
+            .await
+            .with_context(|| format!("Error finding the {role} mailbox"))
+            .into_internal_error_result()
+    };
+
+    Ok(Json(ComposeDefaults {
+        text_signature: identity
+            .as_ref()
+            .and_then(|i| i.text_signature())
+            .filter(|s| !s.is_empty())
+            .map(str::to_string),
+        html_signature: identity
+            .as_ref()
+            .and_then(|i| i.html_signature())
+            .filter(|s| !s.is_empty())
+            .map(str::to_string),
+        identity,
+        identities,
+        drafts_mailbox_id: find_mailbox("drafts").await?,
+        sent_mailbox_id: find_mailbox("sent").await?,
+    }))
+}
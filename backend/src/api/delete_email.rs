@@ -0,0 +1,80 @@
+use super::ApiState;
+use super::drafts::find_mailbox_by_role;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::extract;
+use axum::http::StatusCode;
+use serde::Deserialize;
+use tracing::instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    /// Destroys the email on the server rather than moving it to the trash.
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+/// Deletes an email the way mail clients do: it's moved to the trash, and only destroyed when
+/// it's deleted from the trash, or with `?permanent=true`. Moving is a 409 when the account has
+/// no trash mailbox.
+#[instrument(skip(state))]
+pub async fn delete_email(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+    extract::Query(Params { permanent }): extract::Query<Params>,
+) -> HttpResult<StatusCode> {
+    let api = state.jmap_api(account_id)?;
+
+    let email = state
+        .repo
+        .get_email(account_id, &email_id)
+        .await
+        .context("Error querying email")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()?;
+
+    let trash_id = find_mailbox_by_role(&state, account_id, "trash").await?;
+    let in_trash = trash_id
+        .as_deref()
+        .is_some_and(|trash_id| email.mailbox_ids() == [trash_id]);
+
+    if !permanent && !in_trash {
+        let Some(trash_id) = trash_id else {
+            return Err((
+                StatusCode::CONFLICT,
+                "The account has no trash mailbox, delete permanently instead".to_string(),
+            )
+                .into());
+        };
+
+        api.move_email(email_id.clone(), None, trash_id.clone())
+            .await
+            .context("Error moving email to the trash")
+            .into_internal_error_result()?;
+
+        state
+            .repo
+            .move_email_mailbox(account_id, &email_id, None, &trash_id)
+            .await
+            .context("Error saving email")
+            .into_internal_error_result()?;
+
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    api.destroy_emails(vec![email_id.clone()])
+        .await
+        .context("Error destroying email")
+        .into_internal_error_result()?;
+
+    state
+        .repo
+        .delete_emails(account_id, &[email_id])
+        .await
+        .context("Error deleting email")
+        .into_internal_error_result()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
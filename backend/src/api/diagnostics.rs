@@ -0,0 +1,109 @@
+use super::ApiState;
+use crate::jmap_account::{AccountId, AccountRepositoryExt};
+use crate::jmap_api::ConnectionStatus;
+use crate::repo::{AccountCounts, TableSize};
+use crate::sync::AccountSyncStatus;
+use crate::util::error_log::LoggedEvent;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use crate::util::redact::redact_addresses;
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use serde::Serialize;
+use tracing::instrument;
+use url::Url;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostics {
+    pub version: &'static str,
+    pub accounts: Vec<AccountDiagnostics>,
+    pub tables: Vec<TableSize>,
+    /// The latest warnings and errors logged, oldest first.
+    pub recent_errors: Vec<LoggedEvent>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDiagnostics {
+    pub id: AccountId,
+    /// Only the scheme and host, the rest can identify the user.
+    pub server: Option<String>,
+    pub last_synced_at: Option<String>,
+    /// Unset for accounts that aren't being synced.
+    pub connection: Option<ConnectionStatus>,
+    pub sync_status: Option<AccountSyncStatus>,
+    #[serde(flatten)]
+    pub counts: AccountCounts,
+}
+
+/// Collects what's needed to look into a sync problem into one bundle, for users to attach to
+/// a report. Credentials aren't included, and email addresses are blanked out of the account
+/// names, errors and log messages.
+#[instrument(skip(state))]
+pub async fn get_diagnostics(state: extract::State<ApiState>) -> HttpResult<Json<Diagnostics>> {
+    let summaries = state
+        .repo
+        .list_account_summaries()
+        .await
+        .context("Error listing accounts")
+        .into_internal_error_result()?;
+
+    let mut accounts = Vec::with_capacity(summaries.len());
+    for summary in summaries {
+        let counts = state
+            .repo
+            .get_account_counts(summary.id)
+            .await
+            .context("Error counting account data")
+            .into_internal_error_result()?;
+
+        let (connection, sync_status) = match state.account_states.read().get(&summary.id) {
+            Some(account_state) => (
+                Some(account_state.jmap_api.connection_status()),
+                Some(account_state.sync_status.lock().clone()),
+            ),
+            None => (None, None),
+        };
+
+        accounts.push(AccountDiagnostics {
+            id: summary.id,
+            server: Url::parse(&summary.server_url)
+                .ok()
+                .map(|url| format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default())),
+            last_synced_at: summary.last_synced_at,
+            connection: connection.map(|connection| match connection {
+                ConnectionStatus::Disconnected {
+                    details,
+                    auth_failed,
+                } => ConnectionStatus::Disconnected {
+                    details: details.as_deref().map(redact_addresses),
+                    auth_failed,
+                },
+                connection => connection,
+            }),
+            sync_status: sync_status.map(|status| AccountSyncStatus {
+                last_error: status.last_error.as_deref().map(redact_addresses),
+                ..status
+            }),
+            counts,
+        });
+    }
+
+    let tables = state
+        .repo
+        .get_table_sizes()
+        .await
+        .context("Error getting table sizes")
+        .into_internal_error_result()?;
+
+    // Redacted as they're logged
+    let recent_errors = state.error_log.recent();
+
+    Ok(Json(Diagnostics {
+        version: env!("CARGO_PKG_VERSION"),
+        accounts,
+        tables,
+        recent_errors,
+    }))
+}
@@ -0,0 +1,494 @@
+use super::ApiState;
+use super::idempotency::idempotent;
+use super::identities::resolve_identity;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::{ConnectionStatus, DraftEmail, JmapApi};
+use crate::util::custom_headers::{check_custom_headers, merge_custom_headers};
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::{HeaderMap, StatusCode};
+use jmap_client::email::{Email, EmailAddress};
+use jmap_client::identity::Identity;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftRequest {
+    /// The identity to write as, defaulting to the account's default identity.
+    pub identity_id: Option<String>,
+    #[serde(flatten)]
+    pub draft: DraftEmail,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedDraft {
+    pub id: String,
+}
+
+#[instrument(skip(state))]
+pub async fn list_drafts(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<Vec<Email>>> {
+    state
+        .repo
+        .get_draft_emails(account_id)
+        .await
+        .context("Error listing drafts")
+        .into_internal_error_result()
+        .map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReplyParams {
+    /// Whether to reply to everyone the email was addressed to as well.
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Prepares, without saving, a draft replying to an email. It goes to the email's Reply-To, or
+/// to its From when there's none, and carries the subject and threading headers along. It's
+/// written as the identity the email was addressed to, if any.
+#[instrument(skip(state))]
+pub async fn reply_draft(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+    extract::Query(ReplyParams { all }): extract::Query<ReplyParams>,
+) -> HttpResult<Json<DraftRequest>> {
+    let email = state
+        .repo
+        .get_email(account_id, &email_id)
+        .await
+        .context("Error querying email")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()?;
+
+    // Offline, the identities last fetched do rather than waiting for the connection
+    let api = state.jmap_api(account_id)?;
+    let identities = if matches!(api.connection_status(), ConnectionStatus::Connected) {
+        api.get_identities()
+            .await
+            .context("Error getting identities")
+            .into_internal_error_result()?
+    } else {
+        api.cached_identities().unwrap_or_default()
+    };
+
+    Ok(Json(reply(&email, &identities, all)))
+}
+
+fn reply(email: &Email, identities: &[Identity], all: bool) -> DraftRequest {
+    let to = email
+        .reply_to()
+        .filter(|addresses| !addresses.is_empty())
+        .or(email.from())
+        .unwrap_or_default()
+        .to_vec();
+
+    let cc = if all {
+        let own_addresses = identities
+            .iter()
+            .filter_map(|identity| identity.email().map(str::to_lowercase))
+            .collect::<Vec<_>>();
+
+        let mut cc = Vec::<EmailAddress>::new();
+        for address in [email.to(), email.cc()].into_iter().flatten().flatten() {
+            let is_known = |a: &EmailAddress| a.email().eq_ignore_ascii_case(address.email());
+            if !own_addresses.contains(&address.email().to_lowercase())
+                && !to.iter().any(is_known)
+                && !cc.iter().any(is_known)
+            {
+                cc.push(address.clone());
+            }
+        }
+        cc
+    } else {
+        Vec::new()
+    };
+
+    let subject = email.subject().unwrap_or_default();
+    let subject = if subject
+        .get(..3)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("re:"))
+    {
+        subject.to_string()
+    } else {
+        format!("Re: {subject}")
+    };
+
+    let message_id = email.message_id().unwrap_or_default();
+
+    DraftRequest {
+        identity_id: recipient_identity(email, identities)
+            .and_then(|identity| identity.id())
+            .map(str::to_string),
+        draft: DraftEmail {
+            to,
+            cc,
+            subject: Some(subject),
+            in_reply_to: message_id.to_vec(),
+            references: email
+                .references()
+                .unwrap_or_default()
+                .iter()
+                .chain(message_id)
+                .cloned()
+                .collect(),
+            ..Default::default()
+        },
+    }
+}
+
+/// The identity an email was sent to, going through the recipients in order: one with the
+/// recipient's address, or failing that one for their whole domain (`*@example.com`). `None`
+/// leaves the default identity.
+fn recipient_identity<'a>(email: &Email, identities: &'a [Identity]) -> Option<&'a Identity> {
+    let has_address = |identity: &Identity, recipient: &str| {
+        identity
+            .email()
+            .is_some_and(|own| own.eq_ignore_ascii_case(recipient))
+    };
+    let has_domain = |identity: &Identity, recipient: &str| {
+        let domain = identity.email().and_then(|own| own.strip_prefix("*@"));
+        let recipient_domain = recipient.rsplit_once('@').map(|(_, domain)| domain);
+        domain.is_some_and(|domain| {
+            recipient_domain.is_some_and(|recipient| recipient.eq_ignore_ascii_case(domain))
+        })
+    };
+
+    // Earlier recipients win, To before Cc before Bcc
+    let recipients = [email.to(), email.cc(), email.bcc()]
+        .into_iter()
+        .flatten()
+        .flatten();
+    recipients
+        .map(|address| address.email())
+        .find_map(|recipient| {
+            let find = |matches: fn(&Identity, &str) -> bool| {
+                identities
+                    .iter()
+                    .find(|identity| matches(identity, recipient))
+            };
+            find(has_address).or_else(|| find(has_domain))
+        })
+}
+
+#[instrument(skip(state))]
+pub async fn get_draft(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, draft_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Json<Email>> {
+    find_draft(&state, account_id, &draft_id).await.map(Json)
+}
+
+#[instrument(skip(state, headers))]
+pub async fn create_draft(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    headers: HeaderMap,
+    Json(request): Json<DraftRequest>,
+) -> HttpResult<(StatusCode, Json<SavedDraft>)> {
+    let saved = idempotent(&state, account_id, "create_draft", &headers, || async {
+        let api = state.jmap_api(account_id)?;
+        let id = save_draft(&state, &api, account_id, request).await?;
+        Ok(SavedDraft { id })
+    })
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(saved)))
+}
+
+/// Replaces the content of a draft. Emails are immutable in JMAP, so this saves a new draft
+/// and destroys the old one; the response carries the new id. If the old one can't be
+/// destroyed, the new one is destroyed again, leaving the draft as it was.
+#[instrument(skip(state))]
+pub async fn update_draft(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, draft_id)): extract::Path<(AccountId, String)>,
+    Json(request): Json<DraftRequest>,
+) -> HttpResult<Json<SavedDraft>> {
+    find_draft(&state, account_id, &draft_id).await?;
+
+    let api = state.jmap_api(account_id)?;
+    let id = save_draft(&state, &api, account_id, request).await?;
+
+    if let Err(e) = api.destroy_emails(vec![draft_id]).await {
+        if let Err(e) = api.destroy_emails(vec![id]).await {
+            tracing::error!(
+                ?e,
+                "Error destroying replacement draft, the draft is duplicated"
+            );
+        }
+        return Err(e.context("Error destroying replaced draft")).into_internal_error_result();
+    }
+
+    Ok(Json(SavedDraft { id }))
+}
+
+#[instrument(skip(state))]
+pub async fn delete_draft(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, draft_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<StatusCode> {
+    find_draft(&state, account_id, &draft_id).await?;
+
+    state
+        .jmap_api(account_id)?
+        .destroy_emails(vec![draft_id])
+        .await
+        .context("Error destroying draft")
+        .into_internal_error_result()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Looks up a synced draft, so these routes can't be used to touch non-draft emails.
+pub async fn find_draft(
+    state: &ApiState,
+    account_id: AccountId,
+    draft_id: &str,
+) -> HttpResult<Email> {
+    state
+        .repo
+        .get_draft_email(account_id, draft_id)
+        .await
+        .context("Error querying draft")
+        .into_internal_error_result()?
+        .with_context(|| format!("Draft {draft_id} not found"))
+        .into_not_found_error_result()
+}
+
+pub async fn find_mailbox_by_role(
+    state: &ApiState,
+    account_id: AccountId,
+    role: &str,
+) -> HttpResult<Option<String>> {
+    state
+        .repo
+        .find_mailbox_id_by_role(account_id, role)
+        .await
+        .with_context(|| format!("Error finding {role} mailbox"))
+        .into_internal_error_result()
+}
+
+async fn save_draft(
+    state: &ApiState,
+    api: &JmapApi,
+    account_id: AccountId,
+    DraftRequest {
+        identity_id,
+        mut draft,
+    }: DraftRequest,
+) -> HttpResult<String> {
+    let settings = state
+        .repo
+        .get_account_settings(account_id)
+        .await
+        .context("Error getting account settings")
+        .into_internal_error_result()?;
+    merge_custom_headers(&mut draft.headers, &settings.custom_headers);
+    check_custom_headers(&draft.headers).into_error_result(StatusCode::BAD_REQUEST)?;
+
+    let from = resolve_sender(api, identity_id.as_deref()).await?;
+
+    let drafts_mailbox_id = find_mailbox_by_role(state, account_id, "drafts")
+        .await?
+        .context("Account has no drafts mailbox")
+        .into_not_found_error_result()?;
+
+    api.create_draft(draft, from, drafts_mailbox_id)
+        .await
+        .context("Error saving draft")
+        .into_internal_error_result()
+}
+
+/// The address to write from as the given identity, or the default one.
+pub async fn resolve_sender(api: &JmapApi, identity_id: Option<&str>) -> HttpResult<EmailAddress> {
+    let identity = resolve_identity(api, identity_id).await?;
+    Ok(EmailAddress::from((
+        identity.name().unwrap_or_default().to_string(),
+        identity.email().unwrap_or_default().to_string(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing::{add_synced_account, request, state};
+    use crate::jmap_api::testing::FakeServer;
+    use axum::http::Method;
+    use axum::response::Response;
+    use http_body_util::BodyExt;
+    use jmap_client::mailbox::Mailbox;
+    use serde_json::{Value, json};
+
+    async fn json_body(response: Response) -> Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn drafts_are_saved_edited_deleted_and_sent() {
+        let server = FakeServer::start(
+            |method, args| match method {
+                "Identity/get" => Ok(json!({
+                    "accountId": "a", "state": "i1", "notFound": [],
+                    "list": [{"id": "me", "name": "Me", "email": "me@example.com"}],
+                })),
+                "Email/set" if args["create"].is_object() => Ok(json!({
+                    "accountId": "a", "oldState": "e1", "newState": "e2",
+                    "created": {"email": {"id": "d2", "blobId": "b2", "threadId": "t2", "size": 1}},
+                })),
+                "Email/set" => Ok(json!({
+                    "accountId": "a", "oldState": "e1", "newState": "e2",
+                    "destroyed": args["destroy"],
+                })),
+                "EmailSubmission/set" => Ok(json!({
+                    "accountId": "a", "oldState": "s1", "newState": "s2",
+                    "created": {"send": {"id": "sub1"}},
+                })),
+                _ => Err("unknownMethod"),
+            },
+            Default::default(),
+        )
+        .await;
+        let state = state().await;
+        let account_id = add_synced_account(&state, server.connect().await).await;
+
+        let mailboxes = ["drafts", "sent"].map(|role| {
+            let mailbox = json!({"id": role, "name": role, "role": role}).to_string();
+            serde_json::from_str::<Mailbox>(&mailbox).unwrap()
+        });
+        state
+            .repo
+            .update_mailboxes(account_id, "s1", mailboxes.to_vec(), vec![])
+            .await
+            .unwrap();
+        let draft: Email = serde_json::from_value(json!({
+            "id": "d1",
+            "mailboxIds": {"drafts": true},
+            "keywords": {"$draft": true},
+            "to": [{"email": "bob@example.com"}],
+            "receivedAt": "2025-01-01T00:00:00Z",
+        }))
+        .unwrap();
+        state
+            .repo
+            .update_emails(account_id, &[draft])
+            .await
+            .unwrap();
+
+        let drafts = format!("/drafts/{account_id}");
+        let d1 = format!("/drafts/{account_id}/d1");
+        let new_draft = json!({"to": [{"email": "bob@example.com"}], "subject": "Hi"});
+
+        let response = request(&state, Method::POST, &drafts, Some(new_draft.clone())).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(json_body(response).await, json!({"id": "d2"}));
+
+        let response = request(&state, Method::GET, &drafts, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await[0]["id"], "d1");
+
+        let response = request(&state, Method::GET, &d1, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["id"], "d1");
+
+        let missing = format!("/drafts/{account_id}/missing");
+        let response = request(&state, Method::GET, &missing, None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Editing saves a new draft in place of the old one
+        let response = request(&state, Method::PATCH, &d1, Some(new_draft)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await, json!({"id": "d2"}));
+
+        let response = request(&state, Method::DELETE, &d1, None).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let outbox = format!("/outbox/{account_id}");
+        let send = json!({"draftId": "d1"});
+        let response = request(&state, Method::POST, &outbox, Some(send)).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(json_body(response).await, json!({"submissionId": "sub1"}));
+
+        let calls = server.calls.lock();
+        let destroyed = calls
+            .iter()
+            .filter(|(method, args)| method == "Email/set" && args["destroy"].is_array())
+            .map(|(_, args)| args["destroy"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(destroyed, [json!(["d1"]), json!(["d1"])]);
+        let submissions = calls
+            .iter()
+            .filter(|(method, _)| method == "EmailSubmission/set")
+            .collect::<Vec<_>>();
+        assert_eq!(submissions.len(), 1);
+        let submission = &submissions[0].1["create"]["send"];
+        assert_eq!(submission["emailId"], "d1");
+        assert_eq!(submission["identityId"], "me");
+    }
+
+    fn identities() -> Vec<Identity> {
+        let identities = json!([
+            {"id": "me", "email": "me@example.com"},
+            {"id": "alias", "email": "alias@example.com"},
+            {"id": "corp", "email": "*@corp.example"},
+        ]);
+        serde_json::from_str(&identities.to_string()).unwrap()
+    }
+
+    /// A mailing list post, as the list relays it: the author in From, the list in Sender and
+    /// Reply-To.
+    fn list_post(to: &str) -> Email {
+        serde_json::from_value(json!({
+            "id": "e1",
+            "from": [{"email": "ann@example.org"}],
+            "sender": [{"email": "list-bounces@lists.example.org"}],
+            "replyTo": [{"email": "list@lists.example.org"}],
+            "to": [{"email": to}],
+            "cc": [{"email": "bob@example.org"}, {"email": "me@example.com"}],
+            "subject": "Release",
+            "messageId": ["m1@example.org"],
+            "references": ["m0@example.org"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn replies_go_to_reply_to() {
+        let reply = reply(&list_post("list@lists.example.org"), &identities(), false);
+        let to = reply.draft.to.iter().map(|a| a.email()).collect::<Vec<_>>();
+        assert_eq!(to, ["list@lists.example.org"]);
+        assert!(reply.draft.cc.is_empty());
+        assert_eq!(reply.draft.subject.as_deref(), Some("Re: Release"));
+        assert_eq!(reply.draft.in_reply_to, ["m1@example.org"]);
+        assert_eq!(reply.draft.references, ["m0@example.org", "m1@example.org"]);
+    }
+
+    #[test]
+    fn replies_to_all_leave_out_own_addresses() {
+        let reply = reply(&list_post("list@lists.example.org"), &identities(), true);
+        let cc = reply.draft.cc.iter().map(|a| a.email()).collect::<Vec<_>>();
+        assert_eq!(cc, ["bob@example.org"]);
+    }
+
+    #[test]
+    fn replies_are_written_as_the_addressed_identity() {
+        let identities = identities();
+        let identity_id = |to: &str| reply(&list_post(to), &identities, false).identity_id;
+
+        assert_eq!(identity_id("Alias@Example.com").as_deref(), Some("alias"));
+        assert_eq!(identity_id("sales@corp.example").as_deref(), Some("corp"));
+        // Only Cc'd
+        assert_eq!(identity_id("list@lists.example.org").as_deref(), Some("me"));
+        assert_eq!(
+            reply(&list_post("x@y.example"), &[], false).identity_id,
+            None
+        );
+    }
+}
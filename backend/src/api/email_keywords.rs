@@ -0,0 +1,114 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::instrument;
+
+/// The longest keyword servers have to accept.
+const MAX_KEYWORD_LEN: usize = 255;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct KeywordsRequest {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeywordsResponse {
+    /// All of the email's keywords after the change, as JMAP has them.
+    pub keywords: BTreeMap<String, bool>,
+}
+
+/// Adds and removes keywords of an email, e.g. `{"add": ["$seen"], "remove": ["$flagged"]}`.
+/// The stored email is updated right away, so lists show the change before the server's push.
+#[instrument(skip(state))]
+pub async fn update_email_keywords(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+    Json(KeywordsRequest { add, remove }): Json<KeywordsRequest>,
+) -> HttpResult<Json<KeywordsResponse>> {
+    let api = state.jmap_api(account_id)?;
+
+    if let Some(keyword) = add.iter().chain(&remove).find(|k| !is_valid_keyword(k)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid keyword {keyword:?}"),
+        )
+            .into());
+    }
+
+    // Keywords are case-insensitive, and servers return them lowercased
+    let add = add
+        .iter()
+        .map(|k| k.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    let remove = remove
+        .iter()
+        .map(|k| k.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if let Some(keyword) = add.iter().find(|k| remove.contains(k)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Keyword {keyword} can't be both added and removed"),
+        )
+            .into());
+    }
+
+    state
+        .repo
+        .get_email(account_id, &email_id)
+        .await
+        .context("Error querying email")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()?;
+
+    let changes = add
+        .into_iter()
+        .map(|k| (k, true))
+        .chain(remove.into_iter().map(|k| (k, false)))
+        .collect::<BTreeMap<_, _>>();
+
+    let result = api
+        .set_keywords(vec![email_id.clone()], changes.clone())
+        .await
+        .context("Error updating keywords")
+        .into_internal_error_result()?;
+    if let Some(failure) = result.failed.first() {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!(
+                "Error updating keywords of email {email_id}: {}",
+                failure.reason
+            ),
+        )
+            .into());
+    }
+
+    let keywords = state
+        .repo
+        .set_emails_keywords(account_id, std::slice::from_ref(&email_id), &changes)
+        .await
+        .context("Error saving keywords")
+        .into_internal_error_result()?
+        .remove(&email_id)
+        .unwrap_or_default();
+
+    Ok(Json(KeywordsResponse { keywords }))
+}
+
+/// Whether `keyword` is allowed by RFC 8621, i.e. printable ASCII without IMAP's specials.
+fn is_valid_keyword(keyword: &str) -> bool {
+    !keyword.is_empty()
+        && keyword.len() <= MAX_KEYWORD_LEN
+        && keyword
+            .bytes()
+            .all(|b| (0x21..=0x7e).contains(&b) && !b"(){]%*\"\\".contains(&b))
+}
@@ -0,0 +1,128 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::JmapApi;
+use crate::repo::ExportEmail;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use crate::util::tar;
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract;
+use axum::http::header;
+use axum::response::Response;
+use futures::{StreamExt, stream};
+use jmap_client::mailbox::Mailbox;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::instrument;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Params {
+    /// Only export the emails in this mailbox.
+    pub mailbox_id: Option<String>,
+    /// Only export emails received at or after this time, e.g. `2025-01-01T00:00:00Z`.
+    pub after: Option<String>,
+    /// Only export emails received before this time.
+    pub before: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest<'a> {
+    mailboxes: &'a [Mailbox],
+    /// The exported emails, each stored as `emails/{id}.eml`.
+    emails: &'a [ExportEmail],
+}
+
+/// Streams a tar archive of the account's mail: a `manifest.json` of the mailboxes and of each
+/// email's mailboxes and keywords, followed by every email's raw message.
+///
+/// Emails are downloaded one at a time as the client reads, so nothing but the current message
+/// is held in memory. A failed download ends the archive early rather than leaving gaps.
+#[instrument(skip(state))]
+pub async fn export_account(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    extract::Query(Params {
+        mailbox_id,
+        after,
+        before,
+    }): extract::Query<Params>,
+) -> HttpResult<Response> {
+    let jmap_api = state.jmap_api(account_id)?;
+
+    let mailboxes = state
+        .repo
+        .get_mailboxes(account_id)
+        .await
+        .context("Error querying mailboxes")
+        .into_internal_error_result()?;
+
+    let emails = state
+        .repo
+        .get_export_emails(
+            account_id,
+            mailbox_id.as_deref(),
+            after.as_deref(),
+            before.as_deref(),
+        )
+        .await
+        .context("Error querying emails")
+        .into_internal_error_result()?;
+
+    let manifest = serde_json::to_vec_pretty(&Manifest {
+        mailboxes: &mailboxes,
+        emails: &emails,
+    })
+    .context("Error serializing manifest")
+    .into_internal_error_result()?;
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let head = stream::once(async move { file_entry("manifest.json", manifest, mtime) });
+    let entries = stream::iter(emails).then(move |email| {
+        let jmap_api = jmap_api.clone();
+        async move { email_entry(&jmap_api, email, mtime).await }
+    });
+    let tail = stream::once(async { Ok(tar::END_OF_ARCHIVE.to_vec()) });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"account-{account_id}.tar\""),
+        )
+        .body(Body::from_stream(head.chain(entries).chain(tail)))
+        .context("Error creating response")
+        .into_internal_error_result()
+}
+
+async fn email_entry(
+    jmap_api: &Arc<JmapApi>,
+    email: ExportEmail,
+    mtime: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let blob_id = email
+        .blob_id
+        .with_context(|| format!("Email {} has no blob", email.id))?;
+
+    let data = jmap_api
+        .download_blob(&blob_id)
+        .await
+        .with_context(|| format!("Error downloading email {}", email.id))?;
+
+    file_entry(&format!("emails/{}.eml", email.id), data, mtime)
+}
+
+/// A whole archive entry: the header, the content and the padding up to the next block.
+fn file_entry(path: &str, mut data: Vec<u8>, mtime: u64) -> anyhow::Result<Vec<u8>> {
+    let size = data.len() as u64;
+    let mut entry = tar::file_header(path, size, mtime)?.to_vec();
+    entry.append(&mut data);
+    entry.extend_from_slice(tar::padding(size));
+    Ok(entry)
+}
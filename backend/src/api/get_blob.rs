@@ -1,13 +1,16 @@
 use super::ApiState;
 use crate::jmap_account::AccountId;
-use crate::repo::Blob;
+use crate::jmap_api::is_blob_not_found;
+use crate::repo::{Blob, BlobMetadata};
 use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use crate::util::sniff::sniff_mime_type;
 use anyhow::Context;
+use axum::Json;
 use axum::body::Body;
 use axum::extract;
-use axum::http::{HeaderValue, header};
+use axum::http::{StatusCode, header};
 use axum::response::Response;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 #[derive(Deserialize)]
@@ -34,30 +37,92 @@ pub async fn get_blob(
         sanitize_html,
     }): extract::Query<Params>,
 ) -> HttpResult<Response> {
-    let blob = match state
+    let blob = load_blob(&state, account_id, &blob_id, name, mime_type).await?;
+    blob_response(blob, block_images, sanitize_html)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobInfo {
+    pub name: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: Option<i64>,
+    /// The type going by the blob's content, which can differ from what it claims to be.
+    /// Only known once the blob is cached.
+    pub sniffed_type: Option<&'static str>,
+    pub is_cached: bool,
+}
+
+/// Describes a blob without sending it, so clients can choose between previewing and
+/// offering a download before fetching any bytes.
+#[instrument(skip(state))]
+pub async fn get_blob_info(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, blob_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Json<BlobInfo>> {
+    let BlobMetadata {
+        name,
+        mime_type,
+        size,
+        head,
+    } = state
         .repo
-        .get_blob(account_id, &blob_id)
+        .get_blob_metadata(account_id, &blob_id)
+        .await
+        .context("Error querying blob metadata")
+        .into_internal_error_result()?
+        .with_context(|| format!("Blob {blob_id} not found"))
+        .into_not_found_error_result()?;
+
+    Ok(Json(BlobInfo {
+        name,
+        mime_type,
+        size,
+        sniffed_type: head.as_deref().and_then(sniff_mime_type),
+        is_cached: head.is_some(),
+    }))
+}
+
+/// Reads a blob from the local cache, downloading and caching it on a miss. A blob the server
+/// no longer has, typically because its email was deleted meanwhile, is a 410.
+pub async fn load_blob(
+    state: &ApiState,
+    account_id: AccountId,
+    blob_id: &str,
+    name: Option<String>,
+    mime_type: Option<String>,
+) -> HttpResult<Blob> {
+    match state
+        .repo
+        .get_blob(account_id, blob_id)
         .await
         .context("Error querying blob")
         .into_internal_error_result()?
     {
-        Some(blob) => blob,
+        Some(blob) => Ok(blob),
         None => {
             tracing::info!("Fecthing blob from remote source");
 
-            let api = state
-                .account_states
-                .read()
-                .get(&account_id)
-                .map(|s| s.jmap_api.clone())
-                .context("Account not found")
-                .into_not_found_error_result()?;
-
-            let data = api
-                .download_blob(&blob_id)
+            let data = match state
+                .connected_jmap_api(account_id)
+                .await?
+                .download_blob(blob_id)
                 .await
-                .context("Error downloading blob")
-                .into_internal_error_result()?;
+            {
+                Ok(data) => data,
+                Err(e) if is_blob_not_found(&e) => {
+                    return Err((
+                        StatusCode::GONE,
+                        format!("Blob {blob_id} is no longer available"),
+                    )
+                        .into());
+                }
+                Err(e) => {
+                    return Err(e)
+                        .context("Error downloading blob")
+                        .into_internal_error_result();
+                }
+            };
 
             let blob = Blob {
                 name,
@@ -67,35 +132,38 @@ pub async fn get_blob(
 
             state
                 .repo
-                .save_blob(account_id, &blob_id, &blob)
+                .save_blob(account_id, blob_id, &blob)
                 .await
                 .context("Error saving downloaded blob")
                 .into_internal_error_result()?;
 
-            blob
+            Ok(blob)
         }
+    }
+}
+
+/// The policy HTML from emails is served with, whether or not it was sanitized: no scripts, no
+/// frames, no loading anything from elsewhere. Images can only come from this server, i.e.
+/// inline parts and the image proxy, and not even from there when they're blocked.
+fn email_html_csp(block_images: bool) -> String {
+    let img_src = if block_images {
+        "'none'"
+    } else {
+        "'self' data:"
     };
+    format!(
+        "default-src 'none'; img-src {img_src}; style-src 'unsafe-inline'; \
+         base-uri 'none'; form-action 'none'"
+    )
+}
 
-    let mut response = Response::builder()
-        .header(
-            header::CONTENT_TYPE,
-            blob.mime_type
-                .as_ref()
-                .map(|s| s.as_str())
-                .unwrap_or("application/octet-stream"),
-        )
-        .header(
-            header::ACCESS_CONTROL_ALLOW_ORIGIN,
-            HeaderValue::from_static("*"),
-        )
-        .header(
-            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
-            HeaderValue::from_static("true"),
-        )
-        .header(
-            header::CACHE_CONTROL,
-            HeaderValue::from_static("public, max-age=31536000, immutable"),
-        );
+pub fn blob_response(blob: Blob, block_images: bool, sanitize_html: bool) -> HttpResult<Response> {
+    let mut response = Response::builder().header(
+        header::CONTENT_TYPE,
+        blob.mime_type
+            .as_deref()
+            .unwrap_or("application/octet-stream"),
+    );
 
     if let Some(name) = &blob.name {
         response = response.header(
@@ -104,7 +172,19 @@ pub async fn get_blob(
         );
     }
 
-    if block_images {
+    let is_html = blob.mime_type.as_deref().is_some_and(|mime_type| {
+        mime_type
+            .split(';')
+            .next()
+            .is_some_and(|t| t.trim().eq_ignore_ascii_case("text/html"))
+    });
+
+    if is_html {
+        response = response.header(
+            header::CONTENT_SECURITY_POLICY,
+            email_html_csp(block_images),
+        );
+    } else if block_images {
         response = response.header(header::CONTENT_SECURITY_POLICY, "img-src 'none';");
     }
 
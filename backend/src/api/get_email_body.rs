@@ -0,0 +1,272 @@
+use super::ApiState;
+use super::get_blob::{blob_response, load_blob};
+use crate::jmap_account::AccountId;
+use crate::util::body_parts::{BodySelection, select_body};
+use crate::util::html_sanitizer::sanitize_html_with_cids;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::extract;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use jmap_client::email::{Email, EmailBodyPart, Property};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tracing::instrument;
+use url::form_urlencoded;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BodyFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Params {
+    /// The preferred format. The other one is used when the email doesn't have it.
+    #[serde(default)]
+    pub format: BodyFormat,
+    /// Defaults to what the account's `loadRemoteImages` setting says for the sender.
+    pub block_images: Option<bool>,
+}
+
+/// Serves the main body of an email. HTML bodies are always sanitized.
+///
+/// Unlike the blob it comes from, the body depends on settings that change, e.g. trusting the
+/// sender or the remote image policy. So it's revalidated on every use, with an ETag over what's
+/// served.
+#[instrument(skip(state, headers))]
+pub async fn get_email_body(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+    extract::Query(Params {
+        format,
+        block_images,
+    }): extract::Query<Params>,
+    headers: HeaderMap,
+) -> HttpResult<Response> {
+    let email = state
+        .repo
+        .get_email(account_id, &email_id)
+        .await
+        .context("Error querying email")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()?;
+
+    // Stored emails usually lack the structure, as syncing only fetches the default properties
+    let fetched;
+    let structure = match email.body_structure() {
+        Some(structure) => Some(structure),
+        None => {
+            fetched = fetch_body_structure(&state, account_id, &email_id).await?;
+            fetched.as_ref().and_then(|e| e.body_structure())
+        }
+    };
+
+    let (preferred, fallback) = match format {
+        BodyFormat::Html => ("text/html", "text/plain"),
+        BodyFormat::Text => ("text/plain", "text/html"),
+    };
+
+    let BodySelection { part, inline_parts } = structure
+        .and_then(|s| select_body(s, preferred, fallback))
+        .or_else(|| flattened_body(&email, format))
+        .context("Email has no body")
+        .into_not_found_error_result()?;
+
+    let blob_id = part
+        .blob_id()
+        .context("Email body has no blob")
+        .into_not_found_error_result()?;
+
+    let mime_type = part.content_type().unwrap_or("text/plain").to_string();
+    let is_html = mime_type.eq_ignore_ascii_case("text/html");
+
+    let mut blob = load_blob(&state, account_id, blob_id, None, Some(mime_type)).await?;
+    if is_html {
+        let html = std::str::from_utf8(&blob.data)
+            .context("Error converting email body to string for sanitization")
+            .into_internal_error_result()?;
+        blob.data =
+            sanitize_html_with_cids(html, inline_part_urls(account_id, &inline_parts)).into_bytes();
+    }
+
+    let block_images = match block_images {
+        Some(block_images) => block_images,
+        None => !state
+            .repo
+            .loads_remote_images(account_id, sender(&email))
+            .await
+            .context("Error resolving remote image policy")
+            .into_internal_error_result()?,
+    };
+
+    let etag = body_etag(&blob.data, block_images);
+    let mut response = if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag))
+    {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        blob_response(blob, block_images, false)?
+    };
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, no-cache"),
+    );
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, etag);
+    }
+    Ok(response)
+}
+
+/// Covers the image policy too, as it's sent in the CSP header rather than in the body.
+fn body_etag(data: &[u8], block_images: bool) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([u8::from(block_images)]);
+    hasher.update(data);
+    format!("\"{}\"", hex::encode(hasher.finalize()))
+}
+
+fn sender(email: &Email) -> Option<&str> {
+    Some(email.from()?.first()?.email())
+}
+
+/// Fetches the email's body structure from the server. Failing that, the body can still be
+/// found from what's stored, unless the server says the email is gone, which is a 410.
+async fn fetch_body_structure(
+    state: &ApiState,
+    account_id: AccountId,
+    email_id: &str,
+) -> HttpResult<Option<Email>> {
+    let result = async {
+        state
+            .jmap_api(account_id)
+            .map_err(|_| anyhow::format_err!("Account {account_id} is not being synced"))?
+            .get_emails(
+                vec![email_id.to_string()],
+                Some(vec![Property::BodyStructure]),
+            )
+            .await
+    };
+
+    match result.await {
+        Ok(resp) if resp.not_found().iter().any(|id| id == email_id) => Err((
+            StatusCode::GONE,
+            "The message body is no longer available, the email was deleted".to_string(),
+        )
+            .into()),
+        Ok(mut resp) => Ok(resp.pop()),
+        Err(e) => {
+            tracing::warn!(?e, "Error fetching body structure");
+            Ok(None)
+        }
+    }
+}
+
+/// Falls back to the body parts the server picked, when the structure isn't available.
+fn flattened_body(email: &Email, format: BodyFormat) -> Option<BodySelection<'_>> {
+    let (preferred, fallback) = match format {
+        BodyFormat::Html => (email.html_body(), email.text_body()),
+        BodyFormat::Text => (email.text_body(), email.html_body()),
+    };
+
+    Some(BodySelection {
+        part: preferred
+            .and_then(|parts| parts.first())
+            .or_else(|| fallback.and_then(|parts| parts.first()))?,
+        inline_parts: email
+            .attachments()
+            .unwrap_or_default()
+            .iter()
+            .filter(|p| p.content_id().is_some())
+            .collect(),
+    })
+}
+
+/// Links for `cid:` references. They're relative to this endpoint's
+/// `/mails/{account_id}/{email_id}/body`, so they work whatever the API is mounted under.
+fn inline_part_urls(account_id: AccountId, parts: &[&EmailBodyPart]) -> HashMap<String, String> {
+    parts
+        .iter()
+        .filter_map(|part| {
+            let cid = part
+                .content_id()?
+                .trim_start_matches('<')
+                .trim_end_matches('>');
+            let mut url = format!("../../../blobs/{account_id}/{}", part.blob_id()?);
+            if let Some(mime_type) = part.content_type() {
+                url.push_str("?mimeType=");
+                url.extend(form_urlencoded::byte_serialize(mime_type.as_bytes()));
+            }
+            Some((cid.to_string(), url))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing;
+    use crate::jmap_api::testing::FakeServer;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::json;
+
+    #[test]
+    fn etag_changes_with_the_image_policy() {
+        assert_eq!(body_etag(b"<p>hi</p>", true), body_etag(b"<p>hi</p>", true));
+        assert_ne!(
+            body_etag(b"<p>hi</p>", true),
+            body_etag(b"<p>hi</p>", false)
+        );
+        assert_ne!(body_etag(b"<p>hi</p>", true), body_etag(b"<p>ho</p>", true));
+        assert!(body_etag(b"", false).starts_with('"'));
+    }
+
+    #[tokio::test]
+    async fn deleted_email_is_gone() {
+        let server = FakeServer::start(
+            |method, args| match method {
+                "Email/get" => Ok(json!({
+                    "accountId": "a", "state": "e1", "list": [], "notFound": args["ids"],
+                })),
+                _ => Err("unknownMethod"),
+            },
+            Default::default(),
+        )
+        .await;
+        let state = testing::state().await;
+        let account_id = testing::add_synced_account(&state, server.connect().await).await;
+
+        let result = fetch_body_structure(&state, account_id, "e1").await;
+        assert_eq!(result.map(Json).into_response().status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn missing_blob_is_gone() {
+        let server = FakeServer::start(
+            |_, _| Err("unknownMethod"),
+            Router::new().route(
+                "/download/{account}/{blob}",
+                get(|| async { StatusCode::NOT_FOUND }),
+            ),
+        )
+        .await;
+        let state = testing::state().await;
+        let account_id = testing::add_synced_account(&state, server.connect().await).await;
+
+        let result = load_blob(&state, account_id, "b1", None, None).await;
+        assert_eq!(
+            result.map(|_| ()).into_response().status(),
+            StatusCode::GONE
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::DETAIL_PROPERTIES;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use jmap_client::email::{Email, Property};
+use serde::Deserialize;
+use serde::de::IntoDeserializer;
+use serde::de::value::Error as ValueError;
+use tracing::instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    /// Comma separated JMAP email properties, e.g. `bodyValues,attachments` or
+    /// `header:List-Unsubscribe:asURLs`.
+    pub properties: Option<String>,
+}
+
+/// Fetches email properties that aren't kept locally straight from the server.
+#[instrument(skip(state))]
+pub async fn get_email_details(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+    extract::Query(Params { properties }): extract::Query<Params>,
+) -> HttpResult<Json<Email>> {
+    let mut properties = match properties {
+        Some(properties) => parse_properties(&properties)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid properties: {e}")))?,
+        None => DETAIL_PROPERTIES.to_vec(),
+    };
+
+    if !properties.contains(&Property::Id) {
+        properties.push(Property::Id);
+    }
+
+    state
+        .connected_jmap_api(account_id)
+        .await?
+        .get_emails(vec![email_id.clone()], Some(properties))
+        .await
+        .context("Error fetching email details")
+        .into_internal_error_result()?
+        .take_list()
+        .pop()
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()
+        .map(Json)
+}
+
+fn parse_properties(properties: &str) -> Result<Vec<Property>, ValueError> {
+    properties
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| Property::deserialize(p.into_deserializer()))
+        .collect()
+}
@@ -0,0 +1,145 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::repo::RawHeader;
+use crate::util::auth_results::{AuthenticationResults, parse_authentication_results};
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use tracing::instrument;
+
+/// Lists all header fields of an email in message order, for "show original". They're fetched
+/// from the server the first time and served from the local copy afterwards.
+#[instrument(skip(state))]
+pub async fn get_email_headers(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Json<Vec<RawHeader>>> {
+    load_email_headers(&state, account_id, &email_id)
+        .await
+        .map(Json)
+}
+
+/// Whether the message passed SPF, DKIM and DMARC checks, according to the
+/// `Authentication-Results` header added by the receiving server.
+#[instrument(skip(state))]
+pub async fn get_email_authentication(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Json<AuthenticationResults>> {
+    let headers = load_email_headers(&state, account_id, &email_id).await?;
+
+    // Only the topmost header is from our own server, anything below it came with the message
+    Ok(Json(
+        headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Authentication-Results"))
+            .map(|h| parse_authentication_results(&h.value))
+            .unwrap_or_default(),
+    ))
+}
+
+pub async fn load_email_headers(
+    state: &ApiState,
+    account_id: AccountId,
+    email_id: &str,
+) -> HttpResult<Vec<RawHeader>> {
+    if let Some(headers) = state
+        .repo
+        .get_email_headers(account_id, email_id)
+        .await
+        .context("Error querying email headers")
+        .into_internal_error_result()?
+    {
+        return Ok(headers);
+    }
+
+    let api = state.connected_jmap_api(account_id).await?;
+    let email = api
+        .get_email_headers(email_id.to_string())
+        .await
+        .context("Error fetching email headers")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()?;
+
+    let headers = match email
+        .body_structure()
+        .and_then(|part| part.headers())
+        .filter(|headers| !headers.is_empty())
+    {
+        Some(headers) => headers
+            .iter()
+            .map(|h| RawHeader {
+                name: h.name().to_string(),
+                value: unfold(h.value()),
+            })
+            .collect(),
+
+        // Some servers don't report part headers, so read them off the raw message instead
+        None => {
+            let blob_id = email
+                .blob_id()
+                .context("Email has no blob")
+                .into_not_found_error_result()?;
+
+            let raw = api
+                .download_blob(blob_id)
+                .await
+                .context("Error downloading raw email")
+                .into_internal_error_result()?;
+
+            parse_raw_headers(&raw)
+        }
+    };
+
+    // Emails that were never synced can't be cached, but their headers are still worth returning
+    if let Err(e) = state
+        .repo
+        .save_email_headers(account_id, email_id, &headers)
+        .await
+    {
+        tracing::warn!(?e, "Error caching email headers");
+    }
+
+    Ok(headers)
+}
+
+/// Joins folded header lines and trims the surrounding whitespace.
+fn unfold(value: &str) -> String {
+    value
+        .split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits the header section of an RFC 5322 message into fields.
+pub fn parse_raw_headers(message: &[u8]) -> Vec<RawHeader> {
+    let message = String::from_utf8_lossy(message);
+    let mut headers: Vec<(String, String)> = Vec::new();
+
+    for line in message.lines() {
+        if line.is_empty() {
+            break;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push('\n');
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.to_string()));
+        }
+    }
+
+    headers
+        .into_iter()
+        .map(|(name, value)| RawHeader {
+            name,
+            value: unfold(&value),
+        })
+        .collect()
+}
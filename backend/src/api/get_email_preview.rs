@@ -0,0 +1,75 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use jmap_client::email;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use tracing::instrument;
+
+#[derive(Deserialize)]
+struct SummaryPreview {
+    preview: Option<String>,
+}
+
+/// An email's summary, i.e. its preview text along with the subject, senders and date, for
+/// showing on hover. Unlike the body, this never downloads the email's content: a preview that
+/// wasn't synced is fetched on its own and stored with the summary.
+#[instrument(skip(state))]
+pub async fn get_email_preview(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Json<Box<RawValue>>> {
+    let summary = find_summary(&state, account_id, &email_id).await?;
+    let has_preview =
+        serde_json::from_str::<SummaryPreview>(summary.get()).is_ok_and(|s| s.preview.is_some());
+    if has_preview {
+        return Ok(Json(summary));
+    }
+
+    let preview = state
+        .connected_jmap_api(account_id)
+        .await?
+        .get_emails(
+            vec![email_id.clone()],
+            Some(vec![email::Property::Id, email::Property::Preview]),
+        )
+        .await
+        .context("Error fetching email preview")
+        .into_internal_error_result()?
+        .take_list()
+        .pop()
+        .and_then(|mut email| email.take_preview());
+
+    let Some(preview) = preview else {
+        return Ok(Json(summary));
+    };
+
+    state
+        .repo
+        .set_email_preview(account_id, &email_id, &preview)
+        .await
+        .context("Error saving email preview")
+        .into_internal_error_result()?;
+
+    find_summary(&state, account_id, &email_id).await.map(Json)
+}
+
+async fn find_summary(
+    state: &ApiState,
+    account_id: AccountId,
+    email_id: &str,
+) -> HttpResult<Box<RawValue>> {
+    state
+        .repo
+        .get_email_summaries_by_id(account_id, &[email_id.to_string()])
+        .await
+        .context("Error querying email summary")
+        .into_internal_error_result()?
+        .pop()
+        .map(|(_, summary)| summary)
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()
+}
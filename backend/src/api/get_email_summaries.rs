@@ -0,0 +1,69 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::collections::HashSet;
+use tracing::instrument;
+
+/// Most ids a single batch can ask for.
+const MAX_BATCH_IDS: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct BatchGetRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchGetResponse {
+    /// In the order the ids were asked for.
+    pub summaries: Vec<Box<RawValue>>,
+    /// Ids of the emails that aren't stored locally, e.g. as their mailbox hasn't been synced.
+    pub missing_ids: Vec<String>,
+}
+
+/// Reads the stored summaries of a set of emails at once, e.g. ones found in another view.
+#[instrument(skip(state, ids), fields(ids = ids.len()))]
+pub async fn batch_get_email_summaries(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    Json(BatchGetRequest { ids }): Json<BatchGetRequest>,
+) -> HttpResult<Json<BatchGetResponse>> {
+    if ids.len() > MAX_BATCH_IDS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {MAX_BATCH_IDS} ids can be asked for at once"),
+        )
+            .into());
+    }
+
+    let mut seen = HashSet::new();
+    let ids = ids
+        .into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect::<Vec<_>>();
+
+    let found = state
+        .repo
+        .get_email_summaries_by_id(account_id, &ids)
+        .await
+        .context("Error querying email summaries")
+        .into_internal_error_result()?;
+
+    let found_ids = found.iter().map(|(id, _)| id).collect::<HashSet<_>>();
+    let missing_ids = ids
+        .iter()
+        .filter(|id| !found_ids.contains(id))
+        .cloned()
+        .collect();
+
+    Ok(Json(BatchGetResponse {
+        summaries: found.into_iter().map(|(_, summary)| summary).collect(),
+        missing_ids,
+    }))
+}
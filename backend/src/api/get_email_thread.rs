@@ -0,0 +1,156 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::{ConnectionStatus, SUMMARY_PROPERTIES};
+use crate::repo::ThreadEmail;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::time::Duration;
+use tracing::instrument;
+
+/// How long the thread waits for its missing emails, in case the connection drops meanwhile.
+const THREAD_COMPLETION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Collapse {
+    /// Only unread emails and the latest email carry their full content.
+    Read,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    pub collapse: Option<Collapse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailSummary {
+    pub from: Option<Box<RawValue>>,
+    pub received_at: Option<String>,
+    pub preview: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollapsedEmail {
+    pub id: String,
+    pub unread: bool,
+    pub has_attachment: bool,
+    pub summary: EmailSummary,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ThreadEntry {
+    Full(ThreadEmail),
+    Collapsed(CollapsedEmail),
+}
+
+#[instrument(skip(state))]
+pub async fn get_email_thread(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+    extract::Query(Params { collapse }): extract::Query<Params>,
+) -> HttpResult<Json<Vec<ThreadEntry>>> {
+    // The stored thread can still be shown, just possibly without some of its emails
+    match tokio::time::timeout(
+        THREAD_COMPLETION_TIMEOUT,
+        fetch_missing_thread_emails(&state, account_id, &email_id),
+    )
+    .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!(?e, "Error completing email thread"),
+        Err(_) => tracing::warn!("Timed out completing email thread"),
+    }
+
+    let emails = state
+        .repo
+        .get_email_thread(account_id, &email_id)
+        .await
+        .context("Error querying email thread")
+        .into_internal_error_result()?;
+
+    if emails.is_empty() {
+        return Err((StatusCode::NOT_FOUND, format!("Email {email_id} not found")).into());
+    }
+
+    let latest = emails.len() - 1;
+    emails
+        .into_iter()
+        .enumerate()
+        .map(|(index, email)| {
+            if collapse != Some(Collapse::Read) || email.unread || index == latest {
+                return Ok(ThreadEntry::Full(email));
+            }
+
+            Ok(ThreadEntry::Collapsed(CollapsedEmail {
+                summary: serde_json::from_str(email.email.get())
+                    .context("Error reading email summary")?,
+                id: email.id,
+                unread: email.unread,
+                has_attachment: email.has_attachment,
+            }))
+        })
+        .collect::<anyhow::Result<_>>()
+        .into_internal_error_result()
+        .map(Json)
+}
+
+/// Stores the emails of the email's thread that haven't been synced, e.g. replies that live in
+/// the Sent mailbox. Only the mailboxes being watched are synced, so a thread put together from
+/// stored emails alone can miss some.
+async fn fetch_missing_thread_emails(
+    state: &ApiState,
+    account_id: AccountId,
+    email_id: &str,
+) -> anyhow::Result<()> {
+    let Some(thread_id) = state
+        .repo
+        .get_email(account_id, email_id)
+        .await?
+        .and_then(|email| email.thread_id().map(str::to_string))
+    else {
+        return Ok(());
+    };
+
+    // Requests wait for the connection, which could take as long as the account is offline
+    let Ok(jmap_api) = state.jmap_api(account_id) else {
+        return Ok(());
+    };
+    if !matches!(jmap_api.connection_status(), ConnectionStatus::Connected) {
+        return Ok(());
+    }
+
+    let email_ids = jmap_api
+        .get_threads(vec![thread_id])
+        .await?
+        .take_list()
+        .into_iter()
+        .flat_map(|thread| thread.email_ids().to_vec())
+        .collect::<Vec<_>>();
+
+    let missing = state
+        .repo
+        .find_missing_email_ids(account_id, &email_ids)
+        .await?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    tracing::debug!("Fetching {} emails missing from the thread", missing.len());
+    let emails = jmap_api
+        .get_emails(
+            missing.into_iter().collect(),
+            Some(SUMMARY_PROPERTIES.to_vec()),
+        )
+        .await?
+        .take_list();
+
+    state.repo.update_emails(account_id, &emails).await
+}
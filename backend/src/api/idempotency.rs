@@ -0,0 +1,224 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::time::Duration;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+const MAX_KEY_LEN: usize = 255;
+
+type InFlightKey = (AccountId, &'static str, String);
+
+pub struct Idempotency {
+    /// How long a key is remembered after its request completed.
+    pub ttl: Duration,
+    /// How long an opted-in send refuses to go out again with the same content.
+    pub dedupe_window: Duration,
+    /// Keys whose request is still running. A retry racing the original gets a 409 instead of
+    /// running the request a second time.
+    in_flight: Mutex<HashSet<InFlightKey>>,
+}
+
+impl Idempotency {
+    pub fn new(ttl: Duration, dedupe_window: Duration) -> Self {
+        Self {
+            ttl,
+            dedupe_window,
+            in_flight: Default::default(),
+        }
+    }
+}
+
+struct InFlightGuard<'a> {
+    idempotency: &'a Idempotency,
+    key: InFlightKey,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.idempotency.in_flight.lock().remove(&self.key);
+    }
+}
+
+/// Runs `request` at most once per `Idempotency-Key`: a replay within the TTL gets the original
+/// response back instead. Requests without the header always run.
+pub async fn idempotent<T, F>(
+    state: &ApiState,
+    account_id: AccountId,
+    endpoint: &'static str,
+    headers: &HeaderMap,
+    request: impl FnOnce() -> F,
+) -> HttpResult<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: Future<Output = HttpResult<T>>,
+{
+    let Some(key) = headers.get(IDEMPOTENCY_KEY) else {
+        return request().await;
+    };
+
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LEN)
+        .context("Invalid Idempotency-Key header")
+        .into_error_result(StatusCode::BAD_REQUEST)?;
+
+    run_once(
+        state,
+        account_id,
+        endpoint,
+        key,
+        state.idempotency.ttl,
+        request,
+    )
+    .await
+}
+
+/// Runs `request` at most once per `key` within `ttl`, replaying the original response for the
+/// key otherwise. The key can come from the client, or be derived from the request itself.
+pub async fn run_once<T, F>(
+    state: &ApiState,
+    account_id: AccountId,
+    endpoint: &'static str,
+    key: &str,
+    ttl: Duration,
+    request: impl FnOnce() -> F,
+) -> HttpResult<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: Future<Output = HttpResult<T>>,
+{
+    let idempotency = &*state.idempotency;
+    let in_flight_key = (account_id, endpoint, key.to_string());
+    if !idempotency.in_flight.lock().insert(in_flight_key.clone()) {
+        return Err((
+            StatusCode::CONFLICT,
+            "A request with this Idempotency-Key is already in progress".to_string(),
+        )
+            .into());
+    }
+    let _guard = InFlightGuard {
+        idempotency,
+        key: in_flight_key,
+    };
+
+    if let Some(response) = state
+        .repo
+        .get_idempotent_response(account_id, endpoint, key, ttl)
+        .await
+        .context("Error looking up idempotency key")
+        .into_internal_error_result()?
+    {
+        return serde_json::from_str(&response)
+            .context("Error reading stored response")
+            .into_internal_error_result();
+    }
+
+    let response = request().await?;
+
+    // The request has taken effect by now, so failing here would only invite another retry.
+    let saved = match serde_json::to_string(&response) {
+        Ok(json) => {
+            state
+                .repo
+                .save_idempotent_response(account_id, endpoint, key, &json, ttl)
+                .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = saved {
+        tracing::warn!(?e, "Error saving idempotency key");
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing::state;
+    use crate::repo::testing::add_account;
+    use axum::Json;
+    use axum::http::HeaderValue;
+    use axum::response::IntoResponse;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn with_key(key: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(IDEMPOTENCY_KEY, HeaderValue::from_static(key))])
+    }
+
+    #[tokio::test]
+    async fn replayed_key_runs_once() {
+        let state = state().await;
+        let account_id = add_account(&state.repo, "a").await;
+        let runs = AtomicUsize::new(0);
+        let request = || async { Ok(runs.fetch_add(1, Ordering::SeqCst)) };
+
+        let first = idempotent(&state, account_id, "send", &with_key("k"), request).await;
+        let second = idempotent(&state, account_id, "send", &with_key("k"), request).await;
+        assert_eq!(first.unwrap(), 0);
+        assert_eq!(second.unwrap(), 0);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let other = idempotent(&state, account_id, "send", &with_key("k2"), request).await;
+        let unkeyed = idempotent(&state, account_id, "send", &HeaderMap::new(), request).await;
+        assert_eq!(other.unwrap(), 1);
+        assert_eq!(unkeyed.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn racing_retry_is_a_conflict() {
+        let state = state().await;
+        let account_id = add_account(&state.repo, "a").await;
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+
+        let original = tokio::spawn({
+            let state = state.clone();
+            async move {
+                idempotent(&state, account_id, "send", &with_key("k"), || async {
+                    released.await.unwrap();
+                    Ok(1)
+                })
+                .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let retry = idempotent(&state, account_id, "send", &with_key("k"), || async {
+            Ok(2)
+        })
+        .await;
+        assert_eq!(
+            retry.map(Json).into_response().status(),
+            StatusCode::CONFLICT
+        );
+
+        release.send(()).unwrap();
+        assert_eq!(original.await.unwrap().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_request_can_be_retried() {
+        let state = state().await;
+        let account_id = add_account(&state.repo, "a").await;
+
+        let failed = idempotent(&state, account_id, "send", &with_key("k"), || async {
+            Err::<i32, _>((StatusCode::BAD_GATEWAY, "down".to_string()).into())
+        })
+        .await;
+        assert!(failed.is_err());
+
+        let retried = idempotent(&state, account_id, "send", &with_key("k"), || async {
+            Ok(1)
+        })
+        .await;
+        assert_eq!(retried.unwrap(), 1);
+    }
+}
@@ -0,0 +1,81 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::JmapApi;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use jmap_client::identity::Identity;
+use tracing::instrument;
+
+#[instrument(skip(state))]
+pub async fn list_identities(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<Vec<Identity>>> {
+    state
+        .connected_jmap_api(account_id)
+        .await?
+        .get_identities()
+        .await
+        .context("Error getting identities")
+        .into_internal_error_result()
+        .map(Json)
+}
+
+#[instrument(skip(state))]
+pub async fn get_default_identity(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<Identity>> {
+    let api = state.connected_jmap_api(account_id).await?;
+
+    let identities = api
+        .get_identities()
+        .await
+        .context("Error getting identities")
+        .into_internal_error_result()?;
+
+    default_identity(identities, &api.session_username().await)
+        .context("Account has no identities")
+        .into_not_found_error_result()
+        .map(Json)
+}
+
+/// Picks the identity sending as the account's primary address, or the first one if none does.
+pub fn default_identity(identities: Vec<Identity>, primary_address: &str) -> Option<Identity> {
+    let matching = identities.iter().position(|identity| {
+        identity
+            .email()
+            .is_some_and(|email| email.eq_ignore_ascii_case(primary_address))
+    });
+
+    identities.into_iter().nth(matching.unwrap_or(0))
+}
+
+/// The identity with the given id, or the account's default identity when none is given.
+pub async fn resolve_identity(api: &JmapApi, identity_id: Option<&str>) -> HttpResult<Identity> {
+    let identities = api
+        .get_identities()
+        .await
+        .context("Error getting identities")
+        .into_internal_error_result()?;
+
+    match identity_id {
+        Some(identity_id) => identities
+            .into_iter()
+            .find(|identity| identity.id() == Some(identity_id))
+            .ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("Unknown identity {identity_id}"),
+                )
+                    .into()
+            }),
+
+        None => default_identity(identities, &api.session_username().await)
+            .context("Account has no identities")
+            .into_not_found_error_result(),
+    }
+}
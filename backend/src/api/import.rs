@@ -0,0 +1,342 @@
+use super::ApiState;
+use super::get_email_headers::parse_raw_headers;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::JmapApi;
+use crate::repo::Repository;
+use crate::util::dates::parse_utc_date;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use crate::util::spool::{SpooledBody, TempFile, spool_body};
+use crate::util::tar::TarReader;
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract;
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tracing::instrument;
+
+/// Messages larger than this are refused, as each one is read into memory to upload it.
+const MAX_MESSAGE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The manifest written by the export, as far as importing needs it.
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    mailboxes: Vec<ManifestMailbox>,
+    emails: Vec<ManifestEmail>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestMailbox {
+    id: String,
+    name: String,
+    parent_id: Option<String>,
+    role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEmail {
+    id: String,
+    #[serde(default)]
+    mailbox_ids: HashMap<String, bool>,
+    #[serde(default)]
+    keywords: HashMap<String, bool>,
+    received_at: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportProgress {
+    /// How many emails the manifest lists.
+    pub total: usize,
+    pub imported: usize,
+    /// Emails whose `Message-ID` the account already has.
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportFailure {
+    pub file: String,
+    pub error: String,
+}
+
+/// Imports an archive made by the account export into this account. Mailboxes are matched by
+/// role, then by name under the same parent, and created when there's no match.
+///
+/// The response streams a `progress` event after each email, a `failed` event for each email
+/// that couldn't be imported and a final `done` event. Emails already in the account are skipped
+/// by their `Message-ID`, so an interrupted import can simply be run again.
+#[instrument(skip(state, body))]
+pub async fn import_account(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    body: Body,
+) -> HttpResult<Sse<impl Stream<Item = Result<Event, axum::Error>>>> {
+    let jmap_api = state.jmap_api(account_id)?;
+
+    let (reader, spool_file): (Box<dyn AsyncRead + Send + Sync + Unpin>, _) =
+        match spool_body(body, &state.spool_config)
+            .await
+            .context("Error receiving archive")
+            .into_internal_error_result()?
+        {
+            SpooledBody::Memory(data) => (Box::new(std::io::Cursor::new(data)), None),
+            SpooledBody::File(file) => (
+                Box::new(
+                    tokio::fs::File::open(file.path())
+                        .await
+                        .context("Error opening spooled archive")
+                        .into_internal_error_result()?,
+                ),
+                Some(file),
+            ),
+        };
+
+    let mut archive = TarReader::new(reader, MAX_MESSAGE_SIZE);
+    let manifest: Manifest = match archive.next_file().await {
+        Ok(Some((path, data))) if path == "manifest.json" => serde_json::from_slice(&data)
+            .context("Invalid manifest.json")
+            .into_error_result(StatusCode::BAD_REQUEST)?,
+        Ok(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "The archive must start with manifest.json".to_string(),
+            )
+                .into());
+        }
+        Err(e) => return Err((StatusCode::BAD_REQUEST, format!("{e:#}")).into()),
+    };
+
+    let mailbox_ids = map_mailboxes(&state.repo, &jmap_api, account_id, &manifest.mailboxes)
+        .await
+        .context("Error creating mailboxes")
+        .into_internal_error_result()?;
+
+    let inbox_id = state
+        .repo
+        .find_mailbox_id_by_role(account_id, "inbox")
+        .await
+        .context("Error querying inbox")
+        .into_internal_error_result()?;
+
+    let importer = Importer {
+        archive,
+        _spool_file: spool_file,
+        jmap_api,
+        mailbox_ids,
+        inbox_id,
+        progress: ImportProgress {
+            total: manifest.emails.len(),
+            ..Default::default()
+        },
+        emails: manifest
+            .emails
+            .into_iter()
+            .map(|email| (email.id.clone(), email))
+            .collect(),
+        finished: false,
+    };
+
+    let events = stream::unfold(importer, |mut importer| async move {
+        let events = importer.next_events().await?;
+        Some((stream::iter(events), importer))
+    })
+    .flatten();
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Maps the archive's mailbox ids to this account's, creating the mailboxes that are missing.
+async fn map_mailboxes(
+    repo: &Repository,
+    jmap_api: &JmapApi,
+    account_id: AccountId,
+    mailboxes: &[ManifestMailbox],
+) -> anyhow::Result<HashMap<String, String>> {
+    let existing = repo.get_mailboxes(account_id).await?;
+    let mut mapped = HashMap::<String, String>::new();
+    let mut pending = mailboxes.iter().collect::<Vec<_>>();
+
+    // Parents go first, so their children can be found or created under them
+    while !pending.is_empty() {
+        let (ready, rest): (Vec<_>, Vec<_>) = pending.into_iter().partition(|mailbox| {
+            mailbox.parent_id.as_ref().is_none_or(|parent_id| {
+                mapped.contains_key(parent_id) || !mailboxes.iter().any(|m| &m.id == parent_id)
+            })
+        });
+        anyhow::ensure!(!ready.is_empty(), "The mailbox hierarchy has a cycle");
+
+        for mailbox in ready {
+            let parent_id = mailbox
+                .parent_id
+                .as_ref()
+                .and_then(|id| mapped.get(id))
+                .cloned();
+
+            let by_role = match mailbox.role.as_deref().filter(|r| !r.is_empty()) {
+                Some(role) => repo.find_mailbox_id_by_role(account_id, role).await?,
+                None => None,
+            };
+
+            let by_name = || {
+                existing
+                    .iter()
+                    .find(|m| {
+                        m.name() == Some(mailbox.name.as_str())
+                            && m.parent_id() == parent_id.as_deref()
+                    })
+                    .and_then(|m| m.id())
+                    .map(str::to_string)
+            };
+
+            let id = match by_role.or_else(by_name) {
+                Some(id) => id,
+                None => {
+                    tracing::info!(name = mailbox.name, "Creating mailbox");
+                    jmap_api
+                        .create_mailbox(mailbox.name.clone(), parent_id)
+                        .await?
+                }
+            };
+
+            mapped.insert(mailbox.id.clone(), id);
+        }
+
+        pending = rest;
+    }
+
+    Ok(mapped)
+}
+
+struct Importer {
+    archive: TarReader<Box<dyn AsyncRead + Send + Sync + Unpin>>,
+    /// Removed once the import is over.
+    _spool_file: Option<TempFile>,
+    jmap_api: Arc<JmapApi>,
+    /// The archive's mailbox ids to this account's.
+    mailbox_ids: HashMap<String, String>,
+    /// Where emails go whose mailboxes are all unknown.
+    inbox_id: Option<String>,
+    emails: HashMap<String, ManifestEmail>,
+    progress: ImportProgress,
+    finished: bool,
+}
+
+enum Outcome {
+    Imported,
+    Skipped,
+}
+
+impl Importer {
+    /// Imports the next email of the archive, returning the events to send about it, or `None`
+    /// once the archive has been worked through.
+    async fn next_events(&mut self) -> Option<Vec<Result<Event, axum::Error>>> {
+        if self.finished {
+            return None;
+        }
+
+        let (path, data) = match self.archive.next_file().await {
+            Ok(Some(file)) => file,
+            Ok(None) => return Some(self.finish(None)),
+            Err(e) => {
+                tracing::error!(?e, "Error reading archive");
+                return Some(self.finish(Some(format!("{e:#}"))));
+            }
+        };
+
+        let Some(email_id) = path
+            .strip_prefix("emails/")
+            .and_then(|name| name.strip_suffix(".eml"))
+        else {
+            return Some(Vec::new());
+        };
+
+        let mut events = Vec::new();
+        match self.import(email_id, data).await {
+            Ok(Outcome::Imported) => self.progress.imported += 1,
+            Ok(Outcome::Skipped) => self.progress.skipped += 1,
+            Err(e) => {
+                tracing::warn!(?e, path, "Error importing email");
+                self.progress.failed += 1;
+                events.push(Event::default().event("failed").json_data(ImportFailure {
+                    file: path,
+                    error: format!("{e:#}"),
+                }));
+            }
+        }
+
+        events.push(Event::default().event("progress").json_data(&self.progress));
+        Some(events)
+    }
+
+    async fn import(&self, email_id: &str, data: Vec<u8>) -> anyhow::Result<Outcome> {
+        let message_id = parse_raw_headers(&data)
+            .into_iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Message-ID"))
+            .map(|h| h.value.trim_matches(['<', '>', ' ']).to_string())
+            .filter(|id| !id.is_empty());
+
+        if let Some(message_id) = message_id
+            && self.jmap_api.has_email_with_message_id(message_id).await?
+        {
+            return Ok(Outcome::Skipped);
+        }
+
+        let email = self.emails.get(email_id);
+
+        let mut mailbox_ids = email
+            .iter()
+            .flat_map(|e| &e.mailbox_ids)
+            .filter(|&(_, &included)| included)
+            .filter_map(|(id, _)| self.mailbox_ids.get(id).cloned())
+            .collect::<Vec<_>>();
+        if mailbox_ids.is_empty() {
+            mailbox_ids.extend(self.inbox_id.clone());
+        }
+        anyhow::ensure!(
+            !mailbox_ids.is_empty(),
+            "No mailbox to import the email into"
+        );
+
+        let keywords = email
+            .iter()
+            .flat_map(|e| &e.keywords)
+            .filter(|&(_, &set)| set)
+            .map(|(keyword, _)| keyword.clone())
+            .collect();
+
+        let received_at = email
+            .and_then(|e| e.received_at.as_deref())
+            .and_then(parse_utc_date);
+
+        let blob_id = self
+            .jmap_api
+            .upload_blob(data, Some("message/rfc822"))
+            .await?
+            .take_blob_id();
+
+        self.jmap_api
+            .import_email(blob_id, mailbox_ids, keywords, received_at)
+            .await?;
+
+        Ok(Outcome::Imported)
+    }
+
+    fn finish(&mut self, error: Option<String>) -> Vec<Result<Event, axum::Error>> {
+        self.finished = true;
+
+        let mut events = Vec::new();
+        if let Some(error) = error {
+            events.push(Ok(Event::default().event("error").data(error)));
+        }
+        events.push(Event::default().event("done").json_data(&self.progress));
+        events
+    }
+}
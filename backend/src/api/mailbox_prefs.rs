@@ -0,0 +1,81 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::repo::MailboxDefaultQuery;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use tracing::instrument;
+
+/// The sort and filters a mailbox's list starts with. Mailboxes without any answer with an
+/// empty query, leaving it to the client's own defaults.
+#[instrument(skip(state))]
+pub async fn get_default_query(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, mailbox_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Json<MailboxDefaultQuery>> {
+    ensure_mailbox(&state, account_id, &mailbox_id).await?;
+
+    state
+        .repo
+        .get_mailbox_default_query(account_id, &mailbox_id)
+        .await
+        .context("Error getting mailbox default query")
+        .into_internal_error_result()
+        .map(Json)
+}
+
+#[instrument(skip(state))]
+pub async fn put_default_query(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, mailbox_id)): extract::Path<(AccountId, String)>,
+    Json(query): Json<MailboxDefaultQuery>,
+) -> HttpResult<Json<MailboxDefaultQuery>> {
+    ensure_mailbox(&state, account_id, &mailbox_id).await?;
+
+    // Stored queries end up in watch_mail, which can only sort by some columns
+    if let Some(sort) = query
+        .sorts
+        .iter()
+        .find(|s| s.column.to_sql_column().is_none())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Sorting by {:?} is not supported", sort.column),
+        )
+            .into());
+    }
+
+    state
+        .repo
+        .set_mailbox_default_query(account_id, &mailbox_id, &query)
+        .await
+        .context("Error saving mailbox default query")
+        .into_internal_error_result()?;
+
+    Ok(Json(query))
+}
+
+async fn ensure_mailbox(
+    state: &ApiState,
+    account_id: AccountId,
+    mailbox_id: &str,
+) -> HttpResult<()> {
+    let known = state
+        .repo
+        .get_mailbox_ids(account_id)
+        .await
+        .context("Error getting mailboxes")
+        .into_internal_error_result()?;
+
+    if !known.iter().any(|id| id == mailbox_id) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Mailbox {mailbox_id} not found"),
+        )
+            .into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::JmapApi;
+use crate::repo::{ExportEmail, Repository};
+use crate::util::dates::{format_asctime, parse_utc_date};
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract;
+use axum::http::{StatusCode, header};
+use axum::response::Response;
+use futures::{TryStreamExt, stream};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::instrument;
+
+/// How many emails are looked up at a time while streaming a mailbox.
+const PAGE_SIZE: u32 = 200;
+
+/// Streams every email of a mailbox in mbox format, oldest first, which most mail clients can
+/// import. Lines starting with `From ` get escaped the mboxrd way, and each message gets its
+/// keywords in an `X-Keywords` header.
+///
+/// Like the account export, messages are downloaded one at a time as the client reads, and a
+/// failed download ends the file early.
+#[instrument(skip(state))]
+pub async fn export_mailbox(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, mailbox_id)): extract::Path<(AccountId, String)>,
+) -> HttpResult<Response> {
+    let jmap_api = state.jmap_api(account_id)?;
+
+    let known = state
+        .repo
+        .get_mailbox_ids(account_id)
+        .await
+        .context("Error getting mailboxes")
+        .into_internal_error_result()?;
+    if !known.contains(&mailbox_id) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Mailbox {mailbox_id} not found"),
+        )
+            .into());
+    }
+
+    let filename = format!("mailbox-{account_id}-{mailbox_id}.mbox").replace(
+        |c: char| !c.is_ascii_alphanumeric() && !".-_".contains(c),
+        "_",
+    );
+
+    let pages = Pages {
+        repo: state.repo.clone(),
+        account_id,
+        mailbox_id,
+        after: None,
+        done: false,
+    };
+    let emails = stream::try_unfold(pages, |mut pages| async move {
+        let page = pages.next_page().await?;
+        anyhow::Ok(page.map(|page| (stream::iter(page.into_iter().map(anyhow::Ok)), pages)))
+    })
+    .try_flatten();
+
+    let messages = emails.and_then(move |email| {
+        let jmap_api = jmap_api.clone();
+        async move { mbox_message(&jmap_api, email).await }
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/mbox")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        )
+        .body(Body::from_stream(messages.inspect_err(|e| {
+            tracing::error!(?e, "Error exporting mailbox");
+        })))
+        .context("Error creating response")
+        .into_internal_error_result()
+}
+
+struct Pages {
+    repo: Arc<Repository>,
+    account_id: AccountId,
+    mailbox_id: String,
+    /// Where the next page starts: the `receivedAt` and id of the last email seen.
+    after: Option<(String, String)>,
+    done: bool,
+}
+
+impl Pages {
+    async fn next_page(&mut self) -> anyhow::Result<Option<Vec<ExportEmail>>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let page = self
+            .repo
+            .get_mailbox_export_page(
+                self.account_id,
+                &self.mailbox_id,
+                self.after
+                    .as_ref()
+                    .map(|(received_at, id)| (received_at.as_str(), id.as_str())),
+                PAGE_SIZE,
+            )
+            .await?;
+
+        self.done = page.len() < PAGE_SIZE as usize;
+        self.after = page.last().map(|email| {
+            (
+                email.received_at.clone().unwrap_or_default(),
+                email.id.clone(),
+            )
+        });
+
+        Ok((!page.is_empty()).then_some(page))
+    }
+}
+
+async fn mbox_message(jmap_api: &JmapApi, email: ExportEmail) -> anyhow::Result<Vec<u8>> {
+    let blob_id = email
+        .blob_id
+        .as_deref()
+        .with_context(|| format!("Email {} has no blob", email.id))?;
+
+    let data = jmap_api
+        .download_blob(blob_id)
+        .await
+        .with_context(|| format!("Error downloading email {}", email.id))?;
+
+    let keywords = serde_json::from_str::<HashMap<String, bool>>(email.keywords.get())
+        .context("Error reading keywords")?
+        .into_iter()
+        .filter(|&(_, set)| set)
+        .map(|(keyword, _)| keyword)
+        .collect::<Vec<_>>();
+
+    let received_at = email
+        .received_at
+        .as_deref()
+        .and_then(parse_utc_date)
+        .unwrap_or_default();
+
+    let mut message = format!("From MAILER-DAEMON {}\n", format_asctime(received_at)).into_bytes();
+    if !keywords.is_empty() {
+        message.extend_from_slice(format!("X-Keywords: {}\n", keywords.join(" ")).as_bytes());
+    }
+
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.iter().skip_while(|&&b| b == b'>').take(5).eq(b"From ") {
+            message.push(b'>');
+        }
+        message.extend_from_slice(line);
+        message.push(b'\n');
+    }
+    message.push(b'\n');
+
+    Ok(message)
+}
@@ -1,48 +1,266 @@
-use crate::jmap_account::{Account, AccountId};
-use crate::jmap_api::JmapApi;
+use crate::jmap_account::{Account, AccountId, AccountRepositoryExt};
+use crate::jmap_api::{ConnectionStatus, JmapApi};
 use crate::repo::Repository;
-use crate::sync::SyncCommand;
-use axum::routing::{any, get, post};
+use crate::sync::{AccountSyncStatus, SyncCommand};
+use crate::util::error_log::ErrorLog;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use crate::util::network::NetworkAvailability;
+use crate::util::spool::SpoolConfig;
+use anyhow::Context;
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::Response;
+use axum::routing::{delete, get, patch, post, put};
 use axum_reverse_proxy::ReverseProxy;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
 use tokio::task::JoinSet;
+use tower_http::set_header::SetResponseHeaderLayer;
 
+mod accounts;
+mod append_email;
+mod auth;
+mod availability;
+mod badge;
+mod capabilities;
+mod compose;
+mod delete_email;
+mod diagnostics;
+mod drafts;
+mod email_keywords;
+mod export;
 mod get_blob;
+mod get_email_body;
+mod get_email_details;
+mod get_email_headers;
+mod get_email_preview;
+mod get_email_summaries;
+mod get_email_thread;
+mod idempotency;
+mod identities;
+mod import;
+mod mailbox_prefs;
+mod mbox;
+mod move_email;
+mod notifications;
+mod outbox;
 mod proxy;
+mod search;
 mod static_file;
+mod status;
 mod stream;
 mod sync_mail;
 mod sync_mailbox;
+mod thread_keywords;
+mod trusted_senders;
+mod upload_blob;
+mod virtual_views;
 mod watch_mail;
 mod watch_mailboxes;
 mod watch_threads;
 
+pub use idempotency::Idempotency;
+pub use proxy::ProxyConfig;
+pub use stream::{DEFAULT_MAX_LIST_LIMIT, StreamLimits};
+
 pub struct AccountState {
+    /// The account as its sync started. Its credentials may have been refreshed since.
     pub account: Account,
     pub command_sender: mpsc::Sender<SyncCommand>,
     pub jmap_api: Arc<JmapApi>,
-    pub join_set: JoinSet<anyhow::Result<()>>,
+    pub sync_status: Arc<Mutex<AccountSyncStatus>>,
+    pub _join_set: JoinSet<anyhow::Result<()>>,
 }
 
 #[derive(Clone)]
 pub struct ApiState {
     pub repo: Arc<Repository>,
     pub account_states: Arc<RwLock<HashMap<AccountId, AccountState>>>,
+    /// Ticks whenever the sync has updated `account_states`.
+    pub account_states_updated: watch::Sender<()>,
     pub http_client: reqwest::Client,
+    /// Largest page a list stream will return, regardless of the requested `limit`.
+    pub max_list_limit: usize,
+    /// Largest message a client may send over a websocket, e.g. a sync query.
+    pub ws_max_message_size: usize,
+    pub stream_limits: Arc<StreamLimits>,
+    /// The latest warnings and errors, for the diagnostics bundle.
+    pub error_log: ErrorLog,
+    pub proxy_config: ProxyConfig,
+    pub spool_config: SpoolConfig,
+    pub idempotency: Arc<Idempotency>,
+    /// Grants full access. When unset, the API requires no authentication at all.
+    pub admin_token: Option<Arc<str>>,
+    pub network_availability: watch::Receiver<NetworkAvailability>,
+}
+
+impl ApiState {
+    pub fn jmap_api(&self, account_id: AccountId) -> HttpResult<Arc<JmapApi>> {
+        self.account_states
+            .read()
+            .get(&account_id)
+            .map(|s| s.jmap_api.clone())
+            .context("Account not found")
+            .into_not_found_error_result()
+    }
+
+    /// Like [`Self::jmap_api`], for requests the user is waiting on. A connection that's
+    /// (re)connecting is given a moment to come up, so a request during a brief reconnect goes
+    /// through instead of failing. Fails with a 503 when it doesn't come up in time.
+    pub async fn connected_jmap_api(&self, account_id: AccountId) -> HttpResult<Arc<JmapApi>> {
+        let jmap_api = self.jmap_api(account_id)?;
+        if jmap_api
+            .wait_until_connected(ON_DEMAND_CONNECT_TIMEOUT)
+            .await
+        {
+            return Ok(jmap_api);
+        }
+
+        let details = match jmap_api.connection_status() {
+            ConnectionStatus::Disconnected {
+                details: Some(details),
+                ..
+            } => format!(": {details}"),
+            _ => String::new(),
+        };
+        Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Account {account_id} is offline{details}"),
+        )
+            .into())
+    }
+
+    /// Fails with a 404 unless the account exists, whether or not its sync has started yet.
+    pub async fn ensure_account(&self, account_id: AccountId) -> HttpResult<()> {
+        if self.account_states.read().contains_key(&account_id) {
+            return Ok(());
+        }
+
+        self.repo
+            .get_account(account_id)
+            .await
+            .context("Error querying account")
+            .into_internal_error_result()?
+            .with_context(|| format!("Account {account_id} not found"))
+            .into_not_found_error_result()
+            .map(|_| ())
+    }
+
+    /// The account's sync command channel. Syncing starts a little after an account is added,
+    /// so this waits for it, giving up with `None` if the account goes away in the meantime, or
+    /// with an error if syncing doesn't start in time.
+    pub async fn wait_for_command_sender(
+        &self,
+        account_id: AccountId,
+    ) -> anyhow::Result<Option<mpsc::Sender<SyncCommand>>> {
+        let mut updated = self.account_states_updated.subscribe();
+        let wait = async {
+            loop {
+                let sender = self
+                    .account_states
+                    .read()
+                    .get(&account_id)
+                    .map(|s| s.command_sender.clone());
+                if let Some(sender) = sender {
+                    return Ok(Some(sender));
+                }
+
+                if self.repo.get_account(account_id).await?.is_none() {
+                    return Ok(None);
+                }
+
+                updated
+                    .changed()
+                    .await
+                    .context("Account sync has stopped")?;
+            }
+        };
+
+        tokio::time::timeout(COMMAND_SENDER_TIMEOUT, wait)
+            .await
+            .context("Timed out waiting for the account's sync to start")?
+    }
 }
 
-pub fn build_api_router() -> axum::Router<ApiState> {
+/// How long to wait for an account's sync to start, which it does right after it's added.
+const COMMAND_SENDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a request the user is waiting on waits for the account to (re)connect.
+const ON_DEMAND_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn build_api_router(state: &ApiState) -> axum::Router<ApiState> {
     use axum::Router;
+    use axum::middleware::from_fn_with_state;
 
     let dev_server = ReverseProxy::new("/", "http://localhost:3000");
 
-    Router::new()
-        .route("/mails/{account_id}", post(watch_mail::watch_mail))
+    // Blobs never change once they have an id. Private, as they're only for whoever may read the
+    // account
+    let immutable = Router::new()
         .route("/blobs/{account_id}/{blob_id}", get(get_blob::get_blob))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::CACHE_CONTROL,
+            |resp: &Response| {
+                resp.status()
+                    .is_success()
+                    .then(|| HeaderValue::from_static("private, max-age=31536000, immutable"))
+            },
+        ));
+
+    let read = Router::new()
+        .route("/proxy", get(proxy::proxy))
+        .route("/mails/{account_id}", post(watch_mail::watch_mail))
         .route("/mails/sync/{account_id}", get(sync_mail::sync_mail))
+        .route(
+            "/mails/{account_id}/sortable-columns",
+            get(watch_mail::sortable_columns),
+        )
+        .route(
+            "/mails/{account_id}/batch-get",
+            post(get_email_summaries::batch_get_email_summaries),
+        )
+        .route(
+            "/mails/{account_id}/virtual",
+            get(virtual_views::list_virtual_views),
+        )
+        .route(
+            "/mails/{account_id}/virtual/{view}",
+            get(virtual_views::watch_virtual_view),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/body",
+            get(get_email_body::get_email_body),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/details",
+            get(get_email_details::get_email_details),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/authentication",
+            get(get_email_headers::get_email_authentication),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/headers",
+            get(get_email_headers::get_email_headers),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/preview",
+            get(get_email_preview::get_email_preview),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/availability",
+            get(availability::get_email_availability),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/thread",
+            get(get_email_thread::get_email_thread),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/reply",
+            get(drafts::reply_draft),
+        )
         .route(
             "/mailboxes/sync/{account_id}/{mailbox_id}",
             get(sync_mailbox::sync_mailbox),
@@ -51,7 +269,319 @@ pub fn build_api_router() -> axum::Router<ApiState> {
             "/mailboxes/{account_id}",
             get(watch_mailboxes::watch_mailboxes),
         )
+        .route("/badge", get(badge::watch_badge))
+        .route("/status", get(status::get_status))
+        .route(
+            "/mailboxes/{account_id}/{mailbox_id}/default-query",
+            get(mailbox_prefs::get_default_query),
+        )
+        .route(
+            "/mailboxes/{account_id}/{mailbox_id}/export.mbox",
+            get(mbox::export_mailbox),
+        )
         .route("/threads/{account_id}", get(watch_threads::watch_threads))
-        .route("/proxy", get(proxy::proxy))
-        .merge(dev_server)
+        .route("/search/suggest/{account_id}", get(search::suggest))
+        .route(
+            "/blobs/{account_id}/{blob_id}/info",
+            get(get_blob::get_blob_info),
+        )
+        .route(
+            "/compose/{account_id}/defaults",
+            get(compose::get_compose_defaults),
+        )
+        .route("/drafts/{account_id}", get(drafts::list_drafts))
+        .route("/drafts/{account_id}/{draft_id}", get(drafts::get_draft))
+        .route("/accounts", get(accounts::list_accounts))
+        .route("/accounts/{account_id}", get(accounts::get_account))
+        .route(
+            "/accounts/{account_id}/summary",
+            get(accounts::get_account_summary),
+        )
+        .route(
+            "/accounts/{account_id}/settings",
+            get(accounts::get_account_settings),
+        )
+        .route("/accounts/{account_id}/export", get(export::export_account))
+        .route(
+            "/accounts/{account_id}/notify-prefs",
+            get(notifications::get_notify_prefs),
+        )
+        .route(
+            "/notifications/{account_id}",
+            get(notifications::watch_notifications),
+        )
+        .route("/identities/{account_id}", get(identities::list_identities))
+        .route(
+            "/identities/{account_id}/default",
+            get(identities::get_default_identity),
+        )
+        .route(
+            "/capabilities/{account_id}",
+            get(capabilities::get_capabilities),
+        )
+        .route(
+            "/trusted-senders/{account_id}",
+            get(trusted_senders::list_trusted_senders),
+        )
+        .merge(immutable)
+        .route_layer(from_fn_with_state(state.clone(), auth::require_read));
+
+    let write = Router::new()
+        .route("/blobs/{account_id}", post(upload_blob::upload_blob))
+        .route("/drafts/{account_id}", post(drafts::create_draft))
+        .route(
+            "/drafts/{account_id}/{draft_id}",
+            patch(drafts::update_draft).delete(drafts::delete_draft),
+        )
+        .route(
+            "/mails/{account_id}/append",
+            post(append_email::append_email),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}",
+            delete(delete_email::delete_email),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/move",
+            post(move_email::move_email_between),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/{target}",
+            post(move_email::move_email),
+        )
+        .route(
+            "/mails/{account_id}/{email_id}/keywords",
+            post(email_keywords::update_email_keywords),
+        )
+        .route("/outbox/{account_id}", post(outbox::send_draft))
+        .route(
+            "/threads/{account_id}/{thread_id}/{action}",
+            post(thread_keywords::apply_thread_action),
+        )
+        .route("/accounts", post(accounts::add_account))
+        .route("/accounts/{account_id}", delete(accounts::delete_account))
+        .route(
+            "/accounts/{account_id}/reconnect",
+            post(accounts::reconnect_account),
+        )
+        .route(
+            "/accounts/{account_id}/import",
+            post(import::import_account),
+        )
+        .route(
+            "/accounts/{account_id}/settings",
+            put(accounts::put_account_settings),
+        )
+        .route(
+            "/accounts/{account_id}/notify-prefs",
+            put(notifications::put_notify_prefs),
+        )
+        .route(
+            "/trusted-senders/{account_id}",
+            post(trusted_senders::add_trusted_sender)
+                .delete(trusted_senders::remove_trusted_sender),
+        )
+        .route(
+            "/mailboxes/{account_id}/{mailbox_id}/default-query",
+            put(mailbox_prefs::put_default_query),
+        )
+        .route("/admin/diagnostics", get(diagnostics::get_diagnostics))
+        .route("/tokens", post(auth::create_token))
+        .route_layer(from_fn_with_state(state.clone(), auth::require_admin));
+
+    read.merge(write).merge(dev_server)
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use crate::repo::testing::repository;
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use tower::ServiceExt;
+
+    /// State over a fresh in-memory database, with no accounts syncing and no authentication.
+    pub async fn state() -> ApiState {
+        ApiState {
+            repo: Arc::new(repository().await),
+            account_states: Default::default(),
+            account_states_updated: watch::channel(()).0,
+            http_client: reqwest::Client::new(),
+            max_list_limit: DEFAULT_MAX_LIST_LIMIT,
+            ws_max_message_size: 64 * 1024,
+            stream_limits: Arc::new(StreamLimits::new(16, 16)),
+            error_log: ErrorLog::default(),
+            proxy_config: ProxyConfig {
+                max_bytes: 1024 * 1024,
+                timeout: Duration::from_secs(5),
+                allowed_content_types: Arc::from(["image/".to_string()]),
+                cache_max_bytes: 1024 * 1024,
+            },
+            spool_config: SpoolConfig {
+                threshold: 1024 * 1024,
+                dir: std::env::temp_dir(),
+            },
+            idempotency: Arc::new(Idempotency::new(
+                Duration::from_secs(60),
+                Duration::from_secs(60),
+            )),
+            admin_token: None,
+            network_availability: watch::channel(NetworkAvailability { online: true }).1,
+        }
+    }
+
+    /// Sends a request through the API router, with `body` as JSON if given.
+    pub async fn request(
+        state: &ApiState,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> Response {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+
+        build_api_router(state)
+            .with_state(state.clone())
+            .oneshot(request.unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Adds an account talking to the server behind `jmap_api`, as if its sync had started.
+    pub async fn add_synced_account(state: &ApiState, jmap_api: Arc<JmapApi>) -> AccountId {
+        let account_id = crate::repo::testing::add_account(&state.repo, "a").await;
+        let account = state.repo.get_account(account_id).await.unwrap().unwrap();
+        state.account_states.write().insert(
+            account_id,
+            AccountState {
+                account,
+                command_sender: mpsc::channel(1).0,
+                jmap_api,
+                sync_status: Default::default(),
+                _join_set: JoinSet::new(),
+            },
+        );
+        account_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jmap_api::testing::FakeServer;
+    use crate::repo::Blob;
+    use crate::repo::testing::add_account;
+    use axum::http::Method;
+    use jmap_client::email::Email;
+    use jmap_client::mailbox::Mailbox;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn identities_upload_proxy_and_body_are_routed() {
+        let server = FakeServer::start(
+            |method, _| match method {
+                "Identity/get" => Ok(json!({
+                    "accountId": "a", "state": "i1", "notFound": [],
+                    "list": [{"id": "me", "name": "Me", "email": "me@example.com"}],
+                })),
+                _ => Err("unknownMethod"),
+            },
+            axum::Router::new().route(
+                "/upload/{account}",
+                post(|| async {
+                    axum::Json(json!({
+                        "accountId": "a", "blobId": "b1", "type": "application/json", "size": 2,
+                    }))
+                }),
+            ),
+        )
+        .await;
+        let state = testing::state().await;
+        let account_id = testing::add_synced_account(&state, server.connect().await).await;
+
+        let inbox = json!({"id": "inbox", "name": "Inbox"}).to_string();
+        let inbox: Mailbox = serde_json::from_str(&inbox).unwrap();
+        state
+            .repo
+            .update_mailboxes(account_id, "s1", vec![inbox], vec![])
+            .await
+            .unwrap();
+        let email: Email = serde_json::from_value(json!({
+            "id": "e1",
+            "mailboxIds": {"inbox": true},
+            "receivedAt": "2025-01-01T00:00:00Z",
+            "bodyStructure": {"partId": "1", "blobId": "b2", "type": "text/plain", "size": 2},
+        }))
+        .unwrap();
+        state
+            .repo
+            .update_emails(account_id, &[email])
+            .await
+            .unwrap();
+        let body = Blob {
+            name: None,
+            mime_type: Some("text/plain".to_string()),
+            data: b"hi".to_vec(),
+        };
+        state.repo.save_blob(account_id, "b2", &body).await.unwrap();
+
+        let routes = [
+            (
+                Method::GET,
+                format!("/identities/{account_id}"),
+                StatusCode::OK,
+            ),
+            (
+                Method::POST,
+                format!("/blobs/{account_id}"),
+                StatusCode::CREATED,
+            ),
+            // Without a URL to fetch
+            (Method::GET, "/proxy".to_string(), StatusCode::BAD_REQUEST),
+            (
+                Method::GET,
+                format!("/mails/{account_id}/e1/body"),
+                StatusCode::OK,
+            ),
+        ];
+        for (method, uri, expected) in routes {
+            let body = (method == Method::POST).then(|| json!({}));
+            let status = testing::request(&state, method.clone(), &uri, body)
+                .await
+                .status();
+            assert!(
+                ![StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED].contains(&status),
+                "{method} {uri} is not routed"
+            );
+            assert_eq!(status, expected, "{method} {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn command_sender_of_unknown_account_is_none() {
+        let state = testing::state().await;
+        assert!(state.wait_for_command_sender(1).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn waiting_for_command_sender_ends_when_account_goes() {
+        let state = testing::state().await;
+        let account_id = add_account(&state.repo, "a").await;
+
+        let waiting = tokio::spawn({
+            let state = state.clone();
+            async move { state.wait_for_command_sender(account_id).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        state.repo.mark_account_deleting(account_id).await.unwrap();
+        state.account_states_updated.send_replace(());
+        assert!(waiting.await.unwrap().unwrap().is_none());
+    }
 }
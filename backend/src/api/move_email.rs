@@ -0,0 +1,168 @@
+use super::ApiState;
+use super::drafts::find_mailbox_by_role;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+/// Where the triage shortcuts move an email to, by the role of the mailbox.
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum MoveTarget {
+    Archive,
+    Trash,
+    Spam,
+    Inbox,
+}
+
+impl MoveTarget {
+    fn role(self) -> &'static str {
+        match self {
+            MoveTarget::Archive => "archive",
+            MoveTarget::Trash => "trash",
+            MoveTarget::Spam => "junk",
+            MoveTarget::Inbox => "inbox",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveEmailResponse {
+    /// The mailbox the email was moved into.
+    pub mailbox_id: String,
+}
+
+/// Moves an email to the account's archive, trash, spam or inbox mailbox, so clients don't have
+/// to look up the mailbox by its role themselves. It's a 409 when the account has no such mailbox.
+#[instrument(skip(state))]
+pub async fn move_email(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id, target)): extract::Path<(AccountId, String, MoveTarget)>,
+) -> HttpResult<Json<MoveEmailResponse>> {
+    let api = state.jmap_api(account_id)?;
+
+    state
+        .repo
+        .get_email(account_id, &email_id)
+        .await
+        .context("Error querying email")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()?;
+
+    let role = target.role();
+    let Some(mailbox_id) = find_mailbox_by_role(&state, account_id, role).await? else {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("The account has no mailbox with the {role} role"),
+        )
+            .into());
+    };
+
+    api.move_email(email_id.clone(), None, mailbox_id.clone())
+        .await
+        .context("Error moving email")
+        .into_internal_error_result()?;
+
+    state
+        .repo
+        .move_email_mailbox(account_id, &email_id, None, &mailbox_id)
+        .await
+        .context("Error saving email")
+        .into_internal_error_result()?;
+
+    Ok(Json(MoveEmailResponse { mailbox_id }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveRequest {
+    /// The mailbox to take the email out of. Without it, the email leaves all its mailboxes.
+    pub from_mailbox_id: Option<String>,
+    pub to_mailbox_id: String,
+}
+
+/// Moves an email from one mailbox to another. Any other mailboxes the email is in are left
+/// alone, unless `fromMailboxId` is left out, which moves it into the destination alone.
+#[instrument(skip(state))]
+pub async fn move_email_between(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, email_id)): extract::Path<(AccountId, String)>,
+    Json(MoveRequest {
+        from_mailbox_id,
+        to_mailbox_id,
+    }): Json<MoveRequest>,
+) -> HttpResult<Json<MoveEmailResponse>> {
+    let api = state.jmap_api(account_id)?;
+
+    if from_mailbox_id.as_ref() == Some(&to_mailbox_id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The email is already in the destination mailbox".to_string(),
+        )
+            .into());
+    }
+
+    let email = state
+        .repo
+        .get_email(account_id, &email_id)
+        .await
+        .context("Error querying email")
+        .into_internal_error_result()?
+        .with_context(|| format!("Email {email_id} not found"))
+        .into_not_found_error_result()?;
+
+    if let Some(from) = &from_mailbox_id
+        && !email.mailbox_ids().contains(&from.as_str())
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Email {email_id} is not in mailbox {from}"),
+        )
+            .into());
+    }
+
+    let mailbox_ids = state
+        .repo
+        .get_mailbox_ids(account_id)
+        .await
+        .context("Error querying mailboxes")
+        .into_internal_error_result()?;
+    if !mailbox_ids.contains(&to_mailbox_id) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Mailbox {to_mailbox_id} not found"),
+        )
+            .into());
+    }
+
+    api.move_email(
+        email_id.clone(),
+        from_mailbox_id.clone(),
+        to_mailbox_id.clone(),
+    )
+    .await
+    .context("Error moving email")
+    .into_internal_error_result()?;
+
+    state
+        .repo
+        .move_email_mailbox(
+            account_id,
+            &email_id,
+            from_mailbox_id.as_deref(),
+            &to_mailbox_id,
+        )
+        .await
+        .context("Error saving email")
+        .into_internal_error_result()?;
+
+    Ok(Json(MoveEmailResponse {
+        mailbox_id: to_mailbox_id,
+    }))
+}
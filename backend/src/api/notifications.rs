@@ -0,0 +1,274 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::repo::{NewEmails, NotificationContent, NotifyPrefs, Repository};
+use crate::util::html_sanitizer::html_to_text;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::{Stream, StreamExt, stream};
+use jmap_client::email::{Email, EmailAddress};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tracing::instrument;
+
+/// Emails received longer ago than this aren't "new mail", e.g. when first syncing an account.
+const NEW_MAIL_MAX_AGE: Duration = Duration::from_secs(15 * 60);
+
+/// UTC offsets range from -12:00 to +14:00.
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+/// Longest snippet sent in a notification, in characters.
+const SNIPPET_MAX_CHARS: usize = 140;
+
+/// How often to check whether a do-not-disturb window has ended, to send its summary.
+const DND_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewMailNotification {
+    pub email_id: String,
+    pub thread_id: Option<String>,
+    pub mailbox_ids: Vec<String>,
+    /// Left out, like `subject` and `snippet`, when the preferences hide the content.
+    pub from: Option<Vec<EmailAddress>>,
+    pub subject: Option<String>,
+    /// The start of the body as plain text.
+    pub snippet: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationSummary {
+    pub email_ids: Vec<String>,
+}
+
+#[instrument(skip(state))]
+pub async fn get_notify_prefs(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<NotifyPrefs>> {
+    state
+        .repo
+        .get_notify_prefs(account_id)
+        .await
+        .context("Error getting notification preferences")
+        .into_internal_error_result()
+        .map(Json)
+}
+
+#[instrument(skip(state))]
+pub async fn put_notify_prefs(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    Json(prefs): Json<NotifyPrefs>,
+) -> HttpResult<Json<NotifyPrefs>> {
+    if let Some(mailbox_ids) = &prefs.notify_mailbox_ids {
+        let known = state
+            .repo
+            .get_mailbox_ids(account_id)
+            .await
+            .context("Error getting mailboxes")
+            .into_internal_error_result()?;
+
+        if let Some(unknown) = mailbox_ids.iter().find(|id| !known.contains(id)) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown mailbox {unknown}"),
+            )
+                .into());
+        }
+    }
+
+    if let Some(roles) = &prefs.badge_roles {
+        let present = state
+            .repo
+            .get_role_mailboxes(account_id)
+            .await
+            .context("Error getting role mailboxes")
+            .into_internal_error_result()?;
+
+        if let Some(unknown) = roles
+            .iter()
+            .find(|r| !present.iter().any(|m| &m.role == *r))
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("No mailbox with role {unknown}"),
+            )
+                .into());
+        }
+    }
+
+    if let Some(dnd) = &prefs.dnd
+        && dnd.utc_offset_minutes.abs() > MAX_UTC_OFFSET_MINUTES
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid UTC offset: {}", dnd.utc_offset_minutes),
+        )
+            .into());
+    }
+
+    state
+        .repo
+        .set_notify_prefs(account_id, &prefs)
+        .await
+        .context("Error saving notification preferences")
+        .into_internal_error_result()?;
+
+    Ok(Json(prefs))
+}
+
+/// Streams a `newMail` event for each unread email arriving in one of the mailboxes the
+/// account's notification preferences cover. Other mailboxes keep syncing, silently.
+///
+/// During a do-not-disturb window nothing is sent. If the schedule asks for it, a `summary`
+/// event lists what arrived once the window is over, as long as the stream stays open.
+#[instrument(skip(state))]
+pub async fn watch_notifications(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let ticks = stream::unfold((), |_| async {
+        tokio::time::sleep(DND_CHECK_INTERVAL).await;
+        Some((None, ()))
+    });
+    let inputs = stream::select(
+        BroadcastStream::new(state.repo.subscribe_new_emails()).map(Some),
+        ticks,
+    )
+    .boxed();
+
+    let dispatcher = Dispatcher {
+        repo: state.repo.clone(),
+        account_id,
+        held: Vec::new(),
+    };
+
+    let events = stream::unfold((inputs, dispatcher), |(mut inputs, mut dispatcher)| async {
+        let input = inputs.next().await?;
+        let events = match dispatcher.handle(input).await {
+            Ok(events) => events,
+            Err(e) => {
+                tracing::error!(?e, "Error dispatching notifications");
+                Vec::new()
+            }
+        };
+        Some((stream::iter(events), (inputs, dispatcher)))
+    })
+    .flatten();
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+struct Dispatcher {
+    repo: Arc<Repository>,
+    account_id: AccountId,
+    /// Ids of the emails that arrived during do-not-disturb, to be summarized afterwards.
+    held: Vec<String>,
+}
+
+impl Dispatcher {
+    /// Handles newly stored emails, or just checks whether do-not-disturb is over when `None`.
+    async fn handle(
+        &mut self,
+        input: Option<Result<NewEmails, BroadcastStreamRecvError>>,
+    ) -> anyhow::Result<Vec<Result<Event, axum::Error>>> {
+        let prefs = self.repo.get_notify_prefs(self.account_id).await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let dnd = prefs.dnd.as_ref().filter(|dnd| dnd.is_active_at(now));
+
+        let mut events = Vec::new();
+        if dnd.is_none() && !self.held.is_empty() {
+            events.push(
+                Event::default()
+                    .event("summary")
+                    .json_data(NotificationSummary {
+                        email_ids: std::mem::take(&mut self.held),
+                    }),
+            );
+        }
+
+        let new_emails = match input {
+            Some(Ok(new_emails)) if new_emails.account_id == self.account_id => new_emails,
+            Some(Ok(_)) | None => return Ok(events),
+            Some(Err(e)) => {
+                tracing::warn!(?e, "Missed new emails");
+                return Ok(events);
+            }
+        };
+
+        let notifications = notifications_for(&self.repo, &prefs, new_emails, now).await?;
+        match dnd {
+            Some(dnd) if dnd.summarize => self
+                .held
+                .extend(notifications.into_iter().map(|n| n.email_id)),
+            Some(_) => {}
+            None => events.extend(
+                notifications
+                    .into_iter()
+                    .map(|n| Event::default().event("newMail").json_data(n)),
+            ),
+        }
+
+        Ok(events)
+    }
+}
+
+async fn notifications_for(
+    repo: &Repository,
+    prefs: &NotifyPrefs,
+    NewEmails { account_id, emails }: NewEmails,
+    now: i64,
+) -> anyhow::Result<Vec<NewMailNotification>> {
+    let mailbox_ids = repo.get_notify_mailbox_ids(account_id, prefs).await?;
+
+    Ok(emails
+        .iter()
+        .filter(|email| is_new_mail(email, now))
+        .filter(|email| {
+            email
+                .mailbox_ids()
+                .iter()
+                .any(|id| mailbox_ids.iter().any(|m| m == id))
+        })
+        .filter_map(|email| {
+            let show_sender = prefs.content != NotificationContent::Hidden;
+            Some(NewMailNotification {
+                email_id: email.id()?.to_string(),
+                thread_id: email.thread_id().map(str::to_string),
+                mailbox_ids: email.mailbox_ids().into_iter().map(String::from).collect(),
+                from: show_sender.then(|| email.from().unwrap_or_default().to_vec()),
+                subject: email.subject().filter(|_| show_sender).map(str::to_string),
+                snippet: email
+                    .preview()
+                    .filter(|_| prefs.content == NotificationContent::Full)
+                    .map(snippet)
+                    .filter(|s| !s.is_empty()),
+            })
+        })
+        .collect())
+}
+
+/// The preview is meant to be plain text already, but it's stripped anyway since it ends up
+/// on lock screens and the like.
+fn snippet(preview: &str) -> String {
+    let text = html_to_text(preview);
+    match text.char_indices().nth(SNIPPET_MAX_CHARS) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text,
+    }
+}
+
+fn is_new_mail(email: &Email, now: i64) -> bool {
+    !email.keywords().contains(&"$seen")
+        && email
+            .received_at()
+            .is_some_and(|received_at| now - received_at <= NEW_MAIL_MAX_AGE.as_secs() as i64)
+}
@@ -0,0 +1,258 @@
+use super::ApiState;
+use super::drafts::{find_draft, find_mailbox_by_role};
+use super::idempotency::{IDEMPOTENCY_KEY, idempotent, run_once};
+use super::identities::resolve_identity;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::JmapApi;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::{HeaderMap, StatusCode};
+use jmap_client::email::{Email, EmailAddress, EmailBodyPart};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use tracing::instrument;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendRequest {
+    pub draft_id: String,
+    /// The identity to send as, defaulting to the account's default identity.
+    pub identity_id: Option<String>,
+    /// Refuses to send the same content to the same recipients twice within a short window,
+    /// answering with the first send instead. Off by default, as identical emails can be meant.
+    #[serde(default)]
+    pub dedupe: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendResponse {
+    pub submission_id: String,
+}
+
+/// Sends a draft. Clients retrying over a flaky connection should pass an `Idempotency-Key`
+/// header, so a retry of a send that did go through doesn't send the email twice. Without one,
+/// `dedupe` guards against double clicks by keying on the content instead.
+#[instrument(skip(state, headers))]
+pub async fn send_draft(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    headers: HeaderMap,
+    Json(request): Json<SendRequest>,
+) -> HttpResult<(StatusCode, Json<SendResponse>)> {
+    let response = if request.dedupe && !headers.contains_key(IDEMPOTENCY_KEY) {
+        // Once sent, the draft is gone, so a retry of a send that went through can only be
+        // recognised by the draft's id
+        let dedupe_window = state.idempotency.dedupe_window;
+        let draft_id = request.draft_id.clone();
+        run_once(
+            &state,
+            account_id,
+            "send_draft_by_draft_id",
+            &draft_id,
+            dedupe_window,
+            || async {
+                let draft = find_draft(&state, account_id, &request.draft_id).await?;
+                let key = content_key(request.identity_id.as_deref(), &draft)
+                    .context("Error hashing draft")
+                    .into_internal_error_result()?;
+
+                run_once(
+                    &state,
+                    account_id,
+                    "send_draft_dedupe",
+                    &key,
+                    dedupe_window,
+                    || submit_draft(&state, account_id, request),
+                )
+                .await
+            },
+        )
+        .await?
+    } else {
+        idempotent(&state, account_id, "send_draft", &headers, || {
+            submit_draft(&state, account_id, request)
+        })
+        .await?
+    };
+
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+async fn submit_draft(
+    state: &ApiState,
+    account_id: AccountId,
+    SendRequest {
+        draft_id,
+        identity_id,
+        ..
+    }: SendRequest,
+) -> HttpResult<SendResponse> {
+    let draft = find_draft(state, account_id, &draft_id).await?;
+
+    let api = state.jmap_api(account_id)?;
+    let identity = resolve_identity(&api, identity_id.as_deref()).await?;
+    let identity_id = identity
+        .id()
+        .context("Identity has no id")
+        .into_internal_error_result()?
+        .to_string();
+
+    let drafts_mailbox_id = find_mailbox_by_role(state, account_id, "drafts")
+        .await?
+        .context("Account has no drafts mailbox")
+        .into_not_found_error_result()?;
+    let sent_mailbox_id = find_mailbox_by_role(state, account_id, "sent").await?;
+
+    let submission_id = api
+        .submit_email(draft_id, identity_id, drafts_mailbox_id, sent_mailbox_id)
+        .await
+        .context("Error submitting email")
+        .into_internal_error_result()?;
+
+    // The email is on its way, so this is only worth a warning
+    if let Err(e) = mark_original(state, &api, account_id, &draft).await {
+        tracing::warn!(
+            ?e,
+            "Error marking the original email as answered or forwarded"
+        );
+    }
+
+    Ok(SendResponse { submission_id })
+}
+
+/// Sets `$answered` on the email a reply refers to by `In-Reply-To`, or `$forwarded` on the
+/// one a forward refers to, so clients can show it was dealt with. An original that isn't
+/// stored locally, e.g. one in a mailbox that hasn't been synced, is skipped.
+async fn mark_original(
+    state: &ApiState,
+    api: &JmapApi,
+    account_id: AccountId,
+    draft: &Email,
+) -> anyhow::Result<()> {
+    let subject = draft.subject().unwrap_or_default().trim_start();
+    let is_forward = ["fwd:", "fw:"].iter().any(|prefix| {
+        subject
+            .get(..prefix.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(prefix))
+    });
+
+    // Forwards rarely set In-Reply-To, the original is the last of the references then
+    let (keyword, message_ids) = match (draft.in_reply_to(), draft.references()) {
+        (Some(in_reply_to), _) if !in_reply_to.is_empty() => (
+            if is_forward {
+                "$forwarded"
+            } else {
+                "$answered"
+            },
+            in_reply_to.to_vec(),
+        ),
+        (_, Some([.., last])) if is_forward => ("$forwarded", vec![last.clone()]),
+        _ => return Ok(()),
+    };
+
+    let ids = state
+        .repo
+        .find_email_ids_by_message_id(account_id, &message_ids)
+        .await?;
+    if ids.is_empty() {
+        tracing::debug!(?message_ids, "Original email not found, not marking it");
+        return Ok(());
+    }
+
+    let keywords = BTreeMap::from([(keyword.to_string(), true)]);
+    let updated = api.set_keywords(ids, keywords.clone()).await?.updated;
+    state
+        .repo
+        .set_emails_keywords(account_id, &updated, &keywords)
+        .await?;
+    Ok(())
+}
+
+/// Hashes what makes two sends the same email: who it's from and to, and what it says. Ids and
+/// dates are left out, as a second draft of the same email gets new ones.
+///
+/// What it says is the blobs of its body parts and attachments. Servers mostly derive blob ids
+/// from the content, so the same text gets the same id, while a different one never does. The
+/// preview wouldn't do, as it's cut short.
+fn content_key(identity_id: Option<&str>, draft: &Email) -> anyhow::Result<String> {
+    let addresses = |list: Option<&[EmailAddress]>| {
+        let mut emails = list
+            .unwrap_or_default()
+            .iter()
+            .map(|a| a.email().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        emails.sort();
+        emails
+    };
+
+    fn parts(parts: Option<&[EmailBodyPart]>) -> Vec<(Option<&str>, Option<&str>, Option<&str>)> {
+        parts
+            .unwrap_or_default()
+            .iter()
+            .map(|p| (p.blob_id(), p.name(), p.content_type()))
+            .collect()
+    }
+
+    let content = serde_json::to_vec(&(
+        identity_id,
+        addresses(draft.from()),
+        addresses(draft.to()),
+        addresses(draft.cc()),
+        addresses(draft.bcc()),
+        draft.subject(),
+        parts(draft.text_body()),
+        parts(draft.html_body()),
+        parts(draft.attachments()),
+    ))?;
+
+    Ok(hex::encode(Sha256::digest(content)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn draft(to: &[&str], text_blob: &str, preview: &str) -> Email {
+        serde_json::from_value(json!({
+            "id": format!("draft-{text_blob}"),
+            "from": [{"email": "me@example.com"}],
+            "to": to.iter().map(|email| json!({"email": email})).collect::<Vec<_>>(),
+            "subject": "Hello",
+            "preview": preview,
+            "textBody": [{"partId": "1", "blobId": text_blob, "type": "text/plain"}],
+        }))
+        .unwrap()
+    }
+
+    fn key(draft: &Email) -> String {
+        content_key(Some("identity"), draft).unwrap()
+    }
+
+    #[test]
+    fn same_content_same_key() {
+        assert_eq!(
+            key(&draft(&["a@example.com", "b@example.com"], "blob1", "Hi")),
+            key(&draft(&["B@example.com", "a@example.com"], "blob1", "Hi")),
+        );
+    }
+
+    #[test]
+    fn bodies_differing_past_the_preview_differ() {
+        assert_ne!(
+            key(&draft(&["a@example.com"], "blob1", "Same start")),
+            key(&draft(&["a@example.com"], "blob2", "Same start")),
+        );
+    }
+
+    #[test]
+    fn recipients_and_identity_count() {
+        let first = draft(&["a@example.com"], "blob1", "Hi");
+        assert_ne!(key(&first), key(&draft(&["b@example.com"], "blob1", "Hi")));
+        assert_ne!(key(&first), content_key(None, &first).unwrap());
+    }
+}
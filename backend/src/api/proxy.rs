@@ -1,21 +1,46 @@
 use super::ApiState;
+use crate::repo::{ExternalResource, Repository};
+use crate::util::dates::parse_http_date;
 use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use crate::util::url_guard::ensure_public_url;
 use anyhow::Context;
 use axum::body::Body;
 use axum::extract::{Query, State};
-use axum::http::StatusCode;
-use axum::http::header::{CACHE_CONTROL, CONTENT_LENGTH};
+use axum::http::header::{CACHE_CONTROL, CONTENT_LENGTH, DATE, EXPIRES};
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::Response;
+use futures::StreamExt;
 use reqwest::header::CONTENT_TYPE;
 use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::instrument;
 use url::Url;
 
+/// How long a resource is cached when its server doesn't say.
+const DEFAULT_FRESHNESS: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// Responses larger than this are refused, or cut off if the size isn't known upfront.
+    pub max_bytes: u64,
+    /// Overall time allowed for fetching a resource, including its body.
+    pub timeout: Duration,
+    /// Content type prefixes the proxy may return, e.g. `image/`.
+    pub allowed_content_types: Arc<[String]>,
+    /// Least recently used resources are evicted from the cache once it grows past this.
+    pub cache_max_bytes: u64,
+}
+
 #[derive(Deserialize)]
 pub struct QueryParams {
     pub url: Url,
 }
 
+/// Fetches a remote resource, e.g. an image in an email, for a client that shouldn't reach it
+/// directly. Resources are cached by URL and shared between accounts, so a cached one is served
+/// without contacting its server again until it expires, as its Cache-Control or Expires header
+/// says. Clients are told to cache it for as long as it has left.
 #[instrument(skip(state))]
 pub async fn proxy(
     State(state): State<ApiState>,
@@ -29,29 +54,252 @@ pub async fn proxy(
             .into());
     }
 
+    let cached = state
+        .repo
+        .get_external_cache(url.as_str())
+        .await
+        .context("Error reading proxy cache")
+        .into_internal_error_result()?;
+
+    if let Some(resource) = cached {
+        return resource_response(resource);
+    }
+
+    ensure_public_url(&url)
+        .await
+        .into_error_result(StatusCode::FORBIDDEN)?;
+
+    let ProxyConfig {
+        max_bytes,
+        timeout,
+        allowed_content_types,
+        cache_max_bytes,
+    } = &state.proxy_config;
+
     let downloaded_resp = state
         .http_client
         .get(url.clone())
+        .timeout(*timeout)
         .send()
         .await
         .context("Error proxying request")
         .into_internal_error_result()?;
 
-    let mut resp_builder = Response::builder();
+    let content_type = downloaded_resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
 
-    if let Some(content_type) = downloaded_resp.headers().get(CONTENT_TYPE) {
-        resp_builder = resp_builder.header(CONTENT_TYPE, content_type);
+    if !allowed_content_types
+        .iter()
+        .any(|allowed| content_type.starts_with(allowed.as_str()))
+    {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Refusing to proxy content type '{content_type}'"),
+        )
+            .into());
     }
 
-    if let Some(content_length) = downloaded_resp.headers().get(CONTENT_LENGTH) {
-        resp_builder = resp_builder.header(CONTENT_LENGTH, content_length);
+    if downloaded_resp
+        .content_length()
+        .is_some_and(|len| len > *max_bytes)
+    {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Resource is larger than {max_bytes} bytes"),
+        )
+            .into());
+    }
+
+    let max_age = downloaded_resp
+        .status()
+        .is_success()
+        .then(|| freshness_lifetime(downloaded_resp.headers(), now()))
+        .flatten();
+
+    // The length header may be missing or lying, so count what actually comes through
+    let mut data = Vec::new();
+    let mut body = downloaded_resp.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk
+            .context("Error reading proxied body")
+            .into_internal_error_result()?;
+        if (data.len() + chunk.len()) as u64 > *max_bytes {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Resource is larger than {max_bytes} bytes"),
+            )
+                .into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let resource = ExternalResource {
+        content_type,
+        data,
+        max_age: max_age.unwrap_or_default(),
+    };
+    if max_age.is_some() {
+        cache_resource(&state.repo, url.as_str(), &resource, *cache_max_bytes).await;
+    }
+
+    resource_response(resource)
+}
+
+/// How long a response may be served from the cache without asking its server again, in
+/// seconds, or `None` if it mustn't be cached. Like a browser's, the cache is private, so
+/// `private` responses are fine, but it never revalidates, so `no-cache` ones aren't.
+fn freshness_lifetime(headers: &HeaderMap, now: i64) -> Option<i64> {
+    let directives = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if directives
+        .iter()
+        .any(|d| d == "no-store" || d == "no-cache")
+    {
+        return None;
     }
 
-    // We want the response to be cached indefinitely by the browser
-    resp_builder = resp_builder.header(CACHE_CONTROL, "public, max-age=31536000, immutable");
+    let max_age = directives
+        .iter()
+        .find_map(|d| d.strip_prefix("max-age="))
+        .map(|age| age.trim_matches('"').parse::<i64>().unwrap_or_default());
 
-    resp_builder
-        .body(Body::from_stream(downloaded_resp.bytes_stream()))
+    let date = |name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(parse_http_date)
+    };
+    // An invalid Expires means already expired
+    let expires = date(EXPIRES).map(|expires| {
+        let date = date(DATE).flatten().unwrap_or(now);
+        expires.map_or(0, |expires| expires - date)
+    });
+
+    // Max-age takes precedence over Expires
+    match max_age.or(expires) {
+        Some(lifetime) => (lifetime > 0).then_some(lifetime),
+        None => Some(DEFAULT_FRESHNESS.as_secs() as i64),
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Caching is best-effort: the resource is served either way.
+async fn cache_resource(repo: &Repository, url: &str, resource: &ExternalResource, max_bytes: u64) {
+    if let Err(e) = repo.put_external_cache(url, resource).await {
+        tracing::warn!(?e, "Error caching proxied resource");
+        return;
+    }
+
+    match repo.prune_external_cache(max_bytes).await {
+        Ok(0) => {}
+        Ok(evicted) => tracing::debug!(evicted, "Pruned proxy cache"),
+        Err(e) => tracing::warn!(?e, "Error pruning proxy cache"),
+    }
+}
+
+fn resource_response(
+    ExternalResource {
+        content_type,
+        data,
+        max_age,
+    }: ExternalResource,
+) -> HttpResult<Response> {
+    let cache_control = if max_age > 0 {
+        format!("private, max-age={max_age}")
+    } else {
+        "private, no-store".to_string()
+    };
+
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_LENGTH, data.len())
+        .header(CACHE_CONTROL, cache_control)
+        .body(Body::from(data))
         .context("Error building response")
         .into_internal_error_result()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const NOW: i64 = 1738310400;
+
+    fn lifetime(headers: &[(&'static str, &'static str)]) -> Option<i64> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        freshness_lifetime(&map, NOW)
+    }
+
+    #[test]
+    fn max_age_sets_the_lifetime() {
+        assert_eq!(
+            lifetime(&[("cache-control", "public, max-age=600")]),
+            Some(600)
+        );
+        assert_eq!(
+            lifetime(&[("cache-control", "private, max-age=60")]),
+            Some(60)
+        );
+        assert_eq!(lifetime(&[("cache-control", "max-age=0")]), None);
+        // Max-age wins over Expires
+        assert_eq!(
+            lifetime(&[
+                ("cache-control", "max-age=60"),
+                ("expires", "Fri, 31 Jan 2025 09:00:00 GMT"),
+            ]),
+            Some(60)
+        );
+    }
+
+    #[test]
+    fn expires_sets_the_lifetime() {
+        assert_eq!(
+            lifetime(&[("expires", "Fri, 31 Jan 2025 09:00:00 GMT")]),
+            Some(3600)
+        );
+        // Relative to the server's clock when it sends one
+        assert_eq!(
+            lifetime(&[
+                ("expires", "Fri, 31 Jan 2025 09:00:00 GMT"),
+                ("date", "Fri, 31 Jan 2025 08:30:00 GMT"),
+            ]),
+            Some(1800)
+        );
+        assert_eq!(
+            lifetime(&[("expires", "Thu, 01 Jan 1970 00:00:00 GMT")]),
+            None
+        );
+        assert_eq!(lifetime(&[("expires", "0")]), None);
+    }
+
+    #[test]
+    fn uncacheable_responses() {
+        assert_eq!(lifetime(&[("cache-control", "no-store")]), None);
+        assert_eq!(lifetime(&[("cache-control", "No-Cache, max-age=60")]), None);
+    }
+
+    #[test]
+    fn lifetime_defaults_when_unspecified() {
+        assert_eq!(lifetime(&[]), Some(DEFAULT_FRESHNESS.as_secs() as i64));
+    }
+}
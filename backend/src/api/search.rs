@@ -0,0 +1,66 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+/// How many suggestions are returned at most, split between senders and subjects.
+const MAX_SUGGESTIONS: u32 = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    pub q: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Suggestion {
+    Sender { name: Option<String>, email: String },
+    Subject { subject: String },
+}
+
+/// Search-as-you-type suggestions, answered from local mail only.
+#[instrument(skip(state))]
+pub async fn suggest(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    extract::Query(Params { q }): extract::Query<Params>,
+) -> HttpResult<Json<Vec<Suggestion>>> {
+    let term = q.trim();
+    if term.is_empty() {
+        return Ok(Json(vec![]));
+    }
+
+    let senders = state
+        .repo
+        .suggest_senders(account_id, term, MAX_SUGGESTIONS / 2)
+        .await
+        .context("Error suggesting senders")
+        .into_internal_error_result()?;
+
+    // Subjects fill up whatever senders leave over
+    let subjects = state
+        .repo
+        .suggest_subjects(account_id, term, MAX_SUGGESTIONS - senders.len() as u32)
+        .await
+        .context("Error suggesting subjects")
+        .into_internal_error_result()?;
+
+    Ok(Json(
+        senders
+            .into_iter()
+            .map(|s| Suggestion::Sender {
+                name: s.name,
+                email: s.email,
+            })
+            .chain(
+                subjects
+                    .into_iter()
+                    .map(|subject| Suggestion::Subject { subject }),
+            )
+            .collect(),
+    ))
+}
@@ -8,6 +8,7 @@ use axum::response::IntoResponse;
 use http_body_util::BodyExt;
 use tracing::instrument;
 
+#[allow(dead_code)]
 #[instrument(skip(state, req))]
 pub async fn static_file_or_dev_proxy(
     State(state): State<ApiState>,
@@ -0,0 +1,9 @@
+use super::ApiState;
+use crate::util::network::NetworkAvailability;
+use axum::Json;
+use axum::extract;
+
+/// Whether the server can currently reach the network, for clients to show when it can't.
+pub async fn get_status(state: extract::State<ApiState>) -> Json<NetworkAvailability> {
+    Json(state.network_availability.borrow().clone())
+}
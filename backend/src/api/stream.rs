@@ -1,12 +1,147 @@
+use crate::jmap_account::AccountId;
 use crate::repo::Repository;
+use crate::util::http_error::HttpResult;
 use anyhow::Context;
 use axum::extract::WebSocketUpgrade;
 use axum::extract::ws::Message;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use futures::{FutureExt, StreamExt, TryStream, TryStreamExt};
+use futures::{StreamExt, TryStream, TryStreamExt};
+use parking_lot::Mutex;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::Arc;
 
+/// Default upper bound on the `limit` of list streams. Every change re-serializes the whole
+/// page, so clients should paginate instead of asking for everything at once.
+pub const DEFAULT_MAX_LIST_LIMIT: usize = 200;
+
+/// Validates list pagination, clamping `limit` to `max_limit`. Values that can't be
+/// passed to SQLite are rejected.
+pub fn check_pagination(
+    limit: usize,
+    offset: usize,
+    max_limit: usize,
+) -> HttpResult<(usize, usize)> {
+    if i64::try_from(offset).is_err() {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid offset: {offset}")).into());
+    }
+
+    Ok((limit.min(max_limit), offset))
+}
+
+/// Caps what a client can send over a websocket. Messages up to `max_message_size` reach the
+/// handler, which can then close with a reason when they're too big. Anything far beyond that
+/// gets the connection dropped by the transport, before it's read into memory.
+pub fn limit_websocket(upgrade: WebSocketUpgrade, max_message_size: usize) -> WebSocketUpgrade {
+    let hard_limit = max_message_size.saturating_mul(4);
+    upgrade
+        .max_message_size(hard_limit)
+        .max_frame_size(hard_limit)
+}
+
+/// Caps how many streams can be open at once, in total and per account. Each one holds a DB
+/// change subscription and re-runs its query on changes, so a client leaking sockets would
+/// otherwise wear the whole server down.
+pub struct StreamLimits {
+    pub max_streams: usize,
+    pub max_streams_per_account: usize,
+    open: Mutex<OpenStreams>,
+}
+
+#[derive(Default)]
+struct OpenStreams {
+    total: usize,
+    per_account: HashMap<AccountId, usize>,
+}
+
+impl StreamLimits {
+    pub fn new(max_streams: usize, max_streams_per_account: usize) -> Self {
+        Self {
+            max_streams,
+            max_streams_per_account,
+            open: Default::default(),
+        }
+    }
+
+    /// Counts a new stream for the account, to be held until the stream ends. Answers with a
+    /// 503 when a limit has been reached, before the websocket is upgraded.
+    pub fn open(self: &Arc<Self>, account_id: AccountId) -> HttpResult<StreamPermit> {
+        let mut open = self.open.lock();
+        let for_account = open.per_account.get(&account_id).copied().unwrap_or(0);
+
+        let refused = if open.total >= self.max_streams {
+            Some("Too many open streams")
+        } else if for_account >= self.max_streams_per_account {
+            Some("Too many open streams for this account")
+        } else {
+            None
+        };
+
+        if let Some(reason) = refused {
+            tracing::warn!(
+                %account_id,
+                open_streams = open.total,
+                account_streams = for_account,
+                "Refusing stream"
+            );
+            return Err((StatusCode::SERVICE_UNAVAILABLE, reason.to_string()).into());
+        }
+
+        open.total += 1;
+        *open.per_account.entry(account_id).or_default() += 1;
+        tracing::debug!(%account_id, open_streams = open.total, "Stream opened");
+
+        Ok(StreamPermit {
+            limits: self.clone(),
+            account_id: Some(account_id),
+        })
+    }
+
+    /// Like [`Self::open`], for a stream that isn't about one account. It only counts towards
+    /// the total.
+    pub fn open_unscoped(self: &Arc<Self>) -> HttpResult<StreamPermit> {
+        let mut open = self.open.lock();
+        if open.total >= self.max_streams {
+            tracing::warn!(open_streams = open.total, "Refusing stream");
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many open streams".to_string(),
+            )
+                .into());
+        }
+
+        open.total += 1;
+        tracing::debug!(open_streams = open.total, "Stream opened");
+
+        Ok(StreamPermit {
+            limits: self.clone(),
+            account_id: None,
+        })
+    }
+}
+
+pub struct StreamPermit {
+    limits: Arc<StreamLimits>,
+    account_id: Option<AccountId>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut open = self.limits.open.lock();
+        open.total -= 1;
+        if let Some(account_id) = self.account_id
+            && let Some(count) = open.per_account.get_mut(&account_id)
+        {
+            *count -= 1;
+            if *count == 0 {
+                open.per_account.remove(&account_id);
+            }
+        }
+        tracing::debug!(account_id = ?self.account_id, open_streams = open.total, "Stream closed");
+    }
+}
+
 pub fn db_stream<T, F, Fut>(
     repo: Arc<Repository>,
     tables: &'static [&'static str],
@@ -55,6 +190,7 @@ where
 
 pub fn websocket_db_stream<T, F, Fut>(
     upgrade: WebSocketUpgrade,
+    permit: StreamPermit,
     repo: Arc<Repository>,
     tables: &'static [&'static str],
     query: F,
@@ -64,16 +200,38 @@ where
     F: Fn(Arc<Repository>) -> Fut + Clone + Send + 'static,
     Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
 {
-    upgrade.on_upgrade(move |ws| {
-        let repo = repo.clone();
-        db_stream(repo, tables, query)
+    upgrade.on_upgrade(async move |ws| {
+        let _permit = permit;
+        let result = db_stream(repo, tables, query)
             .map_ok(Message::text)
-            .map_err(|e| axum::Error::new(e))
+            .map_err(axum::Error::new)
             .forward(ws)
-            .map(|r| {
-                if let Err(e) = r {
-                    tracing::error!(?e, "Error in websocket_db_stream");
-                }
-            })
+            .await;
+        if let Err(e) = result {
+            tracing::error!(?e, "Error in websocket_db_stream");
+        }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_beyond_the_max_are_clamped() {
+        assert_eq!(check_pagination(500, 10, 200).unwrap(), (200, 10));
+    }
+
+    #[test]
+    fn limits_within_the_max_pass_through() {
+        assert_eq!(check_pagination(50, 0, 200).unwrap(), (50, 0));
+        assert_eq!(check_pagination(200, 0, 200).unwrap(), (200, 0));
+    }
+
+    #[test]
+    fn offsets_sqlite_cannot_take_are_rejected() {
+        let offset = i64::MAX as usize + 1;
+        let result = check_pagination(10, offset, 200).map(|_| ());
+        assert_eq!(result.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+}
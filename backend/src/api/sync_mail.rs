@@ -1,52 +1,64 @@
 use super::ApiState;
 use crate::jmap_account::AccountId;
-use crate::jmap_api::EmailQuery;
-use crate::sync::{EmailQueryState, SyncCommand, WatchEmailSyncCommand};
+use crate::sync::{
+    EmailQueryState, SequencedEmailQuery, SequencedEmailQueryState, SyncCommand,
+    WatchEmailSyncCommand,
+};
+use crate::util::http_error::HttpResult;
 use anyhow::Context;
 use axum::extract;
-use axum::extract::ws::{Message, WebSocket};
-use axum::http::StatusCode;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, close_code};
 use axum::response::IntoResponse;
 use serde::de::DeserializeOwned;
 use tokio::select;
-use tokio::sync::{mpsc, watch};
+use tokio::sync::watch;
 
+/// Syncs emails matching the queries received over the websocket, reporting the sync state.
+/// For an account whose sync hasn't started yet, the state stays `NotStarted` until it has.
+///
+/// A query may carry a `seq` number, which the states about it echo back. A client replacing
+/// its query, e.g. as the user types a search, can so ignore states of the queries before.
 pub async fn sync_mail(
     state: extract::State<ApiState>,
     account_id: extract::Path<AccountId>,
     upgrade: extract::ws::WebSocketUpgrade,
-) -> impl IntoResponse {
-    let Some(tx) = state
-        .account_states
-        .read()
-        .get(&account_id.0)
-        .map(|s| s.command_sender.clone())
-    else {
-        return (
-            StatusCode::NOT_FOUND,
-            format!("Account {} not found", account_id.0),
-        )
-            .into_response();
-    };
+) -> HttpResult<impl IntoResponse> {
+    let account_id = account_id.0;
+    state.ensure_account(account_id).await?;
+    let permit = state.stream_limits.open(account_id)?;
 
-    upgrade.on_upgrade(async move |mut websocket| {
-        if let Err(e) = handle_sync_mail_websocket(&mut websocket, &tx).await {
+    let upgrade = super::stream::limit_websocket(upgrade, state.ws_max_message_size);
+    Ok(upgrade.on_upgrade(async move |mut websocket| {
+        let _permit = permit;
+        if let Err(e) = handle_sync_mail_websocket(&mut websocket, &state, account_id).await {
             tracing::error!(?e, "Error in sync_mail websocket");
         }
-    })
+    }))
 }
 
 async fn handle_sync_mail_websocket(
     websocket: &mut WebSocket,
-    command_sender: &mpsc::Sender<SyncCommand>,
+    state: &ApiState,
+    account_id: AccountId,
 ) -> anyhow::Result<()> {
     // Wait for the first command to set up the watch
-    let initial_query: EmailQuery = receive_json(websocket)
+    let max_size = state.ws_max_message_size;
+    let initial_query: SequencedEmailQuery = receive_json(websocket, max_size)
         .await
         .context("Failed to receive initial email query")?;
 
+    let not_started = SequencedEmailQueryState {
+        seq: initial_query.seq,
+        state: EmailQueryState::NotStarted,
+    };
     let (query_tx, query_rx) = watch::channel(initial_query);
-    let (state_tx, mut state_rx) = watch::channel(EmailQueryState::NotStarted);
+    let (state_tx, mut state_rx) = watch::channel(not_started.clone());
+
+    send_state(websocket, &not_started).await?;
+    let command_sender = state
+        .wait_for_command_sender(account_id)
+        .await?
+        .context("Account was deleted")?;
 
     command_sender
         .send(SyncCommand::WatchEmails(WatchEmailSyncCommand {
@@ -58,7 +70,7 @@ async fn handle_sync_mail_websocket(
 
     loop {
         select! {
-            new_query = receive_json::<EmailQuery>(websocket) => {
+            new_query = receive_json::<SequencedEmailQuery>(websocket, max_size) => {
                 let query = new_query.context("Failed to receive updated email query")?;
                 tracing::debug!(?query, "New email query");
                 query_tx
@@ -68,19 +80,31 @@ async fn handle_sync_mail_websocket(
 
             changed = state_rx.changed() => {
                 changed.context("Failed to receive email query state change")?;
-                let state = serde_json::to_string(&*state_rx.borrow())
-                    .context("Failed to serialize email query state")?;
-                tracing::debug!(?state, "Email sync state");
-                websocket
-                    .send(Message::text(state))
-                    .await
-                    .context("Failed to send email query state over websocket")?;
+                let state = state_rx.borrow().clone();
+                send_state(websocket, &state).await?;
             }
         }
     }
 }
 
-async fn receive_json<T: DeserializeOwned>(ws: &mut WebSocket) -> anyhow::Result<T> {
+async fn send_state(
+    websocket: &mut WebSocket,
+    state: &SequencedEmailQueryState,
+) -> anyhow::Result<()> {
+    let state = serde_json::to_string(state).context("Failed to serialize email query state")?;
+    tracing::debug!(?state, "Email sync state");
+    websocket
+        .send(Message::text(state))
+        .await
+        .context("Failed to send email query state over websocket")
+}
+
+/// Receives the next JSON message. One larger than `max_size` closes the websocket with a
+/// "message too big" status.
+async fn receive_json<T: DeserializeOwned>(
+    ws: &mut WebSocket,
+    max_size: usize,
+) -> anyhow::Result<T> {
     loop {
         let msg = ws
             .recv()
@@ -89,6 +113,15 @@ async fn receive_json<T: DeserializeOwned>(ws: &mut WebSocket) -> anyhow::Result
             .context("Websocket closed unexpectedly")?;
 
         match msg {
+            Message::Text(text) if text.len() > max_size => {
+                let _ = ws
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::SIZE,
+                        reason: format!("Message exceeds {max_size} bytes").into(),
+                    })))
+                    .await;
+                anyhow::bail!("Websocket message of {} bytes is too big", text.len());
+            }
             Message::Text(text) => {
                 let data = serde_json::from_str(&text)
                     .context("Failed to deserialize JSON message from websocket")?;
@@ -1,50 +1,58 @@
 use crate::jmap_account::AccountId;
 use crate::sync::{EmailQueryState, SyncCommand, WatchMailboxSyncCommand};
+use crate::util::http_error::HttpResult;
 use axum::extract;
 use axum::extract::ws::Message;
-use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use tokio::sync::watch;
 
+/// Keeps a mailbox in sync while the websocket is open, reporting the sync state. A mailbox
+/// that hasn't been synced yet, or an account whose sync hasn't started, reports `NotStarted`
+/// until syncing gets going.
 pub async fn sync_mailbox(
     state: extract::State<super::ApiState>,
     extract::Path((account_id, mailbox_id)): extract::Path<(AccountId, String)>,
     upgrade: extract::WebSocketUpgrade,
-) -> impl IntoResponse {
-    let Some(sender) = state
-        .account_states
-        .read()
-        .get(&account_id)
-        .map(|s| s.command_sender.clone())
-    else {
-        return (StatusCode::NOT_FOUND, "Account not found").into_response();
-    };
+) -> HttpResult<impl IntoResponse> {
+    state.ensure_account(account_id).await?;
+    let permit = state.stream_limits.open(account_id)?;
 
-    let (state_tx, mut state_rx) = watch::channel(EmailQueryState::NotStarted);
+    Ok(upgrade.on_upgrade(async move |mut ws| {
+        let _permit = permit;
+        let (state_tx, mut state_rx) = watch::channel(EmailQueryState::NotStarted);
 
-    if let Err(e) = sender
-        .send(SyncCommand::WatchMailbox(WatchMailboxSyncCommand {
-            mailbox_id,
-            state_tx,
-        }))
-        .await
-    {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to send sync command: {:?}", e),
-        )
-            .into_response();
-    };
+        let sender = async {
+            let text = serde_json::to_string(&EmailQueryState::NotStarted)?;
+            ws.send(Message::text(text)).await?;
+            state.wait_for_command_sender(account_id).await
+        };
 
-    upgrade
-        .on_upgrade(async move |mut ws| {
-            while state_rx.changed().await.is_ok() {
-                let text = serde_json::to_string(&*state_rx.borrow()).unwrap();
-                if let Err(e) = ws.send(Message::text(text)).await {
-                    tracing::error!(?e, "WebSocket send error");
-                    break;
-                }
+        let sender = match sender.await {
+            Ok(Some(sender)) => sender,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::error!(?e, "Error waiting for account sync");
+                return;
             }
-        })
-        .into_response()
+        };
+
+        if let Err(e) = sender
+            .send(SyncCommand::WatchMailbox(WatchMailboxSyncCommand {
+                mailbox_id,
+                state_tx,
+            }))
+            .await
+        {
+            tracing::error!(?e, "Failed to send sync command");
+            return;
+        }
+
+        while state_rx.changed().await.is_ok() {
+            let text = serde_json::to_string(&*state_rx.borrow()).unwrap();
+            if let Err(e) = ws.send(Message::text(text)).await {
+                tracing::error!(?e, "WebSocket send error");
+                break;
+            }
+        }
+    }))
 }
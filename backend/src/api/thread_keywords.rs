@@ -0,0 +1,100 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::jmap_api::BulkSetFailure;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::instrument;
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ThreadAction {
+    Read,
+    Unread,
+    Flag,
+    Unflag,
+}
+
+impl ThreadAction {
+    fn keyword(self) -> (&'static str, bool) {
+        match self {
+            ThreadAction::Read => ("$seen", true),
+            ThreadAction::Unread => ("$seen", false),
+            ThreadAction::Flag => ("$flagged", true),
+            ThreadAction::Unflag => ("$flagged", false),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadActionResponse {
+    /// How many emails of the thread the server updated.
+    pub affected: usize,
+    pub updated: Vec<String>,
+    /// Emails the server refused to update, which keep their keywords.
+    pub failed: Vec<BulkSetFailure>,
+}
+
+/// Marks every email of a thread read or unread, or flags or unflags them, in one `Email/set`.
+/// When the server refuses some of the emails the rest are still updated, and the response is a
+/// `207 Multi-Status` listing the failures.
+#[instrument(skip(state))]
+pub async fn apply_thread_action(
+    state: extract::State<ApiState>,
+    extract::Path((account_id, thread_id, action)): extract::Path<(
+        AccountId,
+        String,
+        ThreadAction,
+    )>,
+) -> HttpResult<(StatusCode, Json<ThreadActionResponse>)> {
+    let ids = state
+        .repo
+        .get_thread_email_ids(account_id, &thread_id)
+        .await
+        .context("Error querying thread emails")
+        .into_internal_error_result()?;
+
+    if ids.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Thread {thread_id} not found"),
+        )
+            .into());
+    }
+
+    let (keyword, value) = action.keyword();
+    let keywords = BTreeMap::from([(keyword.to_string(), value)]);
+    let result = state
+        .jmap_api(account_id)?
+        .set_keywords(ids, keywords.clone())
+        .await
+        .context("Error updating thread emails")
+        .into_internal_error_result()?;
+
+    state
+        .repo
+        .set_emails_keywords(account_id, &result.updated, &keywords)
+        .await
+        .context("Error saving thread emails")
+        .into_internal_error_result()?;
+
+    let status = if result.failed.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+
+    Ok((
+        status,
+        Json(ThreadActionResponse {
+            affected: result.updated.len(),
+            updated: result.updated,
+            failed: result.failed,
+        }),
+    ))
+}
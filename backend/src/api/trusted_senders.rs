@@ -0,0 +1,104 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use anyhow::Context;
+use axum::Json;
+use axum::extract;
+use axum::http::StatusCode;
+use serde::Deserialize;
+use tracing::instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct TrustedSenderRequest {
+    /// An address like `alice@example.com`, or `*@example.com` for anyone at the domain.
+    pub sender: String,
+}
+
+/// Trusted senders always get their remote images loaded, unlike the contacts policy this is
+/// a list the user keeps by hand.
+#[instrument(skip(state))]
+pub async fn list_trusted_senders(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+) -> HttpResult<Json<Vec<String>>> {
+    state.ensure_account(account_id).await?;
+
+    state
+        .repo
+        .get_trusted_senders(account_id)
+        .await
+        .context("Error getting trusted senders")
+        .into_internal_error_result()
+        .map(Json)
+}
+
+#[instrument(skip(state))]
+pub async fn add_trusted_sender(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    Json(TrustedSenderRequest { sender }): Json<TrustedSenderRequest>,
+) -> HttpResult<Json<Vec<String>>> {
+    state.ensure_account(account_id).await?;
+
+    let pattern = parse_pattern(&sender)?;
+    state
+        .repo
+        .add_trusted_sender(account_id, &pattern)
+        .await
+        .context("Error adding trusted sender")
+        .into_internal_error_result()?;
+
+    list_trusted_senders(state, extract::Path(account_id)).await
+}
+
+#[instrument(skip(state))]
+pub async fn remove_trusted_sender(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    Json(TrustedSenderRequest { sender }): Json<TrustedSenderRequest>,
+) -> HttpResult<Json<Vec<String>>> {
+    state.ensure_account(account_id).await?;
+
+    let pattern = parse_pattern(&sender)?;
+    let removed = state
+        .repo
+        .remove_trusted_sender(account_id, &pattern)
+        .await
+        .context("Error removing trusted sender")
+        .into_internal_error_result()?;
+
+    if !removed {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("{pattern} is not a trusted sender"),
+        )
+            .into());
+    }
+
+    list_trusted_senders(state, extract::Path(account_id)).await
+}
+
+/// Normalizes an address or domain wildcard the way they're stored, i.e. in lowercase.
+fn parse_pattern(sender: &str) -> HttpResult<String> {
+    let pattern = sender.trim().to_lowercase();
+    let valid = match pattern.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains(['@', '*'])
+                && (local == "*" || !local.contains('*'))
+                && !pattern.contains(char::is_whitespace)
+        }
+        None => false,
+    };
+
+    if !valid {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Expecting an email address or *@domain, got {sender:?}"),
+        )
+            .into());
+    }
+
+    Ok(pattern)
+}
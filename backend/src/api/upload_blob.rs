@@ -0,0 +1,105 @@
+use super::ApiState;
+use crate::jmap_account::AccountId;
+use crate::repo::Blob;
+use crate::util::http_error::{AnyhowHttpError, HttpResult};
+use crate::util::spool::{SpooledBody, spool_body};
+use anyhow::Context;
+use axum::Json;
+use axum::body::Body;
+use axum::extract;
+use axum::http::{HeaderMap, StatusCode, header};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadedBlob {
+    pub blob_id: String,
+    pub mime_type: String,
+    pub size: usize,
+}
+
+/// Uploads the request body to the server, e.g. as an attachment for a draft. Small blobs are
+/// cached locally as well, since the client usually shows them again straight away. Large ones
+/// are spooled to a temp file and streamed from there, so they never sit in memory as a whole.
+#[instrument(skip(state, headers, body))]
+pub async fn upload_blob(
+    state: extract::State<ApiState>,
+    extract::Path(account_id): extract::Path<AccountId>,
+    extract::Query(Params { name }): extract::Query<Params>,
+    headers: HeaderMap,
+    body: Body,
+) -> HttpResult<(StatusCode, Json<UploadedBlob>)> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+
+    // Unknown accounts fail before the body is received
+    state.jmap_api(account_id)?;
+
+    let spooled = spool_body(body, &state.spool_config)
+        .await
+        .context("Error receiving upload")
+        .into_internal_error_result()?;
+
+    let jmap_api = state.connected_jmap_api(account_id).await?;
+
+    let data = match spooled {
+        SpooledBody::Memory(data) => data,
+        SpooledBody::File(file) => {
+            let mut uploaded = jmap_api
+                .upload_blob_file(file.path(), content_type)
+                .await
+                .context("Error uploading blob")
+                .into_internal_error_result()?;
+
+            return Ok((
+                StatusCode::CREATED,
+                Json(UploadedBlob {
+                    blob_id: uploaded.take_blob_id(),
+                    mime_type: uploaded.content_type().to_string(),
+                    size: uploaded.size(),
+                }),
+            ));
+        }
+    };
+
+    let mut uploaded = jmap_api
+        .upload_blob(data.clone(), content_type)
+        .await
+        .context("Error uploading blob")
+        .into_internal_error_result()?;
+
+    let blob_id = uploaded.take_blob_id();
+    let mime_type = uploaded.content_type().to_string();
+    let size = uploaded.size();
+
+    state
+        .repo
+        .save_blob(
+            account_id,
+            &blob_id,
+            &Blob {
+                name,
+                mime_type: Some(mime_type.clone()),
+                data,
+            },
+        )
+        .await
+        .context("Error caching uploaded blob")
+        .into_internal_error_result()?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UploadedBlob {
+            blob_id,
+            mime_type,
+            size,
+        }),
+    ))
+}
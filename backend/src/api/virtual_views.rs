@@ -0,0 +1,48 @@
+use super::ApiState;
+use super::watch_mail::watch_email_summaries;
+use crate::jmap_account::AccountId;
+use crate::repo::EmailDbQuery;
+use crate::util::http_error::HttpResult;
+use axum::Json;
+use axum::extract;
+use axum::extract::Path;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+
+/// Built-in views over the emails of all mailboxes, picked out by a filter rather than by
+/// being a JMAP mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VirtualView {
+    Flagged,
+    Unread,
+    Attachments,
+}
+
+impl VirtualView {
+    pub const ALL: [Self; 3] = [Self::Flagged, Self::Unread, Self::Attachments];
+
+    fn apply(self, query: &mut EmailDbQuery) {
+        match self {
+            Self::Flagged => query.flagged = Some(true),
+            Self::Unread => query.unread = Some(true),
+            Self::Attachments => query.has_attachment = Some(true),
+        }
+    }
+}
+
+pub async fn list_virtual_views(Path(_): Path<AccountId>) -> Json<Vec<VirtualView>> {
+    Json(VirtualView::ALL.to_vec())
+}
+
+/// Works like `watch_mail`, with the view's filter on top of the query. A `mailboxId` narrows
+/// the view down to that mailbox.
+pub async fn watch_virtual_view(
+    Path((account_id, view)): Path<(AccountId, VirtualView)>,
+    state: extract::State<ApiState>,
+    extract::Query(mut query): extract::Query<EmailDbQuery>,
+    upgrade: extract::ws::WebSocketUpgrade,
+) -> HttpResult<impl IntoResponse> {
+    view.apply(&mut query);
+    watch_email_summaries(&state, account_id, query, upgrade)
+}
@@ -1,22 +1,66 @@
 use super::ApiState;
 use crate::jmap_account::AccountId;
+use crate::jmap_api::EmailSortColumn;
 use crate::repo::EmailDbQuery;
+use crate::util::http_error::HttpResult;
+use axum::Json;
 use axum::extract;
 use axum::extract::Path;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use std::sync::Arc;
 
+/// The columns `watch_mail` can sort by. Others are only sortable on the server.
+pub async fn sortable_columns(Path(_): Path<AccountId>) -> Json<Vec<EmailSortColumn>> {
+    Json(
+        EmailSortColumn::ALL
+            .into_iter()
+            .filter(|c| c.to_sql_column().is_some())
+            .collect(),
+    )
+}
+
 pub async fn watch_mail(
-    account_id: Path<AccountId>,
+    Path(account_id): Path<AccountId>,
     state: extract::State<ApiState>,
-    query: extract::Query<EmailDbQuery>,
+    extract::Query(query): extract::Query<EmailDbQuery>,
     upgrade: extract::ws::WebSocketUpgrade,
-) -> impl IntoResponse {
-    let query = Arc::new(query.0);
+) -> HttpResult<impl IntoResponse> {
+    watch_email_summaries(&state, account_id, query, upgrade)
+}
+
+/// Streams the summaries of the emails matching `query` over the websocket, as they change.
+pub fn watch_email_summaries(
+    state: &ApiState,
+    account_id: AccountId,
+    mut query: EmailDbQuery,
+    upgrade: extract::ws::WebSocketUpgrade,
+) -> HttpResult<impl IntoResponse + use<>> {
+    (query.limit, query.offset) =
+        super::stream::check_pagination(query.limit, query.offset, state.max_list_limit)?;
+
+    if let Some(sort) = query
+        .sorts
+        .iter()
+        .find(|s| s.column.to_sql_column().is_none())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Sorting by {:?} is not supported", sort.column),
+        )
+            .into());
+    }
+
+    let query = Arc::new(query);
 
-    super::stream::websocket_db_stream(upgrade, state.repo.clone(), &["emails"], move |repo| {
-        let account_id = account_id.0;
-        let query = query.clone();
-        async move { repo.get_emails(account_id, &query).await }
-    })
+    Ok(super::stream::websocket_db_stream(
+        upgrade,
+        state.stream_limits.open(account_id)?,
+        state.repo.clone(),
+        &["emails"],
+        move |repo| {
+            let query = query.clone();
+            async move { repo.get_email_summaries(account_id, &query).await }
+        },
+    ))
 }
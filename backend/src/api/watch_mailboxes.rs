@@ -1,5 +1,6 @@
 use super::ApiState;
 use crate::jmap_account::AccountId;
+use crate::util::http_error::HttpResult;
 use axum::extract;
 use axum::response::IntoResponse;
 
@@ -7,12 +8,13 @@ pub async fn watch_mailboxes(
     account_id: extract::Path<AccountId>,
     state: extract::State<ApiState>,
     upgrade: extract::ws::WebSocketUpgrade,
-) -> impl IntoResponse {
+) -> HttpResult<impl IntoResponse> {
     let account_id = account_id.0;
-    super::stream::websocket_db_stream(
+    Ok(super::stream::websocket_db_stream(
         upgrade,
+        state.stream_limits.open(account_id)?,
         state.repo.clone(),
         &["mailboxes"],
         move |repo| async move { repo.get_mailboxes(account_id).await },
-    )
+    ))
 }
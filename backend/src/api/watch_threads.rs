@@ -1,4 +1,5 @@
 use crate::jmap_account::AccountId;
+use crate::util::http_error::HttpResult;
 use axum::extract;
 use axum::response::IntoResponse;
 use serde::Deserialize;
@@ -6,6 +7,7 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct ThreadQuery {
     mailbox_id: String,
+    /// Page size, clamped to [`super::ApiState::max_list_limit`].
     limit: usize,
     offset: usize,
 }
@@ -19,13 +21,21 @@ pub async fn watch_threads(
         offset,
     }): extract::Query<ThreadQuery>,
     upgrade: extract::ws::WebSocketUpgrade,
-) -> impl IntoResponse {
-    super::stream::websocket_db_stream(upgrade, state.repo.clone(), &["emails"], move |repo| {
-        let account_id = account_id.0;
-        let mailbox_id = mailbox_id.clone();
-        async move {
-            repo.get_threads(account_id, &mailbox_id, offset, limit)
-                .await
-        }
-    })
+) -> HttpResult<impl IntoResponse> {
+    let (limit, offset) = super::stream::check_pagination(limit, offset, state.max_list_limit)?;
+
+    Ok(super::stream::websocket_db_stream(
+        upgrade,
+        state.stream_limits.open(account_id.0)?,
+        state.repo.clone(),
+        &["emails"],
+        move |repo| {
+            let account_id = account_id.0;
+            let mailbox_id = mailbox_id.clone();
+            async move {
+                repo.get_threads(account_id, &mailbox_id, offset, limit)
+                    .await
+            }
+        },
+    ))
 }
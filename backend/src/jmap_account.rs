@@ -2,16 +2,43 @@ use crate::repo::Repository;
 use anyhow::Context;
 use derive_more::Debug;
 use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Deserialize, PartialEq, Eq, Debug)]
 pub struct Account {
     pub server_url: String,
     pub credentials: Credentials,
     pub name: String,
+    /// The JMAP account to sync, for sessions exposing more than one. Defaults to the
+    /// session's primary mail account.
+    #[serde(default)]
+    pub jmap_account_id: Option<String>,
+}
+
+impl Account {
+    /// Whether syncing `other` means syncing the same thing. Credentials are left out: the
+    /// `JmapApi` keeps its own copy and refreshes it as needed, saving the result along the way,
+    /// and apart from that they only change on startup, from the accounts file.
+    pub fn same_sync_target(&self, other: &Self) -> bool {
+        self.server_url == other.server_url
+            && self.name == other.name
+            && self.jmap_account_id == other.jmap_account_id
+    }
 }
 
 pub type AccountId = i64;
 
+/// What clients get to see of an account, i.e. everything but its credentials.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSummary {
+    pub id: AccountId,
+    pub name: String,
+    pub server_url: String,
+    pub jmap_account_id: Option<String>,
+    pub last_synced_at: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum Credentials {
     Basic {
@@ -20,14 +47,107 @@ pub enum Credentials {
         #[debug(skip)]
         password: String,
     },
+    /// An OAuth2 access token, or any other token the server takes as `Bearer`.
+    Bearer {
+        #[debug(skip)]
+        token: String,
+    },
+    /// An OAuth2 access token that's refreshed from `token_endpoint` once the server rejects it.
+    RefreshToken {
+        #[debug(skip)]
+        access_token: String,
+        #[debug(skip)]
+        refresh_token: String,
+        token_endpoint: String,
+        client_id: String,
+    },
 }
 
-impl Into<jmap_client::client::Credentials> for Credentials {
-    fn into(self) -> jmap_client::client::Credentials {
-        match self {
-            Credentials::Basic { username, password } => {
-                jmap_client::client::Credentials::basic(&username, &password)
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// Set when the server rotates refresh tokens.
+    refresh_token: Option<String>,
+}
+
+impl Credentials {
+    /// Trades the refresh token for a new access token with an OAuth2 refresh-token grant, or
+    /// `None` for credentials that can't be refreshed.
+    pub async fn refresh(&self, http_client: &reqwest::Client) -> anyhow::Result<Option<Self>> {
+        let Self::RefreshToken {
+            refresh_token,
+            token_endpoint,
+            client_id,
+            ..
+        } = self
+        else {
+            return Ok(None);
+        };
+
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "refresh_token")
+            .append_pair("refresh_token", refresh_token)
+            .append_pair("client_id", client_id)
+            .finish();
+
+        let resp = http_client
+            .post(token_endpoint)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .header(reqwest::header::ACCEPT, "application/json")
+            .body(body)
+            .send()
+            .await
+            .context("Token request failed")?
+            .error_for_status()
+            .context("Token refresh rejected by the token endpoint")?
+            .bytes()
+            .await
+            .context("Error reading token response")?;
+
+        let resp: TokenResponse =
+            serde_json::from_slice(&resp).context("Invalid token response")?;
+
+        Ok(Some(Self::RefreshToken {
+            access_token: resp.access_token,
+            refresh_token: resp.refresh_token.unwrap_or_else(|| refresh_token.clone()),
+            token_endpoint: token_endpoint.clone(),
+            client_id: client_id.clone(),
+        }))
+    }
+
+    /// `stored` when both are refreshable tokens from the same endpoint and client, i.e. when
+    /// `stored` is likely these credentials after a refresh, or else these credentials.
+    pub fn keep_refreshed(self, stored: &Self) -> Self {
+        match (&self, stored) {
+            (
+                Self::RefreshToken {
+                    token_endpoint,
+                    client_id,
+                    ..
+                },
+                Self::RefreshToken {
+                    token_endpoint: stored_endpoint,
+                    client_id: stored_client_id,
+                    ..
+                },
+            ) if token_endpoint == stored_endpoint && client_id == stored_client_id => {
+                stored.clone()
             }
+            _ => self,
+        }
+    }
+}
+
+impl From<Credentials> for jmap_client::client::Credentials {
+    fn from(credentials: Credentials) -> Self {
+        match credentials {
+            Credentials::Basic { username, password } => Self::basic(&username, &password),
+            // Tokens read from files often come with a trailing newline
+            Credentials::Bearer { token } => Self::bearer(token.trim()),
+            Credentials::RefreshToken { access_token, .. } => Self::bearer(access_token.trim()),
         }
     }
 }
@@ -35,13 +155,30 @@ impl Into<jmap_client::client::Credentials> for Credentials {
 pub trait AccountRepositoryExt {
     async fn get_account(&self, account_id: AccountId) -> anyhow::Result<Option<Account>>;
     async fn list_accounts(&self) -> anyhow::Result<Vec<(AccountId, Account)>>;
+    async fn list_account_summaries(&self) -> anyhow::Result<Vec<AccountSummary>>;
     async fn add_account(&self, account: &Account) -> anyhow::Result<AccountId>;
+    async fn update_account(&self, account_id: AccountId, account: &Account) -> anyhow::Result<()>;
+    async fn update_account_credentials(
+        &self,
+        account_id: AccountId,
+        credentials: &Credentials,
+    ) -> anyhow::Result<()>;
+    async fn mark_account_deleting(&self, account_id: AccountId) -> anyhow::Result<bool>;
+    async fn list_deleting_accounts(&self) -> anyhow::Result<Vec<AccountId>>;
+    async fn delete_account(&self, account_id: AccountId) -> anyhow::Result<u64>;
+}
+
+/// Whether adding or renaming an account failed because another account has the name.
+pub fn is_duplicate_account_name(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<sqlx::Error>()
+        .and_then(|e| e.as_database_error())
+        .is_some_and(|e| e.is_unique_violation())
 }
 
 impl AccountRepositoryExt for Repository {
     async fn get_account(&self, account_id: AccountId) -> anyhow::Result<Option<Account>> {
         let record = sqlx::query!(
-            "SELECT url, credentials, name FROM accounts WHERE id = ?",
+            "SELECT url, credentials, name, jmap_account_id FROM accounts WHERE id = ? AND NOT deleting",
             account_id
         )
         .fetch_optional(self.pool())
@@ -54,6 +191,7 @@ impl AccountRepositoryExt for Repository {
                 credentials: serde_json::from_str(&rec.credentials)
                     .context("Error deserializing account credentials")?,
                 name: rec.name,
+                jmap_account_id: rec.jmap_account_id,
             }))
         } else {
             Ok(None)
@@ -61,10 +199,12 @@ impl AccountRepositoryExt for Repository {
     }
 
     async fn list_accounts(&self) -> anyhow::Result<Vec<(AccountId, Account)>> {
-        let records = sqlx::query!("SELECT id, url, credentials, name  FROM accounts")
-            .fetch_all(self.pool())
-            .await
-            .context("Error querying accounts")?;
+        let records = sqlx::query!(
+            "SELECT id, url, credentials, name, jmap_account_id FROM accounts WHERE NOT deleting"
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying accounts")?;
 
         Ok(records
             .into_iter()
@@ -77,25 +217,389 @@ impl AccountRepositoryExt for Repository {
                             .context("Error deserializing account credentials")
                             .unwrap(),
                         name: rec.name,
+                        jmap_account_id: rec.jmap_account_id,
                     },
                 )
             })
             .collect())
     }
 
+    async fn list_account_summaries(&self) -> anyhow::Result<Vec<AccountSummary>> {
+        sqlx::query_as!(
+            AccountSummary,
+            r#"SELECT id AS "id!", name, url AS server_url, jmap_account_id, last_synced_at
+               FROM accounts WHERE NOT deleting ORDER BY id"#
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying account summaries")
+    }
+
     async fn add_account(&self, account: &Account) -> anyhow::Result<AccountId> {
         let credentials = serde_json::to_string(&account.credentials)
             .context("Error serializing account credentials")?;
 
-        Ok(sqlx::query!(
-            "INSERT INTO accounts (url, credentials, name) VALUES (?, ?, ?) RETURNING id",
+        let account_id = sqlx::query!(
+            "INSERT INTO accounts (url, credentials, name, jmap_account_id) VALUES (?, ?, ?, ?) RETURNING id",
             account.server_url,
             credentials,
-            account.name
+            account.name,
+            account.jmap_account_id
         )
         .fetch_one(self.pool())
         .await
         .context("Error inserting account")?
-        .id)
+        .id;
+
+        // Starts syncing the account
+        self.notify_changes(&["accounts"]);
+        Ok(account_id)
+    }
+
+    async fn update_account(&self, account_id: AccountId, account: &Account) -> anyhow::Result<()> {
+        let credentials = serde_json::to_string(&account.credentials)
+            .context("Error serializing account credentials")?;
+
+        let mut tx = self.pool().begin().await?;
+
+        let previous_jmap_account_id = sqlx::query_scalar!(
+            "SELECT jmap_account_id FROM accounts WHERE id = ?",
+            account_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Error querying account")?
+        .flatten();
+
+        // What's synced so far belongs to the other JMAP account, so start over
+        let switched = previous_jmap_account_id != account.jmap_account_id;
+        if switched {
+            delete_synced_data(&mut tx, account_id).await?;
+        }
+
+        let result = sqlx::query!(
+            "UPDATE accounts SET url = ?, credentials = ?, name = ?, jmap_account_id = ?,
+                mailboxes_sync_state = CASE WHEN ? THEN NULL ELSE mailboxes_sync_state END
+             WHERE id = ?",
+            account.server_url,
+            credentials,
+            account.name,
+            account.jmap_account_id,
+            switched,
+            account_id
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Error updating account")?;
+
+        tx.commit().await?;
+
+        if switched {
+            self.notify_changes(&["accounts", "mailboxes", "emails", "mailbox_emails"]);
+        } else {
+            self.notify_changes_with(result, &["accounts"]);
+        }
+        Ok(())
+    }
+
+    /// Saves credentials that were refreshed while syncing. Unlike `update_account`, this doesn't
+    /// announce an account change. The sync that refreshed them already uses them, and other
+    /// account changes don't restart it over credentials alone, see `Account::same_sync_target`.
+    async fn update_account_credentials(
+        &self,
+        account_id: AccountId,
+        credentials: &Credentials,
+    ) -> anyhow::Result<()> {
+        let credentials =
+            serde_json::to_string(credentials).context("Error serializing account credentials")?;
+
+        sqlx::query!(
+            "UPDATE accounts SET credentials = ? WHERE id = ?",
+            credentials,
+            account_id
+        )
+        .execute(self.pool())
+        .await
+        .context("Error updating account credentials")?;
+
+        Ok(())
+    }
+
+    /// Flags the account for deletion, which hides it from listings and lookups until
+    /// [`Self::delete_account`] purges it. Returns whether the account existed.
+    async fn mark_account_deleting(&self, account_id: AccountId) -> anyhow::Result<bool> {
+        let marked = sqlx::query!(
+            "UPDATE accounts SET deleting = true WHERE id = ? AND NOT deleting",
+            account_id
+        )
+        .execute(self.pool())
+        .await
+        .context("Error marking account as deleting")?
+        .rows_affected()
+            > 0;
+
+        if marked {
+            self.notify_changes(&["accounts"]);
+        }
+        Ok(marked)
+    }
+
+    /// Accounts whose purge was interrupted, e.g. by a restart.
+    async fn list_deleting_accounts(&self) -> anyhow::Result<Vec<AccountId>> {
+        sqlx::query_scalar!(r#"SELECT id AS "id!" FROM accounts WHERE deleting"#)
+            .fetch_all(self.pool())
+            .await
+            .context("Error querying deleting accounts")
+    }
+
+    /// Deletes the account and everything stored for it, returning the number of rows removed.
+    async fn delete_account(&self, account_id: AccountId) -> anyhow::Result<u64> {
+        let mut tx = self.pool().begin().await?;
+        let mut deleted = delete_synced_data(&mut tx, account_id).await?;
+
+        deleted += sqlx::query!("DELETE FROM notify_prefs WHERE account_id = ?", account_id)
+            .execute(&mut *tx)
+            .await
+            .context("Error deleting notification preferences")?
+            .rows_affected();
+
+        deleted += sqlx::query!(
+            "DELETE FROM account_settings WHERE account_id = ?",
+            account_id
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Error deleting account settings")?
+        .rows_affected();
+
+        deleted += sqlx::query!("DELETE FROM accounts WHERE id = ?", account_id)
+            .execute(&mut *tx)
+            .await
+            .context("Error deleting account")?
+            .rows_affected();
+
+        tx.commit().await?;
+
+        if deleted > 0 {
+            self.notify_changes(&["accounts", "mailboxes", "emails", "mailbox_emails"]);
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// Deletes everything synced from the server for an account, leaving the account itself.
+async fn delete_synced_data(
+    conn: &mut SqliteConnection,
+    account_id: AccountId,
+) -> anyhow::Result<u64> {
+    let mut deleted = 0;
+
+    // Delete dependants explicitly (children first) rather than relying on one huge cascade
+    deleted += sqlx::query!(
+        "DELETE FROM mailbox_emails WHERE account_id = ?",
+        account_id
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Error deleting mailbox emails")?
+    .rows_affected();
+
+    deleted += sqlx::query!("DELETE FROM email_headers WHERE account_id = ?", account_id)
+        .execute(&mut *conn)
+        .await
+        .context("Error deleting email headers")?
+        .rows_affected();
+
+    deleted += sqlx::query!("DELETE FROM emails WHERE account_id = ?", account_id)
+        .execute(&mut *conn)
+        .await
+        .context("Error deleting emails")?
+        .rows_affected();
+
+    deleted += sqlx::query!("DELETE FROM mailboxes WHERE account_id = ?", account_id)
+        .execute(&mut *conn)
+        .await
+        .context("Error deleting mailboxes")?
+        .rows_affected();
+
+    deleted += sqlx::query!("DELETE FROM blobs WHERE account_id = ?", account_id)
+        .execute(&mut *conn)
+        .await
+        .context("Error deleting blobs")?
+        .rows_affected();
+
+    deleted += sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE account_id = ?",
+        account_id
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Error deleting idempotency keys")?
+    .rows_affected();
+
+    deleted += sqlx::query!("DELETE FROM identities WHERE account_id = ?", account_id)
+        .execute(&mut *conn)
+        .await
+        .context("Error deleting identities")?
+        .rows_affected();
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{
+        AccountSettings, Blob, MailboxDefaultQuery, NotifyPrefs, RawHeader, Repository, testing,
+    };
+    use jmap_client::email::Email;
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// Tables holding rows of a single account, all of which go with it.
+    const ACCOUNT_TABLES: [&str; 11] = [
+        "emails",
+        "mailbox_emails",
+        "mailboxes",
+        "blobs",
+        "email_headers",
+        "idempotency_keys",
+        "identities",
+        "notify_prefs",
+        "account_settings",
+        "trusted_senders",
+        "mailbox_prefs",
+    ];
+
+    /// Stores a row in every one of [`ACCOUNT_TABLES`] for the account.
+    async fn seed_account_tables(repo: &Repository, account_id: AccountId) {
+        let inbox = json!({"id": "inbox", "name": "Inbox"}).to_string();
+        repo.update_mailboxes(
+            account_id,
+            "s1",
+            vec![serde_json::from_str(&inbox).unwrap()],
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        let email: Email = serde_json::from_value(json!({
+            "id": "e1",
+            "mailboxIds": {"inbox": true},
+            "receivedAt": "2025-01-01T00:00:00Z",
+        }))
+        .unwrap();
+        repo.update_emails(account_id, &[email]).await.unwrap();
+
+        let header = RawHeader {
+            name: "Subject".to_string(),
+            value: "Hi".to_string(),
+        };
+        repo.save_email_headers(account_id, "e1", &[header])
+            .await
+            .unwrap();
+
+        let blob = Blob {
+            name: None,
+            mime_type: None,
+            data: b"data".to_vec(),
+        };
+        repo.save_blob(account_id, "b1", &blob).await.unwrap();
+
+        let ttl = Duration::from_secs(60);
+        repo.save_idempotent_response(account_id, "/outbox", "k1", "{}", ttl)
+            .await
+            .unwrap();
+
+        sqlx::query("INSERT INTO identities (account_id, id, email, name) VALUES (?, 'i1', 'me@example.com', 'Me')")
+            .bind(account_id)
+            .execute(repo.pool())
+            .await
+            .unwrap();
+
+        repo.set_notify_prefs(account_id, &NotifyPrefs::default())
+            .await
+            .unwrap();
+        repo.set_account_settings(account_id, &AccountSettings::default())
+            .await
+            .unwrap();
+        repo.add_trusted_sender(account_id, "*@example.com")
+            .await
+            .unwrap();
+        repo.set_mailbox_default_query(account_id, "inbox", &MailboxDefaultQuery::default())
+            .await
+            .unwrap();
+    }
+
+    async fn count_rows(repo: &Repository, table: &str, account_id: AccountId) -> i64 {
+        sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {table} WHERE account_id = ?"
+        ))
+        .bind(account_id)
+        .fetch_one(repo.pool())
+        .await
+        .unwrap()
+    }
+
+    fn account(name: &str) -> Account {
+        Account {
+            server_url: "https://jmap.example.com".to_string(),
+            credentials: Credentials::Bearer {
+                token: "token".to_string(),
+            },
+            name: name.to_string(),
+            jmap_account_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn account_names_are_unique() {
+        let repo = testing::repository().await;
+        repo.add_account(&account("a")).await.unwrap();
+
+        let e = repo.add_account(&account("a")).await.unwrap_err();
+        assert!(is_duplicate_account_name(&e));
+        assert!(repo.add_account(&account("b")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn deleting_accounts_are_hidden_until_purged() {
+        let repo = testing::repository().await;
+        let account_id = repo.add_account(&account("a")).await.unwrap();
+
+        assert!(repo.mark_account_deleting(account_id).await.unwrap());
+        assert!(!repo.mark_account_deleting(account_id).await.unwrap());
+        assert!(repo.get_account(account_id).await.unwrap().is_none());
+        assert!(repo.list_accounts().await.unwrap().is_empty());
+        assert!(repo.list_account_summaries().await.unwrap().is_empty());
+        assert_eq!(repo.list_deleting_accounts().await.unwrap(), [account_id]);
+
+        // The name is free again while the old account is purged
+        let readded = repo.add_account(&account("a")).await.unwrap();
+
+        assert!(repo.delete_account(account_id).await.unwrap() > 0);
+        assert!(repo.list_deleting_accounts().await.unwrap().is_empty());
+        assert_eq!(repo.list_accounts().await.unwrap()[0].0, readded);
+    }
+
+    #[tokio::test]
+    async fn deleting_an_account_empties_its_tables() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        let other_id = testing::add_account(&repo, "b").await;
+        seed_account_tables(&repo, account_id).await;
+        seed_account_tables(&repo, other_id).await;
+
+        for table in ACCOUNT_TABLES {
+            assert_eq!(count_rows(&repo, table, account_id).await, 1, "{table}");
+        }
+
+        assert!(repo.delete_account(account_id).await.unwrap() > 0);
+
+        for table in ACCOUNT_TABLES {
+            assert_eq!(count_rows(&repo, table, account_id).await, 0, "{table}");
+            assert_eq!(count_rows(&repo, table, other_id).await, 1, "{table}");
+        }
     }
 }
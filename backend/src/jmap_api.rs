@@ -1,34 +1,62 @@
-use crate::repo::Blob;
+use crate::jmap_account::Credentials as AccountCredentials;
 use crate::util::network::NetworkAvailability;
+use crate::util::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::util::tasks::AbortHandleExt;
 use anyhow::{Context, bail, format_err};
 use derive_more::Debug as DeriveDebug;
-use futures::StreamExt;
-use futures::future::{Either, select};
+use futures::future::{BoxFuture, Either, select};
+use futures::{Stream, StreamExt};
+use itertools::Itertools;
+use jmap_client::blob::upload::UploadResponse;
 use jmap_client::client::{Client, ClientBuilder, Credentials};
 use jmap_client::client_ws::WebSocketMessage;
+use jmap_client::core::error::{JMAPError, MethodError, MethodErrorType, ProblemType};
 use jmap_client::core::query::{Comparator, Filter, QueryResponse};
 use jmap_client::core::request::Request;
 use jmap_client::core::response::{
     EmailChangesResponse, EmailGetResponse, MailboxChangesResponse, MailboxGetResponse,
-    TaggedMethodResponse,
+    TaggedMethodResponse, ThreadGetResponse,
 };
-use jmap_client::email::Email;
-use jmap_client::{DataType, PushObject, email};
+use jmap_client::core::session::Session;
+use jmap_client::email::{EmailAddress, EmailBodyPart};
+use jmap_client::event_source::PushNotification;
+use jmap_client::event_source::parser::EventParser;
+use jmap_client::identity::Identity;
+use jmap_client::{DataType, PushObject, Set, URI, email};
+use parking_lot::Mutex;
+use reqwest::{StatusCode, header};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
 use std::num::NonZeroUsize;
-use std::pin::pin;
+use std::path::Path;
+use std::pin::{Pin, pin};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::sync::{Notify, broadcast, mpsc, oneshot, watch};
 use tokio::task::JoinSet;
-use tokio::time::sleep_until;
+use tokio::time::{MissedTickBehavior, sleep_until};
+use tokio_util::io::ReaderStream;
 use tracing::{Instrument, instrument};
 use url::Url;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
 pub enum EmailSortColumn {
     Date,
+    SentAt,
+    Subject,
+    Size,
+    From,
+}
+
+impl EmailSortColumn {
+    pub const ALL: [Self; 5] = [
+        Self::Date,
+        Self::SentAt,
+        Self::Subject,
+        Self::Size,
+        Self::From,
+    ];
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -40,12 +68,120 @@ pub struct EmailSort {
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct EmailQuery {
     pub anchor_id: Option<String>,
+    /// Where the window starts relative to the anchor, e.g. -10 for ten emails before it.
+    #[serde(default)]
+    pub anchor_offset: Option<i64>,
+    /// Where the window starts in the results, counting from the end when negative. Can't be
+    /// combined with an anchor.
+    #[serde(default)]
+    pub position: Option<i64>,
     pub mailbox_id: Option<String>,
     pub search_keyword: Option<String>,
     pub sorts: Vec<EmailSort>,
     pub limit: Option<NonZeroUsize>,
 }
 
+/// An email being composed. The sender comes from the identity it's saved or sent as.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DraftEmail {
+    pub to: Vec<EmailAddress>,
+    pub cc: Vec<EmailAddress>,
+    pub bcc: Vec<EmailAddress>,
+    pub subject: Option<String>,
+    pub text_body: Option<String>,
+    pub html_body: Option<String>,
+    pub in_reply_to: Vec<String>,
+    pub references: Vec<String>,
+    /// Extra headers by name, e.g. `X-Mailer`. See [`crate::util::custom_headers`].
+    pub headers: BTreeMap<String, String>,
+}
+
+impl DraftEmail {
+    fn fill(
+        self,
+        email: &mut email::Email<Set>,
+        from: EmailAddress,
+        mailbox_id: &str,
+        keywords: Vec<String>,
+    ) {
+        email
+            .mailbox_ids([mailbox_id])
+            .keywords(keywords)
+            .from([from])
+            .to(self.to)
+            .cc(self.cc)
+            .bcc(self.bcc)
+            .subject(self.subject.unwrap_or_default());
+
+        if !self.in_reply_to.is_empty() {
+            email.in_reply_to(self.in_reply_to);
+        }
+
+        if !self.references.is_empty() {
+            email.references(self.references);
+        }
+
+        for (name, value) in self.headers {
+            email.header(
+                email::Header::as_text(name, false),
+                email::HeaderValue::AsText(value),
+            );
+        }
+
+        if let Some(text) = self.text_body {
+            email.body_value("text".to_string(), text).text_body(
+                EmailBodyPart::new()
+                    .part_id("text")
+                    .content_type("text/plain"),
+            );
+        }
+
+        if let Some(html) = self.html_body {
+            email.body_value("html".to_string(), html).html_body(
+                EmailBodyPart::new()
+                    .part_id("html")
+                    .content_type("text/html"),
+            );
+        }
+    }
+}
+
+/// An error the server returned for a method call, as opposed to a transport failure.
+#[derive(Debug)]
+pub struct JmapMethodError {
+    pub kind: MethodErrorType,
+    pub description: String,
+}
+
+impl JmapMethodError {
+    /// Whether `e`, or the error it wraps, is a method error of the given kind.
+    pub fn is_kind(e: &anyhow::Error, kind: MethodErrorType) -> bool {
+        e.downcast_ref::<Self>().is_some_and(|e| e.kind == kind)
+    }
+}
+
+impl From<MethodError> for JmapMethodError {
+    fn from(e: MethodError) -> Self {
+        Self {
+            description: e.to_string(),
+            kind: e.p_type,
+        }
+    }
+}
+
+impl Display for JmapMethodError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "JMAP method error ({:?}): {}",
+            self.kind, self.description
+        )
+    }
+}
+
+impl std::error::Error for JmapMethodError {}
+
 type JmapRequestBuilder = Box<dyn FnOnce(&mut Request<'_>) + Send + Sync>;
 
 type JmapRequestCallback = oneshot::Sender<anyhow::Result<TaggedMethodResponse>>;
@@ -53,7 +189,10 @@ type JmapRequestCallback = oneshot::Sender<anyhow::Result<TaggedMethodResponse>>
 #[derive(DeriveDebug)]
 pub enum ClientState {
     Disconnected {
+        #[allow(dead_code)]
         last_error: Option<anyhow::Error>,
+        /// Whether the server rejected the credentials, as opposed to not being reachable.
+        auth_failed: bool,
         #[debug(skip)]
         delay_connect_until: Option<Instant>,
     },
@@ -61,20 +200,142 @@ pub enum ClientState {
     Connected(#[debug(skip)] Arc<Client>),
 }
 
+/// The connection to the JMAP server, as reported to clients.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state")]
+pub enum ConnectionStatus {
+    Disconnected {
+        /// Why the last connection attempt, or the connection, failed.
+        details: Option<String>,
+        /// Whether the server rejected the credentials, which reconnecting won't fix.
+        #[serde(rename = "authFailed")]
+        auth_failed: bool,
+    },
+    Connecting,
+    Connected,
+}
+
+impl From<&ClientState> for ConnectionStatus {
+    fn from(state: &ClientState) -> Self {
+        match state {
+            ClientState::Disconnected {
+                last_error,
+                auth_failed,
+                ..
+            } => Self::Disconnected {
+                details: last_error.as_ref().map(|e| format!("{e:#}")),
+                auth_failed: *auth_failed,
+            },
+            ClientState::Connnecting => Self::Connecting,
+            ClientState::Connected(_) => Self::Connected,
+        }
+    }
+}
+
 pub struct JmapApi {
     client_state: watch::Receiver<ClientState>,
+    /// Cuts a reconnect backoff short.
+    reconnect: Arc<Notify>,
     request_sender: mpsc::Sender<(JmapRequestBuilder, JmapRequestCallback)>,
     notification_receiver: broadcast::Receiver<Arc<PushObject>>,
-    tasks: JoinSet<()>,
+    identities: Mutex<Option<(Instant, Vec<Identity>)>>,
+    rate_limiter: RateLimiter,
+    /// Kept for requests that bypass jmap-client, e.g. streaming uploads. Replaced when an
+    /// access token gets refreshed.
+    credentials: Arc<Mutex<AccountCredentials>>,
+    save_credentials: SaveCredentials,
+    /// For requests that bypass jmap-client, and for refreshing access tokens.
+    http_client: reqwest::Client,
+    _tasks: JoinSet<()>,
+}
+
+/// The outcome of an `Email/set` over several emails, which the server applies one by one.
+#[derive(Debug, Default, Serialize)]
+pub struct BulkSetResult {
+    pub updated: Vec<String>,
+    pub failed: Vec<BulkSetFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkSetFailure {
+    pub id: String,
+    /// The server's `SetError`, for showing.
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JmapAccountInfo {
+    pub id: String,
+    pub name: String,
+    pub is_personal: bool,
+    pub is_read_only: bool,
+    /// Whether this is the session's primary mail account, i.e. the one synced by default.
+    pub is_primary: bool,
+    /// Whether this is the account being synced.
+    pub is_selected: bool,
 }
 
+/// What the sync stores of an email: everything lists, search and notifications read, but no
+/// body values or body structure. Bodies are fetched when an email is opened, one at a time,
+/// so a batch of large emails can't blow up a single response.
+pub const SUMMARY_PROPERTIES: &[email::Property] = &[
+    email::Property::Id,
+    email::Property::BlobId,
+    email::Property::ThreadId,
+    email::Property::MailboxIds,
+    email::Property::Keywords,
+    email::Property::Size,
+    email::Property::ReceivedAt,
+    email::Property::MessageId,
+    email::Property::InReplyTo,
+    email::Property::References,
+    email::Property::Sender,
+    email::Property::From,
+    email::Property::To,
+    email::Property::Cc,
+    email::Property::Bcc,
+    email::Property::ReplyTo,
+    email::Property::Subject,
+    email::Property::SentAt,
+    // Only the part descriptors, which the detail view uses to link to each part
+    email::Property::TextBody,
+    email::Property::HtmlBody,
+    email::Property::Attachments,
+    email::Property::HasAttachment,
+    email::Property::Preview,
+];
+
+/// What's fetched of an opened email when the client doesn't ask for anything in particular:
+/// what [`SUMMARY_PROPERTIES`] leaves out, for one email at a time.
+pub const DETAIL_PROPERTIES: &[email::Property] = &[
+    email::Property::Id,
+    email::Property::BodyValues,
+    email::Property::Attachments,
+    email::Property::BodyStructure,
+];
+
+/// How long a fetched identity list is reused before asking the server again.
+const IDENTITIES_CACHE_TTL: Duration = Duration::from_secs(60);
+
 impl JmapApi {
-    #[instrument(skip(credentials, network_availability), level = "debug")]
+    /// `save_credentials` is called with the new credentials whenever an access token is
+    /// refreshed, so they can be persisted.
+    #[instrument(
+        skip(credentials, network_availability, save_credentials),
+        level = "debug"
+    )]
     pub fn new(
         server_url: Url,
-        credentials: impl Into<Credentials> + Clone + Send + Sync + 'static,
+        credentials: AccountCredentials,
+        jmap_account_id: Option<String>,
         network_availability: watch::Receiver<NetworkAvailability>,
+        rate_limit: RateLimitConfig,
+        reconnect_config: ReconnectConfig,
+        save_credentials: SaveCredentials,
     ) -> Self {
+        let credentials = Arc::new(Mutex::new(credentials));
+        let http_client = reqwest::Client::new();
         let (request_sender, mut pending_requests_rx) =
             mpsc::channel::<(JmapRequestBuilder, JmapRequestCallback)>(100);
         let (notification_sender, notification_receiver) =
@@ -82,18 +343,29 @@ impl JmapApi {
 
         let (client_state_tx, client_state) = watch::channel(ClientState::Disconnected {
             last_error: None,
+            auth_failed: false,
             delay_connect_until: None,
         });
 
         let mut tasks = JoinSet::new();
+        let reconnect = Arc::new(Notify::new());
 
         // Establish initial connection
         tasks.spawn({
             let mut network_availability = network_availability.clone();
+            let reconnect = reconnect.clone();
             let span = tracing::info_span!("jmap_connect", server_url = server_url.as_str());
+            let credentials = credentials.clone();
+            let http_client = http_client.clone();
+            let save_credentials = save_credentials.clone();
+            // Whether the last attempt used a freshly refreshed token, which isn't refreshed
+            // again should the server reject it too
+            let mut just_refreshed = false;
+            // Connection attempts that failed in a row, which the backoff grows with
+            let mut failures = 0;
 
             async move {
-                while network_availability.wait_for(|a| a.online).await.is_ok() {
+                loop {
                     let delay_connect_until = {
                         match &*client_state_tx.borrow() {
                             ClientState::Disconnected {
@@ -105,124 +377,170 @@ impl JmapApi {
                     };
 
                     if let Some(deadline) = delay_connect_until {
-                        sleep_until(deadline.into()).await;
+                        tokio::select! {
+                            _ = sleep_until(deadline.into()) => {}
+                            _ = reconnect.notified() => {
+                                tracing::info!("Reconnecting without waiting out the backoff");
+                            }
+                            _ = back_online(&mut network_availability) => {
+                                tracing::info!("Network is back, reconnecting");
+                            }
+                        }
                     };
 
                     let connect = async {
                         let _ = client_state_tx.send(ClientState::Connnecting);
+                        let credentials: Credentials = credentials.lock().clone().into();
 
-                        let client = ClientBuilder::new()
-                            .credentials(credentials.clone())
-                            .follow_redirects([server_url.host_str().unwrap_or_default()])
-                            .connect(server_url.as_str().trim_end_matches('/'))
-                            .await
-                            .context("Failed to connect to JMAP server")?;
+                        // jmap-client panics on credentials that can't go in a header
+                        header::HeaderValue::from_str(&authorization(&credentials)).context(
+                            "The credentials contain characters not allowed in a header",
+                        )?;
 
-                        let ws = client
-                            .connect_ws()
+                        let base_url = jmap_base_url(&server_url);
+                        let trusted_hosts = discover_session_hosts(&base_url).await;
+                        let mut client = ClientBuilder::new()
+                            .credentials(credentials)
+                            .follow_redirects(trusted_hosts.clone())
+                            .connect(base_url.trim_end_matches('/'))
                             .await
                             .context("Failed to connect to JMAP server")?;
 
-                        client
-                            .enable_push_ws(
-                                Some([DataType::Email, DataType::Core, DataType::Mailbox]),
-                                None::<&'static str>,
-                            )
-                            .await
-                            .context("Failed to enable ws push")?;
+                        // Requests go to the URLs the session names, which needn't be on the
+                        // configured host, so redirects among them are followed too
+                        let session = client.session();
+                        tracing::info!(
+                            api_url = session.api_url(),
+                            download_url = session.download_url(),
+                            upload_url = session.upload_url(),
+                            event_source_url = session.event_source_url(),
+                            "Discovered JMAP session"
+                        );
+                        let session_hosts = [
+                            session.api_url(),
+                            session.download_url(),
+                            session.upload_url(),
+                            session.event_source_url(),
+                        ]
+                        .into_iter()
+                        .filter_map(|url| Url::parse(url).ok()?.host_str().map(str::to_string))
+                        .collect::<Vec<_>>();
+                        client.set_follow_redirects(
+                            trusted_hosts.into_iter().chain(session_hosts).unique(),
+                        );
 
-                        anyhow::Ok((Arc::new(client), ws))
+                        if let Some(account_id) = &jmap_account_id {
+                            let session = client.session();
+                            if session.account(account_id).is_none() {
+                                bail!(
+                                    "JMAP account {account_id} not found, the session has: {}",
+                                    session.accounts().join(", ")
+                                );
+                            }
+                            client.set_default_account_id(account_id);
+                        }
+
+                        let client = Arc::new(client);
+                        let transport = PushTransport::establish(&client).await;
+                        anyhow::Ok((client, transport))
                     };
 
-                    let (client, mut ws) = match connect
+                    let (client, transport) = match connect
                         .await
                         .context("Failed to connect to JMAP server")
                     {
                         Ok(v) => {
-                            tracing::info!("Connected to JMAP server");
+                            tracing::info!(transport = v.1.name(), "Connected to JMAP server");
+                            failures = 0;
                             let _ = client_state_tx.send(ClientState::Connected(v.0.clone()));
                             v
                         }
 
+                        Err(e) if is_auth_failure(&e) => {
+                            tracing::error!(?e, "JMAP server rejected the credentials");
+                            let refreshed = if just_refreshed {
+                                None
+                            } else {
+                                refresh_credentials(&credentials, &http_client, &save_credentials)
+                                    .await
+                            };
+                            just_refreshed = refreshed.is_some();
+
+                            // Retry right away with a refreshed token. Otherwise, retrying won't
+                            // help until the credentials are changed, so there's no hurry.
+                            let _ = client_state_tx.send(ClientState::Disconnected {
+                                last_error: Some(e),
+                                auth_failed: true,
+                                delay_connect_until: refreshed
+                                    .is_none()
+                                    .then(|| Instant::now() + AUTH_FAILURE_RETRY_DELAY),
+                            });
+                            continue;
+                        }
+
                         Err(e) => {
-                            tracing::error!(?e, "Failed to connect");
+                            let delay = reconnect_config.delay(failures);
+                            failures += 1;
+                            tracing::error!(?e, ?delay, "Failed to connect");
+                            just_refreshed = false;
                             let _ = client_state_tx.send(ClientState::Disconnected {
                                 last_error: Some(e),
-                                delay_connect_until: Some(Instant::now() + Duration::from_secs(10)),
+                                auth_failed: false,
+                                delay_connect_until: Some(Instant::now() + delay),
                             });
                             continue;
                         }
                     };
+                    just_refreshed = false;
 
-                    // Handle websocket messages
-                    let mut callbacks: HashMap<String, JmapRequestCallback> = Default::default();
-
-                    loop {
-                        match select(pin!(ws.next()), pin!(pending_requests_rx.recv())).await {
-                            Either::Left((Some(Ok(WebSocketMessage::Response(res))), _)) => {
-                                if let Some(callback) =
-                                    res.request_id().and_then(|r| callbacks.remove(r))
-                                {
-                                    if let Some(res) = res.unwrap_method_responses().pop() {
-                                        let _ = callback.send(Ok(res));
-                                    } else {
-                                        let _ = callback.send(Err(format_err!(
-                                            "No method responses in tagged response"
-                                        )));
-                                    }
-                                } else {
-                                    tracing::warn!("Unable to find a callback for a response");
-                                }
-                            }
+                    let session = match transport {
+                        PushTransport::WebSocket(ws) => {
+                            run_ws_session(
+                                &client,
+                                ws,
+                                &mut pending_requests_rx,
+                                &notification_sender,
+                            )
+                            .await
+                        }
 
-                            Either::Left((
-                                Some(Ok(WebSocketMessage::PushNotification(push))),
-                                _,
-                            )) => {
-                                let _ = notification_sender.send(Arc::new(push));
-                            }
+                        PushTransport::EventSource => {
+                            let _pump = tokio::spawn(pump_event_source(
+                                client.clone(),
+                                http_client.clone(),
+                                credentials.clone(),
+                                save_credentials.clone(),
+                                notification_sender.clone(),
+                            ))
+                            .auto_abort();
+                            run_http_session(&client, &mut pending_requests_rx).await
+                        }
 
-                            Either::Left((Some(Err(e)), _)) => {
-                                tracing::error!(?e, "Error receiving WS message, reconnecting...");
-                                let _ = client_state_tx.send(ClientState::Disconnected {
-                                    last_error: Some(e.into()),
-                                    delay_connect_until: Some(
-                                        Instant::now() + Duration::from_secs(10),
-                                    ),
-                                });
-                                break;
-                            }
+                        PushTransport::Polling => {
+                            let _pump = tokio::spawn(poll_for_changes(
+                                client.default_account_id().to_string(),
+                                notification_sender.clone(),
+                            ))
+                            .auto_abort();
+                            run_http_session(&client, &mut pending_requests_rx).await
+                        }
+                    };
 
-                            Either::Left((None, _)) | Either::Right((None, _)) => {
-                                tracing::info!("WS stream or request channel closed, aborting...");
-                                return;
-                            }
+                    match session {
+                        Ok(()) => {
+                            tracing::info!("Request channel closed, aborting...");
+                            return;
+                        }
 
-                            Either::Right((Some((req_builder, callback)), _)) => {
-                                let mut req = client.build();
-                                req_builder(&mut req);
-                                match req.send_ws().await {
-                                    Ok(request_id) => {
-                                        callbacks.insert(request_id, callback);
-                                    }
-                                    Err(e) => {
-                                        tracing::error!(
-                                            ?e,
-                                            "Error sending WS message to JMAP server"
-                                        );
-                                        let e = Arc::new(e);
-                                        let _ = client_state_tx.send(ClientState::Disconnected {
-                                            last_error: Some(e.clone().into()),
-                                            delay_connect_until: Some(
-                                                Instant::now() + Duration::from_secs(10),
-                                            ),
-                                        });
-                                        let _ = callback
-                                            .send(Err(e).context("Error queueing ws request"));
-                                        break;
-                                    }
-                                };
-                            }
+                        Err(e) => {
+                            tracing::error!(?e, "JMAP session ended, reconnecting...");
+                            let _ = client_state_tx.send(ClientState::Disconnected {
+                                last_error: Some(e),
+                                auth_failed: false,
+                                delay_connect_until: Some(
+                                    Instant::now() + reconnect_config.delay(0),
+                                ),
+                            });
                         }
                     }
                 }
@@ -232,9 +550,15 @@ impl JmapApi {
 
         Self {
             client_state,
+            reconnect,
             request_sender,
             notification_receiver,
-            tasks,
+            identities: Default::default(),
+            rate_limiter: RateLimiter::new(rate_limit),
+            credentials,
+            save_credentials,
+            http_client,
+            _tasks: tasks,
         }
     }
 
@@ -242,14 +566,54 @@ impl JmapApi {
         self.notification_receiver.resubscribe()
     }
 
+    pub fn connection_status(&self) -> ConnectionStatus {
+        (&*self.client_state.borrow()).into()
+    }
+
+    /// Skips what's left of the backoff when disconnected, then waits up to `timeout` for the
+    /// connection attempt to succeed or fail.
+    pub async fn reconnect_now(&self, timeout: Duration) -> ConnectionStatus {
+        let mut state = self.client_state.clone();
+        if matches!(*state.borrow_and_update(), ClientState::Disconnected { .. }) {
+            self.reconnect.notify_one();
+
+            let attempt = async {
+                state
+                    .wait_for(|s| matches!(s, ClientState::Connnecting))
+                    .await?;
+                state
+                    .wait_for(|s| !matches!(s, ClientState::Connnecting))
+                    .await
+                    .map(|_| ())
+            };
+            let _ = tokio::time::timeout(timeout, attempt).await;
+        }
+
+        self.connection_status()
+    }
+
+    /// Waits up to `timeout` for the connection to be up, returning whether it is.
+    pub async fn wait_until_connected(&self, timeout: Duration) -> bool {
+        let mut state = self.client_state.clone();
+        tokio::time::timeout(
+            timeout,
+            state.wait_for(|s| matches!(s, ClientState::Connected(_))),
+        )
+        .await
+        .is_ok_and(|r| r.is_ok())
+    }
+
     pub fn subscribe_client_state(&self) -> watch::Receiver<ClientState> {
         self.client_state.clone()
     }
 
-    async fn send_ws_request(
+    async fn send_ws_request<T>(
         &self,
+        unwrap: fn(TaggedMethodResponse) -> jmap_client::Result<T>,
         req: impl FnOnce(&mut Request<'_>) + Send + Sync + 'static,
-    ) -> anyhow::Result<TaggedMethodResponse> {
+    ) -> anyhow::Result<T> {
+        self.rate_limiter.acquire().await;
+
         let (callback, resp_rx) = oneshot::channel();
 
         if self
@@ -261,32 +625,58 @@ impl JmapApi {
             bail!("Queueing request failed");
         }
 
-        Ok(resp_rx
+        let resp = resp_rx
             .await
             .context("Error receiving WS response")?
-            .into_iter()
-            .next()
-            .context("No response received")?)
+            .inspect_err(|e| {
+                if e.downcast_ref().is_some_and(is_rate_limited) {
+                    self.rate_limiter.back_off();
+                }
+            })?;
+
+        match unwrap(resp) {
+            Ok(resp) => {
+                self.rate_limiter.recover();
+                Ok(resp)
+            }
+
+            Err(e) => {
+                if is_rate_limited(&e) {
+                    self.rate_limiter.back_off();
+                }
+
+                match e {
+                    jmap_client::Error::Method(e) => Err(JmapMethodError::from(e).into()),
+                    e => Err(e.into()),
+                }
+            }
+        }
     }
 
+    /// Fetches the given mailboxes, or all of them for `None`.
     #[instrument(skip(self), ret, level = "debug")]
-    pub async fn query_mailboxes(&self) -> anyhow::Result<QueryResponse> {
-        self.send_ws_request(|r| {
-            r.query_mailbox();
+    pub async fn get_mailboxes(
+        &self,
+        ids: Option<Vec<String>>,
+    ) -> anyhow::Result<MailboxGetResponse> {
+        self.send_ws_request(TaggedMethodResponse::unwrap_get_mailbox, move |r| {
+            let req = r.get_mailbox();
+            if let Some(ids) = ids {
+                req.ids(ids);
+            }
         })
-        .await?
-        .unwrap_query_mailbox()
-        .context("Expecting mailbox query response")
+        .await
+        .context("Expecting mailbox get response")
     }
 
+    /// Fetches threads, each with the ids of all its emails, whichever mailboxes they're in.
     #[instrument(skip(self), ret, level = "debug")]
-    pub async fn get_mailboxes(&self, ids: Vec<String>) -> anyhow::Result<MailboxGetResponse> {
-        self.send_ws_request(move |r| {
-            r.get_mailbox().ids(ids);
+    pub async fn get_threads(&self, ids: Vec<String>) -> anyhow::Result<ThreadGetResponse> {
+        self.send_ws_request(TaggedMethodResponse::unwrap_get_thread, move |r| {
+            r.get_thread().ids(ids);
         })
-        .await?
-        .unwrap_get_mailbox()
-        .context("Expecting mailbox get response")
+        .await
+        .context("Expecting thread get response")
     }
 
     #[instrument(skip(self), ret, level = "debug")]
@@ -294,19 +684,35 @@ impl JmapApi {
         &self,
         since_state: String,
     ) -> anyhow::Result<MailboxChangesResponse> {
-        self.send_ws_request(move |r| {
+        self.send_ws_request(TaggedMethodResponse::unwrap_changes_mailbox, move |r| {
             r.changes_mailbox(since_state);
         })
-        .await?
-        .unwrap_changes_mailbox()
+        .await
         .context("Expecting mailbox changes response")
     }
 
     #[instrument(skip(self), ret, level = "debug")]
     pub async fn query_emails(&self, query: EmailQuery) -> anyhow::Result<QueryResponse> {
-        self.send_ws_request(move |req| {
+        anyhow::ensure!(
+            query.anchor_id.is_none() || query.position.is_none(),
+            "A query can't have both an anchor and a position"
+        );
+        let position = query
+            .position
+            .map(i32::try_from)
+            .transpose()
+            .context("Query position out of range")?;
+        let anchor_offset = query
+            .anchor_offset
+            .map(i32::try_from)
+            .transpose()
+            .context("Query anchor offset out of range")?;
+
+        self.send_ws_request(TaggedMethodResponse::unwrap_query_email, move |req| {
             let EmailQuery {
                 anchor_id,
+                anchor_offset: _,
+                position: _,
                 mailbox_id,
                 search_keyword,
                 sorts,
@@ -344,6 +750,18 @@ impl JmapApi {
                             EmailSortColumn::Date => {
                                 Comparator::new(email::query::Comparator::ReceivedAt)
                             }
+                            EmailSortColumn::SentAt => {
+                                Comparator::new(email::query::Comparator::SentAt)
+                            }
+                            EmailSortColumn::Subject => {
+                                Comparator::new(email::query::Comparator::Subject)
+                            }
+                            EmailSortColumn::Size => {
+                                Comparator::new(email::query::Comparator::Size)
+                            }
+                            EmailSortColumn::From => {
+                                Comparator::new(email::query::Comparator::From)
+                            }
                         };
 
                         if s.asc {
@@ -356,23 +774,27 @@ impl JmapApi {
                 query.sort(jmap_sorts);
             }
 
-            // Anchor
+            // Window
             if let Some(anchor_id) = anchor_id {
                 query.anchor(anchor_id);
+                if let Some(anchor_offset) = anchor_offset {
+                    query.anchor_offset(anchor_offset);
+                }
+            }
+            if let Some(position) = position {
+                query.position(position);
             }
         })
-        .await?
-        .unwrap_query_email()
+        .await
         .context("Expecting email query response")
     }
 
     #[instrument(skip(self), ret, level = "debug")]
     pub async fn email_changes(&self, since_state: String) -> anyhow::Result<EmailChangesResponse> {
-        self.send_ws_request(move |r| {
+        self.send_ws_request(TaggedMethodResponse::unwrap_changes_email, move |r| {
             r.changes_email(since_state);
         })
-        .await?
-        .unwrap_changes_email()
+        .await
         .context("Expecting email changes response")
     }
 
@@ -382,17 +804,328 @@ impl JmapApi {
         ids: Vec<String>,
         partial_properties: Option<Vec<email::Property>>,
     ) -> anyhow::Result<EmailGetResponse> {
-        self.send_ws_request(move |r| {
+        self.send_ws_request(TaggedMethodResponse::unwrap_get_email, move |r| {
             let req = r.get_email().ids(ids);
             if let Some(props) = partial_properties {
+                // Body values are only returned when asked for explicitly
+                if props.contains(&email::Property::BodyValues) {
+                    req.arguments().fetch_all_body_values(true);
+                }
                 req.properties(props);
             }
         })
-        .await?
-        .unwrap_get_email()
+        .await
         .context("Expecting email get response")
     }
 
+    /// Saves `draft` into the drafts mailbox and returns the new email's id.
+    pub async fn create_draft(
+        &self,
+        draft: DraftEmail,
+        from: EmailAddress,
+        drafts_mailbox_id: String,
+    ) -> anyhow::Result<String> {
+        self.append_email(
+            draft,
+            from,
+            drafts_mailbox_id,
+            vec!["$draft".to_string(), "$seen".to_string()],
+        )
+        .await
+    }
+
+    /// Creates an email from `draft` straight in `mailbox_id`, without sending it, and returns
+    /// its id.
+    #[instrument(skip(self), ret, level = "debug")]
+    pub async fn append_email(
+        &self,
+        draft: DraftEmail,
+        from: EmailAddress,
+        mailbox_id: String,
+        keywords: Vec<String>,
+    ) -> anyhow::Result<String> {
+        self.send_ws_request(TaggedMethodResponse::unwrap_set_email, move |r| {
+            draft.fill(
+                r.set_email().create_with_id("email"),
+                from,
+                &mailbox_id,
+                keywords,
+            );
+        })
+        .await
+        .context("Expecting email set response")?
+        .created("email")
+        .context("Error creating email")?
+        .id()
+        .map(str::to_string)
+        .context("Server returned no id for the email")
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    pub async fn destroy_emails(&self, ids: Vec<String>) -> anyhow::Result<()> {
+        let mut resp = self
+            .send_ws_request(TaggedMethodResponse::unwrap_set_email, {
+                let ids = ids.clone();
+                move |r| {
+                    r.set_email().destroy(ids);
+                }
+            })
+            .await
+            .context("Expecting email set response")?;
+
+        for id in &ids {
+            resp.destroyed(id)
+                .with_context(|| format!("Error destroying email {id}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the keywords mapped to `true` and clears those mapped to `false` on all of `ids`, in
+    /// a single `Email/set`. The server may refuse some of the emails, e.g. ones deleted in the
+    /// meantime, while updating the rest.
+    #[instrument(skip(self), level = "debug")]
+    pub async fn set_keywords(
+        &self,
+        ids: Vec<String>,
+        keywords: BTreeMap<String, bool>,
+    ) -> anyhow::Result<BulkSetResult> {
+        let mut resp = self
+            .send_ws_request(TaggedMethodResponse::unwrap_set_email, {
+                let ids = ids.clone();
+                move |r| {
+                    let set = r.set_email();
+                    // Keywords end up in a JSON pointer, where these two are special
+                    let escape = |k: &str| k.replace('~', "~0").replace('/', "~1");
+                    for id in ids {
+                        let update = set.update(id);
+                        for (keyword, value) in &keywords {
+                            update.keyword(&escape(keyword), *value);
+                        }
+                    }
+                }
+            })
+            .await
+            .context("Expecting email set response")?;
+
+        let mut result = BulkSetResult::default();
+        for id in ids {
+            match resp.updated(&id) {
+                Ok(_) => result.updated.push(id),
+                Err(e) => {
+                    tracing::warn!(?e, "Error setting keywords on email {id}");
+                    result.failed.push(BulkSetFailure {
+                        id,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Moves `id` from the mailbox `from` into `to`, leaving any other mailboxes it's in alone.
+    /// Without `from` it's moved out of all its mailboxes.
+    #[instrument(skip(self), level = "debug")]
+    pub async fn move_email(
+        &self,
+        id: String,
+        from: Option<String>,
+        to: String,
+    ) -> anyhow::Result<()> {
+        let mut resp = self
+            .send_ws_request(TaggedMethodResponse::unwrap_set_email, {
+                let id = id.clone();
+                move |r| {
+                    let update = r.set_email().update(id);
+                    match from {
+                        Some(from) => {
+                            update.mailbox_id(&from, false).mailbox_id(&to, true);
+                        }
+                        None => {
+                            update.mailbox_ids([to]);
+                        }
+                    }
+                }
+            })
+            .await
+            .context("Expecting email set response")?;
+
+        resp.updated(&id)
+            .with_context(|| format!("Error moving email {id}"))?;
+        Ok(())
+    }
+
+    /// Creates a mailbox and returns its id.
+    #[instrument(skip(self), ret, level = "debug")]
+    pub async fn create_mailbox(
+        &self,
+        name: String,
+        parent_id: Option<String>,
+    ) -> anyhow::Result<String> {
+        self.send_ws_request(TaggedMethodResponse::unwrap_set_mailbox, move |r| {
+            r.set_mailbox()
+                .create_with_id("mailbox")
+                .name(name)
+                .parent_id(parent_id);
+        })
+        .await
+        .context("Expecting mailbox set response")?
+        .created("mailbox")
+        .context("Error creating mailbox")?
+        .id()
+        .map(str::to_string)
+        .context("Server returned no id for the mailbox")
+    }
+
+    /// Imports an uploaded raw message as an email and returns its id.
+    #[instrument(skip(self), ret, level = "debug")]
+    pub async fn import_email(
+        &self,
+        blob_id: String,
+        mailbox_ids: Vec<String>,
+        keywords: Vec<String>,
+        received_at: Option<i64>,
+    ) -> anyhow::Result<String> {
+        let mut resp = self
+            .send_ws_request(TaggedMethodResponse::unwrap_import_email, move |r| {
+                let email = r
+                    .import_email()
+                    .email(blob_id)
+                    .mailbox_ids(mailbox_ids)
+                    .keywords(keywords);
+                if let Some(received_at) = received_at {
+                    email.received_at(received_at);
+                }
+            })
+            .await
+            .context("Expecting email import response")?;
+
+        // The first email of an import request is always created as "i0"
+        resp.created("i0")
+            .context("Error importing email")?
+            .id()
+            .map(str::to_string)
+            .context("Server returned no id for the email")
+    }
+
+    /// Whether the account has an email with the given `Message-ID`, without angle brackets.
+    #[instrument(skip(self), ret, level = "debug")]
+    pub async fn has_email_with_message_id(&self, message_id: String) -> anyhow::Result<bool> {
+        self.send_ws_request(TaggedMethodResponse::unwrap_query_email, move |r| {
+            r.query_email()
+                .filter(email::query::Filter::header("Message-ID", Some(message_id)))
+                .limit(1);
+        })
+        .await
+        .context("Expecting email query response")
+        .map(|resp| !resp.ids().is_empty())
+    }
+
+    /// Submits the draft `email_id` for delivery and returns the submission id. Once the
+    /// server accepts it, the email stops being a draft and moves to the sent mailbox, if any.
+    #[instrument(skip(self), ret, level = "debug")]
+    pub async fn submit_email(
+        &self,
+        email_id: String,
+        identity_id: String,
+        drafts_mailbox_id: String,
+        sent_mailbox_id: Option<String>,
+    ) -> anyhow::Result<String> {
+        self.send_ws_request(
+            TaggedMethodResponse::unwrap_set_email_submission,
+            move |r| {
+                let set = r.set_email_submission();
+                set.create_with_id("send")
+                    .email_id(email_id)
+                    .identity_id(identity_id);
+
+                let email = set.arguments().on_success_update_email("send");
+                email.keyword("$draft", false);
+                if let Some(sent_mailbox_id) = sent_mailbox_id {
+                    email
+                        .mailbox_id(&drafts_mailbox_id, false)
+                        .mailbox_id(&sent_mailbox_id, true);
+                }
+            },
+        )
+        .await
+        .context("Expecting email submission set response")?
+        .created("send")
+        .context("Error submitting email")?
+        .id()
+        .map(str::to_string)
+        .context("Server returned no id for the submission")
+    }
+
+    /// Fetches the email's blob id and the headers of its top-level body part, which are the
+    /// message's own header fields in their original order.
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_email_headers(
+        &self,
+        email_id: String,
+    ) -> anyhow::Result<Option<email::Email>> {
+        self.send_ws_request(TaggedMethodResponse::unwrap_get_email, move |r| {
+            let req = r.get_email().ids([email_id]).properties([
+                email::Property::Id,
+                email::Property::BlobId,
+                email::Property::BodyStructure,
+            ]);
+            req.arguments()
+                .body_properties([email::BodyProperty::PartId, email::BodyProperty::Headers]);
+        })
+        .await
+        .context("Expecting email get response")
+        .map(|mut resp| resp.take_list().pop())
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    pub async fn get_identities(&self) -> anyhow::Result<Vec<Identity>> {
+        if let Some((fetched_at, identities)) = &*self.identities.lock()
+            && fetched_at.elapsed() < IDENTITIES_CACHE_TTL
+        {
+            return Ok(identities.clone());
+        }
+
+        let identities = self
+            .send_ws_request(TaggedMethodResponse::unwrap_get_identity, |r| {
+                r.get_identity();
+            })
+            .await
+            .context("Expecting identity get response")?
+            .take_list();
+
+        self.identities
+            .lock()
+            .replace((Instant::now(), identities.clone()));
+        Ok(identities)
+    }
+
+    /// The identities as last fetched, however long ago, for when a round trip isn't worth it.
+    pub fn cached_identities(&self) -> Option<Vec<Identity>> {
+        self.identities
+            .lock()
+            .as_ref()
+            .map(|(_, identities)| identities.clone())
+    }
+
+    /// The login name of the session, without waiting for a connection.
+    pub fn connected_session_username(&self) -> Option<String> {
+        match &*self.client_state.borrow() {
+            ClientState::Connected(client) => Some(client.session().username().to_string()),
+            _ => None,
+        }
+    }
+
+    /// The login name of the session, which for most servers is the account's primary address.
+    pub async fn session_username(&self) -> String {
+        self.wait_for_client()
+            .await
+            .session()
+            .username()
+            .to_string()
+    }
+
     async fn wait_for_client(&self) -> Arc<Client> {
         let mut receiver = self.client_state.clone();
 
@@ -405,12 +1138,1124 @@ impl JmapApi {
         }
     }
 
+    #[instrument(skip(self, data), err, level = "debug")]
+    pub async fn upload_blob(
+        &self,
+        data: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> anyhow::Result<UploadResponse> {
+        let client = self.wait_for_client().await;
+        self.rate_limiter.acquire().await;
+        match client.upload(None, data, content_type).await {
+            Ok(resp) => {
+                self.rate_limiter.recover();
+                Ok(resp)
+            }
+            Err(e) => {
+                if is_rate_limited(&e) {
+                    self.rate_limiter.back_off();
+                }
+                Err(e).context("Upload blob failed")
+            }
+        }
+    }
+
+    /// The id of the JMAP account this API works on, as pushes name it.
+    pub async fn jmap_account_id(&self) -> String {
+        self.wait_for_client()
+            .await
+            .default_account_id()
+            .to_string()
+    }
+
+    /// The mail accounts the session gives access to, for choosing which one to sync.
+    pub async fn mail_accounts(&self) -> Vec<JmapAccountInfo> {
+        let client = self.wait_for_client().await;
+        let session = client.session();
+        let primary = session
+            .primary_accounts()
+            .find(|(capability, _)| *capability == URI::Mail.as_ref())
+            .map(|(_, id)| id.as_str());
+
+        session
+            .accounts()
+            .filter_map(|id| Some((id, session.account(id)?)))
+            .filter(|(_, account)| account.capability(URI::Mail.as_ref()).is_some())
+            .map(|(id, account)| JmapAccountInfo {
+                id: id.clone(),
+                name: account.name().to_string(),
+                is_personal: account.is_personal(),
+                is_read_only: account.is_read_only(),
+                is_primary: primary == Some(id.as_str()),
+                is_selected: id == client.default_account_id(),
+            })
+            .collect()
+    }
+
+    /// The session's upload URL for the account.
+    pub async fn upload_url(&self) -> String {
+        let client = self.wait_for_client().await;
+        expand_url_template(
+            client.session().upload_url(),
+            &[("accountId", client.default_account_id())],
+        )
+    }
+
+    /// The session's download URL for a blob of the account, to be served as `content_type`
+    /// under the file name `name`.
+    pub async fn download_url(&self, blob_id: &str, name: &str, content_type: &str) -> String {
+        let client = self.wait_for_client().await;
+        expand_url_template(
+            client.session().download_url(),
+            &[
+                ("accountId", client.default_account_id()),
+                ("blobId", blob_id),
+                ("name", name),
+                ("type", content_type),
+            ],
+        )
+    }
+
+    /// Sends a request to one of the session's URLs with the account's credentials, rate
+    /// limited like other requests. An access token the server rejects is refreshed, and the
+    /// request built and sent once more.
+    async fn send_authorized<F>(
+        &self,
+        mut build: impl FnMut() -> F,
+    ) -> anyhow::Result<reqwest::Response>
+    where
+        F: Future<Output = anyhow::Result<reqwest::RequestBuilder>>,
+    {
+        let mut refreshed = false;
+        loop {
+            let authorization = authorization(&self.credentials.lock().clone().into());
+            let request = build().await?.header(header::AUTHORIZATION, authorization);
+
+            self.rate_limiter.acquire().await;
+            let resp = request.send().await?;
+
+            match resp.status() {
+                StatusCode::UNAUTHORIZED if !refreshed => {
+                    refreshed = true;
+                    let credentials = &self.credentials;
+                    if refresh_credentials(credentials, &self.http_client, &self.save_credentials)
+                        .await
+                        .is_some()
+                    {
+                        continue;
+                    }
+                }
+                StatusCode::TOO_MANY_REQUESTS => self.rate_limiter.back_off(),
+                status if status.is_success() => self.rate_limiter.recover(),
+                _ => {}
+            }
+            return Ok(resp);
+        }
+    }
+
+    /// Uploads a file by streaming it from disk, so it never has to fit in memory.
+    /// jmap-client only uploads from a buffer, hence the direct call to the upload endpoint,
+    /// which is rate limited like other requests. An access token the server rejects is
+    /// refreshed, and the upload retried once.
+    #[instrument(skip(self), err, level = "debug")]
+    pub async fn upload_blob_file(
+        &self,
+        path: &Path,
+        content_type: Option<&str>,
+    ) -> anyhow::Result<UploadResponse> {
+        let url = self.upload_url().await;
+        let content_type = content_type.unwrap_or("application/octet-stream");
+
+        let url = &url;
+        let resp = self
+            .send_authorized(|| async move {
+                // The body is consumed by each attempt, so the file is opened afresh for each
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .context("Error opening upload file")?;
+                let len = file
+                    .metadata()
+                    .await
+                    .context("Error reading upload file size")?
+                    .len();
+
+                Ok(self
+                    .http_client
+                    .post(url)
+                    .header(header::CONTENT_LENGTH, len)
+                    .header(header::CONTENT_TYPE, content_type)
+                    .body(reqwest::Body::wrap_stream(ReaderStream::new(file))))
+            })
+            .await
+            .context("Upload request failed")?;
+
+        let body = resp
+            .error_for_status()
+            .context("Upload rejected by server")?
+            .bytes()
+            .await
+            .context("Error reading upload response")?;
+
+        serde_json::from_slice(&body).context("Invalid upload response")
+    }
+
+    /// Downloads a blob into memory, from the session's download URL like uploads, so it's rate
+    /// limited and survives a token refresh. Dropping the future, e.g. when axum drops the
+    /// handler of a client that went away, abandons the request to the server too.
     #[instrument(skip(self), err, level = "debug")]
     pub async fn download_blob(&self, blob_id: &str) -> anyhow::Result<Vec<u8>> {
-        self.wait_for_client()
+        let mut download = DownloadGuard { finished: false };
+        let url = self
+            .download_url(blob_id, blob_id, "application/octet-stream")
+            .await;
+        let result = async {
+            let data = self
+                .send_authorized(|| async { Ok(self.http_client.get(&url)) })
+                .await
+                .context("Download request failed")?
+                .error_for_status()
+                .context("Download rejected by server")?
+                .bytes()
+                .await
+                .context("Error reading download")?;
+            anyhow::Ok(data.to_vec())
+        }
+        .await
+        .context("Download blob failed");
+        download.finished = true;
+        result
+    }
+}
+
+/// Logs a download that is dropped before it finishes, so abandoned downloads can be seen
+/// actually going away.
+struct DownloadGuard {
+    finished: bool,
+}
+
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            tracing::info!("Blob download abandoned");
+        }
+    }
+}
+
+/// Resolves once the network probe says the network came back, cutting the backoff short.
+/// Never resolves while it's up: the probe only hints at a better time to try, and connecting
+/// goes ahead on schedule whatever it says.
+async fn back_online(network_availability: &mut watch::Receiver<NetworkAvailability>) {
+    if !network_availability.borrow_and_update().online
+        && network_availability.wait_for(|a| a.online).await.is_ok()
+    {
+        return;
+    }
+    std::future::pending().await
+}
+
+/// Persists credentials that were refreshed while connecting.
+pub type SaveCredentials =
+    Arc<dyn Fn(AccountCredentials) -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync>;
+
+/// How long to wait between attempts to connect to the server.
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectConfig {
+    /// The wait after the first failed attempt, which doubles with each further one.
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl ReconnectConfig {
+    /// The wait after `failures` attempts in a row have failed, less a random part of up to
+    /// half, so clients that lost the server together don't all come back at once.
+    fn delay(&self, failures: u32) -> Duration {
+        let delay = self
+            .base
+            .saturating_mul(2u32.saturating_pow(failures))
+            .min(self.max);
+        delay.mul_f64(rand::random_range(0.5..=1.0))
+    }
+}
+
+/// How long to wait before trying credentials again that the server rejected and that can't be
+/// refreshed. Reconnecting by hand skips the wait.
+const AUTH_FAILURE_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Whether connecting failed because the server rejected the credentials.
+fn is_auth_failure(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|e| e.downcast_ref::<jmap_client::Error>())
+        .any(|e| match e {
+            jmap_client::Error::Problem(p) => p.status == Some(401),
+            jmap_client::Error::Server(status) => status.starts_with("401"),
+            jmap_client::Error::Transport(e) => e.status() == Some(StatusCode::UNAUTHORIZED),
+            _ => false,
+        })
+}
+
+/// Refreshes the access token in `credentials` and saves the result, returning whether there's
+/// a new token to try. Saving failing only costs another refresh after a restart.
+async fn refresh_credentials(
+    credentials: &Mutex<AccountCredentials>,
+    http_client: &reqwest::Client,
+    save_credentials: &SaveCredentials,
+) -> Option<()> {
+    let current = credentials.lock().clone();
+    let refreshed = match current.refresh(http_client).await {
+        Ok(refreshed) => refreshed?,
+        Err(e) => {
+            tracing::error!(?e, "Error refreshing the access token");
+            return None;
+        }
+    };
+
+    tracing::info!("Refreshed the access token");
+    *credentials.lock() = refreshed.clone();
+    if let Err(e) = save_credentials(refreshed).await {
+        tracing::error!(?e, "Error saving the refreshed credentials");
+    }
+    Some(())
+}
+
+/// The `Authorization` header value for `credentials`, as jmap-client sends it.
+fn authorization(credentials: &Credentials) -> String {
+    match credentials {
+        Credentials::Basic(encoded) => format!("Basic {encoded}"),
+        Credentials::Bearer(token) => format!("Bearer {token}"),
+    }
+}
+
+/// The session's EventSource URL for pushes of `types` (all of them if empty), or `None` if
+/// the server offers no EventSource push.
+fn event_source_url(
+    session: &Session,
+    types: &[DataType],
+    close_after_state: bool,
+    ping_secs: u32,
+) -> Option<String> {
+    if session.event_source_url().is_empty() {
+        return None;
+    }
+
+    let types = if types.is_empty() {
+        "*".to_string()
+    } else {
+        types.iter().map(|t| t.to_string()).join(",")
+    };
+
+    Some(expand_url_template(
+        session.event_source_url(),
+        &[
+            ("types", &types),
+            ("closeafter", if close_after_state { "state" } else { "no" }),
+            ("ping", &ping_secs.to_string()),
+        ],
+    ))
+}
+
+/// Fills in the `{name}` placeholders of a session URL template (RFC 6570 level 1), percent-
+/// encoding the values. Placeholders without a value are left as they are.
+fn expand_url_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut url = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + len];
+        url.push_str(&rest[..start]);
+
+        match values.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => {
+                for b in value.bytes() {
+                    if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                        url.push(char::from(b));
+                    } else {
+                        url.push_str(&format!("%{b:02X}"));
+                    }
+                }
+            }
+            None => url.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+
+    url.push_str(rest);
+    url
+}
+
+/// How many redirects session discovery follows, as many as jmap-client does.
+const MAX_DISCOVERY_REDIRECTS: usize = 5;
+
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The URL to discover the session from. jmap-client appends `/.well-known/jmap` itself, so
+/// that's dropped if the configured URL already ends with it.
+fn jmap_base_url(server_url: &Url) -> String {
+    let url = server_url.as_str().trim_end_matches('/');
+    url.strip_suffix("/.well-known/jmap")
+        .unwrap_or(url)
+        .to_string()
+}
+
+/// The hosts that the well-known JMAP URL redirects through on its way to the session resource,
+/// starting with the configured one. jmap-client only follows redirects to hosts it's told to
+/// trust, and servers commonly send discovery to another host, e.g. `api.example.com`.
+///
+/// The redirects are followed here without credentials, so credentials only go where the
+/// configured server itself points. A redirect from https to plain http is not followed.
+async fn discover_session_hosts(base_url: &str) -> Vec<String> {
+    let mut hosts = Vec::new();
+    let Ok(mut url) = Url::parse(&format!("{base_url}/.well-known/jmap")) else {
+        return hosts;
+    };
+    hosts.extend(url.host_str().map(str::to_string));
+
+    let client = match reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(DISCOVERY_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::warn!(?e, "Error creating session discovery client");
+            return hosts;
+        }
+    };
+
+    for _ in 0..MAX_DISCOVERY_REDIRECTS {
+        let resp = match client.get(url.clone()).send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                // Connecting reports the actual error
+                tracing::debug!(?e, %url, "Error discovering JMAP session");
+                break;
+            }
+        };
+
+        if !resp.status().is_redirection() {
+            break;
+        }
+
+        let Some(next) = resp
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| url.join(l).ok())
+        else {
+            break;
+        };
+
+        if url.scheme() == "https" && next.scheme() != "https" {
+            tracing::warn!(%url, %next, "Not following JMAP discovery redirect to plain http");
+            break;
+        }
+
+        tracing::debug!(%url, %next, "JMAP discovery redirected");
+        if let Some(host) = next.host_str()
+            && !hosts.iter().any(|h| h == host)
+        {
+            hosts.push(host.to_string());
+        }
+        url = next;
+    }
+
+    hosts
+}
+
+/// Whether a blob download failed because the server doesn't have the blob (any more), e.g.
+/// because its email was deleted.
+pub fn is_blob_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        == Some(StatusCode::NOT_FOUND)
+}
+
+/// Whether the server is asking us to slow down.
+fn is_rate_limited(e: &jmap_client::Error) -> bool {
+    match e {
+        jmap_client::Error::Transport(e) => e.status() == Some(StatusCode::TOO_MANY_REQUESTS),
+        jmap_client::Error::Problem(p) => {
+            p.status == Some(429) || matches!(p.error(), ProblemType::JMAP(JMAPError::Limit))
+        }
+        jmap_client::Error::Method(e) => matches!(
+            e.error(),
+            MethodErrorType::RequestTooLarge | MethodErrorType::ServerUnavailable
+        ),
+        _ => false,
+    }
+}
+
+type WsStream = Pin<Box<dyn Stream<Item = jmap_client::Result<WebSocketMessage>> + Send>>;
+
+/// How push notifications are received from the server, in the order of preference.
+enum PushTransport {
+    /// Requests and pushes share a single websocket.
+    WebSocket(WsStream),
+    /// Requests go over HTTP, pushes come from the JMAP EventSource stream.
+    EventSource,
+    /// Requests go over HTTP, changes are checked periodically.
+    Polling,
+}
+
+impl PushTransport {
+    async fn establish(client: &Client) -> Self {
+        let session = client.session();
+
+        if session
+            .websocket_capabilities()
+            .is_some_and(|c| c.supports_push())
+        {
+            let connect_ws = async {
+                let ws = client
+                    .connect_ws()
+                    .await
+                    .context("Failed to connect to JMAP server")?;
+
+                client
+                    .enable_push_ws(Some(PUSH_DATA_TYPES), None::<&'static str>)
+                    .await
+                    .context("Failed to enable ws push")?;
+
+                anyhow::Ok(ws)
+            };
+
+            match connect_ws.await {
+                Ok(ws) => return Self::WebSocket(ws),
+                Err(e) => tracing::warn!(?e, "Websocket push unavailable, falling back"),
+            }
+        }
+
+        if !session.event_source_url().is_empty() {
+            Self::EventSource
+        } else {
+            Self::Polling
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::WebSocket(_) => "websocket",
+            Self::EventSource => "eventsource",
+            Self::Polling => "polling",
+        }
+    }
+}
+
+const PUSH_DATA_TYPES: [DataType; 3] = [DataType::Email, DataType::Core, DataType::Mailbox];
+
+const EVENT_SOURCE_PING_SECS: u32 = 30;
+const EVENT_SOURCE_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Serves requests over the websocket until the request channel closes (`Ok`)
+/// or the connection breaks (`Err`).
+async fn run_ws_session(
+    client: &Client,
+    mut ws: WsStream,
+    pending_requests_rx: &mut mpsc::Receiver<(JmapRequestBuilder, JmapRequestCallback)>,
+    notification_sender: &broadcast::Sender<Arc<PushObject>>,
+) -> anyhow::Result<()> {
+    let mut callbacks: HashMap<String, JmapRequestCallback> = Default::default();
+
+    loop {
+        match select(pin!(ws.next()), pin!(pending_requests_rx.recv())).await {
+            Either::Left((Some(Ok(WebSocketMessage::Response(res))), _)) => {
+                if let Some(callback) = res.request_id().and_then(|r| callbacks.remove(r)) {
+                    if let Some(res) = res.unwrap_method_responses().pop() {
+                        let _ = callback.send(Ok(res));
+                    } else {
+                        let _ = callback
+                            .send(Err(format_err!("No method responses in tagged response")));
+                    }
+                } else {
+                    tracing::warn!("Unable to find a callback for a response");
+                }
+            }
+
+            Either::Left((Some(Ok(WebSocketMessage::PushNotification(push))), _)) => {
+                let _ = notification_sender.send(Arc::new(push));
+            }
+
+            Either::Left((Some(Err(e)), _)) => {
+                return Err(e).context("Error receiving WS message");
+            }
+
+            Either::Left((None, _)) => {
+                bail!("WS stream closed");
+            }
+
+            Either::Right((None, _)) => {
+                return Ok(());
+            }
+
+            Either::Right((Some((req_builder, callback)), _)) => {
+                let mut req = client.build();
+                req_builder(&mut req);
+                match req.send_ws().await {
+                    Ok(request_id) => {
+                        callbacks.insert(request_id, callback);
+                    }
+                    Err(e) => {
+                        let e = Arc::new(e);
+                        let _ = callback.send(Err(e.clone()).context("Error queueing ws request"));
+                        return Err(e).context("Error sending WS message to JMAP server");
+                    }
+                };
+            }
+        }
+    }
+}
+
+/// Serves requests over plain HTTP until the request channel closes. Each request
+/// is sent independently so a slow one doesn't hold up the rest.
+async fn run_http_session(
+    client: &Arc<Client>,
+    pending_requests_rx: &mut mpsc::Receiver<(JmapRequestBuilder, JmapRequestCallback)>,
+) -> anyhow::Result<()> {
+    while let Some((req_builder, callback)) = pending_requests_rx.recv().await {
+        let client = client.clone();
+        tokio::spawn(async move {
+            let mut req = client.build();
+            req_builder(&mut req);
+            let res = req
+                .send()
+                .await
+                .context("Error sending HTTP request to JMAP server")
+                .and_then(|res| {
+                    res.unwrap_method_responses()
+                        .pop()
+                        .context("No method responses in response")
+                });
+            let _ = callback.send(res);
+        });
+    }
+
+    Ok(())
+}
+
+/// Forwards the JMAP EventSource stream onto the push broadcast, reconnecting
+/// (and resuming from the last seen event) whenever the stream drops. The stream is opened
+/// with the account's current access token, which is refreshed once should the server reject it.
+#[instrument(skip_all, level = "info")]
+async fn pump_event_source(
+    client: Arc<Client>,
+    http_client: reqwest::Client,
+    credentials: Arc<Mutex<AccountCredentials>>,
+    save_credentials: SaveCredentials,
+    notification_sender: broadcast::Sender<Arc<PushObject>>,
+) {
+    let Some(url) = event_source_url(
+        &client.session(),
+        &PUSH_DATA_TYPES,
+        false,
+        EVENT_SOURCE_PING_SECS,
+    ) else {
+        tracing::warn!("Session offers no EventSource URL");
+        return;
+    };
+    let mut last_event_id: Option<String> = None;
+
+    loop {
+        let connect = open_event_source(
+            &url,
+            last_event_id.as_deref(),
+            &http_client,
+            &credentials,
+            &save_credentials,
+        );
+
+        match connect.await {
+            Ok(resp) => {
+                tracing::info!("EventSource stream connected");
+
+                let mut stream = resp.bytes_stream();
+                let mut parser = EventParser::default();
+
+                'stream: loop {
+                    while let Some(notification) = parser.filter_notification() {
+                        match notification {
+                            Ok(PushNotification::StateChange(changes)) => {
+                                if let Some(id) = changes.id() {
+                                    last_event_id = Some(id.to_string());
+                                }
+
+                                let _ =
+                                    notification_sender.send(Arc::new(PushObject::StateChange {
+                                        changed: changes.into_inner(),
+                                    }));
+                            }
+
+                            Ok(PushNotification::CalendarAlert(_)) => continue,
+
+                            Err(e) => {
+                                tracing::error!(?e, "Error parsing EventSource message");
+                                break 'stream;
+                            }
+                        }
+                    }
+
+                    match stream.next().await {
+                        Some(Ok(bytes)) => parser.push_bytes(bytes.to_vec()),
+                        Some(Err(e)) => {
+                            tracing::error!(?e, "Error receiving EventSource message");
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+
+                tracing::info!("EventSource stream ended, reconnecting...");
+            }
+
+            Err(e) => {
+                tracing::error!(?e, "Failed to connect EventSource stream");
+            }
+        }
+
+        tokio::time::sleep(EVENT_SOURCE_RECONNECT_DELAY).await;
+    }
+}
+
+/// Opens the EventSource stream at `url`, resuming after `last_event_id` if given.
+async fn open_event_source(
+    url: &str,
+    last_event_id: Option<&str>,
+    http_client: &reqwest::Client,
+    credentials: &Mutex<AccountCredentials>,
+    save_credentials: &SaveCredentials,
+) -> anyhow::Result<reqwest::Response> {
+    let mut refreshed = false;
+    loop {
+        let mut request = http_client
+            .get(url)
+            .header(header::ACCEPT, "text/event-stream")
+            .header(
+                header::AUTHORIZATION,
+                authorization(&credentials.lock().clone().into()),
+            );
+        if let Some(id) = last_event_id {
+            request = request.header("Last-Event-ID", id);
+        }
+
+        let resp = request.send().await?;
+        if resp.status() == StatusCode::UNAUTHORIZED && !refreshed {
+            refreshed = true;
+            if refresh_credentials(credentials, http_client, save_credentials)
+                .await
+                .is_some()
+            {
+                continue;
+            }
+        }
+
+        return resp
+            .error_for_status()
+            .context("EventSource rejected by server");
+    }
+}
+
+/// Emits a synthetic state change on an interval so that the sync workers
+/// pick up changes on servers that offer no push at all.
+#[instrument(skip(notification_sender), level = "info")]
+async fn poll_for_changes(
+    account_id: String,
+    notification_sender: broadcast::Sender<Arc<PushObject>>,
+) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // The first tick completes immediately and the initial sync happens anyway.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let changed = [(
+            account_id.clone(),
+            PUSH_DATA_TYPES
+                .into_iter()
+                .map(|t| (t, String::new()))
+                .collect(),
+        )]
+        .into_iter()
+        .collect();
+
+        let _ = notification_sender.send(Arc::new(PushObject::StateChange { changed }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::response::Redirect;
+    use axum::routing::{get, post};
+    use futures::FutureExt;
+
+    async fn serve(router: Router) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        port
+    }
+
+    #[tokio::test]
+    async fn url_templates_are_expanded() {
+        let template = "https://jmap.example.com/download/{accountId}/{blobId}/{name}?type={type}";
+        assert_eq!(
+            expand_url_template(
+                template,
+                &[
+                    ("accountId", "a1"),
+                    ("blobId", "b1"),
+                    ("name", "my report.pdf"),
+                    ("type", "application/pdf"),
+                ],
+            ),
+            "https://jmap.example.com/download/a1/b1/my%20report.pdf?type=application%2Fpdf"
+        );
+
+        // Placeholders without a value stay, as does an unclosed brace
+        assert_eq!(
+            expand_url_template(
+                "https://x.example/{accountId}/{other}/{",
+                &[("accountId", "a")]
+            ),
+            "https://x.example/a/{other}/{"
+        );
+
+        // The session's download URL names the account, and its EventSource URL the pushes
+        let server = testing::FakeServer::start(|_, _| Err("unknownMethod"), Router::new()).await;
+        let api = server.connect().await;
+        assert_eq!(
+            api.download_url("b 1", "report.pdf", "application/pdf")
+                .await,
+            format!("{}download/a/b%201", server.url)
+        );
+
+        let session = reqwest::get(server.url.join(".well-known/jmap").unwrap())
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let mut session: serde_json::Value = serde_json::from_str(&session).unwrap();
+        let mut session_with = |event_source_url: &str| -> Session {
+            session["eventSourceUrl"] = event_source_url.into();
+            serde_json::from_value(session.clone()).unwrap()
+        };
+
+        let session = session_with(
+            "https://x.example/events?types={types}&closeafter={closeafter}&ping={ping}",
+        );
+        assert_eq!(
+            event_source_url(&session, &[DataType::Email, DataType::Mailbox], true, 30).as_deref(),
+            Some("https://x.example/events?types=Email%2CMailbox&closeafter=state&ping=30")
+        );
+        assert_eq!(
+            event_source_url(&session, &[], false, 0).as_deref(),
+            Some("https://x.example/events?types=%2A&closeafter=no&ping=0")
+        );
+        assert_eq!(event_source_url(&session_with(""), &[], false, 0), None);
+    }
+
+    #[test]
+    fn summaries_leave_bodies_out() {
+        for property in [email::Property::BodyValues, email::Property::BodyStructure] {
+            assert!(!SUMMARY_PROPERTIES.contains(&property), "{property:?}");
+            assert!(DETAIL_PROPERTIES.contains(&property), "{property:?}");
+        }
+    }
+
+    #[test]
+    fn base_url_drops_well_known_suffix() {
+        for url in [
+            "https://mail.example.com",
+            "https://mail.example.com/",
+            "https://mail.example.com/.well-known/jmap",
+        ] {
+            assert_eq!(
+                jmap_base_url(&Url::parse(url).unwrap()),
+                "https://mail.example.com"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn discovery_trusts_redirected_hosts() {
+        let session_port =
+            serve(Router::new().route("/jmap/session", get(|| async { "{}" }))).await;
+        let location = format!("http://localhost:{session_port}/jmap/session");
+        let discovery_port = serve(Router::new().route(
+            "/.well-known/jmap",
+            get(move || async move { Redirect::temporary(&location) }),
+        ))
+        .await;
+
+        let hosts = discover_session_hosts(&format!("http://127.0.0.1:{discovery_port}")).await;
+        assert_eq!(hosts, ["127.0.0.1", "localhost"]);
+    }
+
+    #[tokio::test]
+    async fn discovery_without_redirect_trusts_configured_host() {
+        let port = serve(Router::new().route("/.well-known/jmap", get(|| async { "{}" }))).await;
+        let hosts = discover_session_hosts(&format!("http://127.0.0.1:{port}")).await;
+        assert_eq!(hosts, ["127.0.0.1"]);
+    }
+
+    #[tokio::test]
+    async fn file_uploads_refresh_a_rejected_token() {
+        // The upload endpoint only takes the access token the token endpoint hands out
+        let uploads = Arc::new(Mutex::new(Vec::new()));
+        let routes = Router::new()
+            .route(
+                "/token",
+                post(|| async { axum::Json(serde_json::json!({"access_token": "fresh"})) }),
+            )
+            .route(
+                "/upload/{account_id}",
+                post({
+                    let uploads = uploads.clone();
+                    move |headers: axum::http::HeaderMap, body: String| async move {
+                        let authorization = headers[header::AUTHORIZATION].to_str().unwrap();
+                        uploads.lock().push(authorization.to_string());
+                        if authorization != "Bearer fresh" {
+                            return Err(StatusCode::UNAUTHORIZED);
+                        }
+                        Ok(axum::Json(serde_json::json!({
+                            "accountId": "a", "blobId": "b1", "type": "text/plain",
+                            "size": body.len(),
+                        })))
+                    }
+                }),
+            );
+        let server = testing::FakeServer::start(|_, _| Err("unknownMethod"), routes).await;
+
+        let saved = Arc::new(Mutex::new(None));
+        let api = server
+            .connect_with(
+                AccountCredentials::RefreshToken {
+                    access_token: "stale".to_string(),
+                    refresh_token: "refresh".to_string(),
+                    token_endpoint: server.url.join("/token").unwrap().to_string(),
+                    client_id: "mymail".to_string(),
+                },
+                {
+                    let saved = saved.clone();
+                    Arc::new(move |credentials| {
+                        *saved.lock() = Some(credentials);
+                        async { Ok(()) }.boxed()
+                    })
+                },
+            )
+            .await;
+
+        let path = std::env::temp_dir().join(format!("upload-test-{}", server.url.port().unwrap()));
+        tokio::fs::write(&path, "hello").await.unwrap();
+        let resp = api.upload_blob_file(&path, Some("text/plain")).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(resp.unwrap().blob_id(), "b1");
+        assert_eq!(*uploads.lock(), ["Bearer stale", "Bearer fresh"]);
+        assert!(matches!(
+            saved.lock().as_ref(),
+            Some(AccountCredentials::RefreshToken { access_token, .. }) if access_token == "fresh"
+        ));
+    }
+
+    #[tokio::test]
+    async fn drafts_carry_custom_headers() {
+        let server = testing::FakeServer::start(
+            |method, _| match method {
+                "Email/set" => Ok(serde_json::json!({
+                    "accountId": "a", "oldState": "e1", "newState": "e2",
+                    "created": {"email": {"id": "m1", "blobId": "b1", "threadId": "t1", "size": 1}},
+                })),
+                _ => Err("unknownMethod"),
+            },
+            Router::new(),
+        )
+        .await;
+        let api = server.connect().await;
+
+        let draft = DraftEmail {
+            headers: BTreeMap::from([("X-Mailer".to_string(), "mymail".to_string())]),
+            ..Default::default()
+        };
+        let from = EmailAddress::from("me@example.com");
+        let id = api
+            .create_draft(draft, from, "drafts".to_string())
             .await
-            .download(blob_id)
+            .unwrap();
+        assert_eq!(id, "m1");
+
+        let calls = server.calls.lock();
+        let created = &calls[0].1["create"]["email"];
+        assert_eq!(created["header:X-Mailer:asText"], "mymail");
+        assert_eq!(created["mailboxIds"], serde_json::json!({"drafts": true}));
+    }
+
+    #[tokio::test]
+    async fn keyword_updates_report_refused_emails() {
+        let server = testing::FakeServer::start(
+            |method, _| match method {
+                "Email/set" => Ok(serde_json::json!({
+                    "accountId": "a", "oldState": "e1", "newState": "e2",
+                    "updated": {"m1": null},
+                    "notUpdated": {"m2": {"type": "notFound"}},
+                })),
+                _ => Err("unknownMethod"),
+            },
+            Router::new(),
+        )
+        .await;
+        let api = server.connect().await;
+
+        let result = api
+            .set_keywords(
+                vec!["m1".to_string(), "m2".to_string()],
+                BTreeMap::from([("$seen".to_string(), true), ("a/b".to_string(), false)]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.updated, ["m1"]);
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].id, "m2");
+
+        let calls = server.calls.lock();
+        assert_eq!(
+            calls[0].1["update"]["m1"],
+            serde_json::json!({"keywords/$seen": true, "keywords/a~1b": false})
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use axum::Router;
+    use axum::routing::{get, post};
+    use futures::FutureExt;
+    use serde_json::{Value, json};
+
+    /// Answers a method call, by name and arguments, with its result, or with the type of the
+    /// method error to fail it with.
+    pub trait Handler: Fn(&str, &Value) -> Result<Value, &'static str> + Send + Sync {}
+    impl<F: Fn(&str, &Value) -> Result<Value, &'static str> + Send + Sync> Handler for F {}
+
+    /// A JMAP server over plain HTTP with a single account, `a`, and no push. Method calls are
+    /// answered by a handler and recorded.
+    pub struct FakeServer {
+        pub url: Url,
+        /// The method calls made, by name and arguments.
+        pub calls: Arc<Mutex<Vec<(String, Value)>>>,
+    }
+
+    impl FakeServer {
+        /// Starts the server, with `routes` for anything besides the session and the API.
+        pub async fn start(handler: impl Handler + 'static, routes: Router) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base = format!("http://{}", listener.local_addr().unwrap());
+            let calls = Arc::new(Mutex::new(Vec::new()));
+
+            let session = json!({
+                "capabilities": {
+                    "urn:ietf:params:jmap:core": {
+                        "maxSizeUpload": 1000000, "maxConcurrentUpload": 4,
+                        "maxSizeRequest": 1000000, "maxConcurrentRequests": 4,
+                        "maxCallsInRequest": 16, "maxObjectsInGet": 500,
+                        "maxObjectsInSet": 500, "collationAlgorithms": [],
+                    },
+                    "urn:ietf:params:jmap:mail": {},
+                },
+                "accounts": {"a": {
+                    "name": "me", "isPersonal": true, "isReadOnly": false,
+                    "accountCapabilities": {"urn:ietf:params:jmap:mail": {}},
+                }},
+                "primaryAccounts": {"urn:ietf:params:jmap:mail": "a"},
+                "username": "me@example.com",
+                "apiUrl": format!("{base}/api"),
+                "downloadUrl": format!("{base}/download/{{accountId}}/{{blobId}}"),
+                "uploadUrl": format!("{base}/upload/{{accountId}}"),
+                "eventSourceUrl": "",
+                "state": "session",
+            });
+
+            let api = {
+                let calls = calls.clone();
+                let handler = Arc::new(handler);
+                move |axum::Json(request): axum::Json<Value>| async move {
+                    let mut responses = Vec::new();
+                    for call in request["methodCalls"].as_array().into_iter().flatten() {
+                        let (name, args, id) = (call[0].as_str().unwrap(), &call[1], &call[2]);
+                        calls.lock().push((name.to_string(), args.clone()));
+                        responses.push(match handler(name, args) {
+                            Ok(result) => json!([name, result, id]),
+                            Err(error) => json!(["error", {"type": error}, id]),
+                        });
+                    }
+                    axum::Json(json!({"methodResponses": responses, "sessionState": "session"}))
+                }
+            };
+
+            let router = routes
+                .route(
+                    "/.well-known/jmap",
+                    get(move || async move { axum::Json(session) }),
+                )
+                .route("/api", post(api));
+            tokio::spawn(async move { axum::serve(listener, router).await });
+
+            Self {
+                url: Url::parse(&base).unwrap(),
+                calls,
+            }
+        }
+
+        /// The names of the methods called so far.
+        pub fn methods(&self) -> Vec<String> {
+            self.calls
+                .lock()
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect()
+        }
+
+        /// An API connected to the server with a bearer token.
+        pub async fn connect(&self) -> Arc<JmapApi> {
+            self.connect_with(
+                AccountCredentials::Bearer {
+                    token: "token".to_string(),
+                },
+                Arc::new(|_| async { Ok(()) }.boxed()),
+            )
             .await
-            .context("Download blob failed")
+        }
+
+        pub async fn connect_with(
+            &self,
+            credentials: AccountCredentials,
+            save_credentials: SaveCredentials,
+        ) -> Arc<JmapApi> {
+            let api = JmapApi::new(
+                self.url.clone(),
+                credentials,
+                None,
+                watch::channel(NetworkAvailability { online: true }).1,
+                RateLimitConfig::new(1000.0, 100.0).unwrap(),
+                ReconnectConfig {
+                    base: Duration::from_secs(1),
+                    max: Duration::from_secs(1),
+                },
+                save_credentials,
+            );
+            assert!(
+                api.wait_until_connected(Duration::from_secs(5)).await,
+                "Error connecting to the fake JMAP server"
+            );
+            Arc::new(api)
+        }
     }
 }
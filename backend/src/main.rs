@@ -1,10 +1,28 @@
-use crate::api::ApiState;
-use crate::jmap_account::AccountRepositoryExt;
-use crate::util::network::NetworkAvailability;
+// `ErrorResponse` is what axum handlers return, boxing it buys us nothing.
+#![allow(clippy::result_large_err)]
+
+use crate::api::{ApiState, Idempotency, ProxyConfig};
+use crate::jmap_account::{Account, AccountRepositoryExt};
+use crate::jmap_api::ReconnectConfig;
+use crate::repo::{DbConfig, Repository};
+use crate::util::config::env_or;
+use crate::util::error_log::ErrorLog;
+use crate::util::network::{self, NetworkAvailability};
+use crate::util::rate_limit::RateLimitConfig;
+use crate::util::spool::SpoolConfig;
+use crate::util::url_guard;
+use anyhow::Context;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 use tower_http::cors::{AllowMethods, AllowOrigin, CorsLayer};
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use url::Url;
 
 mod api;
 mod jmap_account;
@@ -16,18 +34,34 @@ mod util;
 #[tokio::main]
 async fn main() {
     let _ = dotenvy::dotenv();
-    tracing_subscriber::fmt::init();
+    let error_log = ErrorLog::default();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO))
+        .with(error_log.clone().with_filter(LevelFilter::WARN))
+        .init();
     let database_file = std::env::var("DATABASE_FILE").unwrap_or(String::from(":memory:"));
 
     tracing::info!("Using database {database_file}");
 
     let repo = Arc::new(
-        repo::Repository::new(&database_file)
-            .await
-            .expect("Failed to initialize DB repository"),
+        repo::Repository::new(
+            &database_file,
+            DbConfig {
+                max_connections: env_or("DB_MAX_CONNECTIONS", 16),
+                busy_timeout: Duration::from_millis(env_or("DB_BUSY_TIMEOUT_MS", 5_000)),
+                acquire_timeout: Duration::from_millis(env_or("DB_ACQUIRE_TIMEOUT_MS", 30_000)),
+                key: std::env::var("DATABASE_KEY").ok().filter(|k| !k.is_empty()),
+            },
+        )
+        .await
+        .expect("Failed to initialize DB repository"),
     );
 
-    if repo
+    if let Ok(accounts_file) = std::env::var("ACCOUNTS_FILE") {
+        bootstrap_accounts(&repo, &accounts_file)
+            .await
+            .expect("Failed to bootstrap accounts from file");
+    } else if repo
         .list_accounts()
         .await
         .expect("Failed to list accounts")
@@ -36,28 +70,79 @@ async fn main() {
         tracing::info!("No accounts found in the database.");
         let server_url =
             std::env::var("JMAP_SERVER_URL").expect("Missing JMAP_SERVER_URL environment variable");
-        let username =
-            std::env::var("JMAP_USERNAME").expect("Missing JMAP_USERNAME environment variable");
-        let password =
-            std::env::var("JMAP_PASSWORD").expect("Missing JMAP_PASSWORD environment variable");
+        let credentials = match std::env::var("JMAP_TOKEN") {
+            Ok(token) => jmap_account::Credentials::Bearer { token },
+            Err(_) => jmap_account::Credentials::Basic {
+                username: std::env::var("JMAP_USERNAME")
+                    .expect("Missing JMAP_USERNAME (or JMAP_TOKEN) environment variable"),
+                password: std::env::var("JMAP_PASSWORD")
+                    .expect("Missing JMAP_PASSWORD environment variable"),
+            },
+        };
 
         let account = jmap_account::Account {
             server_url: server_url.clone(),
-            credentials: jmap_account::Credentials::Basic { username, password },
+            credentials,
             name: String::from("default"),
+            jmap_account_id: std::env::var("JMAP_ACCOUNT_ID").ok(),
         };
         repo.add_account(&account)
             .await
             .expect("Failed to add account");
     }
 
+    tokio::spawn(resume_account_purges(repo.clone()));
+
+    let (network_availability_tx, network_availability_rx) =
+        watch::channel(NetworkAvailability { online: true });
+
+    tokio::spawn(network::monitor_network(
+        network_availability_tx,
+        Duration::from_secs(env_or("NETWORK_PROBE_INTERVAL_SECS", 15)),
+        {
+            let repo = repo.clone();
+            move || {
+                let repo = repo.clone();
+                async move { account_server_urls(&repo).await }
+            }
+        },
+    ));
+
     let api_state = ApiState {
         repo: repo.clone(),
         account_states: Default::default(),
-        http_client: reqwest::Client::new(),
+        account_states_updated: watch::channel(()).0,
+        http_client: build_http_client().expect("Failed to build HTTP client"),
+        max_list_limit: env_or("MAX_LIST_LIMIT", api::DEFAULT_MAX_LIST_LIMIT),
+        ws_max_message_size: env_or("WS_MAX_MESSAGE_BYTES", 64 * 1024),
+        error_log,
+        stream_limits: Arc::new(api::StreamLimits::new(
+            env_or("WS_MAX_STREAMS", 1024),
+            env_or("WS_MAX_STREAMS_PER_ACCOUNT", 128),
+        )),
+        admin_token: std::env::var("ADMIN_TOKEN").ok().map(Into::into),
+        proxy_config: ProxyConfig {
+            max_bytes: env_or("PROXY_MAX_BYTES", 10 * 1024 * 1024),
+            timeout: Duration::from_secs(env_or("PROXY_TIMEOUT_SECS", 15)),
+            cache_max_bytes: env_or("PROXY_CACHE_MAX_BYTES", 64 * 1024 * 1024),
+            allowed_content_types: env_or("PROXY_ALLOWED_CONTENT_TYPES", "image/".to_string())
+                .split(',')
+                .map(|t| t.trim().to_ascii_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect(),
+        },
+        spool_config: SpoolConfig {
+            threshold: env_or("UPLOAD_SPOOL_THRESHOLD_BYTES", 8 * 1024 * 1024),
+            dir: env_or("UPLOAD_SPOOL_DIR", std::env::temp_dir()),
+        },
+        idempotency: Arc::new(Idempotency::new(
+            Duration::from_secs(env_or("IDEMPOTENCY_TTL_SECS", 24 * 60 * 60)),
+            Duration::from_secs(env_or("SEND_DEDUPE_WINDOW_SECS", 60)),
+        )),
+        network_availability: network_availability_rx.clone(),
     };
 
-    let axum_app = api::build_api_router()
+    let axum_app = api::build_api_router(&api_state)
         .layer(
             CorsLayer::new()
                 .allow_origin(AllowOrigin::any())
@@ -65,6 +150,12 @@ async fn main() {
         )
         .with_state(api_state.clone());
 
+    let rate_limit = RateLimitConfig::new(
+        env_or("JMAP_RATE_LIMIT_PER_SEC", 50.0),
+        env_or("JMAP_RATE_LIMIT_BURST", 100.0),
+    )
+    .expect("Invalid JMAP rate limit");
+
     let listener = TcpListener::bind("127.0.0.1:4000")
         .await
         .expect("Failed to bind TCP listener");
@@ -74,16 +165,118 @@ async fn main() {
         listener.local_addr().unwrap()
     );
 
-    let (network_availability_tx, network_availability_rx) =
-        watch::channel(NetworkAvailability { online: true });
-
     tokio::spawn(sync::sync_accounts(
         repo,
         api_state.account_states,
+        api_state.account_states_updated,
         network_availability_rx,
+        rate_limit,
+        ReconnectConfig {
+            base: Duration::from_secs(env_or("JMAP_RECONNECT_BASE_SECS", 5)),
+            max: Duration::from_secs(env_or("JMAP_RECONNECT_MAX_SECS", 5 * 60)),
+        },
+        env_or("MAILBOX_SYNC_CONCURRENCY", 4usize).max(1),
     ));
 
     axum::serve(listener, axum_app)
         .await
         .expect("Error serving axum app")
 }
+
+/// The servers of all accounts, for telling whether the network is up.
+async fn account_server_urls(repo: &Repository) -> Vec<Url> {
+    match repo.list_accounts().await {
+        Ok(accounts) => accounts
+            .into_iter()
+            .filter_map(|(_, account)| Url::parse(&account.server_url).ok())
+            .collect(),
+        Err(e) => {
+            tracing::warn!(?e, "Error listing accounts to probe");
+            Vec::new()
+        }
+    }
+}
+
+/// The client shared by the image proxy and the dev server proxy.
+fn build_http_client() -> anyhow::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(env_or("HTTP_CONNECT_TIMEOUT_SECS", 10)))
+        .timeout(Duration::from_secs(env_or("HTTP_TIMEOUT_SECS", 30)))
+        .redirect(reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= 5 {
+                attempt.error("Too many redirects")
+            } else if url_guard::is_private_host(attempt.url()) {
+                attempt.error("Redirect to a private address")
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .context("Error building HTTP client")
+}
+
+/// Creates or updates the accounts listed in a JSON file, matching existing accounts by name.
+/// The file holds an array of accounts, e.g.
+/// `[{"name": "work", "server_url": "https://jmap.example.com", "credentials": {"Basic": {"username": "me", "password": "secret"}}}]`.
+/// Token-based accounts have `"credentials": {"Bearer": {"token": "..."}}` instead, or
+/// `{"RefreshToken": {"access_token": "...", "refresh_token": "...", "token_endpoint": "...", "client_id": "..."}}`
+/// for OAuth2 tokens that get refreshed.
+async fn bootstrap_accounts(repo: &Repository, accounts_file: &str) -> anyhow::Result<()> {
+    let accounts: Vec<Account> = serde_json::from_slice(
+        &std::fs::read(accounts_file).context("Error reading accounts file")?,
+    )
+    .context("Error parsing accounts file")?;
+
+    let existing: HashMap<_, _> = repo
+        .list_accounts()
+        .await?
+        .into_iter()
+        .map(|(id, account)| (account.name.clone(), (id, account)))
+        .collect();
+
+    for mut account in accounts {
+        // Tokens refreshed since the file was written are newer than the file's, and the
+        // file's refresh token may well have been rotated away
+        if let Some((_, existing_account)) = existing.get(&account.name) {
+            account.credentials = account
+                .credentials
+                .keep_refreshed(&existing_account.credentials);
+        }
+
+        match existing.get(&account.name) {
+            Some((_, existing_account)) if *existing_account == account => {
+                tracing::debug!(?account, "Account unchanged");
+            }
+
+            Some((account_id, _)) => {
+                tracing::info!(?account, "Updating account from accounts file");
+                repo.update_account(*account_id, &account).await?;
+            }
+
+            None => {
+                tracing::info!(?account, "Adding account from accounts file");
+                repo.add_account(&account).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Finishes purging accounts whose deletion was cut short.
+async fn resume_account_purges(repo: Arc<Repository>) {
+    let account_ids = match repo.list_deleting_accounts().await {
+        Ok(account_ids) => account_ids,
+        Err(e) => {
+            tracing::error!(?e, "Error listing deleting accounts");
+            return;
+        }
+    };
+
+    for account_id in account_ids {
+        match repo.delete_account(account_id).await {
+            Ok(deleted) => tracing::info!(account_id, deleted, "Account purged"),
+            Err(e) => tracing::error!(account_id, ?e, "Error purging account"),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AccountSettings {
+    /// Whether HTML bodies load remote images when the client doesn't say.
+    pub load_remote_images: RemoteImagePolicy,
+    /// Headers added to every draft, unless the draft sets a header of the same name itself.
+    pub custom_headers: BTreeMap<String, String>,
+    pub attachment_prefetch: AttachmentPrefetch,
+}
+
+/// Downloading the attachments of flagged and recent emails ahead of time, so they open while
+/// offline too.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AttachmentPrefetch {
+    pub enabled: bool,
+    /// Prefetching stops once the account's cached blobs take up this many bytes, so it never
+    /// crowds out what was opened.
+    pub max_cache_bytes: u64,
+    /// Larger attachments are only downloaded when opened.
+    pub max_attachment_bytes: u64,
+}
+
+impl Default for AttachmentPrefetch {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_cache_bytes: 256 * 1024 * 1024,
+            max_attachment_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RemoteImagePolicy {
+    /// Remote images can tell the sender the email was opened, so they're blocked by default.
+    #[default]
+    Never,
+    Always,
+    /// Only for emails from contacts, i.e. people the account has sent mail to.
+    Contacts,
+}
+
+impl super::Repository {
+    pub async fn get_account_settings(
+        &self,
+        account_id: AccountId,
+    ) -> anyhow::Result<AccountSettings> {
+        sqlx::query_scalar!(
+            "SELECT settings FROM account_settings WHERE account_id = ?",
+            account_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying account settings")?
+        .map(|settings| {
+            serde_json::from_str(&settings).context("Error deserializing account settings")
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+    }
+
+    pub async fn set_account_settings(
+        &self,
+        account_id: AccountId,
+        settings: &AccountSettings,
+    ) -> anyhow::Result<()> {
+        let settings =
+            serde_json::to_string(settings).context("Error serializing account settings")?;
+
+        let result = sqlx::query!(
+            "INSERT INTO account_settings (account_id, settings) VALUES (?, ?)
+             ON CONFLICT DO UPDATE SET settings = EXCLUDED.settings",
+            account_id,
+            settings
+        )
+        .execute(self.pool())
+        .await
+        .context("Error saving account settings")?;
+
+        self.notify_changes_with(result, &["account_settings"]);
+        Ok(())
+    }
+
+    /// Whether an email from `sender` should load remote images by default. Trusted senders
+    /// always do, whatever the policy.
+    pub async fn loads_remote_images(
+        &self,
+        account_id: AccountId,
+        sender: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        if let Some(sender) = sender
+            && self.is_trusted_sender(account_id, sender).await?
+        {
+            return Ok(true);
+        }
+
+        match self
+            .get_account_settings(account_id)
+            .await?
+            .load_remote_images
+        {
+            RemoteImagePolicy::Never => Ok(false),
+            RemoteImagePolicy::Always => Ok(true),
+            RemoteImagePolicy::Contacts => match sender {
+                Some(sender) => self.is_contact(account_id, sender).await,
+                None => Ok(false),
+            },
+        }
+    }
+
+    /// There's no address book, so anyone the account has sent mail to counts as a contact.
+    async fn is_contact(&self, account_id: AccountId, address: &str) -> anyhow::Result<bool> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM mailbox_emails me
+                JOIN mailboxes m ON m.account_id = me.account_id AND m.id = me.mailbox_id
+                JOIN emails e ON e.account_id = me.account_id AND e.id = me.email_id
+                WHERE me.account_id = ?1
+                  AND m.jmap_data->>'$.role' = 'sent'
+                  AND EXISTS (
+                    SELECT 1 FROM json_each(COALESCE(e.`to`, '[]'))
+                    WHERE lower(value->>'$.email') = lower(?2)
+                    UNION ALL
+                    SELECT 1 FROM json_each(COALESCE(e.cc, '[]'))
+                    WHERE lower(value->>'$.email') = lower(?2)
+                  )
+            ) AS "is_contact!: bool"
+            "#,
+            account_id,
+            address
+        )
+        .fetch_one(self.pool())
+        .await
+        .context("Error querying contacts")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::testing;
+    use jmap_client::email::Email;
+    use jmap_client::mailbox::Mailbox;
+    use serde_json::json;
+
+    async fn set_policy(
+        repo: &crate::repo::Repository,
+        account_id: AccountId,
+        policy: RemoteImagePolicy,
+    ) {
+        let settings = AccountSettings {
+            load_remote_images: policy,
+            ..Default::default()
+        };
+        repo.set_account_settings(account_id, &settings)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn never_by_default() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+
+        assert!(
+            !repo
+                .loads_remote_images(account_id, Some("bob@example.com"))
+                .await
+                .unwrap()
+        );
+        assert!(!repo.loads_remote_images(account_id, None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn policy_applies_to_unknown_senders() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+
+        set_policy(&repo, account_id, RemoteImagePolicy::Always).await;
+        assert!(
+            repo.loads_remote_images(account_id, Some("bob@example.com"))
+                .await
+                .unwrap()
+        );
+
+        set_policy(&repo, account_id, RemoteImagePolicy::Contacts).await;
+        assert!(
+            !repo
+                .loads_remote_images(account_id, Some("bob@example.com"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn trusted_senders_override_the_policy() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        set_policy(&repo, account_id, RemoteImagePolicy::Never).await;
+        repo.add_trusted_sender(account_id, "bob@example.com")
+            .await
+            .unwrap();
+        repo.add_trusted_sender(account_id, "*@corp.example")
+            .await
+            .unwrap();
+
+        assert!(
+            repo.loads_remote_images(account_id, Some("Bob@Example.com"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            repo.loads_remote_images(account_id, Some("ann@corp.example"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !repo
+                .loads_remote_images(account_id, Some("eve@example.com"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn contacts_are_people_mailed_from_sent() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        set_policy(&repo, account_id, RemoteImagePolicy::Contacts).await;
+
+        // Mailboxes borrow their strings, so they only deserialize from text
+        let sent = json!({"id": "sent", "name": "Sent", "role": "sent"}).to_string();
+        let sent: Mailbox = serde_json::from_str(&sent).unwrap();
+        repo.update_mailboxes(account_id, "s1", vec![sent], vec![])
+            .await
+            .unwrap();
+
+        let email: Email = serde_json::from_value(json!({
+            "id": "e1",
+            "mailboxIds": {"sent": true},
+            "to": [{"email": "bob@example.com"}],
+            "receivedAt": "2025-01-01T00:00:00Z",
+        }))
+        .unwrap();
+        repo.update_emails(account_id, &[email]).await.unwrap();
+
+        assert!(
+            repo.loads_remote_images(account_id, Some("BOB@example.com"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !repo
+                .loads_remote_images(account_id, Some("eve@example.com"))
+                .await
+                .unwrap()
+        );
+        assert!(!repo.loads_remote_images(account_id, None).await.unwrap());
+    }
+}
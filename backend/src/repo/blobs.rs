@@ -1,5 +1,17 @@
 use crate::jmap_account::AccountId;
+use crate::util::sniff::SNIFF_LEN;
 use anyhow::Context;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// What's known of a blob without reading all of it.
+pub struct BlobMetadata {
+    pub name: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: Option<i64>,
+    /// The first bytes of the blob, when it's cached.
+    pub head: Option<Vec<u8>>,
+}
 
 pub struct Blob {
     pub name: Option<String>,
@@ -7,6 +19,30 @@ pub struct Blob {
     pub data: Vec<u8>,
 }
 
+/// An attachment of a stored email that isn't in the blob cache yet.
+pub struct UncachedAttachment {
+    pub blob_id: String,
+    pub name: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: i64,
+}
+
+/// Which parts of an email can be read from the blob cache, e.g. while offline.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailAvailability {
+    /// Whether a part of the HTML or text body is cached.
+    pub body_cached: bool,
+    pub attachments: Vec<AttachmentAvailability>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentAvailability {
+    pub blob_id: String,
+    pub cached: bool,
+}
+
 impl super::Repository {
     pub async fn get_blob(
         &self,
@@ -52,4 +88,182 @@ impl super::Repository {
         .context("Failed to save blob")?;
         Ok(())
     }
+
+    /// Looks a blob up in the cache, or else among the stored emails' attachments and messages.
+    /// Doesn't count as an access to a cached blob.
+    pub async fn get_blob_metadata(
+        &self,
+        account_id: AccountId,
+        blob_id: &str,
+    ) -> anyhow::Result<Option<BlobMetadata>> {
+        let head_len = SNIFF_LEN as i64;
+        let cached = sqlx::query!(
+            r#"SELECT name, mime_type,
+                      length(data) AS "size!: i64",
+                      substr(data, 1, ?) AS "head!: Vec<u8>"
+               FROM blobs WHERE account_id = ? AND id = ?"#,
+            head_len,
+            account_id,
+            blob_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Failed to fetch cached blob metadata")?;
+
+        if let Some(r) = cached {
+            return Ok(Some(BlobMetadata {
+                name: r.name,
+                mime_type: r.mime_type,
+                size: Some(r.size),
+                head: Some(r.head),
+            }));
+        }
+
+        let r = sqlx::query!(
+            r#"SELECT a.value->>'$.name' AS "name: String",
+                      a.value->>'$.type' AS "mime_type: String",
+                      a.value->>'$.size' AS "size: i64"
+               FROM emails e, json_each(e.jmap_data, '$.attachments') a
+               WHERE e.account_id = ?1 AND a.value->>'$.blobId' = ?2
+               UNION ALL
+               SELECT NULL, 'message/rfc822', e.jmap_data->>'$.size'
+               FROM emails e
+               WHERE e.account_id = ?1 AND e.jmap_data->>'$.blobId' = ?2
+               LIMIT 1"#,
+            account_id,
+            blob_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Failed to look up blob among emails")?;
+
+        Ok(r.map(|r| BlobMetadata {
+            name: r.name,
+            mime_type: r.mime_type,
+            size: r.size,
+            head: None,
+        }))
+    }
+
+    /// Which of a stored email's body and attachments are in the blob cache, or `None` when the
+    /// email isn't stored.
+    pub async fn get_email_availability(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+    ) -> anyhow::Result<Option<EmailAvailability>> {
+        let Some(body_cached) = sqlx::query_scalar!(
+            r#"SELECT EXISTS (
+                   SELECT 1 FROM json_each(e.jmap_data, '$.htmlBody') p
+                   JOIN blobs b ON b.account_id = e.account_id AND b.id = p.value->>'$.blobId'
+                   UNION ALL
+                   SELECT 1 FROM json_each(e.jmap_data, '$.textBody') p
+                   JOIN blobs b ON b.account_id = e.account_id AND b.id = p.value->>'$.blobId'
+               ) AS "body_cached!: bool"
+               FROM emails e WHERE e.account_id = ? AND e.id = ?"#,
+            account_id,
+            email_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying cached body")?
+        else {
+            return Ok(None);
+        };
+
+        let attachments = sqlx::query_as!(
+            AttachmentAvailability,
+            r#"SELECT a.value->>'$.blobId' AS "blob_id!: String",
+                      EXISTS (SELECT 1 FROM blobs b
+                              WHERE b.account_id = e.account_id
+                                AND b.id = a.value->>'$.blobId') AS "cached!: bool"
+               FROM emails e, json_each(e.jmap_data, '$.attachments') a
+               WHERE e.account_id = ? AND e.id = ? AND a.value->>'$.blobId' IS NOT NULL
+               ORDER BY a.key"#,
+            account_id,
+            email_id
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying cached attachments")?;
+
+        Ok(Some(EmailAvailability {
+            body_cached,
+            attachments,
+        }))
+    }
+
+    /// The mailboxes of the stored emails that refer to the blob, as their message, a body part
+    /// or an attachment.
+    pub async fn get_mailbox_ids_of_blob(
+        &self,
+        account_id: AccountId,
+        blob_id: &str,
+    ) -> anyhow::Result<HashSet<String>> {
+        let rows = sqlx::query_scalar!(
+            r#"SELECT DISTINCT me.mailbox_id
+               FROM mailbox_emails me
+               JOIN emails e ON e.account_id = me.account_id AND e.id = me.email_id
+               WHERE me.account_id = ?1
+                 AND (e.jmap_data->>'$.blobId' = ?2
+                      OR EXISTS (SELECT 1 FROM json_each(e.jmap_data, '$.attachments') p
+                                 WHERE p.value->>'$.blobId' = ?2)
+                      OR EXISTS (SELECT 1 FROM json_each(e.jmap_data, '$.htmlBody') p
+                                 WHERE p.value->>'$.blobId' = ?2)
+                      OR EXISTS (SELECT 1 FROM json_each(e.jmap_data, '$.textBody') p
+                                 WHERE p.value->>'$.blobId' = ?2))"#,
+            account_id,
+            blob_id
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying mailboxes of blob")?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    /// How many bytes the account's cached blobs take up.
+    pub async fn get_blob_cache_size(&self, account_id: AccountId) -> anyhow::Result<i64> {
+        sqlx::query_scalar!(
+            r#"SELECT COALESCE(SUM(length(data)), 0) AS "size!: i64"
+               FROM blobs WHERE account_id = ?"#,
+            account_id
+        )
+        .fetch_one(self.pool())
+        .await
+        .context("Error querying blob cache size")
+    }
+
+    /// Attachments of the account's emails that aren't cached and are at most `max_size` bytes,
+    /// those of flagged emails first, then the most recent.
+    pub async fn find_uncached_attachments(
+        &self,
+        account_id: AccountId,
+        max_size: i64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<UncachedAttachment>> {
+        sqlx::query_as!(
+            UncachedAttachment,
+            r#"SELECT a.value->>'$.blobId' AS "blob_id!: String",
+                      a.value->>'$.name' AS "name: String",
+                      a.value->>'$.type' AS "mime_type: String",
+                      a.value->>'$.size' AS "size!: i64"
+               FROM emails e, json_each(e.jmap_data, '$.attachments') a
+               WHERE e.account_id = ?1
+                 AND a.value->>'$.blobId' IS NOT NULL
+                 AND a.value->>'$.size' <= ?2
+                 AND NOT EXISTS (SELECT 1 FROM blobs b
+                                 WHERE b.account_id = e.account_id
+                                   AND b.id = a.value->>'$.blobId')
+               ORDER BY COALESCE(e.jmap_data->>'$.keywords."$flagged"', FALSE) DESC,
+                        e.received_at DESC
+               LIMIT ?3"#,
+            account_id,
+            max_size,
+            limit
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying uncached attachments")
+    }
 }
@@ -0,0 +1,68 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::Row;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableSize {
+    pub name: String,
+    pub rows: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountCounts {
+    pub mailboxes: i64,
+    pub emails: i64,
+    /// Mailboxes with a full sync that was interrupted and is yet to be resumed.
+    pub interrupted_mailbox_syncs: i64,
+}
+
+impl super::Repository {
+    /// Row counts of every table, migrations bookkeeping aside.
+    pub async fn get_table_sizes(&self) -> anyhow::Result<Vec<TableSize>> {
+        let names = sqlx::query_scalar!(
+            r#"SELECT name AS "name!: String" FROM sqlite_master
+               WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name NOT LIKE '_sqlx_%'
+               ORDER BY name"#
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error listing tables")?;
+
+        let mut sizes = Vec::with_capacity(names.len());
+        for name in names {
+            // Names come from sqlite_master, but quote them anyway
+            let rows = sqlx::query(&format!(
+                "SELECT count(*) FROM \"{}\"",
+                name.replace('"', "\"\"")
+            ))
+            .fetch_one(self.pool())
+            .await
+            .with_context(|| format!("Error counting rows of {name}"))?
+            .get(0);
+
+            sizes.push(TableSize { name, rows });
+        }
+
+        Ok(sizes)
+    }
+
+    pub async fn get_account_counts(&self, account_id: AccountId) -> anyhow::Result<AccountCounts> {
+        sqlx::query_as!(
+            AccountCounts,
+            r#"SELECT
+                (SELECT count(*) FROM mailboxes WHERE account_id = ?1) AS "mailboxes!: i64",
+                (SELECT count(*) FROM emails WHERE account_id = ?1) AS "emails!: i64",
+                (SELECT count(*) FROM mailboxes
+                 WHERE account_id = ?1 AND email_sync_resume_state IS NOT NULL)
+                    AS "interrupted_mailbox_syncs!: i64"
+            "#,
+            account_id
+        )
+        .fetch_one(self.pool())
+        .await
+        .context("Error counting account data")
+    }
+}
@@ -1,12 +1,17 @@
+use super::NewEmails;
+use super::search::fts_query;
 use crate::jmap_account::AccountId;
 use crate::jmap_api::{EmailSort, EmailSortColumn};
+use crate::util::snippet::highlight_snippet;
 use anyhow::Context;
 use itertools::Itertools;
 use jmap_client::email::Email;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use sqlx::Row;
 use sqlx::sqlite::SqliteRow;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Instant;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct EmailDbQuery {
@@ -15,10 +20,29 @@ pub struct EmailDbQuery {
     #[serde(rename = "searchKeyword")]
     pub search_keyword: Option<String>,
     pub sorts: Vec<EmailSort>,
+    /// Only emails with, or without, the `$flagged` keyword.
+    pub flagged: Option<bool>,
+    /// Only emails without, or with, the `$seen` keyword.
+    pub unread: Option<bool>,
+    #[serde(rename = "hasAttachment")]
+    pub has_attachment: Option<bool>,
+    /// Page size. The watch API clamps it to its maximum list limit.
     pub limit: usize,
     pub offset: usize,
 }
 
+/// What an account export needs to know of an email.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportEmail {
+    pub id: String,
+    #[serde(skip)]
+    pub blob_id: Option<String>,
+    pub mailbox_ids: Box<RawValue>,
+    pub keywords: Box<RawValue>,
+    pub received_at: Option<String>,
+}
+
 impl super::Repository {
     pub async fn find_missing_email_ids(
         &self,
@@ -57,11 +81,25 @@ impl super::Repository {
         Ok(())
     }
 
+    /// Stores the emails as fetched from the server. Stored copies that differ are replaced, so
+    /// keyword and mailbox changes come through, while identical ones are left alone and don't
+    /// set off a change notification.
     pub async fn update_emails(
         &self,
         account_id: AccountId,
         emails: &[Email],
     ) -> anyhow::Result<()> {
+        // Only worth finding out which emails are new when someone is listening for them
+        let new_ids = if self.new_emails.receiver_count() > 0 {
+            let ids = emails
+                .iter()
+                .filter_map(|e| e.id().map(str::to_string))
+                .collect::<Vec<_>>();
+            Some(self.find_missing_email_ids(account_id, &ids).await?)
+        } else {
+            None
+        };
+
         let emails_as_json = serde_json::to_string(emails).context("Error serializing emails")?;
         let mut changes = 0;
 
@@ -71,7 +109,7 @@ impl super::Repository {
             WHERE true
             ON CONFLICT DO UPDATE
                 SET jmap_data = EXCLUDED.jmap_data
-                WHERE subject IS NULL
+                WHERE jmap_data IS NOT EXCLUDED.jmap_data
             ",
             account_id,
             emails_as_json
@@ -85,18 +123,336 @@ impl super::Repository {
             self.notify_changes(&["emails", "mailbox_emails"]);
         }
 
+        if let Some(new_ids) = new_ids
+            && !new_ids.is_empty()
+        {
+            let _ = self.new_emails.send(NewEmails {
+                account_id,
+                emails: emails
+                    .iter()
+                    .filter(|e| e.id().is_some_and(|id| new_ids.contains(id)))
+                    .cloned()
+                    .collect(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Applies keyword changes that were made on the server to the stored emails, so that they
+    /// show before the next sync: keywords mapped to `true` are set, those mapped to `false`
+    /// cleared. When `$seen` changes, the unread counts of the mailboxes the emails are in follow.
+    /// Returns the keywords of each stored email now. Emails that aren't stored are skipped.
+    pub async fn set_emails_keywords(
+        &self,
+        account_id: AccountId,
+        ids: &[String],
+        changes: &BTreeMap<String, bool>,
+    ) -> anyhow::Result<BTreeMap<String, BTreeMap<String, bool>>> {
+        let ids_json = serde_json::to_string(ids).context("Error serializing email ids")?;
+        let mut tx = self.pool().begin().await?;
+
+        let rows = sqlx::query!(
+            r#"SELECT id AS "id!: String",
+                      COALESCE(jmap_data->'$.keywords', '{}') AS "keywords!: String",
+                      COALESCE(jmap_data->'$.mailboxIds', '{}') AS "mailbox_ids!: String"
+               FROM emails
+               WHERE account_id = ? AND id IN (SELECT value FROM json_each(?))"#,
+            account_id,
+            ids_json
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .context("Error querying email keywords")?;
+
+        let mut updated = BTreeMap::new();
+        let mut unread_changed = false;
+        for row in rows {
+            let mut keywords = serde_json::from_str::<BTreeMap<String, bool>>(&row.keywords)
+                .context("Error deserializing keywords")?;
+            keywords.retain(|_, set| *set);
+            let was_seen = keywords.contains_key("$seen");
+
+            for (keyword, value) in changes {
+                if *value {
+                    keywords.insert(keyword.clone(), true);
+                } else {
+                    keywords.remove(keyword);
+                }
+            }
+            let is_seen = keywords.contains_key("$seen");
+
+            let keywords_json =
+                serde_json::to_string(&keywords).context("Error serializing keywords")?;
+            sqlx::query!(
+                "UPDATE emails SET jmap_data = json_set(jmap_data, '$.keywords', json(?))
+                 WHERE account_id = ? AND id = ?",
+                keywords_json,
+                account_id,
+                row.id
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Error updating email keywords")?;
+
+            let unread_change: i64 = match (was_seen, is_seen) {
+                (true, false) => 1,
+                (false, true) => -1,
+                _ => 0,
+            };
+            if unread_change != 0 {
+                unread_changed = true;
+                sqlx::query!(
+                    "UPDATE mailboxes
+                     SET jmap_data = json_set(jmap_data, '$.unreadEmails',
+                         MAX(0, COALESCE(jmap_data->>'$.unreadEmails', 0) + ?1))
+                     WHERE account_id = ?2
+                       AND id IN (SELECT key FROM json_each(?3) WHERE value = true)",
+                    unread_change,
+                    account_id,
+                    row.mailbox_ids
+                )
+                .execute(&mut *tx)
+                .await
+                .context("Error updating mailbox unread counts")?;
+            }
+
+            updated.insert(row.id, keywords);
+        }
+
+        tx.commit().await?;
+
+        if unread_changed {
+            self.notify_changes(&["emails", "mailboxes"]);
+        } else if !updated.is_empty() {
+            self.notify_changes(&["emails"]);
+        }
+        Ok(updated)
+    }
+
+    /// Stores the preview fetched for an email that was synced without one, which also puts it
+    /// in the email's summary.
+    pub async fn set_email_preview(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+        preview: &str,
+    ) -> anyhow::Result<()> {
+        let result = sqlx::query!(
+            "UPDATE emails SET jmap_data = json_set(jmap_data, '$.preview', ?3)
+             WHERE account_id = ?1 AND id = ?2",
+            account_id,
+            email_id,
+            preview
+        )
+        .execute(self.pool())
+        .await
+        .context("Error updating email preview")?;
+
+        self.notify_changes_with(result, &["emails"]);
+        Ok(())
+    }
+
+    /// Applies a move that was made on the server to the stored email, so that it shows in its
+    /// new mailbox before the next sync. Like [`JmapApi::move_email`], without `from` the email
+    /// leaves all its mailboxes.
+    ///
+    /// [`JmapApi::move_email`]: crate::jmap_api::JmapApi::move_email
+    pub async fn move_email_mailbox(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+        from: Option<&str>,
+        to: &str,
+    ) -> anyhow::Result<()> {
+        let result = sqlx::query!(
+            "UPDATE emails
+            SET jmap_data = CASE
+                WHEN ?3 IS NULL
+                THEN json_set(jmap_data, '$.mailboxIds', json_object(?4, json('true')))
+                ELSE json_patch(
+                    jmap_data,
+                    json_object('mailboxIds', json_object(?3, json('null'), ?4, json('true')))
+                )
+            END
+            WHERE account_id = ?1 AND id = ?2",
+            account_id,
+            email_id,
+            from,
+            to
+        )
+        .execute(self.pool())
+        .await
+        .context("Error updating email mailbox")?;
+
+        self.notify_changes_with(result, &["emails", "mailbox_emails"]);
         Ok(())
     }
 
-    pub async fn get_emails(
+    /// Ids of the stored emails with one of the given `Message-ID`s, without the angle brackets.
+    pub async fn find_email_ids_by_message_id(
+        &self,
+        account_id: AccountId,
+        message_ids: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        let message_ids =
+            serde_json::to_string(message_ids).context("Error serializing message ids")?;
+
+        sqlx::query_scalar!(
+            r#"SELECT DISTINCT e.id AS "id!: String"
+               FROM emails e, json_each(e.jmap_data, '$.messageId') m
+               WHERE e.account_id = ?1 AND m.value IN (SELECT value FROM json_each(?2))"#,
+            account_id,
+            message_ids
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying emails by message id")
+    }
+
+    /// Lists the emails to export, oldest first. `after` and `before` are compared with the
+    /// emails' `receivedAt`, e.g. `2025-01-31T00:00:00Z`.
+    pub async fn get_export_emails(
+        &self,
+        account_id: AccountId,
+        mailbox_id: Option<&str>,
+        after: Option<&str>,
+        before: Option<&str>,
+    ) -> anyhow::Result<Vec<ExportEmail>> {
+        sqlx::query!(
+            r#"
+            SELECT e.id,
+                   e.jmap_data->>'$.blobId' AS "blob_id: String",
+                   COALESCE(e.jmap_data->'$.mailboxIds', '{}') AS "mailbox_ids!: String",
+                   COALESCE(e.jmap_data->'$.keywords', '{}') AS "keywords!: String",
+                   e.received_at
+            FROM emails e
+            WHERE e.account_id = ?1
+              AND (?2 IS NULL OR EXISTS (
+                  SELECT 1 FROM mailbox_emails me
+                  WHERE me.account_id = ?1 AND me.mailbox_id = ?2 AND me.email_id = e.id))
+              AND (?3 IS NULL OR e.received_at >= ?3)
+              AND (?4 IS NULL OR e.received_at < ?4)
+            ORDER BY e.received_at, e.id
+            "#,
+            account_id,
+            mailbox_id,
+            after,
+            before
+        )
+        .try_map(|r| {
+            Ok(ExportEmail {
+                id: r.id,
+                blob_id: r.blob_id,
+                mailbox_ids: RawValue::from_string(r.mailbox_ids)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                keywords: RawValue::from_string(r.keywords)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                received_at: r.received_at,
+            })
+        })
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying emails to export")
+    }
+
+    /// Looks up the summaries of the given emails, in the order of `ids`. Emails that aren't
+    /// stored are left out.
+    pub async fn get_email_summaries_by_id(
+        &self,
+        account_id: AccountId,
+        ids: &[String],
+    ) -> anyhow::Result<Vec<(String, Box<RawValue>)>> {
+        let ids = serde_json::to_string(ids).context("Error serializing email ids")?;
+
+        sqlx::query!(
+            r#"SELECT e.id, e.summary_json AS "summary_json!: String"
+               FROM json_each(?2) ids
+               JOIN emails e ON e.account_id = ?1 AND e.id = ids.value
+               WHERE e.summary_json IS NOT NULL
+               ORDER BY ids.key"#,
+            account_id,
+            ids
+        )
+        .try_map(|r| {
+            Ok((
+                r.id,
+                RawValue::from_string(r.summary_json)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+            ))
+        })
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying email summaries")
+    }
+
+    /// A page of the emails in a mailbox, oldest first, for exporting mailboxes too large to
+    /// list at once. `after` is the `receivedAt` and id of the last email of the previous page.
+    pub async fn get_mailbox_export_page(
+        &self,
+        account_id: AccountId,
+        mailbox_id: &str,
+        after: Option<(&str, &str)>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<ExportEmail>> {
+        let (after_received_at, after_id) = after.unzip();
+
+        sqlx::query!(
+            r#"
+            SELECT e.id,
+                   e.jmap_data->>'$.blobId' AS "blob_id: String",
+                   COALESCE(e.jmap_data->'$.mailboxIds', '{}') AS "mailbox_ids!: String",
+                   COALESCE(e.jmap_data->'$.keywords', '{}') AS "keywords!: String",
+                   e.received_at
+            FROM mailbox_emails me
+            JOIN emails e ON e.account_id = me.account_id AND e.id = me.email_id
+            WHERE me.account_id = ?1 AND me.mailbox_id = ?2
+              AND (?3 IS NULL OR (COALESCE(e.received_at, ''), e.id) > (?3, ?4))
+            ORDER BY COALESCE(e.received_at, ''), e.id
+            LIMIT ?5
+            "#,
+            account_id,
+            mailbox_id,
+            after_received_at,
+            after_id,
+            limit
+        )
+        .try_map(|r| {
+            Ok(ExportEmail {
+                id: r.id,
+                blob_id: r.blob_id,
+                mailbox_ids: RawValue::from_string(r.mailbox_ids)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                keywords: RawValue::from_string(r.keywords)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                received_at: r.received_at,
+            })
+        })
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying mailbox emails to export")
+    }
+
+    /// Lists the summaries of the emails matching `query`, i.e. what list views show of them.
+    /// With a search keyword, each also gets a `snippet` of its preview around the matches.
+    pub async fn get_email_summaries(
         &self,
         account_id: AccountId,
         query: &EmailDbQuery,
-    ) -> anyhow::Result<Vec<Email>> {
-        let sort_clause = query
+    ) -> anyhow::Result<Vec<Box<RawValue>>> {
+        let sorts = query
             .sorts
             .iter()
-            .map(|sort| (sort.column.to_sql_column(), sort.asc))
+            .map(|sort| {
+                sort.column
+                    .to_sql_column()
+                    .map(|column| (column, sort.asc))
+                    .with_context(|| format!("Sorting by {:?} is not supported", sort.column))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let sort_clause = sorts
+            .into_iter()
             .chain(std::iter::once(("id", true)))
             .map(|(column, asc)| {
                 if asc {
@@ -107,10 +463,12 @@ impl super::Repository {
             })
             .join(", ");
 
+        let start = Instant::now();
+
         //language=sqlite
-        sqlx::query(&format!(
+        let r = sqlx::query(&format!(
             "
-            SELECT jmap_data FROM emails
+            SELECT summary_json, jmap_data->>'$.preview' FROM emails
             WHERE account_id = ?1
                 AND (
                     ?2 IS NULL OR
@@ -121,7 +479,23 @@ impl super::Repository {
                 )
                 AND (
                     ?3 IS NULL OR
-                    subject LIKE '%' || ?3 || '%'
+                    subject LIKE '%' || ?3 || '%' OR
+                    id IN (SELECT s.email_id
+                           FROM email_search f
+                           JOIN email_search_ids s ON s.rowid = f.rowid
+                           WHERE email_search MATCH ?9 AND s.account_id = ?1)
+                )
+                AND (
+                    ?6 IS NULL OR
+                    COALESCE(jmap_data->>'$.keywords.\"$flagged\"', FALSE) = ?6
+                )
+                AND (
+                    ?7 IS NULL OR
+                    (NOT COALESCE(jmap_data->>'$.keywords.\"$seen\"', FALSE)) = ?7
+                )
+                AND (
+                    ?8 IS NULL OR
+                    COALESCE(jmap_data->>'$.hasAttachment', FALSE) = ?8
                 )
             ORDER BY {sort_clause}
             LIMIT ?4, ?5
@@ -132,20 +506,299 @@ impl super::Repository {
         .bind(query.search_keyword.as_ref())
         .bind(query.offset as i64)
         .bind(query.limit as i64)
+        .bind(query.flagged)
+        .bind(query.unread)
+        .bind(query.has_attachment)
+        .bind(query.search_keyword.as_deref().and_then(fts_query))
         .try_map(|row: SqliteRow| {
-            serde_json::from_str::<Email>(&row.get::<String, _>(0))
-                .map_err(|e| sqlx::Error::Decode(Box::new(e)))
+            let summary = row.get::<String, _>(0);
+            let summary = match &query.search_keyword {
+                Some(keyword) => with_snippet(
+                    &summary,
+                    row.get::<Option<String>, _>(1)
+                        .as_deref()
+                        .unwrap_or_default(),
+                    keyword,
+                )
+                .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                None => summary,
+            };
+            RawValue::from_string(summary).map_err(|e| sqlx::Error::Decode(Box::new(e)))
         })
         .fetch_all(self.pool())
         .await
-        .context("Error querying emails")
+        .context("Error querying emails");
+
+        tracing::info!("Fetched emails in {:?}ms", start.elapsed().as_millis());
+
+        r
+    }
+}
+
+impl super::Repository {
+    /// Drafts synced from the server, i.e. stored emails carrying the `$draft` keyword.
+    pub async fn get_draft_emails(&self, account_id: AccountId) -> anyhow::Result<Vec<Email>> {
+        sqlx::query!(
+            r#"SELECT jmap_data FROM emails
+               WHERE account_id = ? AND COALESCE(jmap_data->>'$.keywords."$draft"', FALSE)
+               ORDER BY received_at DESC, id"#,
+            account_id
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying drafts")?
+        .into_iter()
+        .map(|r| serde_json::from_str::<Email>(&r.jmap_data).context("Error deserializing draft"))
+        .collect()
+    }
+
+    pub async fn get_email(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+    ) -> anyhow::Result<Option<Email>> {
+        sqlx::query!(
+            "SELECT jmap_data FROM emails WHERE account_id = ? AND id = ?",
+            account_id,
+            email_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying email")?
+        .map(|r| serde_json::from_str::<Email>(&r.jmap_data).context("Error deserializing email"))
+        .transpose()
+    }
+
+    pub async fn get_draft_email(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+    ) -> anyhow::Result<Option<Email>> {
+        sqlx::query!(
+            r#"SELECT jmap_data FROM emails
+               WHERE account_id = ? AND id = ? AND COALESCE(jmap_data->>'$.keywords."$draft"', FALSE)"#,
+            account_id,
+            email_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying draft")?
+        .map(|r| serde_json::from_str::<Email>(&r.jmap_data).context("Error deserializing draft"))
+        .transpose()
     }
 }
 
 impl EmailSortColumn {
-    fn to_sql_column(&self) -> &'static str {
+    /// The column of the emails table to sort by, if the column can be sorted by locally.
+    pub fn to_sql_column(self) -> Option<&'static str> {
         match self {
-            Self::Date => "received_at",
+            Self::Date => Some("received_at"),
+            Self::SentAt => Some("sent_at"),
+            Self::Subject => Some("subject"),
+            Self::From => Some("from_addr"),
+            Self::Size => None,
         }
     }
 }
+
+/// Adds a `snippet` of `preview` highlighting `keyword` to a stored summary.
+fn with_snippet(summary: &str, preview: &str, keyword: &str) -> serde_json::Result<String> {
+    let mut summary = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(summary)?;
+    summary.insert(
+        "snippet".to_string(),
+        highlight_snippet(preview, keyword).into(),
+    );
+    serde_json::to_string(&summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repo::testing;
+    use jmap_client::email::Email;
+    use jmap_client::mailbox::Mailbox;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn keyword_changes_keep_unread_counts() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+
+        let inbox = json!({"id": "inbox", "name": "Inbox", "unreadEmails": 1}).to_string();
+        let inbox: Mailbox = serde_json::from_str(&inbox).unwrap();
+        repo.update_mailboxes(account_id, "s1", vec![inbox], vec![])
+            .await
+            .unwrap();
+
+        let email: Email = serde_json::from_value(json!({
+            "id": "e1",
+            "mailboxIds": {"inbox": true},
+            "keywords": {"$flagged": true},
+            "receivedAt": "2025-01-01T00:00:00Z",
+        }))
+        .unwrap();
+        repo.update_emails(account_id, &[email]).await.unwrap();
+
+        let unread = async || repo.get_mailboxes(account_id).await.unwrap()[0].unread_emails();
+
+        let changes =
+            BTreeMap::from([("$seen".to_string(), true), ("$flagged".to_string(), false)]);
+        let ids = ["e1".to_string(), "missing".to_string()];
+        let updated = repo
+            .set_emails_keywords(account_id, &ids, &changes)
+            .await
+            .unwrap();
+        assert_eq!(
+            updated,
+            BTreeMap::from([(
+                "e1".to_string(),
+                BTreeMap::from([("$seen".to_string(), true)])
+            )])
+        );
+        assert_eq!(unread().await, 0);
+
+        // Setting a keyword that's already set leaves the count alone
+        repo.set_emails_keywords(account_id, &ids, &changes)
+            .await
+            .unwrap();
+        assert_eq!(unread().await, 0);
+
+        let unseen = BTreeMap::from([("$seen".to_string(), false)]);
+        repo.set_emails_keywords(account_id, &ids, &unseen)
+            .await
+            .unwrap();
+        assert_eq!(unread().await, 1);
+    }
+
+    #[tokio::test]
+    async fn restored_keywords_update_the_thread() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        let inbox = json!({"id": "inbox", "name": "Inbox"}).to_string();
+        let inbox: Mailbox = serde_json::from_str(&inbox).unwrap();
+        repo.update_mailboxes(account_id, "s1", vec![inbox], vec![])
+            .await
+            .unwrap();
+
+        let email = |id: &str, keywords: serde_json::Value| -> Email {
+            serde_json::from_value(json!({
+                "id": id,
+                "threadId": "t1",
+                "mailboxIds": {"inbox": true},
+                "keywords": keywords,
+                "receivedAt": "2025-01-01T00:00:00Z",
+            }))
+            .unwrap()
+        };
+        repo.update_emails(
+            account_id,
+            &[email("e1", json!({})), email("e2", json!({}))],
+        )
+        .await
+        .unwrap();
+
+        let unread = async || {
+            let thread = repo.get_email_thread(account_id, "e1").await.unwrap();
+            thread.iter().filter(|e| e.unread).count()
+        };
+        assert_eq!(unread().await, 2);
+
+        // Reading an email on the server reaches the thread, and whoever watches emails
+        let mut changes = repo.subscribe_db_changes();
+        let seen = email("e1", json!({"$seen": true}));
+        repo.update_emails(account_id, std::slice::from_ref(&seen))
+            .await
+            .unwrap();
+        assert!(changes.try_recv().unwrap().tables.contains(&"emails"));
+        assert_eq!(unread().await, 1);
+
+        // Storing it again as it is changes nothing
+        repo.update_emails(account_id, &[seen]).await.unwrap();
+        assert!(changes.try_recv().is_err());
+        assert_eq!(unread().await, 1);
+    }
+
+    #[tokio::test]
+    async fn columns_follow_the_stored_json() {
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        let mailboxes = ["inbox", "archive"]
+            .map(|id| serde_json::from_str(&json!({"id": id, "name": id}).to_string()).unwrap());
+        repo.update_mailboxes(account_id, "s1", mailboxes.to_vec(), vec![])
+            .await
+            .unwrap();
+
+        let store = async |email: serde_json::Value| {
+            let email: Email = serde_json::from_value(email).unwrap();
+            repo.update_emails(account_id, &[email]).await.unwrap();
+        };
+        let columns = async || -> (Option<String>, Option<String>, Option<String>) {
+            sqlx::query_as("SELECT thread_id, received_at, subject FROM emails WHERE id = 'e1'")
+                .fetch_one(repo.pool())
+                .await
+                .unwrap()
+        };
+        let mailbox_rows = async || -> Vec<(String, String, String)> {
+            sqlx::query_as(
+                "SELECT mailbox_id, thread_id, received_at FROM mailbox_emails
+                 WHERE email_id = 'e1' ORDER BY mailbox_id",
+            )
+            .fetch_all(repo.pool())
+            .await
+            .unwrap()
+        };
+        let row = |mailbox: &str, thread: &str, received: &str| {
+            (
+                mailbox.to_string(),
+                thread.to_string(),
+                received.to_string(),
+            )
+        };
+
+        store(json!({
+            "id": "e1",
+            "threadId": "t1",
+            "mailboxIds": {"inbox": true},
+            "subject": "Hello",
+            "receivedAt": "2025-01-01T00:00:00Z",
+        }))
+        .await;
+        assert_eq!(
+            columns().await,
+            (
+                Some("t1".to_string()),
+                Some("2025-01-01T00:00:00Z".to_string()),
+                Some("Hello".to_string())
+            )
+        );
+        assert_eq!(
+            mailbox_rows().await,
+            [row("inbox", "t1", "2025-01-01T00:00:00Z")]
+        );
+
+        store(json!({
+            "id": "e1",
+            "threadId": "t2",
+            "mailboxIds": {"archive": true},
+            "receivedAt": "2025-01-02T00:00:00Z",
+        }))
+        .await;
+        assert_eq!(
+            columns().await,
+            (
+                Some("t2".to_string()),
+                Some("2025-01-02T00:00:00Z".to_string()),
+                None
+            )
+        );
+        assert_eq!(
+            mailbox_rows().await,
+            [row("archive", "t2", "2025-01-02T00:00:00Z")]
+        );
+
+        // Without a thread or a time, the email is a thread of its own
+        store(json!({"id": "e1", "mailboxIds": {"inbox": true}})).await;
+        assert_eq!(columns().await, (None, None, None));
+        assert_eq!(mailbox_rows().await, [row("inbox", "e1", "")]);
+    }
+}
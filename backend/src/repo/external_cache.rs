@@ -0,0 +1,139 @@
+use anyhow::Context;
+
+/// A remote resource cached by the proxy.
+pub struct ExternalResource {
+    pub content_type: String,
+    pub data: Vec<u8>,
+    /// Seconds the resource stays fresh for, from now.
+    pub max_age: i64,
+}
+
+impl super::Repository {
+    /// The cached resource, unless it's missing or has expired.
+    pub async fn get_external_cache(&self, url: &str) -> anyhow::Result<Option<ExternalResource>> {
+        sqlx::query_as!(
+            ExternalResource,
+            r#"UPDATE external_cache SET last_accessed = CURRENT_TIMESTAMP
+             WHERE url = ? AND expires_at > CURRENT_TIMESTAMP
+             RETURNING content_type, data,
+                 unixepoch(expires_at) - unixepoch('now') AS "max_age!: i64""#,
+            url
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error fetching cached resource")
+    }
+
+    pub async fn put_external_cache(
+        &self,
+        url: &str,
+        ExternalResource {
+            content_type,
+            data,
+            max_age,
+        }: &ExternalResource,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO external_cache (url, content_type, data, last_accessed, expires_at)
+             VALUES (?, ?, ?, CURRENT_TIMESTAMP, datetime('now', format('%+d seconds', ?)))
+             ON CONFLICT DO UPDATE SET content_type = EXCLUDED.content_type,
+                                       data = EXCLUDED.data,
+                                       last_accessed = EXCLUDED.last_accessed,
+                                       expires_at = EXCLUDED.expires_at",
+            url,
+            content_type,
+            data,
+            max_age
+        )
+        .execute(self.pool())
+        .await
+        .context("Error caching resource")?;
+        Ok(())
+    }
+
+    /// Evicts expired resources, then the least recently accessed ones until the rest take up
+    /// at most `max_bytes`. Returns how many were evicted.
+    pub async fn prune_external_cache(&self, max_bytes: u64) -> anyhow::Result<u64> {
+        let max_bytes = i64::try_from(max_bytes).unwrap_or(i64::MAX);
+        let result = sqlx::query!(
+            "DELETE FROM external_cache
+             WHERE expires_at <= CURRENT_TIMESTAMP
+                OR url IN (
+                 SELECT url FROM (
+                     SELECT url,
+                            SUM(length(data)) OVER (
+                                ORDER BY last_accessed DESC, url
+                                ROWS UNBOUNDED PRECEDING
+                            ) AS kept
+                     FROM external_cache
+                     WHERE expires_at > CURRENT_TIMESTAMP
+                 )
+                 WHERE kept > ?
+             )",
+            max_bytes
+        )
+        .execute(self.pool())
+        .await
+        .context("Error pruning external cache")?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::testing;
+
+    fn resource(size: usize, max_age: i64) -> ExternalResource {
+        ExternalResource {
+            content_type: "image/png".to_string(),
+            data: vec![0; size],
+            max_age,
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_resources_are_misses() {
+        let repo = testing::repository().await;
+        repo.put_external_cache("https://a.example/fresh", &resource(1, 60))
+            .await
+            .unwrap();
+        repo.put_external_cache("https://a.example/stale", &resource(1, -1))
+            .await
+            .unwrap();
+
+        let fresh = repo
+            .get_external_cache("https://a.example/fresh")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!((59..=60).contains(&fresh.max_age));
+        assert!(
+            repo.get_external_cache("https://a.example/stale")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(repo.prune_external_cache(u64::MAX).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn pruning_keeps_within_the_limit() {
+        let repo = testing::repository().await;
+        let urls = ["https://a.example/1", "https://a.example/2"];
+        for url in urls {
+            repo.put_external_cache(url, &resource(10, 60))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(repo.prune_external_cache(15).await.unwrap(), 1);
+        let mut kept = 0;
+        for url in urls {
+            kept += repo.get_external_cache(url).await.unwrap().iter().count();
+        }
+        assert_eq!(kept, 1);
+    }
+}
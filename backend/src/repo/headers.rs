@@ -0,0 +1,49 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl super::Repository {
+    pub async fn get_email_headers(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+    ) -> anyhow::Result<Option<Vec<RawHeader>>> {
+        sqlx::query!(
+            "SELECT headers FROM email_headers WHERE account_id = ? AND email_id = ?",
+            account_id,
+            email_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying email headers")?
+        .map(|r| serde_json::from_str(&r.headers).context("Error deserializing email headers"))
+        .transpose()
+    }
+
+    pub async fn save_email_headers(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+        headers: &[RawHeader],
+    ) -> anyhow::Result<()> {
+        let headers = serde_json::to_string(headers).context("Error serializing email headers")?;
+
+        sqlx::query!(
+            "INSERT INTO email_headers (account_id, email_id, headers) VALUES (?, ?, ?)
+             ON CONFLICT DO UPDATE SET headers = EXCLUDED.headers",
+            account_id,
+            email_id,
+            headers
+        )
+        .execute(self.pool())
+        .await
+        .context("Error saving email headers")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+use std::time::Duration;
+
+impl super::Repository {
+    /// Returns the stored response for a key, as long as it's younger than `ttl`.
+    pub async fn get_idempotent_response(
+        &self,
+        account_id: AccountId,
+        endpoint: &str,
+        key: &str,
+        ttl: Duration,
+    ) -> anyhow::Result<Option<String>> {
+        let max_age = format!("-{} seconds", ttl.as_secs());
+        sqlx::query_scalar!(
+            "SELECT response FROM idempotency_keys
+             WHERE account_id = ? AND endpoint = ? AND idempotency_key = ?
+               AND created_at > strftime('%Y-%m-%dT%H:%M:%SZ', 'now', ?)",
+            account_id,
+            endpoint,
+            key,
+            max_age
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying idempotency key")
+    }
+
+    /// Stores the response for a key, dropping the endpoint's keys that have outlived `ttl`
+    /// along the way.
+    pub async fn save_idempotent_response(
+        &self,
+        account_id: AccountId,
+        endpoint: &str,
+        key: &str,
+        response: &str,
+        ttl: Duration,
+    ) -> anyhow::Result<()> {
+        let max_age = format!("-{} seconds", ttl.as_secs());
+        let mut tx = self.pool().begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM idempotency_keys
+             WHERE endpoint = ? AND created_at <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now', ?)",
+            endpoint,
+            max_age
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Error pruning idempotency keys")?;
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO idempotency_keys (account_id, endpoint, idempotency_key, response)
+             VALUES (?, ?, ?, ?)",
+            account_id,
+            endpoint,
+            key,
+            response
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Error saving idempotency key")?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
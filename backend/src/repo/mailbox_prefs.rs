@@ -0,0 +1,62 @@
+use crate::jmap_account::AccountId;
+use crate::jmap_api::EmailSort;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// How a mailbox's email list is sorted and filtered unless the user picks otherwise. The
+/// fields mirror those of [`super::EmailDbQuery`], for clients to seed their query with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MailboxDefaultQuery {
+    pub sorts: Vec<EmailSort>,
+    pub flagged: Option<bool>,
+    pub unread: Option<bool>,
+    pub has_attachment: Option<bool>,
+}
+
+impl super::Repository {
+    pub async fn get_mailbox_default_query(
+        &self,
+        account_id: AccountId,
+        mailbox_id: &str,
+    ) -> anyhow::Result<MailboxDefaultQuery> {
+        sqlx::query_scalar!(
+            "SELECT default_query FROM mailbox_prefs WHERE account_id = ? AND mailbox_id = ?",
+            account_id,
+            mailbox_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying mailbox preferences")?
+        .flatten()
+        .map(|query| {
+            serde_json::from_str(&query).context("Error deserializing mailbox default query")
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+    }
+
+    pub async fn set_mailbox_default_query(
+        &self,
+        account_id: AccountId,
+        mailbox_id: &str,
+        query: &MailboxDefaultQuery,
+    ) -> anyhow::Result<()> {
+        let query =
+            serde_json::to_string(query).context("Error serializing mailbox default query")?;
+
+        let result = sqlx::query!(
+            "INSERT INTO mailbox_prefs (account_id, mailbox_id, default_query) VALUES (?, ?, ?)
+             ON CONFLICT DO UPDATE SET default_query = EXCLUDED.default_query",
+            account_id,
+            mailbox_id,
+            query
+        )
+        .execute(self.pool())
+        .await
+        .context("Error saving mailbox preferences")?;
+
+        self.notify_changes_with(result, &["mailbox_prefs"]);
+        Ok(())
+    }
+}
@@ -1,6 +1,27 @@
 use crate::jmap_account::AccountId;
 use anyhow::Context;
 use jmap_client::mailbox::Mailbox;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// A mailbox with a role, e.g. the Inbox.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleMailbox {
+    pub role: String,
+    pub id: String,
+    /// As counted by the server.
+    pub unread_emails: i64,
+}
+
+/// How many unread emails an account's badge counts.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountUnread {
+    pub account_id: AccountId,
+    /// As counted by the server, and 0 until the mailboxes have been synced.
+    pub unread_emails: i64,
+}
 
 impl super::Repository {
     pub async fn get_mailboxes_sync_state(
@@ -66,7 +87,9 @@ impl super::Repository {
         };
 
         sqlx::query!(
-            "UPDATE accounts SET mailboxes_sync_state = ? WHERE id = ?",
+            "UPDATE accounts
+            SET mailboxes_sync_state = ?, last_synced_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+            WHERE id = ?",
             new_state,
             account_id
         )
@@ -77,12 +100,25 @@ impl super::Repository {
         tx.commit().await?;
 
         if num_inserted > 0 || num_deleted > 0 {
-            self.notify_changes(&["mailboxes"]);
+            self.notify_changes(&["accounts", "mailboxes"]);
+        } else {
+            self.notify_changes(&["accounts"]);
         }
 
         Ok(())
     }
 
+    pub async fn clear_mailboxes_sync_state(&self, account_id: AccountId) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE accounts SET mailboxes_sync_state = NULL WHERE id = ?",
+            account_id
+        )
+        .execute(self.pool())
+        .await
+        .context("Error clearing mailboxes sync state")?;
+        Ok(())
+    }
+
     pub async fn get_mailboxes(&self, account_id: AccountId) -> anyhow::Result<Vec<Mailbox>> {
         sqlx::query!(
             "SELECT jmap_data FROM mailboxes WHERE account_id = ?",
@@ -98,6 +134,67 @@ impl super::Repository {
         .collect()
     }
 
+    /// The id of the account's mailbox with the given role, e.g. `drafts` or `sent`.
+    pub async fn find_mailbox_id_by_role(
+        &self,
+        account_id: AccountId,
+        role: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let row = sqlx::query!(
+            "SELECT id FROM mailboxes WHERE account_id = ? AND jmap_data->>'$.role' = ?",
+            account_id,
+            role
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying mailbox by role")?;
+
+        Ok(row.map(|row| row.id))
+    }
+
+    pub async fn get_role_mailboxes(
+        &self,
+        account_id: AccountId,
+    ) -> anyhow::Result<Vec<RoleMailbox>> {
+        sqlx::query_as!(
+            RoleMailbox,
+            r#"SELECT jmap_data->>'$.role' AS "role!: String",
+                      id,
+                      COALESCE(jmap_data->>'$.unreadEmails', 0) AS "unread_emails!: i64"
+               FROM mailboxes
+               WHERE account_id = ? AND jmap_data->>'$.role' IS NOT NULL
+               ORDER BY 1"#,
+            account_id
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying role mailboxes")
+    }
+
+    /// The unread emails in the mailboxes each account's notification preferences count toward
+    /// the badge, the Inbox by default, for a badge covering all accounts.
+    pub async fn get_badge_unread_counts(&self) -> anyhow::Result<Vec<AccountUnread>> {
+        sqlx::query_as!(
+            AccountUnread,
+            r#"SELECT a.id AS "account_id!",
+                      COALESCE(SUM(m.jmap_data->>'$.unreadEmails'), 0) AS "unread_emails!: i64"
+               FROM accounts a
+               LEFT JOIN notify_prefs np ON np.account_id = a.id
+               LEFT JOIN mailboxes m
+                   ON m.account_id = a.id
+                   AND m.jmap_data->>'$.role' IN (
+                       SELECT value
+                       FROM json_each(COALESCE(np.prefs->>'$.badgeRoles', '["inbox"]'))
+                   )
+               WHERE NOT a.deleting
+               GROUP BY a.id
+               ORDER BY a.id"#
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying unread counts")
+    }
+
     pub async fn get_mailbox_ids(&self, account_id: AccountId) -> anyhow::Result<Vec<String>> {
         let rows = sqlx::query!("SELECT id FROM mailboxes WHERE account_id = ?", account_id)
             .fetch_all(self.pool())
@@ -107,6 +204,26 @@ impl super::Repository {
         Ok(rows.into_iter().map(|row| row.id).collect())
     }
 
+    /// Returns the mailboxes the given emails are stored in.
+    pub async fn get_mailbox_ids_of_emails(
+        &self,
+        account_id: AccountId,
+        email_ids: &[String],
+    ) -> anyhow::Result<HashSet<String>> {
+        let email_ids = serde_json::to_string(email_ids)?;
+        let rows = sqlx::query_scalar!(
+            "SELECT DISTINCT mailbox_id FROM mailbox_emails
+             WHERE account_id = ? AND email_id IN (SELECT value FROM json_each(?))",
+            account_id,
+            email_ids
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying mailboxes of emails")?;
+
+        Ok(rows.into_iter().collect())
+    }
+
     pub async fn get_mailbox_email_sync_state(
         &self,
         account_id: AccountId,
@@ -124,21 +241,160 @@ impl super::Repository {
         .email_sync_state)
     }
 
+    /// Where an interrupted full sync of the mailbox got to: the state it started from, and
+    /// the last email it stored, if any.
+    pub async fn get_mailbox_sync_resume(
+        &self,
+        account_id: AccountId,
+        mailbox_id: &str,
+    ) -> anyhow::Result<Option<(String, Option<String>)>> {
+        let row = sqlx::query!(
+            "SELECT email_sync_resume_state, email_sync_resume_anchor FROM mailboxes
+             WHERE account_id = ? AND id = ?",
+            account_id,
+            mailbox_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying mailbox sync progress")?
+        .context("Mailbox not found")?;
+
+        Ok(row
+            .email_sync_resume_state
+            .map(|state| (state, row.email_sync_resume_anchor)))
+    }
+
+    pub async fn set_mailbox_sync_resume(
+        &self,
+        account_id: AccountId,
+        mailbox_id: &str,
+        state: &str,
+        anchor: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE mailboxes SET email_sync_resume_state = ?, email_sync_resume_anchor = ?
+             WHERE account_id = ? AND id = ?",
+            state,
+            anchor,
+            account_id,
+            mailbox_id
+        )
+        .execute(self.pool())
+        .await
+        .context("Error saving mailbox sync progress")?;
+        Ok(())
+    }
+
+    /// Sets the state email changes are fetched from, which also ends any full sync in progress.
     pub async fn set_mailbox_email_sync_state(
         &self,
         account_id: AccountId,
         mailbox_id: &str,
-        sync_state: &str,
+        sync_state: Option<&str>,
     ) -> anyhow::Result<()> {
+        let mut tx = self.pool().begin().await?;
+
         sqlx::query!(
-            "UPDATE mailboxes SET email_sync_state = ? WHERE account_id = ? AND id = ?",
+            "UPDATE mailboxes
+             SET email_sync_state = ?, email_sync_resume_state = NULL, email_sync_resume_anchor = NULL
+             WHERE account_id = ? AND id = ?",
             sync_state,
             account_id,
             mailbox_id
         )
-        .execute(self.pool())
+        .execute(&mut *tx)
         .await
         .context("Error updating mailbox email sync state")?;
+
+        // Only a new state means a sync actually completed
+        if sync_state.is_some() {
+            sqlx::query!(
+                "UPDATE accounts SET last_synced_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                WHERE id = ?",
+                account_id
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Error updating account last synced time")?;
+        }
+
+        tx.commit().await?;
+
+        if sync_state.is_some() {
+            self.notify_changes(&["accounts"]);
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::repo::testing;
+    use jmap_client::email::Email;
+    use jmap_client::mailbox::Mailbox;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn accounts_sharing_mailbox_ids_stay_apart() {
+        let repo = testing::repository().await;
+        let accounts = [
+            testing::add_account(&repo, "a").await,
+            testing::add_account(&repo, "b").await,
+        ];
+
+        for (account_id, (unread, email_id)) in accounts.into_iter().zip([(1, "m1"), (2, "m2")]) {
+            let inbox =
+                json!({"id": "inbox", "name": "Inbox", "role": "inbox", "unreadEmails": unread});
+            let inbox: Mailbox = serde_json::from_str(&inbox.to_string()).unwrap();
+            repo.update_mailboxes(account_id, "s1", vec![inbox], vec![])
+                .await
+                .unwrap();
+
+            let email: Email = serde_json::from_value(json!({
+                "id": email_id,
+                "mailboxIds": {"inbox": true},
+                "receivedAt": "2025-01-01T00:00:00Z",
+            }))
+            .unwrap();
+            repo.update_emails(account_id, &[email]).await.unwrap();
+        }
+        let [a, b] = accounts;
+
+        repo.set_mailbox_email_sync_state(a, "inbox", Some("e1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.get_mailbox_email_sync_state(b, "inbox").await.unwrap(),
+            None
+        );
+
+        let ids = ["m1".to_string(), "m2".to_string()];
+        assert_eq!(
+            repo.get_mailbox_ids_of_emails(b, &ids).await.unwrap(),
+            HashSet::from(["inbox".to_string()])
+        );
+        assert_eq!(
+            repo.get_role_mailboxes(b).await.unwrap()[0].unread_emails,
+            2
+        );
+
+        // Deleting one account's mailbox leaves the other's, and its emails, alone
+        repo.update_mailboxes(a, "s2", vec![], vec!["inbox".to_string()])
+            .await
+            .unwrap();
+        assert!(repo.get_mailbox_ids(a).await.unwrap().is_empty());
+        assert_eq!(repo.get_mailbox_ids(b).await.unwrap(), ["inbox"]);
+        assert_eq!(
+            repo.get_mailbox_email_sync_state(b, "inbox").await.unwrap(),
+            None
+        );
+        assert!(
+            repo.find_missing_email_ids(b, &["m2".to_string()])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -1,18 +1,41 @@
+mod account_settings;
 mod blobs;
+mod diagnostics;
 mod emails;
+mod external_cache;
+mod headers;
+mod idempotency;
+mod mailbox_prefs;
 mod mailboxes;
+mod notify_prefs;
+mod search;
 mod threads;
+mod tokens;
+mod trusted_senders;
 
 use anyhow::Context;
+use derive_more::Debug as DeriveDebug;
 use sqlx::SqlitePool;
 use sqlx::migrate::Migrator;
-use sqlx::sqlite::{SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqliteQueryResult};
+use sqlx::sqlite::{
+    SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteQueryResult,
+};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
 
-pub use blobs::Blob;
+pub use account_settings::AccountSettings;
+pub use blobs::{Blob, BlobMetadata, EmailAvailability, UncachedAttachment};
+pub use diagnostics::{AccountCounts, TableSize};
 
-pub use emails::EmailDbQuery;
+pub use emails::{EmailDbQuery, ExportEmail};
+pub use external_cache::ExternalResource;
+pub use headers::RawHeader;
+pub use mailbox_prefs::MailboxDefaultQuery;
+pub use mailboxes::{AccountUnread, RoleMailbox};
+pub use notify_prefs::{NewEmails, NotificationContent, NotifyPrefs};
+pub use threads::ThreadEmail;
+pub use tokens::TokenScope;
 
 #[derive(Clone)]
 pub struct Changes {
@@ -22,21 +45,72 @@ pub struct Changes {
 pub struct Repository {
     pool: SqlitePool,
     changes: broadcast::Sender<Changes>,
+    new_emails: broadcast::Sender<NewEmails>,
 }
 
 static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
+/// The file name SQLite takes for a database that only lives in memory.
+const IN_MEMORY: &str = ":memory:";
+
+#[derive(DeriveDebug, Clone)]
+pub struct DbConfig {
+    pub max_connections: u32,
+    /// How long a statement waits for a lock held by another connection before failing.
+    pub busy_timeout: Duration,
+    /// How long to wait for a free connection from the pool.
+    pub acquire_timeout: Duration,
+    /// Opens the database with SQLCipher using this key, which needs the `sqlcipher` feature.
+    ///
+    /// There's no going back and forth: a database created with a key can only ever be opened
+    /// with that key, and an existing plain database doesn't get encrypted by setting one.
+    #[debug(skip)]
+    pub key: Option<String>,
+}
+
 impl Repository {
-    pub async fn new(database_file: &str) -> anyhow::Result<Self> {
-        let pool = SqlitePool::connect_with(
-            SqliteConnectOptions::new()
-                .filename(database_file)
-                .journal_mode(SqliteJournalMode::Wal)
-                .auto_vacuum(SqliteAutoVacuum::Incremental)
-                .create_if_missing(true),
-        )
-        .await
-        .context("Failed to connect to the database")?;
+    pub async fn new(database_file: &str, config: DbConfig) -> anyhow::Result<Self> {
+        tracing::info!(?config, "Opening database");
+
+        let mut options = SqliteConnectOptions::new()
+            .filename(database_file)
+            .journal_mode(SqliteJournalMode::Wal)
+            .auto_vacuum(SqliteAutoVacuum::Incremental)
+            .busy_timeout(config.busy_timeout)
+            .create_if_missing(true);
+
+        if let Some(key) = &config.key {
+            // Plain SQLite ignores the pragma, which would quietly leave the data unencrypted
+            anyhow::ensure!(
+                cfg!(feature = "sqlcipher"),
+                "A database key is set, but this build doesn't support encryption. \
+                 Rebuild with the `sqlcipher` feature"
+            );
+            // sqlx sends the key before any other pragma, as SQLCipher requires
+            options = options.pragma("key", format!("'{}'", key.replace('\'', "''")));
+        }
+
+        let mut pool_options = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(config.acquire_timeout);
+        if database_file == IN_MEMORY {
+            // Each connection to `:memory:` opens a database of its own, so there can only be
+            // one, and it must never be closed or the data goes with it
+            pool_options = pool_options
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
+
+        let pool = pool_options
+            .connect_with(options)
+            .await
+            .context(if config.key.is_some() {
+                "Failed to connect to the database. If it exists, it can't be decrypted with \
+                 the given key, or isn't encrypted at all"
+            } else {
+                "Failed to connect to the database"
+            })?;
 
         MIGRATOR
             .run(&pool)
@@ -44,8 +118,13 @@ impl Repository {
             .context("Failed to run database migrations")?;
 
         let (changes, _) = broadcast::channel(16);
+        let (new_emails, _) = broadcast::channel(16);
 
-        Ok(Self { pool, changes })
+        Ok(Self {
+            pool,
+            changes,
+            new_emails,
+        })
     }
 
     pub fn pool(&self) -> &SqlitePool {
@@ -67,4 +146,86 @@ impl Repository {
     pub fn subscribe_db_changes(&self) -> broadcast::Receiver<Changes> {
         self.changes.subscribe()
     }
+
+    pub fn subscribe_new_emails(&self) -> broadcast::Receiver<NewEmails> {
+        self.new_emails.subscribe()
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::{DbConfig, Repository};
+    use crate::jmap_account::{Account, AccountId, AccountRepositoryExt, Credentials};
+    use std::time::Duration;
+
+    pub fn config(max_connections: u32) -> DbConfig {
+        DbConfig {
+            max_connections,
+            busy_timeout: Duration::from_secs(5),
+            acquire_timeout: Duration::from_secs(5),
+            key: None,
+        }
+    }
+
+    /// A fresh in-memory database.
+    pub async fn repository() -> Repository {
+        Repository::new(":memory:", config(1))
+            .await
+            .expect("Error creating test repository")
+    }
+
+    pub async fn add_account(repo: &Repository, name: &str) -> AccountId {
+        repo.add_account(&Account {
+            server_url: "https://jmap.example.com".to_string(),
+            credentials: Credentials::Bearer {
+                token: "token".to_string(),
+            },
+            name: name.to_string(),
+            jmap_account_id: None,
+        })
+        .await
+        .expect("Error adding test account")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jmap_account::AccountRepositoryExt;
+
+    #[tokio::test]
+    async fn pool_keeps_to_the_configured_size() {
+        let path = std::env::temp_dir().join(format!("pool-test-{}.db", std::process::id()));
+        let config = DbConfig {
+            acquire_timeout: Duration::from_millis(100),
+            ..testing::config(2)
+        };
+        let repo = Repository::new(path.to_str().unwrap(), config)
+            .await
+            .unwrap();
+
+        let first = repo.pool().acquire().await.unwrap();
+        let second = repo.pool().acquire().await.unwrap();
+        assert!(repo.pool().acquire().await.is_err());
+        drop((first, second));
+        repo.pool().close().await;
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_database_gets_a_single_connection() {
+        let repo = Repository::new(IN_MEMORY, testing::config(16))
+            .await
+            .unwrap();
+        assert_eq!(repo.pool().options().get_max_connections(), 1);
+
+        // A second connection would see an empty database without the accounts table
+        testing::add_account(&repo, "a").await;
+        let (a, b) = tokio::join!(repo.list_accounts(), repo.list_accounts());
+        assert_eq!(a.unwrap().len(), 1);
+        assert_eq!(b.unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,266 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+use jmap_client::email::Email;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotifyPrefs {
+    /// Mailboxes whose new mail is notified about. `None` means the Inbox.
+    pub notify_mailbox_ids: Option<Vec<String>>,
+    /// When to hold notifications back. Syncing carries on regardless.
+    pub dnd: Option<DndSchedule>,
+    /// How much of an email notifications may reveal.
+    pub content: NotificationContent,
+    /// Roles of the mailboxes whose unread emails count toward the badge. `None` means the Inbox.
+    pub badge_roles: Option<Vec<String>>,
+}
+
+impl NotifyPrefs {
+    /// The roles counted toward the badge, resolving the default to the Inbox.
+    pub fn badge_roles(&self) -> Vec<&str> {
+        match &self.badge_roles {
+            Some(roles) => roles.iter().map(String::as_str).collect(),
+            None => vec!["inbox"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationContent {
+    /// Sender, subject and a snippet of the body.
+    #[default]
+    Full,
+    /// Sender and subject only.
+    SenderAndSubject,
+    /// Nothing but that there's a new message.
+    Hidden,
+}
+
+/// A recurring do-not-disturb window, e.g. 22:00 to 07:00 on weeknights.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DndSchedule {
+    pub start: TimeOfDay,
+    /// Before `start` for windows past midnight. Equal to `start` for a whole day.
+    pub end: TimeOfDay,
+    /// The days the window starts on.
+    pub days: Vec<Weekday>,
+    /// The timezone the times are in, as a fixed offset from UTC.
+    pub utc_offset_minutes: i32,
+    /// Whether to send a summary of what arrived during the window once it's over.
+    #[serde(default)]
+    pub summarize: bool,
+}
+
+impl DndSchedule {
+    pub fn is_active_at(&self, unix_secs: i64) -> bool {
+        let local = unix_secs + i64::from(self.utc_offset_minutes) * 60;
+        let day = local.div_euclid(86400);
+        let minute = (local.rem_euclid(86400) / 60) as u16;
+        let starts_on = |day: i64| self.days.contains(&Weekday::of_epoch_day(day));
+
+        let (start, end) = (self.start.minutes, self.end.minutes);
+        if start < end {
+            starts_on(day) && (start..end).contains(&minute)
+        } else {
+            (starts_on(day) && minute >= start) || (starts_on(day - 1) && minute < end)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    const ALL: [Self; 7] = [
+        Self::Mon,
+        Self::Tue,
+        Self::Wed,
+        Self::Thu,
+        Self::Fri,
+        Self::Sat,
+        Self::Sun,
+    ];
+
+    /// The weekday of a day counted from 1970-01-01, which was a Thursday.
+    fn of_epoch_day(day: i64) -> Self {
+        Self::ALL[(day + 3).rem_euclid(7) as usize]
+    }
+}
+
+/// A time of day as `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    minutes: u16,
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        let (hours, minutes) = value
+            .split_once(':')
+            .and_then(|(h, m)| Some((h.parse::<u16>().ok()?, m.parse::<u16>().ok()?)))
+            .filter(|(h, m)| *h < 24 && *m < 60)
+            .with_context(|| format!("Invalid time of day: {value}"))?;
+
+        Ok(Self {
+            minutes: hours * 60 + minutes,
+        })
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        format!("{:02}:{:02}", time.minutes / 60, time.minutes % 60)
+    }
+}
+
+/// Emails stored for the first time, as opposed to updates of ones already synced.
+#[derive(Debug, Clone)]
+pub struct NewEmails {
+    pub account_id: AccountId,
+    pub emails: Arc<[Email]>,
+}
+
+impl super::Repository {
+    pub async fn get_notify_prefs(&self, account_id: AccountId) -> anyhow::Result<NotifyPrefs> {
+        sqlx::query_scalar!(
+            "SELECT prefs FROM notify_prefs WHERE account_id = ?",
+            account_id
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying notification preferences")?
+        .map(|prefs| {
+            serde_json::from_str(&prefs).context("Error deserializing notification preferences")
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+    }
+
+    pub async fn set_notify_prefs(
+        &self,
+        account_id: AccountId,
+        prefs: &NotifyPrefs,
+    ) -> anyhow::Result<()> {
+        let prefs =
+            serde_json::to_string(prefs).context("Error serializing notification preferences")?;
+
+        let result = sqlx::query!(
+            "INSERT INTO notify_prefs (account_id, prefs) VALUES (?, ?)
+             ON CONFLICT DO UPDATE SET prefs = EXCLUDED.prefs",
+            account_id,
+            prefs
+        )
+        .execute(self.pool())
+        .await
+        .context("Error saving notification preferences")?;
+
+        self.notify_changes_with(result, &["notify_prefs"]);
+        Ok(())
+    }
+
+    /// The mailboxes to notify about, resolving the default to the Inbox.
+    pub async fn get_notify_mailbox_ids(
+        &self,
+        account_id: AccountId,
+        prefs: &NotifyPrefs,
+    ) -> anyhow::Result<Vec<String>> {
+        match &prefs.notify_mailbox_ids {
+            Some(ids) => Ok(ids.clone()),
+            None => Ok(self
+                .find_mailbox_id_by_role(account_id, "inbox")
+                .await?
+                .into_iter()
+                .collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday, 2024-01-01 00:00 UTC.
+    const MONDAY: i64 = 1_704_067_200;
+
+    fn schedule(
+        start: &str,
+        end: &str,
+        days: Vec<Weekday>,
+        utc_offset_minutes: i32,
+    ) -> DndSchedule {
+        DndSchedule {
+            start: start.to_string().try_into().unwrap(),
+            end: end.to_string().try_into().unwrap(),
+            days,
+            utc_offset_minutes,
+            summarize: false,
+        }
+    }
+
+    fn at(day: i64, hours: i64, minutes: i64) -> i64 {
+        MONDAY + day * 86400 + hours * 3600 + minutes * 60
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let dnd = schedule("09:00", "17:00", vec![Weekday::Mon], 0);
+        assert!(!dnd.is_active_at(at(0, 8, 59)));
+        assert!(dnd.is_active_at(at(0, 9, 0)));
+        assert!(dnd.is_active_at(at(0, 16, 59)));
+        assert!(!dnd.is_active_at(at(0, 17, 0)));
+        assert!(!dnd.is_active_at(at(1, 12, 0)));
+    }
+
+    #[test]
+    fn window_past_midnight_belongs_to_its_start_day() {
+        let dnd = schedule("22:00", "07:00", vec![Weekday::Fri], 0);
+        assert!(dnd.is_active_at(at(4, 23, 0)));
+        assert!(dnd.is_active_at(at(5, 6, 59)));
+        assert!(!dnd.is_active_at(at(5, 7, 0)));
+        assert!(!dnd.is_active_at(at(5, 23, 0)));
+        assert!(!dnd.is_active_at(at(4, 6, 0)));
+    }
+
+    #[test]
+    fn equal_start_and_end_is_the_whole_day() {
+        let dnd = schedule("00:00", "00:00", vec![Weekday::Sun], 0);
+        assert!(dnd.is_active_at(at(6, 0, 0)));
+        assert!(dnd.is_active_at(at(6, 23, 59)));
+        assert!(!dnd.is_active_at(at(7, 0, 0)));
+    }
+
+    #[test]
+    fn times_are_in_the_schedule_timezone() {
+        // 09:00 on Monday at UTC+10 is 23:00 on Sunday in UTC.
+        let dnd = schedule("09:00", "10:00", vec![Weekday::Mon], 600);
+        assert!(dnd.is_active_at(at(-1, 23, 0)));
+        assert!(!dnd.is_active_at(at(0, 9, 0)));
+
+        let dnd = schedule("09:00", "10:00", vec![Weekday::Mon], -300);
+        assert!(dnd.is_active_at(at(0, 14, 30)));
+    }
+
+    #[test]
+    fn time_of_day_round_trips() {
+        let time = TimeOfDay::try_from("07:05".to_string()).unwrap();
+        assert_eq!(String::from(time), "07:05");
+        assert!(TimeOfDay::try_from("24:00".to_string()).is_err());
+        assert!(TimeOfDay::try_from("7".to_string()).is_err());
+    }
+}
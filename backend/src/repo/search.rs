@@ -0,0 +1,92 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+use itertools::Itertools;
+
+pub struct SenderSuggestion {
+    pub name: Option<String>,
+    pub email: String,
+}
+
+impl super::Repository {
+    /// Senders whose name or address contains `term`, the most frequent and recent first.
+    pub async fn suggest_senders(
+        &self,
+        account_id: AccountId,
+        term: &str,
+        limit: u32,
+    ) -> anyhow::Result<Vec<SenderSuggestion>> {
+        let pattern = like_pattern(term);
+
+        let rows = sqlx::query!(
+            r#"SELECT MAX(sender.value->>'$.name') AS "name: String",
+                      sender.value->>'$.email' AS "email!: String"
+               FROM emails, json_each(emails."from") AS sender
+               WHERE emails.account_id = ?1
+                 AND sender.value->>'$.email' IS NOT NULL
+                 AND (sender.value->>'$.email' LIKE ?2 ESCAPE '\' OR
+                      sender.value->>'$.name' LIKE ?2 ESCAPE '\')
+               GROUP BY lower(sender.value->>'$.email')
+               ORDER BY COUNT(*) DESC, MAX(emails.received_at) DESC
+               LIMIT ?3"#,
+            account_id,
+            pattern,
+            limit
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying sender suggestions")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| SenderSuggestion {
+                name: row.name,
+                email: row.email,
+            })
+            .collect())
+    }
+
+    /// Distinct subjects containing `term`, most recently received first.
+    pub async fn suggest_subjects(
+        &self,
+        account_id: AccountId,
+        term: &str,
+        limit: u32,
+    ) -> anyhow::Result<Vec<String>> {
+        let pattern = like_pattern(term);
+
+        let rows = sqlx::query!(
+            r#"SELECT subject AS "subject!: String" FROM emails
+               WHERE account_id = ? AND subject LIKE ? ESCAPE '\'
+               GROUP BY subject
+               ORDER BY MAX(received_at) DESC
+               LIMIT ?"#,
+            account_id,
+            pattern,
+            limit
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying subject suggestions")?;
+
+        Ok(rows.into_iter().map(|row| row.subject).collect())
+    }
+}
+
+/// An FTS5 query matching text with words starting with each of the words of `keyword`, or
+/// `None` when it has none.
+pub fn fts_query(keyword: &str) -> Option<String> {
+    let query = keyword
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .join(" ");
+    (!query.is_empty()).then_some(query)
+}
+
+/// A `LIKE` pattern matching values that contain `term` literally.
+fn like_pattern(term: &str) -> String {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
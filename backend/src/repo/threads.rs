@@ -10,6 +10,15 @@ pub struct Thread {
     pub emails: Box<RawValue>,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadEmail {
+    pub id: String,
+    pub unread: bool,
+    pub has_attachment: bool,
+    pub email: Box<RawValue>,
+}
+
 impl super::Repository {
     pub async fn get_threads(
         &self,
@@ -58,4 +67,57 @@ impl super::Repository {
 
         r
     }
+
+    /// An email without a `threadId` is listed as a thread of its own, under its own id.
+    pub async fn get_thread_email_ids(
+        &self,
+        account_id: AccountId,
+        thread_id: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        sqlx::query_scalar!(
+            "SELECT id FROM emails
+             WHERE account_id = ?1 AND (thread_id = ?2 OR (thread_id IS NULL AND id = ?2))",
+            account_id,
+            thread_id
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to fetch thread email ids")
+    }
+
+    /// Returns every stored email in the same thread as `email_id`, oldest first.
+    /// An empty list means the email itself isn't stored.
+    pub async fn get_email_thread(
+        &self,
+        account_id: AccountId,
+        email_id: &str,
+    ) -> anyhow::Result<Vec<ThreadEmail>> {
+        sqlx::query!(
+            r#"
+            SELECT e.id,
+                   e.jmap_data AS "email!: String",
+                   COALESCE(e.jmap_data->>'$.keywords."$seen"', FALSE) AS "seen!: bool",
+                   COALESCE(e.jmap_data->>'$.hasAttachment', FALSE) AS "has_attachment!: bool"
+            FROM emails e
+            WHERE e.account_id = ?1
+              AND (e.thread_id = (SELECT thread_id FROM emails WHERE account_id = ?1 AND id = ?2)
+                   OR e.id = ?2)
+            ORDER BY e.received_at, e.id
+            "#,
+            account_id,
+            email_id
+        )
+        .try_map(|r| {
+            Ok(ThreadEmail {
+                id: r.id,
+                unread: !r.seen,
+                has_attachment: r.has_attachment,
+                email: RawValue::from_string(r.email)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+            })
+        })
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to fetch email thread")
+    }
 }
@@ -0,0 +1,68 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// What a read-only token may see. `None` means no restriction.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenScope {
+    pub account_ids: Option<Vec<AccountId>>,
+    pub mailbox_ids: Option<Vec<String>>,
+}
+
+impl super::Repository {
+    pub async fn add_api_token(&self, token_hash: &str, scope: &TokenScope) -> anyhow::Result<()> {
+        let account_ids = scope
+            .account_ids
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .context("Error serializing token accounts")?;
+        let mailbox_ids = scope
+            .mailbox_ids
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .context("Error serializing token mailboxes")?;
+
+        sqlx::query!(
+            "INSERT INTO api_tokens (token_hash, account_ids, mailbox_ids) VALUES (?, ?, ?)",
+            token_hash,
+            account_ids,
+            mailbox_ids
+        )
+        .execute(self.pool())
+        .await
+        .context("Error inserting API token")?;
+        Ok(())
+    }
+
+    pub async fn get_api_token_scope(
+        &self,
+        token_hash: &str,
+    ) -> anyhow::Result<Option<TokenScope>> {
+        let Some(row) = sqlx::query!(
+            "SELECT account_ids, mailbox_ids FROM api_tokens WHERE token_hash = ?",
+            token_hash
+        )
+        .fetch_optional(self.pool())
+        .await
+        .context("Error querying API token")?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(TokenScope {
+            account_ids: row
+                .account_ids
+                .map(|ids| serde_json::from_str(&ids))
+                .transpose()
+                .context("Error deserializing token accounts")?,
+            mailbox_ids: row
+                .mailbox_ids
+                .map(|ids| serde_json::from_str(&ids))
+                .transpose()
+                .context("Error deserializing token mailboxes")?,
+        }))
+    }
+}
@@ -0,0 +1,77 @@
+use crate::jmap_account::AccountId;
+use anyhow::Context;
+
+impl super::Repository {
+    pub async fn get_trusted_senders(&self, account_id: AccountId) -> anyhow::Result<Vec<String>> {
+        sqlx::query_scalar!(
+            "SELECT pattern FROM trusted_senders WHERE account_id = ? ORDER BY pattern",
+            account_id
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Error querying trusted senders")
+    }
+
+    pub async fn add_trusted_sender(
+        &self,
+        account_id: AccountId,
+        pattern: &str,
+    ) -> anyhow::Result<()> {
+        let result = sqlx::query!(
+            "INSERT INTO trusted_senders (account_id, pattern) VALUES (?, ?)
+             ON CONFLICT DO NOTHING",
+            account_id,
+            pattern
+        )
+        .execute(self.pool())
+        .await
+        .context("Error adding trusted sender")?;
+
+        self.notify_changes_with(result, &["trusted_senders"]);
+        Ok(())
+    }
+
+    pub async fn remove_trusted_sender(
+        &self,
+        account_id: AccountId,
+        pattern: &str,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM trusted_senders WHERE account_id = ? AND pattern = ?",
+            account_id,
+            pattern
+        )
+        .execute(self.pool())
+        .await
+        .context("Error removing trusted sender")?;
+
+        let removed = result.rows_affected() > 0;
+        self.notify_changes_with(result, &["trusted_senders"]);
+        Ok(removed)
+    }
+
+    /// Whether `address` is trusted, by itself or through its domain.
+    pub async fn is_trusted_sender(
+        &self,
+        account_id: AccountId,
+        address: &str,
+    ) -> anyhow::Result<bool> {
+        let address = address.to_lowercase();
+        let domain = match address.rsplit_once('@') {
+            Some((_, domain)) => format!("*@{domain}"),
+            None => return Ok(false),
+        };
+
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS (
+                SELECT 1 FROM trusted_senders WHERE account_id = ? AND pattern IN (?, ?)
+            ) AS "trusted!: bool""#,
+            account_id,
+            address,
+            domain
+        )
+        .fetch_one(self.pool())
+        .await
+        .context("Error querying trusted senders")
+    }
+}
@@ -1,10 +1,12 @@
+mod prefetch_attachments;
 mod sync_account;
 mod sync_accounts;
 mod sync_mailbox_list;
 mod sync_mailboxes;
 mod watch_emails;
 
-use serde::Serialize;
+use crate::jmap_api::EmailQuery;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
 pub use sync_mailboxes::WatchMailboxSyncCommand;
@@ -13,11 +15,59 @@ pub use watch_emails::WatchEmailSyncCommand;
 pub use sync_account::SyncCommand;
 pub use sync_accounts::sync_accounts;
 
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(tag = "state")]
+pub enum AccountSyncState {
+    #[default]
+    Running,
+    Restarting {
+        details: String,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSyncStatus {
+    #[serde(flatten)]
+    pub state: AccountSyncState,
+    /// The most recent failure, kept after the pipeline has been restarted.
+    pub last_error: Option<String>,
+    /// How many times the pipeline has been restarted since the account started syncing.
+    pub restarts: u32,
+}
+
 #[derive(Serialize, Debug, Clone)]
 #[serde(tag = "state")]
 pub enum EmailQueryState {
     NotStarted,
     InProgress,
-    Error { details: String },
+    /// Emails are being fetched, `fetched` of `total` so far.
+    Fetching {
+        fetched: usize,
+        total: usize,
+    },
+    Error {
+        details: String,
+    },
     UpToDate,
 }
+
+/// An email query from a client, which may number its queries so it can tell which one the
+/// states it gets back are about.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SequencedEmailQuery {
+    #[serde(default)]
+    pub seq: Option<u64>,
+    #[serde(flatten)]
+    pub query: EmailQuery,
+}
+
+/// The state of a client's email query, along with the number of the query it's about. States
+/// of a query the client has since replaced can then be ignored.
+#[derive(Serialize, Debug, Clone)]
+pub struct SequencedEmailQueryState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(flatten)]
+    pub state: EmailQueryState,
+}
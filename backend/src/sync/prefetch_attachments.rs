@@ -0,0 +1,117 @@
+use crate::jmap_account::AccountId;
+use crate::jmap_api::{ClientState, JmapApi};
+use crate::repo::{Blob, Repository, UncachedAttachment};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::instrument;
+
+/// How many attachments are looked up at a time.
+const BATCH_SIZE: u32 = 20;
+
+/// Downloads the attachments of flagged and recent emails into the blob cache, as the account's
+/// `attachmentPrefetch` setting allows, whenever emails or the setting change.
+#[instrument(skip(repo, jmap_api), ret, level = "info")]
+pub async fn prefetch_attachments(
+    repo: Arc<Repository>,
+    account_id: AccountId,
+    jmap_api: Arc<JmapApi>,
+) -> anyhow::Result<()> {
+    let mut client_state = jmap_api.subscribe_client_state();
+    let mut changes = repo.subscribe_db_changes();
+    // Attachments that failed to download aren't tried again until the sync restarts
+    let mut failed = HashSet::new();
+
+    loop {
+        let settings = repo
+            .get_account_settings(account_id)
+            .await?
+            .attachment_prefetch;
+
+        if settings.enabled {
+            let mut cache_size = repo.get_blob_cache_size(account_id).await? as u64;
+
+            'batches: loop {
+                let attachments = repo
+                    .find_uncached_attachments(
+                        account_id,
+                        settings.max_attachment_bytes as i64,
+                        BATCH_SIZE + failed.len() as u32,
+                    )
+                    .await?
+                    .into_iter()
+                    .filter(|a| !failed.contains(&a.blob_id))
+                    .collect::<Vec<_>>();
+
+                if attachments.is_empty() {
+                    break;
+                }
+
+                for attachment in attachments {
+                    if cache_size + attachment.size as u64 > settings.max_cache_bytes {
+                        tracing::debug!(cache_size, "Prefetch budget used up");
+                        break 'batches;
+                    }
+
+                    // Failing while offline would put the attachment off until the sync restarts
+                    if client_state
+                        .wait_for(|s| matches!(s, ClientState::Connected(_)))
+                        .await
+                        .is_err()
+                    {
+                        return Ok(());
+                    }
+
+                    match prefetch(&repo, account_id, &jmap_api, &attachment).await {
+                        Ok(size) => cache_size += size,
+                        Err(e) => {
+                            tracing::warn!(?e, blob_id = attachment.blob_id, "Error prefetching");
+                            failed.insert(attachment.blob_id);
+                        }
+                    }
+                }
+            }
+        }
+
+        loop {
+            let changes = match changes.recv().await {
+                Ok(changes) => changes,
+                Err(RecvError::Lagged(_)) => break,
+                Err(RecvError::Closed) => return Ok(()),
+            };
+
+            if changes
+                .tables
+                .iter()
+                .any(|t| *t == "emails" || *t == "account_settings")
+            {
+                break;
+            }
+        }
+    }
+}
+
+/// Downloads an attachment into the blob cache, returning its size.
+async fn prefetch(
+    repo: &Repository,
+    account_id: AccountId,
+    jmap_api: &JmapApi,
+    attachment: &UncachedAttachment,
+) -> anyhow::Result<u64> {
+    let data = jmap_api.download_blob(&attachment.blob_id).await?;
+    let size = data.len() as u64;
+
+    repo.save_blob(
+        account_id,
+        &attachment.blob_id,
+        &Blob {
+            name: attachment.name.clone(),
+            mime_type: attachment.mime_type.clone(),
+            data,
+        },
+    )
+    .await?;
+
+    tracing::debug!(blob_id = attachment.blob_id, size, "Prefetched attachment");
+    Ok(size)
+}
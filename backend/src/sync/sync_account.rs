@@ -1,3 +1,4 @@
+use super::prefetch_attachments;
 use super::sync_mailbox_list;
 use super::sync_mailboxes;
 use super::sync_mailboxes::WatchMailboxSyncCommand;
@@ -6,6 +7,7 @@ use super::watch_emails::WatchEmailSyncCommand;
 use crate::jmap_account::AccountId;
 use crate::jmap_api::JmapApi;
 use crate::repo::Repository;
+use anyhow::format_err;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::task::JoinSet;
@@ -22,43 +24,73 @@ pub async fn sync_account(
     repo: Arc<Repository>,
     account_id: AccountId,
     jmap_api: Arc<JmapApi>,
-    mut sync_commands: mpsc::Receiver<SyncCommand>,
+    sync_commands: &mut mpsc::Receiver<SyncCommand>,
+    mailbox_sync_concurrency: usize,
 ) -> anyhow::Result<()> {
     let (mailbox_watch_request_tx, mailbox_watch_request_rx) = mpsc::channel(16);
 
+    // The workers keep the account in sync for as long as it exists, so if one of them exits
+    // the whole pipeline has to be restarted
+    let mut workers = JoinSet::new();
+
+    // Tasks serving individual client commands, which may come and go
     let mut join_set = JoinSet::new();
 
-    join_set.spawn(sync_mailbox_list::sync_mailbox_list(
+    workers.spawn(sync_mailbox_list::sync_mailbox_list(
         repo.clone(),
         account_id,
         jmap_api.clone(),
     ));
 
-    join_set.spawn(sync_mailboxes::sync_mailboxes(
+    workers.spawn(sync_mailboxes::sync_mailboxes(
         repo.clone(),
         account_id,
         jmap_api.clone(),
         mailbox_watch_request_rx,
+        mailbox_sync_concurrency,
     ));
 
-    while let Some(cmd) = sync_commands.recv().await {
-        match cmd {
-            SyncCommand::WatchEmails(cmd) => {
-                join_set.spawn(watch_emails::handle_watch_command(
-                    repo.clone(),
-                    account_id,
-                    jmap_api.clone(),
-                    cmd,
-                ));
+    workers.spawn(prefetch_attachments::prefetch_attachments(
+        repo.clone(),
+        account_id,
+        jmap_api.clone(),
+    ));
+
+    loop {
+        tokio::select! {
+            cmd = sync_commands.recv() => match cmd {
+                Some(SyncCommand::WatchEmails(cmd)) => {
+                    join_set.spawn(watch_emails::handle_watch_command(
+                        repo.clone(),
+                        account_id,
+                        jmap_api.clone(),
+                        cmd,
+                    ));
+                }
+                Some(SyncCommand::WatchMailbox(watch_cmd)) => {
+                    join_set.spawn(sync_mailboxes::handle_watch_mailbox_command(
+                        watch_cmd,
+                        mailbox_watch_request_tx.clone(),
+                    ));
+                }
+                None => return Ok(()),
+            },
+
+            Some(result) = workers.join_next() => {
+                return match result {
+                    Ok(Ok(())) => Err(format_err!("Sync worker exited unexpectedly")),
+                    Ok(Err(e)) => Err(e.context("Sync worker failed")),
+                    Err(e) => Err(format_err!("Sync worker panicked: {e}")),
+                };
             }
-            SyncCommand::WatchMailbox(watch_cmd) => {
-                join_set.spawn(sync_mailboxes::handle_watch_mailbox_command(
-                    watch_cmd,
-                    mailbox_watch_request_tx.clone(),
-                ));
+
+            Some(result) = join_set.join_next() => {
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => tracing::warn!(?e, "Sync command failed"),
+                    Err(e) => tracing::error!(?e, "Sync command panicked"),
+                }
             }
         }
     }
-
-    Ok(())
 }
@@ -1,12 +1,18 @@
 use crate::api::AccountState;
 use crate::jmap_account::{AccountId, AccountRepositoryExt};
-use crate::jmap_api::JmapApi;
+use crate::jmap_api::{JmapApi, ReconnectConfig};
 use crate::repo::Repository;
+use crate::sync::{AccountSyncState, AccountSyncStatus, SyncCommand};
 use crate::util::network::NetworkAvailability;
+use crate::util::rate_limit::RateLimitConfig;
 use anyhow::Context;
-use parking_lot::RwLock;
+use futures::FutureExt;
+use parking_lot::{Mutex, RwLock};
+use std::any::Any;
 use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, watch};
 use tokio::task::JoinSet;
 use tracing::{Instrument, info_span, instrument};
@@ -15,7 +21,11 @@ use tracing::{Instrument, info_span, instrument};
 pub async fn sync_accounts(
     repo: Arc<Repository>,
     states: Arc<RwLock<HashMap<AccountId, AccountState>>>,
+    states_updated: watch::Sender<()>,
     network_availability_rx: watch::Receiver<NetworkAvailability>,
+    rate_limit: RateLimitConfig,
+    reconnect: ReconnectConfig,
+    mailbox_sync_concurrency: usize,
 ) -> anyhow::Result<()> {
     let mut changes = repo.subscribe_db_changes();
     loop {
@@ -41,7 +51,7 @@ pub async fn sync_accounts(
             // Add states for new accounts
             for (account_id, account) in accounts {
                 match states.get(&account_id) {
-                    Some(existing_state) if existing_state.account == account => {
+                    Some(existing_state) if existing_state.account.same_sync_target(&account) => {
                         // Account already being synced with the same configuration
                         continue;
                     }
@@ -54,21 +64,39 @@ pub async fn sync_accounts(
                 let jmap_api = Arc::new(JmapApi::new(
                     account.server_url.parse().context("Invalid server URL")?,
                     account.credentials.clone(),
+                    account.jmap_account_id.clone(),
                     network_availability_rx.clone(),
+                    rate_limit,
+                    reconnect,
+                    {
+                        let repo = repo.clone();
+                        Arc::new(move |credentials| {
+                            let repo = repo.clone();
+                            async move {
+                                repo.update_account_credentials(account_id, &credentials)
+                                    .await
+                            }
+                            .boxed()
+                        })
+                    },
                 ));
 
                 let mut join_set = JoinSet::new();
 
                 let (command_sender, command_receiver) = mpsc::channel(16);
 
+                let sync_status = Arc::new(Mutex::new(AccountSyncStatus::default()));
+
                 join_set.spawn(
-                    super::sync_account::sync_account(
+                    supervise_account(
                         repo.clone(),
                         account_id,
                         jmap_api.clone(),
                         command_receiver,
+                        sync_status.clone(),
+                        mailbox_sync_concurrency,
                     )
-                    .instrument(info_span!("sync_account")),
+                    .instrument(info_span!("sync_account", account_id)),
                 );
 
                 states.insert(
@@ -76,12 +104,14 @@ pub async fn sync_accounts(
                     AccountState {
                         command_sender,
                         jmap_api,
-                        join_set,
+                        sync_status,
+                        _join_set: join_set,
                         account,
                     },
                 );
             }
         }
+        states_updated.send_replace(());
 
         loop {
             let changes = changes
@@ -97,3 +127,76 @@ pub async fn sync_accounts(
         }
     }
 }
+
+/// How long to wait before the first restart of a failed sync. It doubles with every failure
+/// in a row, up to `MAX_RESTART_DELAY`.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(5);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// A sync that ran at least this long before failing counts as having recovered.
+const HEALTHY_RUN: Duration = Duration::from_secs(5 * 60);
+
+/// Runs the account's sync pipeline, restarting it whenever it fails or panics. It only stops
+/// once the account's command channel is closed, i.e. the account is no longer synced.
+async fn supervise_account(
+    repo: Arc<Repository>,
+    account_id: AccountId,
+    jmap_api: Arc<JmapApi>,
+    mut commands: mpsc::Receiver<SyncCommand>,
+    status: Arc<Mutex<AccountSyncStatus>>,
+    mailbox_sync_concurrency: usize,
+) -> anyhow::Result<()> {
+    let mut restart_delay = MIN_RESTART_DELAY;
+
+    // The JmapApi, and so its connection, is shared by every run rather than recreated, so
+    // restarting doesn't leave sockets behind
+    loop {
+        let started_at = Instant::now();
+        let result = AssertUnwindSafe(super::sync_account::sync_account(
+            repo.clone(),
+            account_id,
+            jmap_api.clone(),
+            &mut commands,
+            mailbox_sync_concurrency,
+        ))
+        .catch_unwind()
+        .await;
+
+        let error = match result {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => format!("{e:?}"),
+            Err(panic) => panic_message(panic.as_ref()),
+        };
+
+        if started_at.elapsed() >= HEALTHY_RUN {
+            restart_delay = MIN_RESTART_DELAY;
+        }
+
+        tracing::error!(
+            error,
+            "Account sync failed, restarting in {restart_delay:?}"
+        );
+        {
+            let mut status = status.lock();
+            status.state = AccountSyncState::Restarting {
+                details: error.clone(),
+            };
+            status.last_error = Some(error);
+        }
+
+        tokio::time::sleep(restart_delay).await;
+        restart_delay = (restart_delay * 2).min(MAX_RESTART_DELAY);
+
+        let mut status = status.lock();
+        status.state = AccountSyncState::Running;
+        status.restarts += 1;
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Sync task panicked".to_string())
+}
@@ -1,7 +1,8 @@
 use crate::jmap_account::AccountId;
-use crate::jmap_api::JmapApi;
+use crate::jmap_api::{JmapApi, JmapMethodError};
 use crate::repo::Repository;
 use anyhow::Context;
+use jmap_client::core::error::MethodErrorType;
 use jmap_client::{DataType, PushObject};
 use std::sync::Arc;
 use tracing::instrument;
@@ -14,42 +15,85 @@ pub async fn sync_mailbox_list(
 ) -> anyhow::Result<()> {
     let mut push_sub = jmap_api.subscribe_pushes();
     loop {
-        let (new_state, updated, deleted) = match repo.get_mailboxes_sync_state(account_id).await? {
+        let changes = match repo.get_mailboxes_sync_state(account_id).await? {
             Some(since_state) if !since_state.is_empty() => {
-                let mut resp = jmap_api.mailboxes_changes(since_state).await?;
+                match jmap_api.mailboxes_changes(since_state).await {
+                    Ok(changes) => Some(changes),
+                    Err(e)
+                        if JmapMethodError::is_kind(
+                            &e,
+                            MethodErrorType::CannotCalculateChanges,
+                        ) =>
+                    {
+                        tracing::warn!("Server can't calculate mailbox changes, resyncing list");
+                        repo.clear_mailboxes_sync_state(account_id).await?;
+                        None
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            _ => None,
+        };
+
+        let has_more_changes = changes.as_ref().is_some_and(|c| c.has_more_changes());
+        let (new_state, updated, deleted) = match changes {
+            Some(mut resp) => {
                 let mut updated = resp.take_created();
                 updated.extend(resp.take_updated());
-                (resp.take_new_state(), updated, resp.take_destroyed())
-            }
+                let deleted = resp.take_destroyed();
+                tracing::info!(
+                    "Updating {} mailboxes, deleted {}",
+                    updated.len(),
+                    deleted.len()
+                );
 
-            _ => {
-                let mut resp = jmap_api.query_mailboxes().await?;
-                tracing::info!("Got mailbox query: {resp:?}");
-                (resp.take_query_state(), resp.take_ids(), vec![])
+                let new_state = resp.take_new_state();
+                let updated = if updated.is_empty() {
+                    vec![]
+                } else {
+                    jmap_api
+                        .get_mailboxes(Some(updated))
+                        .await
+                        .context("Error getting mailboxes")?
+                        .take_list()
+                };
+                (new_state, updated, deleted)
             }
-        };
 
-        tracing::info!(
-            "Updating {} mailboxes, deleted {}",
-            updated.len(),
-            deleted.len()
-        );
-
-        // Fetch all updated mailboxes details
-        let updated = if updated.is_empty() {
-            vec![]
-        } else {
-            jmap_api
-                .get_mailboxes(updated)
-                .await
-                .context("Error getting mailboxes")?
-                .take_list()
+            None => {
+                // One Mailbox/get of all mailboxes, whose state Mailbox/changes resumes from
+                let mut resp = jmap_api
+                    .get_mailboxes(None)
+                    .await
+                    .context("Error getting mailboxes")?;
+                let mailboxes = resp.take_list();
+
+                // Anything we have that the server no longer lists is gone
+                let deleted = repo
+                    .get_mailbox_ids(account_id)
+                    .await?
+                    .into_iter()
+                    .filter(|id| !mailboxes.iter().any(|m| m.id() == Some(id.as_str())))
+                    .collect::<Vec<_>>();
+                tracing::info!(
+                    "Got {} mailboxes, deleted {}",
+                    mailboxes.len(),
+                    deleted.len()
+                );
+
+                (resp.take_state(), mailboxes, deleted)
+            }
         };
 
         repo.update_mailboxes(account_id, &new_state, updated, deleted)
             .await
             .context("Failed to update mailboxes")?;
 
+        if has_more_changes {
+            continue;
+        }
+
         loop {
             match push_sub.recv().await?.as_ref() {
                 PushObject::StateChange { changed }
@@ -69,3 +113,57 @@ pub async fn sync_mailbox_list(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jmap_api::testing::FakeServer;
+    use crate::repo::testing;
+    use jmap_client::mailbox::Mailbox;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn expired_state_resyncs_the_list_once() {
+        let server = FakeServer::start(
+            |method, _| match method {
+                "Mailbox/changes" => Err("cannotCalculateChanges"),
+                "Mailbox/get" => Ok(json!({
+                    "accountId": "a",
+                    "state": "s2",
+                    "list": [{"id": "inbox", "name": "Inbox", "role": "inbox"}],
+                    "notFound": [],
+                })),
+                _ => Err("unknownMethod"),
+            },
+            Default::default(),
+        )
+        .await;
+        let api = server.connect().await;
+
+        let repo = Arc::new(testing::repository().await);
+        let account_id = testing::add_account(&repo, "a").await;
+        let gone = json!({"id": "gone", "name": "Gone"}).to_string();
+        let gone: Mailbox = serde_json::from_str(&gone).unwrap();
+        repo.update_mailboxes(account_id, "expired", vec![gone], vec![])
+            .await
+            .unwrap();
+
+        let _sync = tokio::spawn(sync_mailbox_list(repo.clone(), account_id, api));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while repo.get_mailboxes_sync_state(account_id).await.unwrap() != Some("s2".into()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Mailbox list wasn't resynced");
+
+        assert_eq!(repo.get_mailbox_ids(account_id).await.unwrap(), ["inbox"]);
+
+        // The one full sync is a single Mailbox/get of everything, and nothing follows it
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.methods(), ["Mailbox/changes", "Mailbox/get"]);
+        let calls = server.calls.lock();
+        assert!(calls[1].1.get("ids").is_none_or(|ids| ids.is_null()));
+    }
+}
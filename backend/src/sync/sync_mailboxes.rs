@@ -1,18 +1,25 @@
 use super::EmailQueryState;
 use crate::jmap_account::AccountId;
-use crate::jmap_api::{EmailQuery, EmailSort, EmailSortColumn, JmapApi};
+use crate::jmap_api::{
+    EmailQuery, EmailSort, EmailSortColumn, JmapApi, JmapMethodError, SUMMARY_PROPERTIES,
+};
 use crate::repo::Repository;
 use crate::util::tasks::{AbortHandleExt, AutoAbortHandle};
 use anyhow::{Context, bail};
 use derive_more::Debug;
 use itertools::Itertools;
+use jmap_client::core::error::MethodErrorType;
+use jmap_client::email::Property;
 use jmap_client::{DataType, PushObject};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::select;
-use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, broadcast, mpsc, oneshot, watch};
 use tracing::instrument;
 
+/// Asks an account's sync to keep one of its mailboxes synced. Mailbox ids are only unique
+/// within an account, so the command must go to that account's own command channel.
 #[derive(Debug)]
 pub struct WatchMailboxSyncCommand {
     pub mailbox_id: String,
@@ -34,7 +41,11 @@ pub async fn handle_watch_mailbox_command(
         .await
         .context("Failed to send mailbox watch request")?;
 
-    let mut rx = rx.await.context("Mailbox watch request cancelled")?;
+    // The mailbox may take a while to show up, during which the client may have gone
+    let mut rx = select! {
+        rx = rx => rx.context("Mailbox watch request cancelled")?,
+        _ = state_tx.closed() => return Ok(()),
+    };
 
     loop {
         state_tx.send(rx.borrow().clone())?;
@@ -47,47 +58,93 @@ pub async fn sync_mailboxes(
     account_id: AccountId,
     jmap_api: Arc<JmapApi>,
     mut mailbox_watch_request_rx: mpsc::Receiver<(String, WatchRequest)>,
+    max_concurrent_syncs: usize,
 ) -> anyhow::Result<()> {
     let mut sub = repo.subscribe_db_changes();
-    let push_notification = jmap_api.subscribe_pushes();
+    let mut push_notification = jmap_api.subscribe_pushes();
+    let limiter = MailboxSyncLimiter::new(max_concurrent_syncs);
 
     struct MailboxSyncState {
         watch_request_sender: mpsc::Sender<WatchRequest>,
-        handle: AutoAbortHandle,
+        /// Holds at most one pending resync, so pushes arriving in a burst coalesce.
+        push_sender: mpsc::Sender<()>,
+        _handle: AutoAbortHandle,
     }
 
+    // Keyed by mailbox id, which other accounts may use too. That's fine as every account
+    // runs its own sync_mailboxes.
     let mut mailbox_workers: HashMap<String, MailboxSyncState> = Default::default();
 
+    // Watch requests for mailboxes that aren't known yet, e.g. before the first mailbox list
+    // sync of a new account. They're handed over once the mailbox shows up.
+    let mut pending_watches: HashMap<String, Vec<WatchRequest>> = Default::default();
+
+    // The account's email state as of the last push, to tell which mailboxes the next one
+    // touches. Until it's known, a push resyncs every mailbox.
+    let mut email_state: Option<String> = None;
+
     loop {
-        let mailboxes: HashSet<String> = repo
-            .get_mailbox_ids(account_id)
-            .await?
-            .into_iter()
-            .collect();
+        let mut mailboxes = repo.get_mailbox_ids(account_id).await?;
+        let inbox_id = repo.find_mailbox_id_by_role(account_id, "inbox").await?;
 
         // Drop all workers for mailboxes that no longer exist
-        mailbox_workers.retain(|mailbox_id, _| mailboxes.contains(mailbox_id));
+        let existing: HashSet<&String> = mailboxes.iter().collect();
+        mailbox_workers.retain(|mailbox_id, _| existing.contains(mailbox_id));
+
+        pending_watches.retain(|_, requests| {
+            requests.retain(|r| !r.is_closed());
+            !requests.is_empty()
+        });
+
+        // On a cold start, the Inbox and the mailboxes someone is already waiting for go
+        // first, so they get to sync before the rest
+        mailboxes.sort_by_key(|id| {
+            (
+                inbox_id.as_ref() != Some(id),
+                !pending_watches.contains_key(id),
+            )
+        });
 
         // Start workers for new mailboxes
         for mailbox_id in mailboxes {
             if !mailbox_workers.contains_key(&mailbox_id) {
                 let (watch_request_sender, watch_request_rx) = mpsc::channel(10);
+                let (push_sender, push_rx) = mpsc::channel(1);
                 mailbox_workers.insert(
                     mailbox_id.clone(),
                     MailboxSyncState {
                         watch_request_sender,
-                        handle: tokio::spawn(sync_mailbox(
+                        push_sender,
+                        _handle: tokio::spawn(sync_mailbox(
                             repo.clone(),
                             account_id,
-                            mailbox_id,
+                            inbox_id.as_ref() == Some(&mailbox_id),
+                            mailbox_id.clone(),
                             jmap_api.clone(),
-                            push_notification.resubscribe(),
+                            push_rx,
                             watch_request_rx,
+                            limiter.clone(),
                         ))
                         .auto_abort(),
                     },
                 );
             }
+
+            let (Some(requests), Some(worker)) = (
+                pending_watches.remove(&mailbox_id),
+                mailbox_workers.get(&mailbox_id),
+            ) else {
+                continue;
+            };
+
+            for request in requests {
+                if let Err(e) = worker.watch_request_sender.try_send(request) {
+                    tracing::debug!(
+                        ?e,
+                        "Failed to send watch request to mailbox {mailbox_id} worker, channel full"
+                    );
+                }
+            }
         }
 
         loop {
@@ -98,7 +155,8 @@ pub async fn sync_mailboxes(
                     };
 
                     let Some(worker) = mailbox_workers.get(&mailbox_id) else {
-                        tracing::debug!("No worker for mailbox {mailbox_id}, cannot handle watch request");
+                        tracing::debug!("No worker for mailbox {mailbox_id} yet, holding on to watch request");
+                        pending_watches.entry(mailbox_id).or_default().push(watch_request);
                         continue;
                     };
 
@@ -106,6 +164,60 @@ pub async fn sync_mailboxes(
                         tracing::debug!(?e, "Failed to send watch request to mailbox {mailbox_id} worker, channel full");
                     }
                 }
+                p = push_notification.recv() => {
+                    let jmap_account_id = jmap_api.jmap_account_id().await;
+                    let mut pushed_state = match p {
+                        Ok(push) => match pushed_email_state(&push, &jmap_account_id) {
+                            Some(state) => state,
+                            None => continue,
+                        },
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            tracing::warn!("Missed {n} push notifications, resyncing all mailboxes");
+                            email_state = None;
+                            String::new()
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            bail!("Push notification channel closed unexpectedly");
+                        }
+                    };
+
+                    // Pushes that queued up meanwhile are covered by the same round of changes
+                    while let Ok(push) = push_notification.try_recv() {
+                        if let Some(state) = pushed_email_state(&push, &jmap_account_id) {
+                            pushed_state = state;
+                        }
+                    }
+
+                    let changed = match email_state.take() {
+                        Some(since_state) => {
+                            changed_mailboxes(&repo, &jmap_api, account_id, since_state).await
+                        }
+                        None => Ok(None),
+                    };
+
+                    let changed = match changed {
+                        Ok(Some((new_state, mailbox_ids))) => {
+                            email_state = Some(new_state);
+                            Some(mailbox_ids)
+                        }
+                        Ok(None) => None,
+                        Err(e) => {
+                            tracing::warn!(?e, "Error working out changed mailboxes, resyncing all");
+                            None
+                        }
+                    };
+
+                    if email_state.is_none() && !pushed_state.is_empty() {
+                        email_state = Some(pushed_state);
+                    }
+
+                    for (mailbox_id, worker) in &mailbox_workers {
+                        if changed.as_ref().is_none_or(|ids| ids.contains(mailbox_id)) {
+                            // A full channel means a resync is pending already
+                            let _ = worker.push_sender.try_send(());
+                        }
+                    }
+                }
                 m = sub.recv() => {
                     match m {
                         Ok(change) if change.tables.contains(&"mailboxes") => {
@@ -125,46 +237,127 @@ pub async fn sync_mailboxes(
     }
 }
 
+/// The new email state a push announces for the synced JMAP account, if it's about its emails
+/// at all. Changes to other accounts of the same session are none of this account's business.
+fn pushed_email_state(push: &PushObject, jmap_account_id: &str) -> Option<String> {
+    match push {
+        PushObject::StateChange { changed } => {
+            changed.get(jmap_account_id)?.get(&DataType::Email).cloned()
+        }
+        _ => None,
+    }
+}
+
+/// Works out which mailboxes the email changes since `since_state` touch: the ones the changed
+/// emails are stored in, for what they've left, and the ones they're in now. Returns `None`
+/// when the server can't tell the changes from that state any more.
+async fn changed_mailboxes(
+    repo: &Repository,
+    jmap_api: &JmapApi,
+    account_id: AccountId,
+    since_state: String,
+) -> anyhow::Result<Option<(String, HashSet<String>)>> {
+    let Some((new_state, updated, destroyed)) = fetch_email_changes(jmap_api, since_state).await?
+    else {
+        return Ok(None);
+    };
+
+    let mut mailbox_ids = repo
+        .get_mailbox_ids_of_emails(account_id, &[updated.as_slice(), &destroyed].concat())
+        .await?;
+
+    for chunk in updated.chunks(200) {
+        let emails = jmap_api
+            .get_emails(
+                chunk.to_vec(),
+                Some(vec![Property::Id, Property::MailboxIds]),
+            )
+            .await
+            .context("Error getting mailboxes of changed emails")?
+            .take_list();
+
+        mailbox_ids.extend(
+            emails
+                .iter()
+                .flat_map(|email| email.mailbox_ids())
+                .map(str::to_string),
+        );
+    }
+
+    tracing::debug!(?mailbox_ids, "Email changes touch mailboxes");
+    Ok(Some((new_state, mailbox_ids)))
+}
+
 pub type WatchRequest = oneshot::Sender<watch::Receiver<EmailQueryState>>;
 
+/// Caps how many of an account's mailboxes sync at once, so a change in a large account
+/// doesn't set off hundreds of syncs together.
+#[derive(Clone)]
+struct MailboxSyncLimiter {
+    shared: Arc<Semaphore>,
+    /// An extra slot for priority mailboxes, so they never queue up behind the others.
+    priority: Arc<Semaphore>,
+}
+
+impl MailboxSyncLimiter {
+    fn new(max_concurrent: usize) -> Self {
+        Self {
+            shared: Arc::new(Semaphore::new(max_concurrent)),
+            priority: Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    async fn acquire(&self, priority: bool) -> anyhow::Result<OwnedSemaphorePermit> {
+        let permit = if priority {
+            select! {
+                p = self.priority.clone().acquire_owned() => p,
+                p = self.shared.clone().acquire_owned() => p,
+            }
+        } else {
+            self.shared.clone().acquire_owned().await
+        };
+
+        permit.context("Mailbox sync limiter closed")
+    }
+}
+
+/// Syncs a mailbox for as long as it has watchers. Priority mailboxes, i.e. the Inbox, get
+/// ahead of the others when the number of concurrent syncs is at its limit.
 #[instrument(
-    skip(repo, jmap_api, email_notification, watcher_requests),
+    skip(repo, jmap_api, pushes, watcher_requests, limiter),
     level = "info"
 )]
-pub async fn sync_mailbox(
+#[allow(clippy::too_many_arguments)]
+async fn sync_mailbox(
     repo: Arc<Repository>,
     account_id: AccountId,
+    priority: bool,
     mailbox_id: String,
     jmap_api: Arc<JmapApi>,
-    mut email_notification: broadcast::Receiver<Arc<PushObject>>,
+    mut pushes: mpsc::Receiver<()>,
     mut watcher_requests: mpsc::Receiver<WatchRequest>,
+    limiter: MailboxSyncLimiter,
 ) -> anyhow::Result<()> {
     let (state_tx, _state_rx) = watch::channel(EmailQueryState::NotStarted);
+    let started_at = Instant::now();
+    let mut synced_before = false;
 
     loop {
         let wait_for_push = async {
             if state_tx.receiver_count() > 1 {
                 tracing::debug!("Waiting for push notification for emails");
-                loop {
-                    match email_notification.recv().await?.as_ref() {
-                        PushObject::StateChange { changed }
-                            if changed.values().any(|m| m.contains_key(&DataType::Email)) =>
-                        {
-                            break anyhow::Ok(());
-                        }
-
-                        _ => continue,
-                    }
-                }
+                pushes.recv().await
             } else {
-                futures::future::pending::<()>().await;
-                Ok(())
+                futures::future::pending().await
             }
         };
 
         select! {
             r = wait_for_push => {
-                r.context("Failed to wait for push notification")?;
+                if r.is_none() {
+                    tracing::debug!("Push channel closed, stop syncing");
+                    return Ok(());
+                }
                 tracing::info!("Received push notification");
                 if state_tx.receiver_count() < 2 {
                     tracing::info!("No active watchers, not syncing");
@@ -186,11 +379,15 @@ pub async fn sync_mailbox(
             }
         }
 
-        tracing::info!("Start syncing mailbox");
-
         let _ = state_tx.send(EmailQueryState::InProgress);
+        let _permit = limiter.acquire(priority).await?;
+
+        // This sync covers whatever was pushed while waiting for a slot
+        while pushes.try_recv().is_ok() {}
+
+        tracing::info!("Start syncing mailbox");
 
-        match sync_mailbox_once(&repo, account_id, &mailbox_id, &jmap_api).await {
+        match sync_mailbox_once(&repo, account_id, &mailbox_id, &jmap_api, &state_tx).await {
             Ok(_) => {}
             Err(e) => {
                 tracing::error!(?e, "Sync failed");
@@ -201,44 +398,88 @@ pub async fn sync_mailbox(
             }
         }
 
+        if !synced_before {
+            synced_before = true;
+            tracing::info!(elapsed = ?started_at.elapsed(), "First sync of mailbox done");
+        }
+
         let _ = state_tx.send(EmailQueryState::UpToDate);
     }
 }
 
-#[instrument(skip(repo, jmap_api), ret, level = "debug")]
+/// Brings the mailbox's emails up to date, reporting progress on `state_tx` as they are fetched.
+#[instrument(skip(repo, jmap_api, state_tx), ret, level = "debug")]
 pub async fn sync_mailbox_once(
     repo: &Repository,
     account_id: AccountId,
     mailbox_id: &str,
     jmap_api: &JmapApi,
+    state_tx: &watch::Sender<EmailQueryState>,
 ) -> anyhow::Result<()> {
     let mut updated = vec![];
     let mut deleted = vec![];
-    let new_state: String;
-    match repo
-        .get_mailbox_email_sync_state(account_id, &mailbox_id)
+
+    let changed_state = match repo
+        .get_mailbox_email_sync_state(account_id, mailbox_id)
         .await
         .context("Error getting mailbox email sync state")?
     {
-        Some(last_state) => loop {
-            let mut changes = jmap_api
-                .email_changes(last_state.clone())
-                .await
-                .context("Error updating email changes")?;
-            updated.extend(changes.take_updated());
-            updated.extend(changes.take_created());
-            deleted.extend(changes.take_destroyed());
-
-            if !changes.has_more_changes() {
-                new_state = changes.take_new_state();
-                break;
+        Some(last_state) => match fetch_email_changes(jmap_api, last_state).await? {
+            Some((new_state, changed, destroyed)) => {
+                updated = changed;
+                deleted = destroyed;
+                Some(new_state)
+            }
+
+            None => {
+                // Forget the expired state so that a failed resync doesn't retry it forever
+                repo.set_mailbox_email_sync_state(account_id, mailbox_id, None)
+                    .await
+                    .context("Error clearing mailbox email sync state")?;
+                None
             }
         },
 
+        None => None,
+    };
+
+    let mut total = updated.len();
+    let mut fetched = 0;
+    let full_sync = changed_state.is_none();
+    let new_state = match changed_state {
+        Some(new_state) => new_state,
+
         None => {
+            let resume = repo
+                .get_mailbox_sync_resume(account_id, mailbox_id)
+                .await
+                .context("Error getting mailbox sync progress")?;
+            let resuming = resume.is_some();
+
+            // Ending on the state the interrupted sync started from lets the next round of
+            // changes cover whatever happened to the emails it already stored
+            let (started_state, anchor) = match resume {
+                Some(resume) => resume,
+                None => {
+                    // Email/changes takes the state of the emails, not that of the query. Taking
+                    // it before the query means the next changes cover whatever happens meanwhile.
+                    let email_state = jmap_api
+                        .get_emails(vec![], Some(vec![Property::Id]))
+                        .await
+                        .context("Error getting email state")?
+                        .take_state();
+                    repo.set_mailbox_sync_resume(account_id, mailbox_id, &email_state, None)
+                        .await
+                        .context("Error saving mailbox sync progress")?;
+                    (email_state, None)
+                }
+            };
+
             let mut emails = jmap_api
                 .query_emails(EmailQuery {
                     anchor_id: None,
+                    anchor_offset: None,
+                    position: None,
                     mailbox_id: Some(mailbox_id.to_string()),
                     search_keyword: None,
                     sorts: vec![EmailSort {
@@ -250,15 +491,27 @@ pub async fn sync_mailbox_once(
                 .await
                 .context("Error querying emails")?;
 
-            new_state = emails.take_query_state();
             updated.extend(emails.take_ids());
+            // The server may return fewer ids than it counts, never more
+            total = emails.total().unwrap_or_default().max(updated.len());
+
+            if resuming {
+                fetched = skip_stored(repo, account_id, &mut updated, anchor.as_deref())
+                    .await
+                    .context("Error skipping stored emails")?;
+                tracing::info!("Resuming mailbox sync, {fetched} emails already stored");
+            }
+
+            started_state
         }
-    }
+    };
 
     while !updated.is_empty() {
         let chunk_size = updated.len().min(200);
+        let chunk = updated.drain(0..chunk_size).collect_vec();
+        let anchor = chunk.last().cloned();
         let emails = jmap_api
-            .get_emails(updated.drain(0..chunk_size).collect_vec(), None)
+            .get_emails(chunk, Some(SUMMARY_PROPERTIES.to_vec()))
             .await
             .context("Error getting emails")?
             .take_list();
@@ -268,6 +521,15 @@ pub async fn sync_mailbox_once(
         repo.update_emails(account_id, &emails)
             .await
             .context("Error updating emails")?;
+
+        if full_sync {
+            repo.set_mailbox_sync_resume(account_id, mailbox_id, &new_state, anchor.as_deref())
+                .await
+                .context("Error saving mailbox sync progress")?;
+        }
+
+        fetched += chunk_size;
+        let _ = state_tx.send(EmailQueryState::Fetching { fetched, total });
     }
 
     if !deleted.is_empty() {
@@ -278,9 +540,289 @@ pub async fn sync_mailbox_once(
             .context("Error deleting emails")?;
     }
 
-    repo.set_mailbox_email_sync_state(account_id, mailbox_id, &new_state)
+    repo.set_mailbox_email_sync_state(account_id, mailbox_id, Some(&new_state))
         .await
         .context("Error setting mailbox email sync state")?;
 
     Ok(())
 }
+
+/// Takes the emails an interrupted sync got through, i.e. those up to `anchor` in the query
+/// order, out of `ids` when they're stored. Ones that arrived since are left in. Returns how
+/// many were taken out.
+async fn skip_stored(
+    repo: &Repository,
+    account_id: AccountId,
+    ids: &mut Vec<String>,
+    anchor: Option<&str>,
+) -> anyhow::Result<usize> {
+    let Some(position) = anchor.and_then(|anchor| ids.iter().position(|id| id == anchor)) else {
+        return Ok(0);
+    };
+
+    let done = ids.drain(..=position).collect_vec();
+    let missing = repo.find_missing_email_ids(account_id, &done).await?;
+    let skipped = done.len() - missing.len();
+    ids.splice(0..0, done.into_iter().filter(|id| missing.contains(id)));
+    Ok(skipped)
+}
+
+/// Collects the email changes since `since_state`, returning the new state with the changed
+/// and destroyed ids, or `None` when the server can no longer calculate changes from that state.
+async fn fetch_email_changes(
+    jmap_api: &JmapApi,
+    mut since_state: String,
+) -> anyhow::Result<Option<(String, Vec<String>, Vec<String>)>> {
+    let mut updated = vec![];
+    let mut deleted = vec![];
+
+    loop {
+        let mut changes = match jmap_api.email_changes(since_state).await {
+            Ok(changes) => changes,
+            Err(e) if JmapMethodError::is_kind(&e, MethodErrorType::CannotCalculateChanges) => {
+                tracing::warn!("Server can't calculate email changes, resyncing mailbox");
+                return Ok(None);
+            }
+            Err(e) => return Err(e).context("Error updating email changes"),
+        };
+
+        updated.extend(changes.take_updated());
+        updated.extend(changes.take_created());
+        deleted.extend(changes.take_destroyed());
+        since_state = changes.take_new_state();
+
+        if !changes.has_more_changes() {
+            return Ok(Some((since_state, updated, deleted)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jmap_api::testing::FakeServer;
+    use crate::repo::testing;
+    use jmap_client::mailbox::Mailbox;
+    use serde_json::json;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    #[tokio::test]
+    async fn expired_state_resyncs_the_mailbox_once() {
+        let server = FakeServer::start(
+            |method, args| match method {
+                "Email/changes" => Err("cannotCalculateChanges"),
+                "Email/query" => Ok(json!({
+                    "accountId": "a", "queryState": "q1", "canCalculateChanges": false,
+                    "position": 0, "ids": ["m1"], "total": 1,
+                })),
+                "Email/get" if args["ids"] == json!([]) => Ok(json!({
+                    "accountId": "a", "state": "e2", "list": [], "notFound": [],
+                })),
+                "Email/get" => Ok(json!({
+                    "accountId": "a", "state": "e2", "notFound": [],
+                    "list": [{
+                        "id": "m1",
+                        "mailboxIds": {"inbox": true},
+                        "receivedAt": "2025-01-01T00:00:00Z",
+                    }],
+                })),
+                _ => Err("unknownMethod"),
+            },
+            Default::default(),
+        )
+        .await;
+        let api = server.connect().await;
+
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        let inbox = json!({"id": "inbox", "name": "Inbox", "role": "inbox"}).to_string();
+        let inbox: Mailbox = serde_json::from_str(&inbox).unwrap();
+        repo.update_mailboxes(account_id, "s1", vec![inbox], vec![])
+            .await
+            .unwrap();
+        repo.set_mailbox_email_sync_state(account_id, "inbox", Some("expired"))
+            .await
+            .unwrap();
+
+        let (state_tx, _state_rx) = watch::channel(EmailQueryState::NotStarted);
+        sync_mailbox_once(&repo, account_id, "inbox", &api, &state_tx)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            server.methods(),
+            ["Email/changes", "Email/get", "Email/query", "Email/get"]
+        );
+        // Later changes resume from the state of the emails, not that of the query
+        assert_eq!(
+            repo.get_mailbox_email_sync_state(account_id, "inbox")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("e2")
+        );
+        assert!(
+            repo.find_missing_email_ids(account_id, &["m1".to_string()])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn interrupted_sync_resumes_after_the_stored_emails() {
+        let ids = (0..250).map(|i| format!("m{i:03}")).collect_vec();
+        let fail_second_chunk = Arc::new(AtomicBool::new(true));
+        let server = FakeServer::start(
+            {
+                let ids = ids.clone();
+                let fail_second_chunk = fail_second_chunk.clone();
+                move |method, args| match method {
+                    "Email/query" => Ok(json!({
+                        "accountId": "a", "queryState": "q1", "canCalculateChanges": false,
+                        "position": 0, "ids": ids, "total": ids.len(),
+                    })),
+                    "Email/get" if args["ids"][0] == "m200" && fail_second_chunk.load(SeqCst) => {
+                        Err("serverFail")
+                    }
+                    "Email/get" => {
+                        let list = args["ids"].as_array().unwrap().iter().map(|id| {
+                            json!({
+                                "id": id,
+                                "mailboxIds": {"inbox": true},
+                                "receivedAt": "2025-01-01T00:00:00Z",
+                            })
+                        });
+                        Ok(json!({
+                            "accountId": "a", "state": "e1", "notFound": [],
+                            "list": list.collect_vec(),
+                        }))
+                    }
+                    _ => Err("unknownMethod"),
+                }
+            },
+            Default::default(),
+        )
+        .await;
+        let api = server.connect().await;
+
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        let inbox = json!({"id": "inbox", "name": "Inbox", "role": "inbox"}).to_string();
+        let inbox: Mailbox = serde_json::from_str(&inbox).unwrap();
+        repo.update_mailboxes(account_id, "s1", vec![inbox], vec![])
+            .await
+            .unwrap();
+
+        let (state_tx, _state_rx) = watch::channel(EmailQueryState::NotStarted);
+        sync_mailbox_once(&repo, account_id, "inbox", &api, &state_tx)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            repo.find_missing_email_ids(account_id, &ids).await.unwrap(),
+            ids[200..].iter().cloned().collect()
+        );
+
+        fail_second_chunk.store(false, SeqCst);
+        server.calls.lock().clear();
+        sync_mailbox_once(&repo, account_id, "inbox", &api, &state_tx)
+            .await
+            .unwrap();
+
+        // Only the emails after the saved anchor are fetched again
+        let calls = server.calls.lock().clone();
+        let fetched = calls
+            .iter()
+            .filter(|(method, _)| method == "Email/get")
+            .flat_map(|(_, args)| {
+                serde_json::from_value::<Vec<String>>(args["ids"].clone()).unwrap()
+            })
+            .collect_vec();
+        assert_eq!(fetched, ids[200..]);
+        assert!(
+            repo.find_missing_email_ids(account_id, &ids)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn pushed_changes_touch_old_and_new_mailboxes() {
+        let server = FakeServer::start(
+            |method, args| match method {
+                "Email/changes" => Ok(json!({
+                    "accountId": "a", "oldState": "e1", "newState": "e2",
+                    "hasMoreChanges": false,
+                    "created": ["m4"], "updated": ["m2"], "destroyed": ["m3"],
+                })),
+                "Email/get" => {
+                    assert_eq!(args["ids"], json!(["m2", "m4"]));
+                    Ok(json!({
+                        "accountId": "a", "state": "e2", "notFound": [],
+                        "list": [
+                            {"id": "m2", "mailboxIds": {"archive": true}},
+                            {"id": "m4", "mailboxIds": {"inbox": true}},
+                        ],
+                    }))
+                }
+                _ => Err("unknownMethod"),
+            },
+            Default::default(),
+        )
+        .await;
+        let api = server.connect().await;
+
+        let repo = testing::repository().await;
+        let account_id = testing::add_account(&repo, "a").await;
+        let mailboxes = ["drafts", "old", "sent", "archive", "inbox"]
+            .map(|id| serde_json::from_str(&json!({"id": id, "name": id}).to_string()).unwrap());
+        repo.update_mailboxes(account_id, "s1", mailboxes.to_vec(), vec![])
+            .await
+            .unwrap();
+        let stored = [("m1", "drafts"), ("m2", "old"), ("m3", "sent")].map(|(id, mailbox)| {
+            serde_json::from_value(json!({
+                "id": id,
+                "mailboxIds": {mailbox: true},
+                "receivedAt": "2025-01-01T00:00:00Z",
+            }))
+            .unwrap()
+        });
+        repo.update_emails(account_id, &stored).await.unwrap();
+
+        let (state, mailbox_ids) = changed_mailboxes(&repo, &api, account_id, "e1".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state, "e2");
+        assert_eq!(
+            mailbox_ids,
+            HashSet::from(["old", "sent", "archive", "inbox"].map(str::to_string))
+        );
+    }
+
+    #[test]
+    fn pushes_for_other_accounts_are_ignored() {
+        let push =
+            |value: serde_json::Value| -> PushObject { serde_json::from_value(value).unwrap() };
+
+        let ours = push(json!({
+            "@type": "StateChange",
+            "changed": {"a": {"Email": "e2", "Mailbox": "s2"}, "shared": {"Email": "x9"}},
+        }));
+        assert_eq!(pushed_email_state(&ours, "a").as_deref(), Some("e2"));
+
+        let theirs = push(json!({
+            "@type": "StateChange",
+            "changed": {"shared": {"Email": "x9"}},
+        }));
+        assert_eq!(pushed_email_state(&theirs, "a"), None);
+
+        let mailboxes_only = push(json!({
+            "@type": "StateChange",
+            "changed": {"a": {"Mailbox": "s2"}},
+        }));
+        assert_eq!(pushed_email_state(&mailboxes_only, "a"), None);
+    }
+}
@@ -1,9 +1,11 @@
 use crate::jmap_account::AccountId;
-use crate::jmap_api::{EmailQuery, JmapApi};
+use crate::jmap_api::{JmapApi, JmapMethodError, SUMMARY_PROPERTIES};
 use crate::repo::Repository;
-use crate::sync::EmailQueryState;
+use crate::sync::{EmailQueryState, SequencedEmailQuery, SequencedEmailQueryState};
 use anyhow::Context;
 use futures::future::{Either, select};
+use jmap_client::core::error::MethodErrorType;
+use jmap_client::email::Property;
 use jmap_client::{DataType, PushObject};
 use std::fmt::{Debug, Formatter};
 use std::pin::pin;
@@ -11,8 +13,8 @@ use std::sync::Arc;
 use tokio::sync::watch;
 
 pub struct WatchEmailSyncCommand {
-    pub query_rx: watch::Receiver<EmailQuery>,
-    pub state_tx: watch::Sender<EmailQueryState>,
+    pub query_rx: watch::Receiver<SequencedEmailQuery>,
+    pub state_tx: watch::Sender<SequencedEmailQueryState>,
 }
 
 impl Debug for WatchEmailSyncCommand {
@@ -39,13 +41,31 @@ pub async fn handle_watch_command(
     let mut push_sub = jmap_api.subscribe_pushes();
 
     loop {
+        let SequencedEmailQuery { seq, query } = query_rx.borrow().clone();
+        let send_state = |state| state_tx.send(SequencedEmailQueryState { seq, state });
+
         let fetch_results = async {
-            state_tx.send(EmailQueryState::InProgress)?;
-            let query = query_rx.borrow().clone();
+            send_state(EmailQueryState::InProgress)?;
+
+            let changes = match &last_sync_state {
+                Some(state) => match jmap_api.email_changes(state.state.clone()).await {
+                    Ok(changes) => Some((state, changes)),
+                    Err(e)
+                        if JmapMethodError::is_kind(
+                            &e,
+                            MethodErrorType::CannotCalculateChanges,
+                        ) =>
+                    {
+                        tracing::warn!("Server can't calculate changes, re-querying emails");
+                        None
+                    }
+                    Err(e) => return Err(e),
+                },
+                None => None,
+            };
 
-            let (updated, destroyed, new_state) = match &last_sync_state {
-                Some(state) => {
-                    let mut changes = jmap_api.email_changes(state.state.clone()).await?;
+            let (updated, destroyed, new_state) = match changes {
+                Some((state, mut changes)) => {
                     let new_total = state
                         .total
                         .map(|total| total + changes.created().len() - changes.destroyed().len());
@@ -61,13 +81,18 @@ pub async fn handle_watch_command(
                     )
                 }
 
-                _ => {
+                None => {
+                    // Email/changes takes the state of the emails, not that of the query
+                    let state = jmap_api
+                        .get_emails(vec![], Some(vec![Property::Id]))
+                        .await?
+                        .take_state();
                     let mut resp = jmap_api.query_emails(query.clone()).await?;
                     (
                         resp.take_ids(),
                         vec![],
                         LastSyncState {
-                            state: resp.take_query_state(),
+                            state,
                             total: resp.total(),
                         },
                     )
@@ -81,7 +106,10 @@ pub async fn handle_watch_command(
 
             if !updated.is_empty() {
                 let emails = jmap_api
-                    .get_emails(updated.into_iter().collect(), None)
+                    .get_emails(
+                        updated.into_iter().collect(),
+                        Some(SUMMARY_PROPERTIES.to_vec()),
+                    )
                     .await?
                     .take_list();
 
@@ -99,13 +127,13 @@ pub async fn handle_watch_command(
 
         match fetch_results.await {
             Ok(new_state) => {
-                state_tx.send(EmailQueryState::UpToDate)?;
+                send_state(EmailQueryState::UpToDate)?;
                 last_sync_state.replace(new_state);
             }
 
             Err(e) => {
                 tracing::error!("Error syncing emails: {e:?}");
-                state_tx.send(EmailQueryState::Error {
+                send_state(EmailQueryState::Error {
                     details: e.to_string(),
                 })?;
             }
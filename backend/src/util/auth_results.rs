@@ -0,0 +1,124 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthResult {
+    Pass,
+    Fail,
+    #[default]
+    None,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationResults {
+    pub spf: AuthResult,
+    pub dkim: AuthResult,
+    pub dmarc: AuthResult,
+    /// Whether the sender shown in `From` is vouched for, i.e. DMARC passed.
+    pub verified_sender: bool,
+}
+
+/// Parses an `Authentication-Results` header value (RFC 8601). The header is free-form in
+/// practice, so anything that can't be understood is treated as no result at all.
+pub fn parse_authentication_results(value: &str) -> AuthenticationResults {
+    let mut results = AuthenticationResults::default();
+
+    // The first element is the authserv-id of the server that did the checks
+    for resinfo in strip_comments(value).split(';').skip(1) {
+        let Some((method, result)) = resinfo
+            .split_whitespace()
+            .next()
+            .and_then(|token| token.split_once('='))
+        else {
+            continue;
+        };
+
+        let result = match result.to_ascii_lowercase().as_str() {
+            "pass" => AuthResult::Pass,
+            "fail" | "softfail" | "permerror" => AuthResult::Fail,
+            _ => AuthResult::None,
+        };
+
+        let slot = match method.to_ascii_lowercase().as_str() {
+            "spf" => &mut results.spf,
+            "dkim" => &mut results.dkim,
+            "dmarc" => &mut results.dmarc,
+            _ => continue,
+        };
+
+        // A message can carry several signatures; one passing one is enough
+        if *slot != AuthResult::Pass {
+            *slot = result;
+        }
+    }
+
+    results.verified_sender = results.dmarc == AuthResult::Pass;
+    results
+}
+
+/// Removes `(...)` comments, which may contain anything, including `;` and `=`.
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0usize;
+    value
+        .chars()
+        .filter(|c| match c {
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' => {
+                depth = depth.saturating_sub(1);
+                false
+            }
+            _ => depth == 0,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gmail_style_header() {
+        let results = parse_authentication_results(
+            "mx.google.com;
+       dkim=pass header.i=@example.com header.s=s1 header.b=abc;
+       spf=pass (google.com: domain of ann@example.com designates 1.2.3.4 as permitted sender) smtp.mailfrom=ann@example.com;
+       dmarc=pass (p=REJECT sp=REJECT dis=NONE) header.from=example.com",
+        );
+        assert_eq!(results.spf, AuthResult::Pass);
+        assert_eq!(results.dkim, AuthResult::Pass);
+        assert_eq!(results.dmarc, AuthResult::Pass);
+        assert!(results.verified_sender);
+    }
+
+    #[test]
+    fn failures_and_missing_results() {
+        let results = parse_authentication_results(
+            "mx.example.net; spf=SoftFail smtp.mailfrom=x.example; dmarc=none header.from=x.example",
+        );
+        assert_eq!(results.spf, AuthResult::Fail);
+        assert_eq!(results.dkim, AuthResult::None);
+        assert_eq!(results.dmarc, AuthResult::None);
+        assert!(!results.verified_sender);
+    }
+
+    #[test]
+    fn one_passing_signature_is_enough() {
+        let results = parse_authentication_results(
+            "mx.example.net; dkim=fail header.d=list.example; dkim=pass header.d=example.com",
+        );
+        assert_eq!(results.dkim, AuthResult::Pass);
+    }
+
+    #[test]
+    fn comments_and_garbage_are_ignored() {
+        let results =
+            parse_authentication_results("mx.example.net (spf=pass; dkim=pass); nonsense; =; ;");
+        assert_eq!(results.spf, AuthResult::None);
+        assert_eq!(results.dkim, AuthResult::None);
+        assert_eq!(parse_authentication_results("").dmarc, AuthResult::None);
+    }
+}
@@ -0,0 +1,158 @@
+use jmap_client::email::EmailBodyPart;
+
+/// The part to show as an email's body, along with the parts its HTML may refer to by `cid:`.
+pub struct BodySelection<'a> {
+    pub part: &'a EmailBodyPart,
+    pub inline_parts: Vec<&'a EmailBodyPart>,
+}
+
+/// Walks a body structure for the part of type `preferred` to show, e.g. `text/html`, or one of
+/// type `fallback` when there's none.
+pub fn select_body<'a>(
+    structure: &'a EmailBodyPart,
+    preferred: &str,
+    fallback: &str,
+) -> Option<BodySelection<'a>> {
+    find_body(structure, preferred).or_else(|| find_body(structure, fallback))
+}
+
+fn find_body<'a>(part: &'a EmailBodyPart, mime_type: &str) -> Option<BodySelection<'a>> {
+    let content_type = part
+        .content_type()
+        .unwrap_or("text/plain")
+        .to_ascii_lowercase();
+
+    let Some(children) = part
+        .sub_parts()
+        .filter(|_| content_type.starts_with("multipart/"))
+    else {
+        return (content_type == mime_type && !is_attachment(part)).then(|| BodySelection {
+            part,
+            inline_parts: Vec::new(),
+        });
+    };
+
+    match content_type.as_str() {
+        // Alternatives come in increasing order of faithfulness, so the last match wins
+        "multipart/alternative" => children.iter().rev().find_map(|c| find_body(c, mime_type)),
+
+        // The first part is the root, the others are resources it refers to
+        "multipart/related" => {
+            let (root, resources) = children.split_first()?;
+            let mut selection = find_body(root, mime_type)?;
+            selection
+                .inline_parts
+                .extend(resources.iter().filter(|p| p.content_id().is_some()));
+            Some(selection)
+        }
+
+        // Mixed and anything unknown: the first body found, with any sibling inline parts,
+        // which some clients put here rather than in a multipart/related
+        _ => {
+            let mut selection = children.iter().find_map(|c| find_body(c, mime_type))?;
+            selection.inline_parts.extend(children.iter().filter(|p| {
+                p.content_id().is_some()
+                    && p.sub_parts().is_none()
+                    && !std::ptr::eq(*p, selection.part)
+            }));
+            Some(selection)
+        }
+    }
+}
+
+fn is_attachment(part: &EmailBodyPart) -> bool {
+    part.content_disposition()
+        .is_some_and(|d| d.eq_ignore_ascii_case("attachment"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn structure(value: serde_json::Value) -> EmailBodyPart {
+        serde_json::from_str(&value.to_string()).unwrap()
+    }
+
+    fn leaf(part_id: &str, mime_type: &str) -> serde_json::Value {
+        json!({"partId": part_id, "type": mime_type})
+    }
+
+    fn part_ids(selection: &BodySelection) -> (String, Vec<String>) {
+        (
+            selection.part.part_id().unwrap().to_string(),
+            selection
+                .inline_parts
+                .iter()
+                .map(|p| p.part_id().unwrap().to_string())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn alternative_inside_mixed_with_attachment() {
+        let body = structure(json!({
+            "type": "multipart/mixed",
+            "subParts": [
+                {
+                    "type": "multipart/alternative",
+                    "subParts": [leaf("1", "text/plain"), leaf("2", "text/html")],
+                },
+                {"partId": "3", "type": "text/html", "disposition": "attachment"},
+            ],
+        }));
+
+        let html = select_body(&body, "text/html", "text/plain").unwrap();
+        assert_eq!(part_ids(&html), ("2".to_string(), vec![]));
+        let text = select_body(&body, "text/plain", "text/html").unwrap();
+        assert_eq!(part_ids(&text), ("1".to_string(), vec![]));
+    }
+
+    #[test]
+    fn related_resources_come_with_their_root() {
+        let body = structure(json!({
+            "type": "multipart/alternative",
+            "subParts": [
+                leaf("1", "text/plain"),
+                {
+                    "type": "multipart/related",
+                    "subParts": [
+                        leaf("2", "text/html"),
+                        {"partId": "3", "type": "image/png", "cid": "logo"},
+                        leaf("4", "image/png"),
+                    ],
+                },
+            ],
+        }));
+
+        let html = select_body(&body, "text/html", "text/plain").unwrap();
+        assert_eq!(part_ids(&html), ("2".to_string(), vec!["3".to_string()]));
+    }
+
+    #[test]
+    fn inline_parts_beside_the_body_in_mixed() {
+        let body = structure(json!({
+            "type": "multipart/mixed",
+            "subParts": [
+                leaf("1", "text/html"),
+                {"partId": "2", "type": "image/gif", "cid": "pic"},
+            ],
+        }));
+
+        let html = select_body(&body, "text/html", "text/plain").unwrap();
+        assert_eq!(part_ids(&html), ("1".to_string(), vec!["2".to_string()]));
+    }
+
+    #[test]
+    fn falls_back_when_preferred_type_is_missing() {
+        let body = structure(leaf("1", "text/plain"));
+        let selection = select_body(&body, "text/html", "text/plain").unwrap();
+        assert_eq!(part_ids(&selection), ("1".to_string(), vec![]));
+
+        let attachment_only = structure(json!({
+            "type": "multipart/mixed",
+            "subParts": [{"partId": "1", "type": "text/plain", "disposition": "attachment"}],
+        }));
+        assert!(select_body(&attachment_only, "text/html", "text/plain").is_none());
+    }
+}
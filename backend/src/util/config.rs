@@ -0,0 +1,12 @@
+use std::str::FromStr;
+
+/// Reads and parses an environment variable, falling back to `default` when it's unset or invalid.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            tracing::warn!("Invalid value for {name}: {value}, using default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::bail;
+use std::collections::BTreeMap;
+
+/// Headers that are set from the email's own fields, or that only servers along the way may
+/// add. Letting clients set these would produce duplicates or forged trace headers.
+const FORBIDDEN: &[&str] = &[
+    "bcc",
+    "cc",
+    "content-transfer-encoding",
+    "content-type",
+    "date",
+    "from",
+    "in-reply-to",
+    "message-id",
+    "mime-version",
+    "references",
+    "reply-to",
+    "sender",
+    "subject",
+    "to",
+    "received",
+    "return-path",
+    "dkim-signature",
+    "domainkey-signature",
+    "authentication-results",
+    "arc-authentication-results",
+    "arc-message-signature",
+    "arc-seal",
+    "received-spf",
+    "delivered-to",
+];
+
+/// Checks headers a client wants added to an email, e.g. `X-Mailer`.
+pub fn check_custom_headers(headers: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    for (name, value) in headers {
+        // Printable ASCII other than the colon, as RFC 5322 has it
+        if name.is_empty() || !name.bytes().all(|b| (33..=126).contains(&b) && b != b':') {
+            bail!("Invalid header name {name:?}");
+        }
+
+        if FORBIDDEN.iter().any(|f| name.eq_ignore_ascii_case(f)) {
+            bail!("The {name} header can't be set");
+        }
+
+        if value.contains(['\r', '\n']) {
+            bail!("The value of {name} can't span lines");
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds `defaults` to `headers` where it doesn't have a header of the same name already.
+pub fn merge_custom_headers(
+    headers: &mut BTreeMap<String, String>,
+    defaults: &BTreeMap<String, String>,
+) {
+    for (name, value) in defaults {
+        if !headers.keys().any(|n| n.eq_ignore_ascii_case(name)) {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn checks_names_and_values() {
+        assert!(check_custom_headers(&headers(&[("X-Mailer", "mymail 1.0")])).is_ok());
+        assert!(check_custom_headers(&headers(&[("List-Unsubscribe", "<mailto:x@y>")])).is_ok());
+
+        assert!(check_custom_headers(&headers(&[("", "x")])).is_err());
+        assert!(check_custom_headers(&headers(&[("X Mailer", "x")])).is_err());
+        assert!(check_custom_headers(&headers(&[("X:Mailer", "x")])).is_err());
+        assert!(check_custom_headers(&headers(&[("X-Mäiler", "x")])).is_err());
+        assert!(check_custom_headers(&headers(&[("X-Mailer", "a\r\nBcc: b@c")])).is_err());
+    }
+
+    #[test]
+    fn forbids_own_and_trace_headers_in_any_case() {
+        for name in [
+            "From",
+            "message-id",
+            "RECEIVED",
+            "DKIM-Signature",
+            "ARC-Seal",
+        ] {
+            assert!(
+                check_custom_headers(&headers(&[(name, "x")])).is_err(),
+                "{name} was allowed"
+            );
+        }
+    }
+
+    #[test]
+    fn own_headers_win_over_defaults() {
+        let mut own = headers(&[("x-mailer", "mine")]);
+        merge_custom_headers(
+            &mut own,
+            &headers(&[("X-Mailer", "default"), ("X-Org", "acme")]),
+        );
+        assert_eq!(own, headers(&[("X-Org", "acme"), ("x-mailer", "mine")]));
+    }
+}
@@ -0,0 +1,92 @@
+/// Month names as in `asctime`.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Day names as in `asctime`, starting from Thursday, the weekday of the epoch.
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// Parses a date the way JMAP writes them, e.g. `2025-01-31T08:00:00Z`, as a unix timestamp.
+pub fn parse_utc_date(date: &str) -> Option<i64> {
+    let (date, time) = date.strip_suffix('Z')?.split_once('T')?;
+    let time = time.split('.').next()?;
+
+    let mut date = date.splitn(3, '-').map(|n| n.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|n| n.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    // Days since the epoch, from http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Parses a date the way HTTP headers write them, e.g. `Fri, 31 Jan 2025 08:00:00 GMT`, as a
+/// unix timestamp. The obsolete formats servers may still send aren't understood.
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    let (_, date) = date.split_once(", ")?;
+    let mut parts = date.strip_suffix(" GMT")?.split(' ');
+    let (day, month, year, time) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let month = MONTHS.iter().position(|m| *m == month)? + 1;
+    parse_utc_date(&format!("{year}-{month:02}-{day}T{time}Z"))
+}
+
+/// Formats a unix timestamp the way `asctime` does in UTC, e.g. `Fri Jan 31 08:00:00 2025`, as
+/// mbox separator lines want it.
+pub fn format_asctime(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+
+    // The inverse of the above, from the same source
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{} {} {day:>2} {:02}:{:02}:{:02} {year}",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_round_trip() {
+        let timestamp = parse_utc_date("2025-01-31T08:00:00Z").unwrap();
+        assert_eq!(timestamp, 1738310400);
+        assert_eq!(format_asctime(timestamp), "Fri Jan 31 08:00:00 2025");
+    }
+
+    #[test]
+    fn http_dates() {
+        assert_eq!(
+            parse_http_date("Fri, 31 Jan 2025 08:00:00 GMT"),
+            Some(1738310400)
+        );
+        assert_eq!(parse_http_date("0"), None);
+        assert_eq!(parse_http_date("Friday, 31-Jan-25 08:00:00 GMT"), None);
+    }
+}
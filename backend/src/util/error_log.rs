@@ -0,0 +1,109 @@
+use crate::util::redact::redact_addresses;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Write};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+
+/// How many warnings and errors are kept.
+const CAPACITY: usize = 100;
+
+/// Longest message kept, in bytes. Errors with long chains of causes can get large.
+const MAX_MESSAGE_LEN: usize = 2000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggedEvent {
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// Keeps the latest events logged, with email addresses redacted, for diagnostics. Install it as a layer of the tracing
+/// subscriber, filtered to warnings and errors.
+#[derive(Clone, Default)]
+pub struct ErrorLog {
+    events: Arc<Mutex<VecDeque<LoggedEvent>>>,
+}
+
+impl ErrorLog {
+    /// The kept events, oldest first.
+    pub fn recent(&self) -> Vec<LoggedEvent> {
+        self.events.lock().iter().cloned().collect()
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ErrorLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        // Redacted before truncating, which could otherwise cut an address short of looking
+        // like one
+        let mut message = redact_addresses(&visitor.message);
+        if message.len() > MAX_MESSAGE_LEN {
+            let end = message.floor_char_boundary(MAX_MESSAGE_LEN);
+            message.truncate(end);
+            message.push('…');
+        }
+
+        let mut events = self.events.lock();
+        if events.len() == CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(LoggedEvent {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message,
+        });
+    }
+}
+
+/// Formats an event like the console does: the message, then the other fields as `name=value`.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message.insert_str(0, &format!("{value:?}"));
+        } else {
+            let _ = write!(self.message, " {}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn long_messages_are_redacted_before_truncating() {
+        let log = ErrorLog::default();
+        let subscriber = tracing_subscriber::registry().with(log.clone());
+
+        // An address straddling the cut would lose the dot that marks it as one
+        let padding = "x ".repeat((MAX_MESSAGE_LEN - 10) / 2);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::error!("{padding}alice@example.com");
+        });
+
+        let message = &log.recent()[0].message;
+        assert!(!message.contains("alice"), "{message}");
+        assert!(message.len() <= MAX_MESSAGE_LEN + '…'.len_utf8());
+    }
+}
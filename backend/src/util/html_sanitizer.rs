@@ -1,8 +1,36 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 
 pub fn sanitize_html(html: &str) -> String {
+    sanitize_html_with_cids(html, HashMap::new())
+}
+
+/// Like [sanitize_html], pointing `cid:` references to inline parts at the given URLs.
+/// References to unknown parts are dropped.
+pub fn sanitize_html_with_cids(html: &str, cid_urls: HashMap<String, String>) -> String {
     ammonia::Builder::default()
         .add_tags(["img"])
         .add_generic_attributes(["loading"])
+        .add_url_schemes(["cid"])
+        .attribute_filter(move |_, _, value| match value.strip_prefix("cid:") {
+            Some(cid) => cid_urls.get(cid).map(|url| Cow::Owned(url.clone())),
+            None => Some(Cow::Borrowed(value)),
+        })
         .clean(html)
         .to_string()
-}
\ No newline at end of file
+}
+
+/// The text of an HTML fragment, with all markup dropped and whitespace collapsed.
+pub fn html_to_text(html: &str) -> String {
+    let text = ammonia::Builder::default()
+        .tags(Default::default())
+        .clean(html)
+        .to_string()
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&");
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
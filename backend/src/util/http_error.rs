@@ -1,5 +1,5 @@
 use axum::http::StatusCode;
-use axum::response::{ErrorResponse, IntoResponse};
+use axum::response::ErrorResponse;
 
 pub type HttpResult<T> = Result<T, ErrorResponse>;
 
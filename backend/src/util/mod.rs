@@ -1,4 +1,17 @@
+pub mod auth_results;
+pub mod body_parts;
+pub mod config;
+pub mod custom_headers;
+pub mod dates;
+pub mod error_log;
+pub mod html_sanitizer;
 pub mod http_error;
 pub mod network;
+pub mod rate_limit;
+pub mod redact;
+pub mod sniff;
+pub mod snippet;
+pub mod spool;
+pub mod tar;
 pub mod tasks;
-pub mod html_sanitizer;
+pub mod url_guard;
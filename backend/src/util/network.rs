@@ -1,3 +1,70 @@
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::watch;
+use url::Url;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct NetworkAvailability {
     pub online: bool,
 }
+
+/// How long a server may take to answer before it counts as unreachable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keeps `availability` up to date by sending a request to the servers `targets` names, e.g. the
+/// JMAP servers, every `interval`. The network counts as up while any of them answers, whatever
+/// the answer, or when there are none.
+///
+/// It's only a hint, for e.g. reconnecting right away once the network is back. A probe can be
+/// wrong, so nothing should wait on it to say the network is up.
+pub async fn monitor_network<F, Fut>(
+    availability: watch::Sender<NetworkAvailability>,
+    interval: Duration,
+    targets: F,
+) where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Vec<Url>>,
+{
+    // Goes through the same proxies as the JMAP client, from HTTP_PROXY and the like
+    let client = match reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(?e, "Error creating the network probe client");
+            return;
+        }
+    };
+
+    while !availability.is_closed() {
+        let online = any_reachable(&client, &targets().await).await;
+
+        availability.send_if_modified(|a| {
+            if a.online == online {
+                return false;
+            }
+            tracing::info!(online, "Network availability changed");
+            a.online = online;
+            true
+        });
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn any_reachable(client: &reqwest::Client, urls: &[Url]) -> bool {
+    if urls.is_empty() {
+        return true;
+    }
+
+    let probes = urls
+        .iter()
+        .map(|url| async move { client.head(url.clone()).send().await.is_ok() });
+
+    futures::future::join_all(probes)
+        .await
+        .into_iter()
+        .any(|reachable| reachable)
+}
@@ -0,0 +1,175 @@
+use anyhow::ensure;
+use parking_lot::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    /// Sustained number of requests allowed per second.
+    pub requests_per_sec: f64,
+    /// Number of requests that can be made in a burst before pacing kicks in.
+    pub burst: f64,
+}
+
+impl RateLimitConfig {
+    /// Refuses configs the bucket can't work with: a rate of 0 never refills, and a burst below 1
+    /// never holds a whole token.
+    pub fn new(requests_per_sec: f64, burst: f64) -> anyhow::Result<Self> {
+        ensure!(
+            requests_per_sec.is_finite() && requests_per_sec > 0.0,
+            "Rate limit must be a positive number of requests per second, got {requests_per_sec}"
+        );
+        ensure!(
+            burst.is_finite() && burst >= 1.0,
+            "Rate limit burst must be at least 1, got {burst}"
+        );
+        Ok(Self {
+            requests_per_sec,
+            burst,
+        })
+    }
+}
+
+/// A token bucket whose refill rate drops when the server pushes back and
+/// creeps back up to the configured rate as requests succeed again.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    rate: f64,
+    last_refill: Instant,
+}
+
+/// The slowest the bucket gets, as a fraction of the configured rate.
+const MIN_RATE_FACTOR: f64 = 1.0 / 16.0;
+const RECOVERY_FACTOR: f64 = 1.1;
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            bucket: Mutex::new(Bucket {
+                tokens: config.burst,
+                rate: config.requests_per_sec,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                bucket.refill(self.config.burst);
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.rate)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// The server told us to slow down: drain the bucket and halve the refill rate.
+    pub fn back_off(&self) {
+        let mut bucket = self.bucket.lock();
+        bucket.tokens = 0.0;
+        bucket.rate = (bucket.rate / 2.0).max(self.config.requests_per_sec * MIN_RATE_FACTOR);
+        tracing::warn!(rate = bucket.rate, "Rate limited by server, slowing down");
+    }
+
+    pub fn recover(&self) {
+        let mut bucket = self.bucket.lock();
+        bucket.rate = (bucket.rate * RECOVERY_FACTOR).min(self.config.requests_per_sec);
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, capacity: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests_per_sec: f64, burst: f64) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig::new(requests_per_sec, burst).unwrap())
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        assert!(RateLimitConfig::new(0.0, 10.0).is_err());
+        assert!(RateLimitConfig::new(-1.0, 10.0).is_err());
+        assert!(RateLimitConfig::new(f64::NAN, 10.0).is_err());
+        assert!(RateLimitConfig::new(10.0, 0.5).is_err());
+        assert!(RateLimitConfig::new(10.0, f64::INFINITY).is_err());
+        assert!(RateLimitConfig::new(0.5, 1.0).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_is_immediate() {
+        let limiter = limiter(10.0, 5.0);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_after_burst() {
+        let limiter = limiter(10.0, 2.0);
+        limiter.acquire().await;
+        limiter.acquire().await;
+
+        let start = Instant::now();
+        limiter.acquire().await;
+        let waited = start.elapsed();
+        assert!(
+            waited >= Duration::from_millis(100) && waited < Duration::from_millis(110),
+            "waited {waited:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refill_is_capped_at_burst() {
+        let limiter = limiter(10.0, 2.0);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_and_recovers() {
+        let limiter = limiter(16.0, 1.0);
+        for _ in 0..10 {
+            limiter.back_off();
+        }
+        assert_eq!(limiter.bucket.lock().rate, 1.0);
+
+        // With the bucket drained at the slowest rate, the next token takes a second
+        let start = Instant::now();
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        for _ in 0..100 {
+            limiter.recover();
+        }
+        assert_eq!(limiter.bucket.lock().rate, 16.0);
+    }
+}
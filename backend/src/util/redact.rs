@@ -0,0 +1,48 @@
+const REDACTED: &str = "<redacted>";
+
+/// Replaces anything that looks like an email address.
+pub fn redact_addresses(text: &str) -> String {
+    let is_local = |c: char| c.is_alphanumeric() || "._%+-".contains(c);
+    let is_domain = |c: char| c.is_alphanumeric() || ".-".contains(c);
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('@') {
+        let local_start = rest[..at]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_local(c))
+            .last()
+            .map_or(at, |(i, _)| i);
+        let domain_end = rest[at + 1..]
+            .char_indices()
+            .take_while(|&(_, c)| is_domain(c))
+            .last()
+            .map_or(at + 1, |(i, c)| at + 1 + i + c.len_utf8());
+
+        let domain = &rest[at + 1..domain_end];
+        if local_start < at && domain.contains('.') {
+            redacted.push_str(&rest[..local_start]);
+            redacted.push_str(REDACTED);
+        } else {
+            redacted.push_str(&rest[..domain_end]);
+        }
+        rest = &rest[domain_end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_redacted() {
+        assert_eq!(
+            redact_addresses("Error sending to Bob.Smith+x@mail.example.com: 550"),
+            "Error sending to <redacted>: 550"
+        );
+        assert_eq!(redact_addresses("user@localhost @ 5"), "user@localhost @ 5");
+    }
+}
@@ -0,0 +1,30 @@
+/// Leading bytes of the formats worth previewing, and the type they identify.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BM", "image/bmp"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+];
+
+/// How many leading bytes `sniff_mime_type` needs at most.
+pub const SNIFF_LEN: usize = 16;
+
+/// Guesses the type of `data` from its first bytes, whatever it claims to be.
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some("video/mp4");
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+        .map(|&(_, mime_type)| mime_type)
+}
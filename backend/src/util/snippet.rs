@@ -0,0 +1,160 @@
+/// How many characters of text a snippet shows.
+const SNIPPET_CHARS: usize = 160;
+
+/// How many characters a snippet shows before the first match.
+const LEAD_CHARS: usize = 40;
+
+/// A snippet of `text` around the first occurrence of any of the whitespace-separated `terms`,
+/// as HTML with the occurrences wrapped in `<mark>`, like JMAP's `SearchSnippet`. Matching
+/// ignores case. Without any occurrence it's the start of the text.
+pub fn highlight_snippet(text: &str, terms: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let terms = terms
+        .split_whitespace()
+        .map(|t| t.chars().flat_map(char::to_lowercase).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let chars = text.chars().collect::<Vec<_>>();
+    let matches = find_matches(&chars, &terms);
+
+    let start = match matches.first() {
+        Some(&(first, _)) => {
+            let start = first.saturating_sub(LEAD_CHARS);
+            // Start at a word if there's one close by, rather than halfway through one
+            match chars[start..first].iter().position(|c| *c == ' ') {
+                Some(space) if start > 0 => start + space + 1,
+                _ => start,
+            }
+        }
+        None => 0,
+    };
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+
+    let mut pos = start;
+    for &(match_start, match_end) in &matches {
+        if match_start < start {
+            continue;
+        }
+        if match_start >= end {
+            break;
+        }
+        let match_end = match_end.min(end);
+        escape_into(&mut snippet, &chars[pos..match_start]);
+        snippet.push_str("<mark>");
+        escape_into(&mut snippet, &chars[match_start..match_end]);
+        snippet.push_str("</mark>");
+        pos = match_end;
+    }
+    escape_into(&mut snippet, &chars[pos..end]);
+
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// The non-overlapping ranges of `chars` that match one of `terms`, preferring the longest
+/// term where several match at the same place.
+fn find_matches(chars: &[char], terms: &[Vec<char>]) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let len = terms
+            .iter()
+            .filter_map(|term| match_len(&chars[i..], term))
+            .max();
+        match len {
+            Some(len) if len > 0 => {
+                matches.push((i, i + len));
+                i += len;
+            }
+            _ => i += 1,
+        }
+    }
+    matches
+}
+
+/// How many characters at the start of `chars` make up `term`, ignoring case.
+fn match_len(chars: &[char], term: &[char]) -> Option<usize> {
+    let mut lowered = Vec::new();
+    for (n, c) in chars.iter().enumerate() {
+        if lowered.len() >= term.len() {
+            return (lowered == term).then_some(n);
+        }
+        lowered.extend(c.to_lowercase());
+        if !term.starts_with(&lowered[..lowered.len().min(term.len())]) {
+            return None;
+        }
+    }
+    (lowered == term).then_some(chars.len())
+}
+
+fn escape_into(out: &mut String, chars: &[char]) {
+    for &c in chars {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_every_term_ignoring_case() {
+        assert_eq!(
+            highlight_snippet("Lunch on  Friday?\nSee you Friday", "friday LUNCH"),
+            "<mark>Lunch</mark> on <mark>Friday</mark>? See you <mark>Friday</mark>"
+        );
+        assert_eq!(
+            highlight_snippet("Émile wrote", "émile"),
+            "<mark>Émile</mark> wrote"
+        );
+    }
+
+    #[test]
+    fn prefers_the_longest_term() {
+        assert_eq!(
+            highlight_snippet("foobar foo", "foo foobar"),
+            "<mark>foobar</mark> <mark>foo</mark>"
+        );
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            highlight_snippet("a <b> & \"c\"", "b"),
+            "a &lt;<mark>b</mark>&gt; &amp; &quot;c&quot;"
+        );
+    }
+
+    #[test]
+    fn starts_near_the_first_match_at_a_word() {
+        let text = format!("{}needle{}", "word ".repeat(30), " tail".repeat(50));
+        let snippet = highlight_snippet(&text, "needle");
+        assert!(snippet.starts_with("…word "), "{snippet}");
+        assert!(snippet.contains("word <mark>needle</mark> tail"));
+        assert!(snippet.ends_with('…'));
+
+        let plain = snippet.replace("<mark>", "").replace("</mark>", "");
+        assert_eq!(plain.chars().count(), SNIPPET_CHARS + 2);
+    }
+
+    #[test]
+    fn without_a_match_shows_the_start() {
+        let text = "x".repeat(SNIPPET_CHARS + 10);
+        let snippet = highlight_snippet(&text, "needle");
+        assert_eq!(snippet, format!("{}…", "x".repeat(SNIPPET_CHARS)));
+        assert_eq!(highlight_snippet("short", ""), "short");
+    }
+}
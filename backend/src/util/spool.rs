@@ -0,0 +1,87 @@
+use anyhow::Context;
+use axum::body::Body;
+use futures::StreamExt;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    /// Bodies larger than this are written to disk instead of being kept in memory.
+    pub threshold: usize,
+    /// Where spooled bodies are written to.
+    pub dir: PathBuf,
+}
+
+/// A request body, either buffered in memory or spooled to a temporary file.
+pub enum SpooledBody {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+/// A file that is removed when dropped, whether or not the work on it succeeded.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::warn!(?e, path = ?self.path, "Error removing temp file");
+        }
+    }
+}
+
+/// Reads `body` into memory, switching over to a temporary file once it grows past the threshold.
+pub async fn spool_body(body: Body, config: &SpoolConfig) -> anyhow::Result<SpooledBody> {
+    let mut stream = body.into_data_stream();
+    let mut buffer = Vec::new();
+
+    while let Some(chunk) = stream.next().await {
+        buffer.extend_from_slice(&chunk.context("Error reading request body")?);
+        if buffer.len() > config.threshold {
+            break;
+        }
+    }
+
+    if buffer.len() <= config.threshold {
+        return Ok(SpooledBody::Memory(buffer));
+    }
+
+    tokio::fs::create_dir_all(&config.dir)
+        .await
+        .context("Error creating spool directory")?;
+
+    // Created before the file itself, so a failed write below still cleans up.
+    let temp_file = TempFile {
+        path: config.dir.join(format!(
+            "upload-{}",
+            hex::encode(rand::random::<[u8; 16]>())
+        )),
+    };
+
+    let mut file = tokio::fs::File::create_new(temp_file.path())
+        .await
+        .context("Error creating spool file")?;
+
+    file.write_all(&buffer)
+        .await
+        .context("Error writing spool file")?;
+    drop(buffer);
+
+    while let Some(chunk) = stream.next().await {
+        file.write_all(&chunk.context("Error reading request body")?)
+            .await
+            .context("Error writing spool file")?;
+    }
+
+    file.flush().await.context("Error writing spool file")?;
+    Ok(SpooledBody::File(temp_file))
+}
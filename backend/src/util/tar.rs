@@ -0,0 +1,214 @@
+//! Just enough of the ustar format to stream regular files into and out of an archive.
+
+use anyhow::Context;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const BLOCK_SIZE: usize = 512;
+
+/// Two empty blocks mark the end of an archive.
+pub const END_OF_ARCHIVE: [u8; 2 * BLOCK_SIZE] = [0; 2 * BLOCK_SIZE];
+
+/// Builds the header for a regular file of `size` bytes. Paths longer than 100 bytes are split
+/// into the prefix field at a `/`.
+pub fn file_header(path: &str, size: u64, mtime: u64) -> anyhow::Result<[u8; BLOCK_SIZE]> {
+    let (prefix, name) = match path.len() {
+        0..=100 => ("", path),
+        _ => path
+            .char_indices()
+            .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100)
+            .map(|(i, _)| (&path[..i], &path[i + 1..]))
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Path too long for a tar archive: {path}"))?,
+    };
+
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is calculated with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    write_octal(&mut header[148..155], checksum as u64);
+
+    Ok(header)
+}
+
+/// The zeros to follow `size` bytes of file content with, so the next header starts on a block.
+pub fn padding(size: u64) -> &'static [u8] {
+    const ZEROS: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+    let rest = size as usize % BLOCK_SIZE;
+    &ZEROS[..(BLOCK_SIZE - rest) % BLOCK_SIZE]
+}
+
+/// Writes `value` as zero-padded octal followed by a NUL. It must fit the field.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    let (digits_field, terminator) = field.split_at_mut(field.len() - 1);
+    digits_field.copy_from_slice(digits.as_bytes());
+    terminator[0] = 0;
+}
+
+/// Reads the regular files of an archive in order, skipping anything else.
+pub struct TarReader<R> {
+    reader: R,
+    /// Larger files are refused rather than read into memory.
+    max_file_size: u64,
+}
+
+impl<R: AsyncRead + Unpin> TarReader<R> {
+    pub fn new(reader: R, max_file_size: u64) -> Self {
+        Self {
+            reader,
+            max_file_size,
+        }
+    }
+
+    /// The next file's path and content, or `None` at the end of the archive.
+    pub async fn next_file(&mut self) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+        loop {
+            let mut header = [0u8; BLOCK_SIZE];
+            match self.reader.read_exact(&mut header).await {
+                Ok(_) => {}
+                // Some writers leave out the end-of-archive blocks
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e).context("Error reading archive"),
+            }
+
+            if header.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+
+            let size = read_octal(&header[124..136]).context("Invalid entry size")?;
+            if size > self.max_file_size {
+                anyhow::bail!("Archive entry of {size} bytes is too large");
+            }
+
+            let mut data = vec![0u8; size as usize];
+            self.reader
+                .read_exact(&mut data)
+                .await
+                .context("Archive ended in the middle of an entry")?;
+
+            let mut padding = [0u8; BLOCK_SIZE];
+            self.reader
+                .read_exact(&mut padding[..self::padding(size).len()])
+                .await
+                .context("Archive ended in the middle of an entry")?;
+
+            // Directories, links, extended headers and the like carry no mail
+            if !matches!(header[156], b'0' | 0) {
+                continue;
+            }
+
+            let name = read_str(&header[..100]);
+            let prefix = read_str(&header[345..500]);
+            let path = if prefix.is_empty() {
+                name
+            } else {
+                format!("{prefix}/{name}")
+            };
+
+            return Ok(Some((path, data)));
+        }
+    }
+}
+
+fn read_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn read_octal(field: &[u8]) -> anyhow::Result<u64> {
+    let digits = read_str(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    u64::from_str_radix(digits, 8).with_context(|| format!("Invalid octal number {digits:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (path, data) in files {
+            archive.extend(file_header(path, data.len() as u64, 0).unwrap());
+            archive.extend(*data);
+            archive.extend(padding(data.len() as u64));
+        }
+        archive.extend(END_OF_ARCHIVE);
+        archive
+    }
+
+    async fn read_all(
+        archive: &[u8],
+        max_file_size: u64,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let mut reader = TarReader::new(archive, max_file_size);
+        let mut files = Vec::new();
+        while let Some(file) = reader.next_file().await? {
+            files.push(file);
+        }
+        Ok(files)
+    }
+
+    #[tokio::test]
+    async fn files_round_trip() {
+        let long_path = format!("{}/{}.eml", "d".repeat(120), "f".repeat(90));
+        let archive = archive(&[
+            ("a.eml", b"hello"),
+            ("empty.eml", b""),
+            (&long_path, &[7; BLOCK_SIZE]),
+        ]);
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+
+        let files = read_all(&archive, 1024).await.unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ("a.eml".to_string(), b"hello".to_vec()),
+                ("empty.eml".to_string(), Vec::new()),
+                (long_path, vec![7; BLOCK_SIZE]),
+            ]
+        );
+    }
+
+    #[test]
+    fn header_checksum_and_path_limits() {
+        let header = file_header("a.eml", 5, 0).unwrap();
+        let checksum = read_octal(&header[148..156]).unwrap();
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        assert_eq!(checksum, sum);
+
+        assert!(file_header(&"x".repeat(101), 0, 0).is_err());
+        assert!(file_header(&format!("{}/a", "d".repeat(156)), 0, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn other_entries_and_large_files() {
+        let mut archive = archive(&[("a.eml", b"hello")]);
+        let mut directory = file_header("dir", 0, 0).unwrap();
+        directory[156] = b'5';
+        archive.splice(0..0, directory);
+
+        let files = read_all(&archive, 1024).await.unwrap();
+        assert_eq!(files, vec![("a.eml".to_string(), b"hello".to_vec())]);
+        assert!(read_all(&archive, 4).await.is_err());
+        assert!(
+            read_all(&archive[..2 * BLOCK_SIZE + 3], 1024)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{Context, bail};
+use std::net::IpAddr;
+use url::{Host, Url};
+
+/// Whether an address is reachable on the public internet, as opposed to loopback, private,
+/// link-local and other special-purpose ranges that a proxy must never be pointed at.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64))
+        }
+
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Whether the URL's host is an IP literal (or `localhost`) that isn't public. Hostnames aren't
+/// resolved, so this is cheap enough to check on every redirect.
+pub fn is_private_host(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => !is_public_ip(ip.into()),
+        Some(Host::Ipv6(ip)) => !is_public_ip(ip.into()),
+        Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        None => true,
+    }
+}
+
+/// Resolves the URL's host and fails unless every address it resolves to is public.
+pub async fn ensure_public_url(url: &Url) -> anyhow::Result<()> {
+    if is_private_host(url) {
+        bail!("Refusing to access private address {url}");
+    }
+
+    // IP literals were checked above, and `host_str` would keep an IPv6 one's brackets
+    let Some(Host::Domain(host)) = url.host() else {
+        return Ok(());
+    };
+    let port = url.port_or_known_default().context("URL has no port")?;
+
+    for addr in tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Error resolving {host}"))?
+    {
+        if !is_public_ip(addr.ip()) {
+            bail!("Refusing to access {host}, it resolves to private address {addr}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn private_hosts() {
+        for private in [
+            "http://127.0.0.1/",
+            "http://10.1.2.3/",
+            "http://100.64.0.1/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "http://LOCALHOST:8080/",
+        ] {
+            assert!(is_private_host(&url(private)), "{private}");
+        }
+
+        for public in [
+            "https://8.8.8.8/",
+            "https://[2606:4700::1]/",
+            "https://example.com/",
+        ] {
+            assert!(!is_private_host(&url(public)), "{public}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ip_literals_are_checked_without_resolving() {
+        assert!(
+            ensure_public_url(&url("https://[2606:4700::1]/a.png"))
+                .await
+                .is_ok()
+        );
+        assert!(
+            ensure_public_url(&url("https://8.8.8.8/a.png"))
+                .await
+                .is_ok()
+        );
+        assert!(ensure_public_url(&url("http://[::1]:4000/")).await.is_err());
+    }
+}
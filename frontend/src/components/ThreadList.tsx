@@ -16,7 +16,7 @@ const log = parentLog.child({ "component": "ThreadList" });
 const apiUrl: string = import.meta.env.VITE_BASE_URL;
 
 export type EmailSort = {
-    column: 'Date';
+    column: 'Date' | 'SentAt' | 'Subject' | 'From';
     asc: boolean;
 }
 
@@ -62,7 +62,10 @@ export type Thread = zod.infer<typeof ThreadSchema>;
 export type BodyPart = zod.infer<typeof BodyPartSchema>;
 
 type EmailQuery = {
+    seq?: number,
     anchor_id?: string,
+    anchor_offset?: number,
+    position?: number,
     mailbox_id?: string,
     search_keyword?: string,
     sorts: EmailSort[],